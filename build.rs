@@ -1,6 +1,10 @@
 use std::env;
 
 fn main() {
+    // only the Linux AIO backend links libaio
+    if env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("linux") {
+        return
+    }
     let out_dir = env::var("OUT_DIR").unwrap();
     std::process::Command::new("make")
         .args(&[format!("{}/libaio.a", out_dir)])
@@ -11,5 +15,6 @@ fn main() {
     eprintln!("{}", out_dir);
     // the current source version of libaio is 0.3.112
     println!("cargo:rerun-if-changed={}/libaio.a", out_dir);
+    println!("cargo:rerun-if-changed=libaio");
     println!("cargo:rustc-link-search=native={}", out_dir);
 }
@@ -19,5 +19,5 @@
 #include <libaio.h>
 #include "syscall.h"
 
-io_syscall3(int, io_cancel_0_4, io_cancel, io_context_t, ctx, struct iocb *, iocb, struct io_event *, event)
-DEFSYMVER(io_cancel_0_4, io_cancel, 0.4)
+io_syscall3(int, io_cancel, io_cancel, io_context_t, ctx, struct iocb *, iocb, struct io_event *, event)
+//DEFSYMVER(io_cancel_0_4, io_cancel, 0.4)
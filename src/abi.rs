@@ -1,13 +1,21 @@
+//! The Linux AIO ABI (the iocbs, the io_events and the syscalls of libaio), exported by the
+//! `raw-abi` feature for custom submission logic (see also `AIOManager::submit_raw`). The
+//! names follow linux/include/uapi/linux/aio_abi.h, and are kept as they are.
+
 // libaio ABI adapted from:
 // https://raw.githubusercontent.com/jsgf/libaio-rust/master/src/aioabi.rs
 #![allow(dead_code)]
 
-use libc::{c_long, c_int, size_t};
+use libc::size_t;
+#[cfg(target_os = "linux")]
+use libc::{c_long, c_int};
+#[cfg(target_os = "linux")]
 pub use libc::timespec;
 use std::mem::zeroed;
 use std::default::Default;
 
 #[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum IOCmd {
     PRead = 0,
     PWrite = 1,
@@ -73,12 +81,30 @@ impl Default for IOEvent {
 pub enum IOContext {}
 pub type IOContextPtr = *mut IOContext;
 
+// The ring the completions are put in, which is what the context points to (see aio_ring in
+// linux/fs/aio.c), followed by the io_events.
+#[repr(C)]
+pub struct AIORing {
+    pub id: u32,
+    pub nr: u32,                  // the number of io_events
+    pub head: u32,
+    pub tail: u32,
+
+    pub magic: u32,
+    pub compat_features: u32,
+    pub incompat_features: u32,
+    pub header_length: u32,       // size of aio_ring
+}
+
+pub const AIO_RING_MAGIC: u32 = 0xa10a10a1;
+
 #[repr(C)]
 pub struct IOVector {
     pub iov_base: *mut u8,
     pub iov_len: size_t,
 }
 
+#[cfg(target_os = "linux")]
 #[link(name = "aio", kind = "static")]
 extern "C" {
     pub fn io_queue_init(maxevents: c_int, ctxp: *mut IOContextPtr) -> c_int;
@@ -91,7 +117,11 @@ extern "C" {
     pub fn io_getevents(ctx_id: IOContextPtr, min_nr: c_long,
                         nr: c_long, events: *mut IOEvent,
                         timeout: *mut timespec) -> c_int;
-    pub fn io_set_eventfd(iocb: *mut IOCb, eventfd: c_int);
+    pub fn io_pgetevents(ctx_id: IOContextPtr, min_nr: c_long,
+                         nr: c_long, events: *mut IOEvent,
+                         timeout: *mut timespec,
+                         sigmask: *mut libc::sigset_t) -> c_int;
+    // io_set_eventfd is inline in libaio.h, see IOCB_FLAG_RESFD instead
 }
 
 #[cfg(test)]
@@ -0,0 +1,74 @@
+//! Buffers with the alignment required by O_DIRECT.
+
+use crate::StableDeref;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+
+/// A zero-initialized heap buffer whose address is aligned (allocated by `posix_memalign(3)`),
+/// as required for the IOs on files opened with `O_DIRECT`.
+pub struct AlignedBuf {
+    ptr: NonNull<u8>,
+    len: usize,
+    align: usize,
+}
+
+// the buffer exclusively owns its memory
+unsafe impl Send for AlignedBuf {}
+unsafe impl Sync for AlignedBuf {}
+
+impl AlignedBuf {
+    /// Allocate `len` bytes aligned to `align`, which must be a power of two and a multiple of the
+    /// pointer size. Returns the errno on failure.
+    pub fn new(len: usize, align: usize) -> Result<Self, i32> {
+        let mut ptr = std::ptr::null_mut();
+        // always allocate something to have a unique pointer
+        let ret = unsafe { libc::posix_memalign(&mut ptr, align, len.max(1)) };
+        if ret != 0 {
+            return Err(ret)
+        }
+        unsafe { std::ptr::write_bytes(ptr as *mut u8, 0, len) };
+        Ok(AlignedBuf {
+            ptr: NonNull::new(ptr as *mut u8).unwrap(),
+            len,
+            align,
+        })
+    }
+
+    /// Allocate an aligned copy of the data.
+    pub fn from_slice(data: &[u8], align: usize) -> Result<Self, i32> {
+        let mut buf = Self::new(data.len(), align)?;
+        buf.copy_from_slice(data);
+        Ok(buf)
+    }
+
+    pub fn alignment(&self) -> usize {
+        self.align
+    }
+
+    /// Shorten the buffer to `len` bytes, keeping the allocation (no-op if it is not longer).
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len)
+    }
+}
+
+impl Deref for AlignedBuf {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+// the memory is on the heap
+unsafe impl StableDeref for AlignedBuf {}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        unsafe { libc::free(self.ptr.as_ptr() as *mut libc::c_void) }
+    }
+}
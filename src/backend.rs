@@ -0,0 +1,313 @@
+//! The engines that carry out the submitted iocbs.
+
+use crate::{abi, Error};
+#[cfg(target_os = "linux")]
+use crate::{LIBAIO_EAGAIN, LIBAIO_ENOMEM, LIBAIO_ENOSYS};
+#[cfg(target_os = "linux")] use parking_lot::RwLock;
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+
+/// The submission/completion machinery behind an AIOManager. All engines speak the libaio
+/// ABI: operations are described by iocbs and their completions are reported as io_events
+/// carrying the `aio_data` of the iocb.
+pub(crate) trait AioBackend: Send + Sync {
+    /// Submit the iocbs, returning the number of accepted ones (from the front) or the negated
+    /// errno.
+    fn submit(&self, iocbs: &mut [*mut abi::IOCb]) -> libc::c_int;
+
+    /// Wait for at least `min_nr` finished operations, returning the number of events filled or
+    /// the negated errno.
+    fn reap(
+        &self,
+        min_nr: usize,
+        events: &mut [abi::IOEvent],
+        timeout: Option<&mut libc::timespec>,
+    ) -> libc::c_int;
+
+    /// Attempt to cancel a submitted operation. The completion of the operation (with
+    /// `-ECANCELED` or its actual result) is still reported by `reap`.
+    fn cancel(&self, iocb: *mut abi::IOCb) -> libc::c_int;
+
+    /// Release what the engine holds against a system-wide limit (the AIO events of the kernel)
+    /// ahead of dropping it, once no operation is in flight. Any later call fails.
+    fn destroy(&self) {}
+
+    /// Whether the engine carries out `IOCmd::Noop` (the kernel AIO rejects it).
+    fn supports_noop(&self) -> bool {
+        true
+    }
+
+    /// Same as `reap`, but only for the AIOs of the given context (see `AIOBuilder::shards`).
+    fn reap_shard(
+        &self,
+        _shard: usize,
+        min_nr: usize,
+        events: &mut [abi::IOEvent],
+        timeout: Option<&mut libc::timespec>,
+    ) -> libc::c_int {
+        self.reap(min_nr, events, timeout)
+    }
+}
+
+/// The available engines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// Linux native AIO (`io_submit(2)`/`io_getevents(2)`), which fails with
+    /// `Error::NotSupported` on the other systems.
+    Libaio,
+    /// io_uring (requires Linux 5.6 or later).
+    #[cfg(feature = "uring")]
+    Uring,
+    /// Blocking syscalls carried out by a small pool of threads, which works everywhere.
+    ThreadPool,
+    /// POSIX AIO (`aio_read(3)`/`aio_write(3)`/`aio_suspend(3)`), for the systems without the
+    /// Linux AIO syscalls, where it is the default. It carries out neither the vectored
+    /// operations nor the polls, nor the per-operation flags, whose AIOs fail with `EINVAL`.
+    #[cfg(feature = "posix-aio")]
+    PosixAio,
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        #[cfg(not(target_os = "linux"))]
+        return Self::fallback();
+        #[cfg(all(target_os = "linux", feature = "uring"))]
+        return Backend::Uring;
+        #[cfg(all(target_os = "linux", not(feature = "uring")))]
+        return Backend::Libaio;
+    }
+}
+
+impl Backend {
+    /// The engine to fall back to when the chosen one is not supported (see
+    /// `AIOBuilder::allow_fallback`).
+    pub(crate) fn fallback() -> Self {
+        #[cfg(feature = "posix-aio")]
+        return Backend::PosixAio;
+        #[cfg(not(feature = "posix-aio"))]
+        return Backend::ThreadPool;
+    }
+
+    /// Set up the engine, which waits for the completions with the signals in `sigmask`
+    /// blocked instead of those of the calling thread, if given (ignored by the thread pool,
+    /// whose waits are never interrupted by signals).
+    pub(crate) fn create(
+        self,
+        maxevents: u32,
+        sigmask: Option<libc::sigset_t>,
+    ) -> Result<Box<dyn AioBackend>, Error> {
+        Ok(match self {
+            #[cfg(target_os = "linux")]
+            Backend::Libaio => Box::new(AIOContext::new(maxevents, sigmask)?),
+            #[cfg(not(target_os = "linux"))]
+            Backend::Libaio => {
+                let _ = sigmask;
+                return Err(Error::NotSupported)
+            }
+            #[cfg(feature = "uring")]
+            Backend::Uring => {
+                Box::new(crate::uring::UringContext::new(maxevents, sigmask)?)
+            }
+            Backend::ThreadPool => {
+                Box::new(crate::threadpool::ThreadPoolContext::new(maxevents))
+            }
+            #[cfg(feature = "posix-aio")]
+            Backend::PosixAio => {
+                Box::new(crate::posix::PosixContext::new(maxevents))
+            }
+        })
+    }
+}
+
+/// Translate the negated errno from setting up an IO context of `maxevents`.
+#[cfg(target_os = "linux")]
+pub(crate) fn setup_error(ret: libc::c_int, maxevents: u32) -> Error {
+    match ret {
+        LIBAIO_EAGAIN => {
+            let (in_use, limit) = aio_limits();
+            Error::MaxEventsTooLarge {
+                requested: maxevents,
+                in_use,
+                limit,
+            }
+        }
+        LIBAIO_ENOMEM => Error::LowKernelRes,
+        LIBAIO_ENOSYS => Error::NotSupported,
+        e => Error::Sys(-e),
+    }
+}
+
+/// The AIO events in use system-wide and their limit, if they can be read.
+#[cfg(target_os = "linux")]
+fn aio_limits() -> (Option<u64>, Option<u64>) {
+    let read = |name| {
+        std::fs::read_to_string(format!("/proc/sys/fs/{}", name))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    };
+    (read("aio-nr"), read("aio-max-nr"))
+}
+
+// NOTE: I assume it io_context_t is thread-safe, no?
+#[cfg(target_os = "linux")]
+struct AIOContext {
+    // null once destroyed
+    ctx: AtomicPtr<abi::IOContext>,
+    // waits with io_pgetevents(2) if set
+    sigmask: Option<libc::sigset_t>,
+    // held (shared) while in io_getevents(2), as the ring may only be read by either the kernel
+    // or us at a time
+    ring: RwLock<()>,
+}
+#[cfg(target_os = "linux")]
+unsafe impl Sync for AIOContext {}
+#[cfg(target_os = "linux")]
+unsafe impl Send for AIOContext {}
+
+#[cfg(target_os = "linux")]
+impl AIOContext {
+    fn new(
+        maxevents: u32,
+        sigmask: Option<libc::sigset_t>,
+    ) -> Result<Self, Error> {
+        let mut ctx = std::ptr::null_mut();
+        unsafe {
+            match abi::io_setup(maxevents as libc::c_int, &mut ctx) {
+                0 => Ok(AIOContext {
+                    ctx: AtomicPtr::new(ctx),
+                    sigmask,
+                    ring: RwLock::new(()),
+                }),
+                e => Err(setup_error(e, maxevents)),
+            }
+        }
+    }
+
+    /// Take the completions straight from the ring the kernel shares with us, saving the
+    /// syscall, if at least `min_nr` are already there and no other thread is reaping.
+    fn reap_ring(
+        &self,
+        min_nr: usize,
+        events: &mut [abi::IOEvent],
+    ) -> Option<usize> {
+        let _ring = self.ring.try_write()?;
+        let ring = self.ctx.load(Ordering::Acquire) as *mut abi::AIORing;
+        if ring.is_null() {
+            return None
+        }
+        unsafe {
+            if (*ring).magic != abi::AIO_RING_MAGIC ||
+                (*ring).incompat_features != 0
+            {
+                return None
+            }
+            let nr = (*ring).nr;
+            // written by the kernel once the io_events before it are
+            let tail = (*(&(*ring).tail as *const u32 as *const AtomicU32))
+                .load(Ordering::Acquire);
+            let head = &*(&(*ring).head as *const u32 as *const AtomicU32);
+            let mut h = head.load(Ordering::Acquire);
+            let avail = ((tail + nr - h) % nr) as usize;
+            if avail < min_nr {
+                return None
+            }
+            let n = avail.min(events.len());
+            let ring_events = ring.add(1) as *const abi::IOEvent;
+            for ev in events[..n].iter_mut() {
+                *ev = std::ptr::read_volatile(ring_events.add(h as usize));
+                h = (h + 1) % nr;
+            }
+            // lets the kernel reuse the io_events
+            head.store(h, Ordering::Release);
+            Some(n)
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl AioBackend for AIOContext {
+    fn submit(&self, iocbs: &mut [*mut abi::IOCb]) -> libc::c_int {
+        let ctx = self.ctx.load(Ordering::Acquire);
+        if ctx.is_null() {
+            return -libc::EINVAL
+        }
+        unsafe {
+            abi::io_submit(ctx, iocbs.len() as libc::c_long, iocbs.as_mut_ptr())
+        }
+    }
+
+    fn reap(
+        &self,
+        min_nr: usize,
+        events: &mut [abi::IOEvent],
+        timeout: Option<&mut libc::timespec>,
+    ) -> libc::c_int {
+        if let Some(n) = self.reap_ring(min_nr, events) {
+            return n as libc::c_int
+        }
+        let _ring = self.ring.read();
+        let ctx = self.ctx.load(Ordering::Acquire);
+        if ctx.is_null() {
+            return -libc::EINVAL
+        }
+        let timeout = timeout
+            .map(|t| t as *mut libc::timespec)
+            .unwrap_or(std::ptr::null_mut());
+        let (min_nr, nr) =
+            (min_nr as libc::c_long, events.len() as libc::c_long);
+        unsafe {
+            match self.sigmask {
+                Some(mut sigmask) => abi::io_pgetevents(
+                    ctx,
+                    min_nr,
+                    nr,
+                    events.as_mut_ptr(),
+                    timeout,
+                    &mut sigmask,
+                ),
+                None => abi::io_getevents(
+                    ctx,
+                    min_nr,
+                    nr,
+                    events.as_mut_ptr(),
+                    timeout,
+                ),
+            }
+        }
+    }
+
+    fn cancel(&self, iocb: *mut abi::IOCb) -> libc::c_int {
+        // the kernel never completes the cancellation synchronously (it returns -EINPROGRESS
+        // on success), so the event buffer is unused
+        let ctx = self.ctx.load(Ordering::Acquire);
+        if ctx.is_null() {
+            return -libc::EINVAL
+        }
+        let mut ev = abi::IOEvent::default();
+        unsafe { abi::io_cancel(ctx, iocb, &mut ev) }
+    }
+
+    fn destroy(&self) {
+        // not while the ring is read
+        let _ring = self.ring.write();
+        let ctx = self.ctx.swap(std::ptr::null_mut(), Ordering::AcqRel);
+        if !ctx.is_null() {
+            unsafe {
+                assert_eq!(abi::io_destroy(ctx), 0);
+            }
+        }
+    }
+
+    fn supports_noop(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for AIOContext {
+    fn drop(&mut self) {
+        self.destroy()
+    }
+}
@@ -0,0 +1,95 @@
+//! The barriers between the AIOs on a file descriptor: those scheduled after a barrier are only
+//! submitted once all those scheduled before it are finished (or submitted, see `FdOrder`).
+
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap};
+use std::os::unix::io::RawFd;
+
+/// How the operations on the same file descriptor are ordered (see `AIOBuilder::fd_order`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FdOrder {
+    /// Submitted in the order of scheduling, possibly with the same `io_submit`.
+    Submission,
+    /// Each submitted once the previous one is finished, so they also finish in order.
+    Completion,
+}
+
+#[derive(Default)]
+pub(crate) struct Barriers(Mutex<HashMap<RawFd, Epochs>>);
+
+/// The AIOs on a file descriptor, split by the barriers.
+#[derive(Default)]
+struct Epochs {
+    // the epoch of the AIOs scheduled from now on, raised by each barrier
+    current: u64,
+    // the number of unfinished AIOs per epoch
+    unfinished: BTreeMap<u64, usize>,
+}
+
+impl Barriers {
+    /// Put a barrier after the AIOs scheduled so far on the file descriptor.
+    pub(crate) fn barrier(&self, fd: RawFd) {
+        if let Some(e) = self.0.lock().get_mut(&fd) {
+            // nothing to wait for otherwise
+            if e.unfinished.contains_key(&e.current) {
+                e.current += 1
+            }
+        }
+    }
+
+    /// Account for a newly scheduled AIO, returning its epoch.
+    pub(crate) fn enter(&self, fd: RawFd) -> u64 {
+        let mut fds = self.0.lock();
+        let e = fds.entry(fd).or_default();
+        *e.unfinished.entry(e.current).or_insert(0) += 1;
+        e.current
+    }
+
+    /// Account for a newly scheduled AIO behind all the others, returning its epoch.
+    pub(crate) fn enter_last(&self, fd: RawFd) -> u64 {
+        let mut fds = self.0.lock();
+        let e = fds.entry(fd).or_default();
+        if e.unfinished.contains_key(&e.current) {
+            e.current += 1
+        }
+        e.unfinished.insert(e.current, 1);
+        e.current
+    }
+
+    /// Account for a finished (or never submitted) AIO.
+    pub(crate) fn leave(&self, fd: RawFd, epoch: u64) {
+        let mut fds = self.0.lock();
+        let e = fds.get_mut(&fd).unwrap();
+        let n = e.unfinished.get_mut(&epoch).unwrap();
+        *n -= 1;
+        if *n == 0 {
+            e.unfinished.remove(&epoch);
+            if e.unfinished.is_empty() {
+                fds.remove(&fd);
+            }
+        }
+    }
+
+    /// Whether all the AIOs before the epoch are finished, but those `ahead` (about to be
+    /// submitted first).
+    pub(crate) fn is_ready(
+        &self,
+        fd: RawFd,
+        epoch: u64,
+        ahead: &[u64],
+    ) -> bool {
+        self.0.lock().get(&fd).is_none_or(|e| {
+            e.unfinished.range(..epoch).all(|(e, _)| ahead.contains(e))
+        })
+    }
+
+    /// Whether some AIOs after the epoch are waiting.
+    pub(crate) fn is_blocking(&self, fd: RawFd, epoch: u64) -> bool {
+        self.0.lock().get(&fd).is_some_and(|e| {
+            e.unfinished
+                .keys()
+                .next_back()
+                .is_some_and(|last| *last > epoch)
+        })
+    }
+}
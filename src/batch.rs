@@ -0,0 +1,155 @@
+//! Batches of operations submitted together, in order.
+
+use crate::{abi, AIOFuture, AIOManagerHandle, IoPriority, OpOptions};
+use std::os::unix::io::{AsFd, AsRawFd, RawFd};
+
+/// An operation of a batch (see `AIOManager::submit_batch`), on a raw file descriptor which the
+/// caller has to keep open until the operation is finished.
+pub struct Request {
+    fd: RawFd,
+    offset: u64,
+    data: Box<[u8]>,
+    opcode: abi::IOCmd,
+    priority: Option<IoPriority>,
+}
+
+impl Request {
+    pub fn read(fd: RawFd, offset: u64, length: usize) -> Self {
+        Request {
+            fd,
+            offset,
+            data: vec![0; length].into_boxed_slice(),
+            opcode: abi::IOCmd::PRead,
+            priority: None,
+        }
+    }
+
+    pub fn write(fd: RawFd, offset: u64, data: Box<[u8]>) -> Self {
+        Request {
+            fd,
+            offset,
+            data,
+            opcode: abi::IOCmd::PWrite,
+            priority: None,
+        }
+    }
+
+    pub fn priority(mut self, priority: IoPriority) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+}
+
+impl AIOManagerHandle {
+    /// Submit the operations with a single `io_submit`, in the given order, which is done as soon
+    /// as there is room for all of them in the context (so a batch longer than
+    /// `AIOBuilder::max_events` fails with `EINVAL`). The futures are returned in the same order.
+    /// A batch is not held back by `AIOBuilder::max_pending`, though it counts towards it, and
+    /// its operations are neither split (see `AIOBuilder::max_io_size`) nor merged.
+    pub fn submit_batch(&self, reqs: Vec<Request>) -> Vec<AIOFuture<'static>> {
+        if reqs.len() > self.max_events {
+            return reqs
+                .into_iter()
+                .map(|r| self.fail(Box::new(r.data), libc::EINVAL))
+                .collect()
+        }
+        let mut aios = Vec::with_capacity(reqs.len());
+        let futs = reqs
+            .into_iter()
+            .map(|r| {
+                match self.new_aio(
+                    r.fd,
+                    r.offset,
+                    Box::new(r.data),
+                    r.priority,
+                    r.opcode,
+                    OpOptions::default(),
+                ) {
+                    Ok(aio) => {
+                        let fut = AIOFuture::new(self.notifier.clone(), aio.id);
+                        aios.push(aio);
+                        fut
+                    }
+                    Err(data) => self.fail(data, libc::EINVAL),
+                }
+            })
+            .collect();
+        if aios.is_empty() {
+            return futs
+        }
+        if let Some(permits) = self.notifier.permits.as_ref() {
+            permits.force(aios.len())
+        }
+        self.scheduler_in.queues.enqueue_batch(aios, &self.notifier);
+        futs
+    }
+
+    /// Read the regions of the file, given as `(offset, length)`, where the ones overlapping or
+    /// adjacent to each other are read together, and the reads are submitted as a single batch
+    /// (or several, if there are more than `AIOBuilder::max_events` of them). Resolves to the
+    /// buffer of each region, in the given order, or the errno of its read. A region past the
+    /// end of the file gets the bytes up to it.
+    pub async fn read_regions(
+        &self,
+        fd: &impl AsFd,
+        regions: &[(u64, usize)],
+    ) -> Vec<Result<Box<[u8]>, i32>> {
+        let fd = fd.as_fd().as_raw_fd();
+        let mut order = (0..regions.len())
+            .filter(|&i| regions[i].1 > 0)
+            .collect::<Vec<_>>();
+        order.sort_by_key(|&i| regions[i].0);
+        // the start and the end of each read, and the regions it covers
+        let mut reads: Vec<(u64, u64, Vec<usize>)> = Vec::new();
+        for i in order {
+            let (offset, len) = regions[i];
+            let end = offset + len as u64;
+            match reads.last_mut() {
+                Some((_, e, covered)) if offset <= *e => {
+                    *e = (*e).max(end);
+                    covered.push(i)
+                }
+                _ => reads.push((offset, end, vec![i])),
+            }
+        }
+        // all submitted before any is waited for
+        let futs = reads
+            .chunks(self.max_events.max(1))
+            .flat_map(|c| {
+                self.submit_batch(
+                    c.iter()
+                        .map(|(s, e, _)| {
+                            Request::read(fd, *s, (e - s) as usize)
+                        })
+                        .collect(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut bufs = regions
+            .iter()
+            .map(|_| Ok(Box::default()))
+            .collect::<Vec<_>>();
+        for ((start, _, covered), fut) in reads.into_iter().zip(futs) {
+            let (res, data) = fut.await;
+            let n = match res {
+                Ok(n) => n,
+                Err(e) => {
+                    for i in covered {
+                        bufs[i] = Err(e)
+                    }
+                    continue
+                }
+            };
+            if covered.len() == 1 && n == data.len() {
+                bufs[covered[0]] = Ok(data);
+                continue
+            }
+            for i in covered {
+                let (offset, len) = regions[i];
+                let from = ((offset - start) as usize).min(n);
+                bufs[i] = Ok(data[from..(from + len).min(n)].into())
+            }
+        }
+        bufs
+    }
+}
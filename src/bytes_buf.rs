@@ -0,0 +1,85 @@
+//! Zero-copy exchange of the data with the `bytes` crate.
+
+use crate::{
+    abi, AIOBuffer, AIOFuture, AIOManagerHandle, IoPriority, OpOptions,
+};
+use bytes::{Bytes, BytesMut};
+use std::any::Any;
+use std::os::unix::io::{AsFd, AsRawFd};
+
+impl AIOBuffer for Bytes {
+    fn iocb_buf(&self) -> (u64, u64) {
+        if self.is_empty() {
+            (0, 0)
+        } else {
+            (self.as_ptr() as u64, self.len() as u64)
+        }
+    }
+
+    fn to_vec(&self) -> Vec<u8> {
+        self[..].to_vec()
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any + Send> {
+        self
+    }
+}
+
+impl AIOBuffer for BytesMut {
+    fn iocb_buf(&self) -> (u64, u64) {
+        if self.is_empty() {
+            (0, 0)
+        } else {
+            (self.as_ptr() as u64, self.len() as u64)
+        }
+    }
+
+    fn to_vec(&self) -> Vec<u8> {
+        self[..].to_vec()
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any + Send> {
+        self
+    }
+}
+
+impl AIOManagerHandle {
+    /// Same as `read`, but into a `BytesMut`, which can be frozen and shared without copying.
+    pub fn read_bytes<'a>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        length: usize,
+        priority: Option<IoPriority>,
+    ) -> AIOFuture<'a, BytesMut> {
+        let data = BytesMut::zeroed(length);
+        let fd = fd.as_fd().as_raw_fd();
+        self.schedule(
+            fd,
+            offset,
+            Box::new(data),
+            priority,
+            abi::IOCmd::PRead,
+            OpOptions::default(),
+        )
+    }
+
+    /// Same as `write`, but from a `Bytes` (e.g., received from the network).
+    pub fn write_bytes<'a>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        data: Bytes,
+        priority: Option<IoPriority>,
+    ) -> AIOFuture<'a, Bytes> {
+        let fd = fd.as_fd().as_raw_fd();
+        self.schedule(
+            fd,
+            offset,
+            Box::new(data),
+            priority,
+            abi::IOCmd::PWrite,
+            OpOptions::default(),
+        )
+    }
+}
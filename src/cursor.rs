@@ -0,0 +1,82 @@
+//! Sequential access to a file through positioned AIOs.
+
+use crate::{AIOManagerHandle, AIOResult, StableDeref};
+use std::io::{self, SeekFrom};
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd};
+
+/// A cursor over a file, whose reads and writes start at the current position and advance it by
+/// the number of bytes transferred.
+pub struct AIOCursor<'a> {
+    aiomgr: &'a AIOManagerHandle,
+    fd: BorrowedFd<'a>,
+    pos: u64,
+}
+
+impl<'a> AIOCursor<'a> {
+    pub fn new(aiomgr: &'a AIOManagerHandle, fd: &'a impl AsFd) -> Self {
+        AIOCursor {
+            aiomgr,
+            fd: fd.as_fd(),
+            pos: 0,
+        }
+    }
+
+    /// The current position of the cursor.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Move the cursor, returning the new position (like `std::io::Seek::seek`).
+    pub fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let off = match pos {
+            SeekFrom::Start(off) => Some(off),
+            SeekFrom::Current(d) => offset_by(self.pos, d),
+            SeekFrom::End(d) => offset_by(self.len()?, d),
+        }
+        .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))?;
+        self.pos = off;
+        Ok(off)
+    }
+
+    /// Read up to `length` bytes at the cursor.
+    pub async fn read(&mut self, length: usize) -> AIOResult {
+        let (res, data) =
+            self.aiomgr.read(&self.fd, self.pos, length, None).await;
+        self.advance(res);
+        (res, data)
+    }
+
+    /// Write the data at the cursor (see `AIOManager::write` for the accepted buffer types).
+    pub async fn write<B>(&mut self, data: B) -> AIOResult<B>
+    where
+        B: StableDeref<Target = [u8]> + Send + 'static,
+    {
+        let (res, data) =
+            self.aiomgr.write(&self.fd, self.pos, data, None).await;
+        self.advance(res);
+        (res, data)
+    }
+
+    fn advance(&mut self, res: Result<usize, i32>) {
+        if let Ok(n) = res {
+            self.pos += n as u64
+        }
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstat(self.fd.as_raw_fd(), &mut stat) } < 0 {
+            return Err(io::Error::last_os_error())
+        }
+        Ok(stat.st_size as u64)
+    }
+}
+
+/// Offset the position by `delta` bytes, or None if it goes out of range.
+pub(crate) fn offset_by(base: u64, delta: i64) -> Option<u64> {
+    if delta < 0 {
+        base.checked_sub(delta.unsigned_abs())
+    } else {
+        base.checked_add(delta as u64)
+    }
+}
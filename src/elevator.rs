@@ -0,0 +1,58 @@
+//! Sorting the submissions per file descriptor by their offsets, as an elevator (C-SCAN) would.
+
+use crate::abi;
+use std::collections::HashMap;
+use std::os::unix::io::RawFd;
+
+#[derive(Default)]
+pub(crate) struct Elevator {
+    // where the last submission ended per fd, from which the next sweep starts
+    heads: HashMap<RawFd, u64>,
+}
+
+impl Elevator {
+    /// Sort the reads and writes on each fd by their offsets, those from the head onwards
+    /// first, then those before it from the lowest offset. They take the places of those on the
+    /// same fd, so the other fds and the other operations (e.g., fsync) keep theirs.
+    pub(crate) fn sort(&mut self, iocbs: &mut [*mut abi::IOCb]) {
+        let mut slots = HashMap::<RawFd, Vec<usize>>::new();
+        for (i, iocb) in iocbs.iter().enumerate() {
+            let iocb = unsafe { &**iocb };
+            if is_data(iocb) {
+                slots.entry(iocb.aio_fildes as RawFd).or_default().push(i)
+            }
+        }
+        for (fd, slots) in slots {
+            let head = self.heads.get(&fd).copied().unwrap_or(0);
+            let mut sorted =
+                slots.iter().map(|i| iocbs[*i]).collect::<Vec<_>>();
+            sorted.sort_by_key(|iocb| {
+                let offset = unsafe { (**iocb).aio_offset };
+                (offset < head, offset)
+            });
+            let last = unsafe { &**sorted.last().unwrap() };
+            self.heads.insert(fd, end(last));
+            for (i, iocb) in slots.into_iter().zip(sorted) {
+                iocbs[i] = iocb
+            }
+        }
+    }
+}
+
+fn is_data(iocb: &abi::IOCb) -> bool {
+    let op = iocb.aio_lio_opcode;
+    op == abi::IOCmd::PRead as u16 ||
+        op == abi::IOCmd::PWrite as u16 ||
+        op == abi::IOCmd::PReadV as u16 ||
+        op == abi::IOCmd::PWriteV as u16
+}
+
+/// Where the operation ends (or starts, for the vectored ones, whose lengths are not at hand).
+fn end(iocb: &abi::IOCb) -> u64 {
+    let op = iocb.aio_lio_opcode;
+    if op == abi::IOCmd::PRead as u16 || op == abi::IOCmd::PWrite as u16 {
+        iocb.aio_offset + iocb.aio_nbytes
+    } else {
+        iocb.aio_offset
+    }
+}
@@ -0,0 +1,129 @@
+//! The errors of setting up and using an AIOManager.
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The `requested` max_events are more than the system-wide limit on the AIO events
+    /// (`/proc/sys/fs/aio-max-nr`) allows on top of those `in_use` (`/proc/sys/fs/aio-nr`), as
+    /// far as they can be read (see `AIOBuilder::clamp_max_events`).
+    MaxEventsTooLarge {
+        requested: u32,
+        in_use: Option<u64>,
+        limit: Option<u64>,
+    },
+    LowKernelRes,
+    NotSupported,
+    /// No room for one more AIO without queueing it (see `AIOManager::try_read`).
+    QueueFull,
+    /// The AIO is cancelled (see `AIOManager::cancel`).
+    Cancelled,
+    /// The AIOManager no longer takes AIOs.
+    Shutdown,
+    /// `io_submit` failed, with the errno.
+    Submit(i32),
+    /// Any other failed system call, with the errno.
+    Sys(i32),
+    /// The offset, the buffer address or the length (`got`) of an operation on an `O_DIRECT`
+    /// file descriptor is not a multiple of the `required` alignment (see
+    /// `AIOBuilder::validate_alignment`).
+    Misaligned {
+        required: usize,
+        got: u64,
+    },
+    OtherError,
+}
+
+impl Error {
+    /// Translate the errno an AIO is resolved with.
+    pub fn from_errno(errno: i32) -> Self {
+        match errno {
+            libc::ECANCELED => Error::Cancelled,
+            libc::ESHUTDOWN => Error::Shutdown,
+            e => Error::Sys(e),
+        }
+    }
+
+    /// The errno closest to the error, if any.
+    pub fn errno(&self) -> Option<i32> {
+        match self {
+            Error::MaxEventsTooLarge { .. } | Error::QueueFull => {
+                Some(libc::EAGAIN)
+            }
+            Error::LowKernelRes => Some(libc::ENOMEM),
+            Error::Misaligned { .. } => Some(libc::EINVAL),
+            Error::NotSupported => Some(libc::ENOSYS),
+            Error::Cancelled => Some(libc::ECANCELED),
+            Error::Shutdown => Some(libc::ESHUTDOWN),
+            Error::Submit(e) | Error::Sys(e) => Some(*e),
+            Error::OtherError => None,
+        }
+    }
+
+    /// The last errno set by a failed system call.
+    pub(crate) fn last_os_error() -> Self {
+        Error::Sys(
+            std::io::Error::last_os_error()
+                .raw_os_error()
+                .unwrap_or(libc::EIO),
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::MaxEventsTooLarge {
+                requested,
+                in_use: Some(in_use),
+                limit: Some(limit),
+            } => write!(
+                f,
+                "max_events {} exceeds the system-wide limit: {} of {} AIO events in use \
+                 (see /proc/sys/fs/aio-max-nr)",
+                requested, in_use, limit
+            ),
+            Error::MaxEventsTooLarge { requested, .. } => write!(
+                f,
+                "max_events {} exceeds the system-wide limit",
+                requested
+            ),
+            Error::LowKernelRes => write!(f, "insufficient kernel resources"),
+            Error::NotSupported => write!(f, "AIO is not supported"),
+            Error::QueueFull => write!(f, "no room for one more AIO"),
+            Error::Cancelled => write!(f, "the AIO is cancelled"),
+            Error::Shutdown => write!(f, "the AIOManager is shut down"),
+            Error::Submit(e) => write!(
+                f,
+                "io_submit failed: {}",
+                std::io::Error::from_raw_os_error(*e)
+            ),
+            Error::Sys(e) => {
+                write!(f, "{}", std::io::Error::from_raw_os_error(*e))
+            }
+            Error::Misaligned { required, got } => write!(
+                f,
+                "misaligned for O_DIRECT: {} is not a multiple of {}",
+                got, required
+            ),
+            Error::OtherError => write!(f, "AIO error"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for std::io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::QueueFull => {
+                std::io::Error::new(std::io::ErrorKind::WouldBlock, e)
+            }
+            Error::Sys(errno) => std::io::Error::from_raw_os_error(errno),
+            Error::Misaligned { .. } => {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, e)
+            }
+            e => std::io::Error::other(e),
+        }
+    }
+}
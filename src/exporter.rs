@@ -0,0 +1,207 @@
+//! Export the counters of an AIOManager as prometheus metrics, refreshed upon every scrape.
+
+use crate::{AIOManagerHandle, AIONotifier};
+use parking_lot::Mutex;
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{
+    CounterVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+struct Exporter {
+    notifier: Arc<AIONotifier>,
+    // the counters are reset and set again by each collection
+    collecting: Mutex<()>,
+    submitted: IntCounter,
+    completed: IntCounter,
+    failed: IntCounter,
+    cancelled: IntCounter,
+    read_bytes: IntCounter,
+    written_bytes: IntCounter,
+    deadline_misses: IntCounter,
+    slow_ops: IntCounter,
+    retries: IntCounter,
+    submit_eagain: IntCounter,
+    in_flight: IntGauge,
+    pending: IntGauge,
+    fd_ops: IntCounterVec,
+    fd_bytes: IntCounterVec,
+    fd_errors: IntCounterVec,
+    fd_latency: CounterVec,
+}
+
+impl Exporter {
+    fn new(
+        notifier: Arc<AIONotifier>,
+        namespace: &str,
+    ) -> prometheus::Result<Self> {
+        let opts =
+            |name: &str, help: &str| Opts::new(name, help).namespace(namespace);
+        Ok(Exporter {
+            notifier,
+            collecting: Mutex::new(()),
+            submitted: IntCounter::with_opts(opts(
+                "aio_submitted_total",
+                "The AIOs accepted by the kernel.",
+            ))?,
+            completed: IntCounter::with_opts(opts(
+                "aio_completed_total",
+                "The AIOs finished successfully.",
+            ))?,
+            failed: IntCounter::with_opts(opts(
+                "aio_failed_total",
+                "The AIOs finished with an error other than ECANCELED.",
+            ))?,
+            cancelled: IntCounter::with_opts(opts(
+                "aio_cancelled_total",
+                "The AIOs cancelled.",
+            ))?,
+            read_bytes: IntCounter::with_opts(opts(
+                "aio_read_bytes_total",
+                "The bytes read.",
+            ))?,
+            written_bytes: IntCounter::with_opts(opts(
+                "aio_written_bytes_total",
+                "The bytes written.",
+            ))?,
+            deadline_misses: IntCounter::with_opts(opts(
+                "aio_deadline_misses_total",
+                "The AIOs finished after their deadlines.",
+            ))?,
+            slow_ops: IntCounter::with_opts(opts(
+                "aio_slow_ops_total",
+                "The AIOs slower than the threshold.",
+            ))?,
+            retries: IntCounter::with_opts(opts(
+                "aio_retries_total",
+                "The AIOs submitted again after a transient error.",
+            ))?,
+            submit_eagain: IntCounter::with_opts(opts(
+                "aio_submit_eagain_total",
+                "The submissions which found the context full.",
+            ))?,
+            in_flight: IntGauge::with_opts(opts(
+                "aio_in_flight",
+                "The AIOs submitted but not yet finished.",
+            ))?,
+            pending: IntGauge::with_opts(opts(
+                "aio_pending",
+                "The AIOs scheduled but not yet finished.",
+            ))?,
+            fd_ops: IntCounterVec::new(
+                opts("aio_fd_ops_total", "The AIOs finished per fd."),
+                &["fd"],
+            )?,
+            fd_bytes: IntCounterVec::new(
+                opts("aio_fd_bytes_total", "The bytes read or written per fd."),
+                &["fd"],
+            )?,
+            fd_errors: IntCounterVec::new(
+                opts("aio_fd_errors_total", "The AIOs failed per fd."),
+                &["fd"],
+            )?,
+            fd_latency: CounterVec::new(
+                opts(
+                    "aio_fd_latency_seconds_total",
+                    "The time from the scheduling to the completion of the AIOs per fd.",
+                ),
+                &["fd"],
+            )?,
+        })
+    }
+
+    fn counters(&self) -> [&IntCounter; 10] {
+        [
+            &self.submitted,
+            &self.completed,
+            &self.failed,
+            &self.cancelled,
+            &self.read_bytes,
+            &self.written_bytes,
+            &self.deadline_misses,
+            &self.slow_ops,
+            &self.retries,
+            &self.submit_eagain,
+        ]
+    }
+
+    fn collectors(&self) -> Vec<&dyn Collector> {
+        let mut cs = self
+            .counters()
+            .iter()
+            .map(|c| *c as &dyn Collector)
+            .collect::<Vec<_>>();
+        cs.extend_from_slice(&[
+            &self.in_flight as &dyn Collector,
+            &self.pending,
+            &self.fd_ops,
+            &self.fd_bytes,
+            &self.fd_errors,
+            &self.fd_latency,
+        ]);
+        cs
+    }
+}
+
+impl Collector for Exporter {
+    fn desc(&self) -> Vec<&Desc> {
+        self.collectors().iter().flat_map(|c| c.desc()).collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let _collecting = self.collecting.lock();
+        let n = &self.notifier;
+        let s = n.counters.snapshot();
+        let values = [
+            s.submitted,
+            s.completed,
+            s.failed,
+            s.cancelled,
+            s.bytes_read,
+            s.bytes_written,
+            s.deadline_misses,
+            s.slow_ops,
+            s.retries,
+            s.submit_eagain,
+        ];
+        for (c, v) in self.counters().iter().zip(values.iter()) {
+            c.reset();
+            c.inc_by(*v);
+        }
+        self.in_flight.set(s.in_flight as i64);
+        self.pending.set(n.npending.load(Ordering::Relaxed) as i64);
+        self.fd_ops.reset();
+        self.fd_bytes.reset();
+        self.fd_errors.reset();
+        self.fd_latency.reset();
+        if let Some(fd_stats) = n.fd_stats.as_ref() {
+            for (fd, s) in fd_stats.lock().iter() {
+                let fd = fd.to_string();
+                let fd = [fd.as_str()];
+                self.fd_ops.with_label_values(&fd).inc_by(s.ops);
+                self.fd_bytes.with_label_values(&fd).inc_by(s.bytes);
+                self.fd_errors.with_label_values(&fd).inc_by(s.errors);
+                self.fd_latency
+                    .with_label_values(&fd)
+                    .inc_by(s.total_latency.as_secs_f64());
+            }
+        }
+        self.collectors().iter().flat_map(|c| c.collect()).collect()
+    }
+}
+
+impl AIOManagerHandle {
+    /// Register the counters of the AIOs (see `stats`), and those per fd if enabled by
+    /// `AIOBuilder::fd_stats`, as prometheus metrics under the given namespace (e.g., to tell
+    /// the managers apart).
+    pub fn register_metrics(
+        &self,
+        registry: &Registry,
+        namespace: &str,
+    ) -> prometheus::Result<()> {
+        let exporter = Exporter::new(self.notifier.clone(), namespace)?;
+        registry.register(Box::new(exporter))
+    }
+}
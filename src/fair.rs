@@ -0,0 +1,134 @@
+//! Sharing each lane among the tags of the AIOs (see `AIOManager::read_with_tag`), in
+//! proportion to their weights and by the bytes, with a deficit round robin.
+
+use crate::abi;
+use crate::tenant::Tenants;
+use crate::throttle::rw_bytes;
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+/// The bytes a tag of weight 1 is given at each round.
+const QUANTUM: u64 = 64 << 10;
+/// The bytes an AIO counts for at least (e.g., an fsync).
+const MIN_COST: u64 = 4 << 10;
+
+/// A scheduled AIO, as sent to its lane.
+pub(crate) struct Enqueued {
+    pub(crate) iocb: AtomicPtr<abi::IOCb>,
+    pub(crate) tag: u32,
+}
+
+/// The weights of the tags, 1 by default (see `AIOManager::set_tag_weight`).
+#[derive(Default)]
+pub(crate) struct TagWeights(Mutex<HashMap<u32, u32>>);
+
+impl TagWeights {
+    pub(crate) fn set(&self, tag: u32, weight: u32) {
+        let mut weights = self.0.lock();
+        match weight {
+            1 => weights.remove(&tag),
+            w => weights.insert(tag, w),
+        };
+    }
+
+    fn get(&self, tag: u32) -> u32 {
+        self.0.lock().get(&tag).copied().unwrap_or(1)
+    }
+}
+
+struct Flow {
+    iocbs: VecDeque<AtomicPtr<abi::IOCb>>,
+    // the bytes left to the tag in this round
+    deficit: u64,
+}
+
+/// The AIOs of a lane taken from its queue, by their tags.
+#[derive(Default)]
+pub(crate) struct FairQueue {
+    flows: HashMap<u32, Flow>,
+    // the tags with some AIOs, the next one to be served first
+    active: VecDeque<u32>,
+    len: usize,
+}
+
+impl FairQueue {
+    pub(crate) fn push(&mut self, e: Enqueued) {
+        let flow = self.flows.entry(e.tag).or_insert_with(|| Flow {
+            iocbs: VecDeque::new(),
+            deficit: 0,
+        });
+        if flow.iocbs.is_empty() {
+            self.active.push_back(e.tag)
+        }
+        flow.iocbs.push_back(e.iocb);
+        self.len += 1;
+    }
+
+    /// The next AIO, skipping the tags beyond the quotas of their tenants.
+    pub(crate) fn pop(
+        &mut self,
+        weights: &TagWeights,
+        tenants: &Tenants,
+    ) -> Option<*mut abi::IOCb> {
+        // the tags skipped in a row
+        let mut nskipped = 0;
+        loop {
+            if nskipped == self.active.len() {
+                return None
+            }
+            let tag = *self.active.front()?;
+            let flow = self.flows.get_mut(&tag).unwrap();
+            let iocb = flow.iocbs.front().unwrap().load(Ordering::Acquire);
+            if !tenants.may_submit(tag, iocb) {
+                nskipped += 1;
+                self.active.rotate_left(1);
+                continue
+            }
+            let cost = rw_bytes(unsafe { &*iocb })
+                .map_or(0, |(_, n)| n)
+                .max(MIN_COST);
+            // no need to take turns alone
+            if self.active.len() > 1 && flow.deficit < cost {
+                flow.deficit += QUANTUM * weights.get(tag) as u64;
+                self.active.rotate_left(1);
+                nskipped = 0;
+                continue
+            }
+            tenants.submitted(tag, iocb);
+            flow.deficit = flow.deficit.saturating_sub(cost);
+            flow.iocbs.pop_front();
+            if flow.iocbs.is_empty() {
+                // no credit is kept while idle
+                self.flows.remove(&tag);
+                self.active.pop_front();
+            }
+            self.len -= 1;
+            return Some(iocb)
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether any AIO is within the quotas of its tenant.
+    pub(crate) fn is_ready(&self, tenants: &Tenants) -> bool {
+        self.active.iter().any(|tag| {
+            let iocb = self.flows[tag].iocbs.front().unwrap();
+            tenants.may_submit(*tag, iocb.load(Ordering::Acquire))
+        })
+    }
+
+    /// Take all the AIOs, in no particular order.
+    pub(crate) fn drain(
+        &mut self,
+    ) -> impl Iterator<Item = *mut abi::IOCb> + '_ {
+        self.active.clear();
+        self.len = 0;
+        self.flows
+            .drain()
+            .flat_map(|(_, f)| f.iocbs.into_iter())
+            .map(|p| p.load(Ordering::Acquire))
+    }
+}
@@ -0,0 +1,304 @@
+//! Injecting faults into the IOs of an engine, for testing the error handling of the
+//! applications (see `AIOBuilder::fault_injection`).
+
+use crate::abi;
+use crate::backend::AioBackend;
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The faults to inject, each into every `n`th IO of its kind (0 for never). The default injects
+/// none.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Faults {
+    /// Fail every `n`th IO with `EIO`, whether it was carried out or not.
+    pub eio_every: u64,
+    /// Carry out only the first half of every `n`th read or write (not vectored), which then
+    /// reports the bytes actually transferred.
+    pub short_every: u64,
+    /// Write only the first half of every `n`th write (not vectored), which still reports all
+    /// of its bytes as written, as a write torn by a crash would.
+    pub torn_every: u64,
+    /// Hold back every `n`th completion for `delay`, so the IOs finished after it overtake it.
+    pub delay_every: u64,
+    pub delay: Duration,
+}
+
+/// The faults injected into the IOs of the AIOManagers it is given to (see
+/// `AIOBuilder::fault_injection`), which can be changed at any time.
+#[derive(Clone)]
+pub struct FaultInjector(Arc<Mutex<Injector>>);
+
+#[derive(Default)]
+struct Injector {
+    faults: Faults,
+    // the IOs submitted, the reads and writes among them, the writes among those, and the
+    // completions reaped
+    nops: u64,
+    nrw: u64,
+    nwrites: u64,
+    nreaped: u64,
+    injected: u64,
+}
+
+/// A fault injected into a submitted IO, to finish upon its completion.
+enum Fault {
+    Eio,
+    // the original length of the shortened read or write
+    Short(u64),
+    Torn(u64),
+}
+
+struct Injected {
+    iocb: *mut abi::IOCb,
+    fault: Fault,
+}
+// the iocb is owned by its AIO, which outlives the operation
+unsafe impl Send for Injected {}
+
+impl FaultInjector {
+    pub fn new(faults: Faults) -> Self {
+        FaultInjector(Arc::new(Mutex::new(Injector {
+            faults,
+            ..Default::default()
+        })))
+    }
+
+    /// Replace the faults, counting the IOs of each kind from 0 again.
+    pub fn set(&self, faults: Faults) {
+        let mut inj = self.0.lock();
+        *inj = Injector {
+            faults,
+            injected: inj.injected,
+            ..Default::default()
+        }
+    }
+
+    /// The number of faults injected so far.
+    pub fn injected(&self) -> u64 {
+        self.0.lock().injected
+    }
+
+    pub(crate) fn wrap(&self, inner: Box<dyn AioBackend>) -> FaultyContext {
+        FaultyContext {
+            inner,
+            injector: self.clone(),
+            injected: Mutex::new(HashMap::new()),
+            held: Mutex::new(VecDeque::new()),
+        }
+    }
+}
+
+impl Injector {
+    /// The fault to inject into the submitted IO, if any.
+    fn submitted(&mut self, iocb: &abi::IOCb) -> Option<Fault> {
+        let f = self.faults;
+        let every = |n: u64, every: u64| every > 0 && n.is_multiple_of(every);
+        self.nops += 1;
+        let mut fault = every(self.nops, f.eio_every).then_some(Fault::Eio);
+        let op = iocb.aio_lio_opcode;
+        let (read, write) = (
+            op == abi::IOCmd::PRead as u16,
+            op == abi::IOCmd::PWrite as u16,
+        );
+        // a shortened IO must still be longer than 0
+        if (read || write) && iocb.aio_nbytes > 1 {
+            self.nrw += 1;
+            if write {
+                self.nwrites += 1;
+                if fault.is_none() && every(self.nwrites, f.torn_every) {
+                    fault = Some(Fault::Torn(iocb.aio_nbytes))
+                }
+            }
+            if fault.is_none() && every(self.nrw, f.short_every) {
+                fault = Some(Fault::Short(iocb.aio_nbytes))
+            }
+        }
+        if fault.is_some() {
+            self.injected += 1
+        }
+        fault
+    }
+
+    /// Whether to hold back the next completion.
+    fn delays(&mut self) -> Option<Duration> {
+        self.nreaped += 1;
+        let f = self.faults;
+        if f.delay_every == 0 || !self.nreaped.is_multiple_of(f.delay_every) {
+            return None
+        }
+        self.injected += 1;
+        Some(f.delay)
+    }
+}
+
+/// Half of the length, in whole 4 KiB blocks from 8 KiB up, so the IOs on `O_DIRECT` file
+/// descriptors stay aligned.
+fn cut(n: u64) -> u64 {
+    if n >= 8192 {
+        (n / 2) & !4095
+    } else {
+        n / 2
+    }
+}
+
+pub(crate) struct FaultyContext {
+    inner: Box<dyn AioBackend>,
+    injector: FaultInjector,
+    // the faults injected into the IOs in flight, by their aio_data
+    injected: Mutex<HashMap<u64, Injected>>,
+    // the completions held back, in the order they were
+    held: Mutex<VecDeque<(Instant, abi::IOEvent)>>,
+}
+
+impl FaultyContext {
+    /// Finish the fault injected into the IO of the completion, if any.
+    fn finish(&self, ev: &mut abi::IOEvent) {
+        let inj = match self.injected.lock().remove(&ev.data) {
+            Some(inj) => inj,
+            None => return,
+        };
+        let iocb = unsafe { &mut *inj.iocb };
+        match inj.fault {
+            Fault::Eio => ev.res = -libc::EIO as i64,
+            Fault::Short(n) => iocb.aio_nbytes = n,
+            Fault::Torn(n) => {
+                // the iocb may be submitted again, in full
+                iocb.aio_nbytes = n;
+                if ev.res >= 0 {
+                    ev.res = n as i64
+                }
+            }
+        }
+    }
+
+    /// Move the held completions that are due to the events, returning their number.
+    fn take_due(&self, events: &mut [abi::IOEvent]) -> usize {
+        let now = Instant::now();
+        let mut held = self.held.lock();
+        let mut n = 0;
+        while n < events.len() {
+            match held.front() {
+                Some((due, _)) if *due <= now => {
+                    events[n] = held.pop_front().unwrap().1;
+                    n += 1
+                }
+                _ => break,
+            }
+        }
+        n
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.held.lock().front().map(|(due, _)| *due)
+    }
+}
+
+impl AioBackend for FaultyContext {
+    fn submit(&self, iocbs: &mut [*mut abi::IOCb]) -> libc::c_int {
+        // registered ahead, as the IOs may be reaped as soon as they are submitted
+        let mut faulty = Vec::new();
+        {
+            let mut inj = self.injector.0.lock();
+            let mut injected = self.injected.lock();
+            for iocb in iocbs.iter() {
+                let i = unsafe { &mut **iocb };
+                let fault = match inj.submitted(i) {
+                    Some(f) => f,
+                    None => continue,
+                };
+                if let Fault::Short(n) | Fault::Torn(n) = fault {
+                    i.aio_nbytes = cut(n)
+                }
+                faulty.push(*iocb);
+                injected.insert(i.aio_data, Injected { iocb: *iocb, fault });
+            }
+        }
+        let ret = self.inner.submit(iocbs);
+        let nacc = ret.max(0) as usize;
+        let mut injected = self.injected.lock();
+        // the ones not accepted are submitted again as they were
+        for iocb in faulty.into_iter().filter(|p| !iocbs[..nacc].contains(p)) {
+            let i = unsafe { &mut *iocb };
+            if let Some(Injected {
+                fault: Fault::Short(n) | Fault::Torn(n),
+                ..
+            }) = injected.remove(&i.aio_data)
+            {
+                i.aio_nbytes = n
+            }
+        }
+        ret
+    }
+
+    fn reap(
+        &self,
+        min_nr: usize,
+        events: &mut [abi::IOEvent],
+        timeout: Option<&mut libc::timespec>,
+    ) -> libc::c_int {
+        let deadline = timeout.map(|t| {
+            Instant::now() + Duration::new(t.tv_sec as u64, t.tv_nsec as u32)
+        });
+        let mut nev = self.take_due(events);
+        while nev < events.len() {
+            // no later than the first held completion is due
+            let until = match (deadline, self.next_due()) {
+                (Some(d), Some(due)) => Some(d.min(due)),
+                (d, due) => d.or(due),
+            };
+            let mut ts = until.map(|until| {
+                crate::to_timespec(
+                    until.saturating_duration_since(Instant::now()),
+                )
+            });
+            let ret = self.inner.reap(
+                min_nr.saturating_sub(nev),
+                &mut events[nev..],
+                ts.as_mut(),
+            );
+            if ret < 0 {
+                if nev > 0 {
+                    break
+                }
+                return ret
+            }
+            let mut inj = self.injector.0.lock();
+            // the ones not held back are moved to the front
+            let mut kept = nev;
+            for i in nev..nev + ret as usize {
+                let mut ev = events[i].clone();
+                self.finish(&mut ev);
+                match inj.delays() {
+                    Some(delay) => {
+                        self.held.lock().push_back((Instant::now() + delay, ev))
+                    }
+                    None => {
+                        events[kept] = ev;
+                        kept += 1
+                    }
+                }
+            }
+            drop(inj);
+            nev = kept;
+            nev += self.take_due(&mut events[nev..]);
+            if nev >= min_nr || deadline.is_some_and(|d| Instant::now() >= d) {
+                break
+            }
+        }
+        nev as libc::c_int
+    }
+
+    fn cancel(&self, iocb: *mut abi::IOCb) -> libc::c_int {
+        self.inner.cancel(iocb)
+    }
+
+    fn destroy(&self) {
+        self.inner.destroy()
+    }
+
+    fn supports_noop(&self) -> bool {
+        self.inner.supports_noop()
+    }
+}
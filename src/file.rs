@@ -0,0 +1,123 @@
+//! A file owned together with its outstanding operations, so it can never be
+//! closed while the kernel still works on it.
+
+use crate::{
+    abi, AIOBuffer, AIOFuture, AIOManagerHandle, IOVecBuffer, IoPriority,
+    OpOptions, StableDeref, WriteBuf,
+};
+use std::fs::File;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::Arc;
+
+/// A file whose IOs are carried out by an AIOManager. Every operation holds a reference to the
+/// file, which is therefore only closed after both the AIOFile is dropped and all its operations
+/// are finished (even those whose futures were dropped).
+pub struct AIOFile<'a> {
+    file: Arc<File>,
+    aiomgr: &'a AIOManagerHandle,
+}
+
+impl<'a> AIOFile<'a> {
+    pub fn new(file: File, aiomgr: &'a AIOManagerHandle) -> Self {
+        AIOFile {
+            file: Arc::new(file),
+            aiomgr,
+        }
+    }
+
+    /// The underlying file.
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    pub fn read_at(
+        &self,
+        offset: u64,
+        length: usize,
+        priority: Option<IoPriority>,
+    ) -> AIOFuture<'static> {
+        let data = self.aiomgr.pool.get(length);
+        self.schedule(offset, Box::new(data), priority, abi::IOCmd::PRead)
+    }
+
+    /// See `AIOManager::write` for the accepted buffer types.
+    pub fn write_at<B>(
+        &self,
+        offset: u64,
+        data: B,
+        priority: Option<IoPriority>,
+    ) -> AIOFuture<'static, B>
+    where
+        B: StableDeref<Target = [u8]> + Send + 'static,
+    {
+        let data = Box::new(WriteBuf(data));
+        self.schedule(offset, data, priority, abi::IOCmd::PWrite)
+    }
+
+    /// See `AIOManager::read_vectored`.
+    pub fn read_vectored_at(
+        &self,
+        offset: u64,
+        lengths: &[usize],
+        priority: Option<IoPriority>,
+    ) -> AIOFuture<'static, Vec<Box<[u8]>>> {
+        let bufs = lengths
+            .iter()
+            .map(|len| self.aiomgr.pool.get(*len))
+            .collect();
+        let data = Box::new(IOVecBuffer::new(bufs));
+        self.schedule(offset, data, priority, abi::IOCmd::PReadV)
+    }
+
+    /// See `AIOManager::write_vectored`.
+    pub fn write_vectored_at(
+        &self,
+        offset: u64,
+        data: Vec<Box<[u8]>>,
+        priority: Option<IoPriority>,
+    ) -> AIOFuture<'static, Vec<Box<[u8]>>> {
+        let data = Box::new(IOVecBuffer::new(data));
+        self.schedule(offset, data, priority, abi::IOCmd::PWriteV)
+    }
+
+    /// Flush both the data and the metadata of the file (see `AIOManager::fsync`).
+    pub fn sync(&self) -> AIOFuture<'static> {
+        self.aiomgr.sync(
+            self.as_raw_fd(),
+            abi::IOCmd::FSync,
+            OpOptions::file(self.file.clone()),
+        )
+    }
+
+    /// Flush the data of the file (see `AIOManager::fdatasync`).
+    pub fn sync_data(&self) -> AIOFuture<'static> {
+        self.aiomgr.sync(
+            self.as_raw_fd(),
+            abi::IOCmd::FdSync,
+            OpOptions::file(self.file.clone()),
+        )
+    }
+
+    fn schedule<B>(
+        &self,
+        offset: u64,
+        data: Box<dyn AIOBuffer>,
+        priority: Option<IoPriority>,
+        opcode: abi::IOCmd,
+    ) -> AIOFuture<'static, B> {
+        self.aiomgr.schedule(
+            self.as_raw_fd(),
+            offset,
+            data,
+            priority,
+            opcode,
+            OpOptions::file(self.file.clone()),
+        )
+    }
+}
+
+impl AsRawFd for AIOFile<'_> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
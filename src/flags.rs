@@ -0,0 +1,60 @@
+//! Per-operation flags, as in `preadv2(2)`/`pwritev2(2)`.
+
+use std::ops::{BitOr, BitOrAssign};
+
+#[cfg(target_os = "linux")]
+use libc::{RWF_APPEND, RWF_DSYNC, RWF_HIPRI, RWF_NOWAIT, RWF_SYNC};
+
+// those of linux/fs.h, as the other systems have none (their AIOs with any fail with EINVAL)
+#[cfg(not(target_os = "linux"))]
+const RWF_HIPRI: libc::c_int = 0x01;
+#[cfg(not(target_os = "linux"))]
+const RWF_DSYNC: libc::c_int = 0x02;
+#[cfg(not(target_os = "linux"))]
+const RWF_SYNC: libc::c_int = 0x04;
+#[cfg(not(target_os = "linux"))]
+const RWF_NOWAIT: libc::c_int = 0x08;
+#[cfg(not(target_os = "linux"))]
+const RWF_APPEND: libc::c_int = 0x10;
+
+/// A set of `RWF_*` flags of an operation, combined with `|`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RWFlags(u32);
+
+impl RWFlags {
+    /// Poll for the completion (only for the files opened with `O_DIRECT`).
+    pub const HIPRI: Self = RWFlags(RWF_HIPRI as u32);
+    /// Write with the semantics of `O_DSYNC`.
+    pub const DSYNC: Self = RWFlags(RWF_DSYNC as u32);
+    /// Write with the semantics of `O_SYNC`.
+    pub const SYNC: Self = RWFlags(RWF_SYNC as u32);
+    /// Fail with `EAGAIN` instead of blocking (e.g., on a page cache miss).
+    pub const NOWAIT: Self = RWFlags(RWF_NOWAIT as u32);
+    /// Append to the end of the file, ignoring the offset.
+    pub const APPEND: Self = RWFlags(RWF_APPEND as u32);
+
+    pub const fn empty() -> Self {
+        RWFlags(0)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for RWFlags {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        RWFlags(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for RWFlags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0
+    }
+}
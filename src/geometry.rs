@@ -0,0 +1,85 @@
+//! The geometry of the block devices, to configure the alignment and the sizes of the IOs.
+
+use crate::Error;
+use std::os::unix::io::{AsFd, AsRawFd, RawFd};
+
+// from linux/fs.h
+const BLKSSZGET: libc::c_ulong = 0x1268;
+const BLKPBSZGET: libc::c_ulong = 0x127b;
+#[cfg(target_pointer_width = "64")]
+const BLKGETSIZE64: libc::c_ulong = 0x80081272;
+#[cfg(target_pointer_width = "32")]
+const BLKGETSIZE64: libc::c_ulong = 0x80041272;
+
+/// The geometry of a block device (or of a file, see `Geometry::of`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Geometry {
+    /// The smallest unit the device addresses, which the offsets, the buffers and the lengths
+    /// of the IOs on `O_DIRECT` file descriptors are aligned to (see `AIOBuilder::alignment`).
+    pub logical_block_size: usize,
+    /// The unit the device writes at once, the alignment for the best performance.
+    pub physical_block_size: usize,
+    /// The size in bytes.
+    pub size: u64,
+    /// The largest IO the device takes at once (see `AIOBuilder::max_io_size`), if known.
+    pub max_transfer: Option<usize>,
+}
+
+impl Geometry {
+    /// Query the geometry of the block device, or of a regular file: the block size of its
+    /// file system for both block sizes, its size, and the largest transfer of the underlying
+    /// device. Only takes a few syscalls (and reads from sysfs) which never wait for IOs, so it
+    /// can be called from the async code.
+    pub fn of(fd: &impl AsFd) -> Result<Self, Error> {
+        let fd = fd.as_fd().as_raw_fd();
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstat(fd, &mut stat) } < 0 {
+            return Err(Error::last_os_error())
+        }
+        if stat.st_mode & libc::S_IFMT != libc::S_IFBLK {
+            return Ok(Geometry {
+                logical_block_size: stat.st_blksize as usize,
+                physical_block_size: stat.st_blksize as usize,
+                size: stat.st_size as u64,
+                max_transfer: max_transfer(stat.st_dev),
+            })
+        }
+        let mut logical: libc::c_int = 0;
+        let mut physical: libc::c_uint = 0;
+        let mut size: u64 = 0;
+        ioctl(fd, BLKSSZGET, &mut logical as *mut _ as *mut libc::c_void)?;
+        ioctl(fd, BLKPBSZGET, &mut physical as *mut _ as *mut libc::c_void)?;
+        ioctl(fd, BLKGETSIZE64, &mut size as *mut _ as *mut libc::c_void)?;
+        Ok(Geometry {
+            logical_block_size: logical as usize,
+            physical_block_size: physical as usize,
+            size,
+            max_transfer: max_transfer(stat.st_rdev),
+        })
+    }
+}
+
+fn ioctl(
+    fd: RawFd,
+    req: libc::c_ulong,
+    arg: *mut libc::c_void,
+) -> Result<(), Error> {
+    if unsafe { libc::ioctl(fd, req as _, arg) } < 0 {
+        return Err(Error::last_os_error())
+    }
+    Ok(())
+}
+
+/// The `max_sectors_kb` of the device's queue (that of the whole disk for a partition).
+fn max_transfer(dev: libc::dev_t) -> Option<usize> {
+    let dir =
+        format!("/sys/dev/block/{}:{}", libc::major(dev), libc::minor(dev));
+    ["queue", "../queue"].iter().find_map(|queue| {
+        let kb = std::fs::read_to_string(format!(
+            "{}/{}/max_sectors_kb",
+            dir, queue
+        ))
+        .ok()?;
+        kb.trim().parse::<usize>().ok().map(|kb| kb * 1024)
+    })
+}
@@ -0,0 +1,116 @@
+//! A group of operations awaited together.
+
+use crate::{AIOFuture, AIOManagerHandle, AIOResult, IoPriority};
+use std::future::Future;
+use std::os::unix::io::AsFd;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Reads and writes scheduled one by one, then awaited as a whole: the group resolves to the
+/// total number of bytes transferred, or the errno of the first failed operation (in the order
+/// of scheduling, with a short write failing with `EIO`), once all of them are finished. The
+/// buffers of all the operations are handed back in the order of scheduling either way.
+pub struct IoGroup<'a> {
+    aiomgr: &'a AIOManagerHandle,
+    ops: Vec<Op<'a>>,
+}
+
+struct Op<'a> {
+    fut: AIOFuture<'a>,
+    // the length of a write, whose short write fails the group
+    write_len: Option<usize>,
+    done: Option<AIOResult>,
+}
+
+impl<'a> IoGroup<'a> {
+    pub(crate) fn new(aiomgr: &'a AIOManagerHandle) -> Self {
+        IoGroup {
+            aiomgr,
+            ops: Vec::new(),
+        }
+    }
+
+    pub fn read(
+        &mut self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        length: usize,
+        priority: Option<IoPriority>,
+    ) -> &mut Self {
+        self.push(self.aiomgr.read(fd, offset, length, priority), None)
+    }
+
+    pub fn write(
+        &mut self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        data: Box<[u8]>,
+        priority: Option<IoPriority>,
+    ) -> &mut Self {
+        let len = data.len();
+        self.push(self.aiomgr.write(fd, offset, data, priority), Some(len))
+    }
+
+    /// The number of operations in the group.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    fn push(
+        &mut self,
+        fut: AIOFuture<'a>,
+        write_len: Option<usize>,
+    ) -> &mut Self {
+        self.ops.push(Op {
+            fut,
+            write_len,
+            done: None,
+        });
+        self
+    }
+}
+
+impl Future for IoGroup<'_> {
+    type Output = AIOResult<Vec<Box<[u8]>>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut finished = true;
+        for op in this.ops.iter_mut().filter(|op| op.done.is_none()) {
+            match Pin::new(&mut op.fut).poll(cx) {
+                Poll::Ready(r) => op.done = Some(r),
+                Poll::Pending => finished = false,
+            }
+        }
+        if !finished {
+            return Poll::Pending
+        }
+        let mut res = Ok(0);
+        let mut bufs = Vec::with_capacity(this.ops.len());
+        for op in this.ops.drain(..) {
+            let (r, buf) = op.done.unwrap();
+            let r = match (r, op.write_len) {
+                (Ok(n), Some(len)) if n < len => Err(libc::EIO),
+                (r, _) => r,
+            };
+            res = match (res, r) {
+                (Ok(total), Ok(n)) => Ok(total + n),
+                (Ok(_), Err(e)) => Err(e),
+                (res, _) => res,
+            };
+            bufs.push(buf);
+        }
+        Poll::Ready((res, bufs))
+    }
+}
+
+impl AIOManagerHandle {
+    /// Start a group of operations to be awaited together (see `IoGroup`).
+    pub fn group(&self) -> IoGroup<'_> {
+        IoGroup::new(self)
+    }
+}
@@ -0,0 +1,203 @@
+//! Growing the context when the submissions keep finding it full, instead of holding them back.
+
+use crate::abi;
+use crate::backend::{AioBackend, Backend};
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicIsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long the submissions find the context full before it is grown.
+const GROW_AFTER: Duration = Duration::from_millis(10);
+/// The longest wait on the current context while the previous one is being drained.
+const DRAIN_POLL: Duration = Duration::from_millis(1);
+
+struct Ctx {
+    backend: Box<dyn AioBackend>,
+    size: u32,
+    // the submitted iocbs yet to be reaped (briefly negative if reaped before being counted)
+    inflight: AtomicIsize,
+}
+
+impl Ctx {
+    fn new(backend: Box<dyn AioBackend>, size: u32) -> Arc<Self> {
+        Arc::new(Ctx {
+            backend,
+            size,
+            inflight: AtomicIsize::new(0),
+        })
+    }
+}
+
+struct Contexts {
+    cur: Arc<Ctx>,
+    // replaced by cur, so it takes no more submissions and goes once all its iocbs are reaped
+    old: Option<Arc<Ctx>>,
+    // since when the submissions find cur full
+    full_since: Option<Instant>,
+    // the largest size to grow to (lowered if growing fails)
+    max: u32,
+}
+
+pub(crate) struct GrowingContext {
+    backend: Backend,
+    sigmask: Option<libc::sigset_t>,
+    ctxs: Mutex<Contexts>,
+}
+
+impl GrowingContext {
+    pub(crate) fn new(
+        ctx: Box<dyn AioBackend>,
+        backend: Backend,
+        size: u32,
+        max: u32,
+        sigmask: Option<libc::sigset_t>,
+    ) -> Self {
+        GrowingContext {
+            backend,
+            sigmask,
+            ctxs: Mutex::new(Contexts {
+                cur: Ctx::new(ctx, size),
+                old: None,
+                full_since: None,
+                max,
+            }),
+        }
+    }
+
+    /// Replace the current context by one twice as large, unless the previous one is still
+    /// being drained.
+    fn grow(&self, ctxs: &mut Contexts) {
+        if ctxs.old.is_some() || ctxs.cur.size >= ctxs.max {
+            return
+        }
+        let size = ctxs.cur.size.saturating_mul(2).min(ctxs.max);
+        match self.backend.create(size, self.sigmask) {
+            Ok(backend) => {
+                diag!(info, "aio context grown to {} events", size);
+                let cur =
+                    std::mem::replace(&mut ctxs.cur, Ctx::new(backend, size));
+                ctxs.old = Some(cur);
+                ctxs.full_since = None;
+            }
+            Err(e) => {
+                diag!(
+                    warn,
+                    "failed to grow the aio context to {} events: {}",
+                    size,
+                    e
+                );
+                ctxs.max = ctxs.cur.size
+            }
+        }
+    }
+}
+
+impl AioBackend for GrowingContext {
+    fn submit(&self, iocbs: &mut [*mut abi::IOCb]) -> libc::c_int {
+        // held throughout, so the previous context takes no more submissions once replaced
+        let mut ctxs = self.ctxs.lock();
+        let ret = ctxs.cur.backend.submit(iocbs);
+        if ret > 0 {
+            ctxs.cur.inflight.fetch_add(ret as isize, Ordering::AcqRel);
+        }
+        let full =
+            ret == -libc::EAGAIN || (ret >= 0 && (ret as usize) < iocbs.len());
+        if !full {
+            ctxs.full_since = None;
+            return ret
+        }
+        let since = *ctxs.full_since.get_or_insert_with(Instant::now);
+        if since.elapsed() >= GROW_AFTER {
+            self.grow(&mut ctxs)
+        }
+        ret
+    }
+
+    fn reap(
+        &self,
+        min_nr: usize,
+        events: &mut [abi::IOEvent],
+        timeout: Option<&mut libc::timespec>,
+    ) -> libc::c_int {
+        let (cur, old) = {
+            let ctxs = self.ctxs.lock();
+            (ctxs.cur.clone(), ctxs.old.clone())
+        };
+        let mut nev = 0;
+        let mut draining = false;
+        if let Some(old) = old {
+            let mut no_wait = libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            };
+            let ret = old.backend.reap(0, events, Some(&mut no_wait));
+            if ret < 0 {
+                return ret
+            }
+            nev = ret as usize;
+            if old.inflight.fetch_sub(nev as isize, Ordering::AcqRel) >
+                nev as isize
+            {
+                draining = true
+            } else {
+                let mut ctxs = self.ctxs.lock();
+                if ctxs.old.as_ref().is_some_and(|o| Arc::ptr_eq(o, &old)) {
+                    ctxs.old = None
+                }
+            }
+            if nev >= min_nr || nev == events.len() {
+                return nev as libc::c_int
+            }
+        }
+        // come back to the previous context soon
+        let mut bounded;
+        let timeout = if draining {
+            let t = match timeout {
+                Some(t) => Duration::new(t.tv_sec as u64, t.tv_nsec as u32)
+                    .min(DRAIN_POLL),
+                None => DRAIN_POLL,
+            };
+            bounded = crate::to_timespec(t);
+            Some(&mut bounded)
+        } else {
+            timeout
+        };
+        let ret = cur.backend.reap(min_nr - nev, &mut events[nev..], timeout);
+        if ret < 0 {
+            return if nev > 0 { nev as libc::c_int } else { ret }
+        }
+        cur.inflight.fetch_sub(ret as isize, Ordering::AcqRel);
+        (nev + ret as usize) as libc::c_int
+    }
+
+    fn cancel(&self, iocb: *mut abi::IOCb) -> libc::c_int {
+        let (cur, old) = {
+            let ctxs = self.ctxs.lock();
+            (ctxs.cur.clone(), ctxs.old.clone())
+        };
+        let ret = cur.backend.cancel(iocb);
+        match old {
+            // not found in the current context
+            Some(old) if ret == -libc::EINVAL => old.backend.cancel(iocb),
+            // io_uring reports a missing target only through the completion of the cancel
+            Some(old) => {
+                old.backend.cancel(iocb);
+                ret
+            }
+            None => ret,
+        }
+    }
+
+    fn destroy(&self) {
+        let ctxs = self.ctxs.lock();
+        ctxs.cur.backend.destroy();
+        if let Some(old) = ctxs.old.as_ref() {
+            old.backend.destroy()
+        }
+    }
+
+    fn supports_noop(&self) -> bool {
+        self.ctxs.lock().cur.backend.supports_noop()
+    }
+}
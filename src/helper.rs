@@ -0,0 +1,359 @@
+//! A small pool of helper threads carrying out the blocking operations without an AIO
+//! counterpart (e.g., hole punching, or opening files), whose futures resolve the same way as
+//! those of the AIOs.
+
+use crate::{AIOFuture, AIONotifier};
+use parking_lot::Mutex;
+use std::ffi::{CStr, CString};
+use std::mem::ManuallyDrop;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+
+const NTHREADS: usize = 4;
+
+// from linux/fs.h
+#[cfg(target_os = "linux")]
+const BLKDISCARD: libc::c_ulong = 0x1277;
+#[cfg(target_os = "linux")]
+const FICLONERANGE: libc::c_ulong = 0x4020940d;
+
+// from linux/fs.h
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct FileCloneRange {
+    src_fd: i64,
+    src_offset: u64,
+    src_length: u64,
+    dest_offset: u64,
+}
+
+/// A blocking operation, returning its result in the same form as `io_event.res`.
+type Op = Box<dyn FnOnce() -> i64 + Send>;
+
+/// The operation of an AIO, carried out once the AIOs it comes after are finished.
+struct Job {
+    id: u64,
+    after: Vec<u64>,
+    op: Op,
+}
+
+pub(crate) struct Helper {
+    queue_in: crossbeam_channel::Sender<Job>,
+    threads: Vec<std::thread::JoinHandle<()>>,
+}
+
+/// A blocking operation on the helper threads resolving to a value rather than to the bytes
+/// transferred (see `AIOManager::open_at`). It counts as pending on the manager like an AIO.
+pub struct BlockingFuture<'a, T> {
+    aio: AIOFuture<'a>,
+    // set by the operation once it succeeds
+    slot: Arc<Mutex<Option<T>>>,
+}
+
+impl<'a, T> BlockingFuture<'a, T> {
+    pub(crate) fn new(aio: AIOFuture<'a>, slot: Arc<Mutex<Option<T>>>) -> Self {
+        BlockingFuture { aio, slot }
+    }
+
+    /// The id of the AIO carrying out the call (see `AIOFuture::id`).
+    pub fn id(&self) -> u64 {
+        self.aio.id()
+    }
+}
+
+impl<T> std::future::Future for BlockingFuture<'_, T> {
+    type Output = std::io::Result<T>;
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context,
+    ) -> std::task::Poll<Self::Output> {
+        let this = self.get_mut();
+        Pin::new(&mut this.aio).poll(cx).map(|(res, _)| {
+            res.map_err(std::io::Error::from_raw_os_error)?;
+            // never run if the manager was closed in the meantime
+            this.slot.lock().take().ok_or_else(|| {
+                std::io::Error::from_raw_os_error(libc::ESHUTDOWN)
+            })
+        })
+    }
+}
+
+#[cfg(feature = "futures-core")]
+impl<T> futures_core::future::FusedFuture for BlockingFuture<'_, T> {
+    fn is_terminated(&self) -> bool {
+        self.aio.done
+    }
+}
+
+/// How the data in a range of a file is to be accessed (see `AIOManager::advise`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Advice {
+    Normal,
+    Sequential,
+    Random,
+    /// Accessed only once.
+    NoReuse,
+    /// Accessed soon, so it is read ahead into the page cache.
+    WillNeed,
+    /// Not accessed soon, so it is dropped from the page cache (once written back).
+    DontNeed,
+}
+
+impl Advice {
+    fn to_raw(self) -> libc::c_int {
+        match self {
+            Advice::Normal => libc::POSIX_FADV_NORMAL,
+            Advice::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+            Advice::Random => libc::POSIX_FADV_RANDOM,
+            Advice::NoReuse => libc::POSIX_FADV_NOREUSE,
+            Advice::WillNeed => libc::POSIX_FADV_WILLNEED,
+            Advice::DontNeed => libc::POSIX_FADV_DONTNEED,
+        }
+    }
+}
+
+impl Helper {
+    pub(crate) fn spawn(notifier: Arc<AIONotifier>) -> std::io::Result<Self> {
+        let (queue_in, queue_out) = crossbeam_channel::unbounded::<Job>();
+        let threads = (0..NTHREADS)
+            .map(|_| {
+                let queue_out = queue_out.clone();
+                let notifier = notifier.clone();
+                std::thread::Builder::new()
+                    .name("aio-helper".to_string())
+                    .spawn(move || {
+                        for job in queue_out {
+                            notifier.wait_finished(&job.after);
+                            notifier.finish(job.id, (job.op)())
+                        }
+                    })
+            })
+            .collect::<std::io::Result<_>>()?;
+        Ok(Helper { queue_in, threads })
+    }
+
+    /// Carry out the operation of the AIO once the AIOs `after` are finished, on any of the
+    /// threads, so the operations are not ordered among themselves.
+    pub(crate) fn run(&self, id: u64, after: Vec<u64>, op: Op) {
+        // the thread only quits once the helper is dropped
+        self.queue_in.send(Job { id, after, op }).unwrap()
+    }
+
+    /// Wait for the operations so far to finish.
+    pub(crate) fn join(self) -> std::thread::Result<()> {
+        drop(self.queue_in);
+        self.threads.into_iter().try_for_each(|t| t.join())
+    }
+}
+
+/// The result of a syscall in the same form as `io_event.res`.
+fn res(ret: libc::c_int) -> i64 {
+    if ret < 0 {
+        -std::io::Error::last_os_error()
+            .raw_os_error()
+            .unwrap_or(libc::EIO) as i64
+    } else {
+        ret as i64
+    }
+}
+
+/// Release the range of a block device (`BLKDISCARD`), or punch a hole into that of a file.
+pub(crate) fn discard(fd: RawFd, offset: u64, len: u64) -> i64 {
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::fstat(fd, &mut stat) };
+    if ret < 0 {
+        return res(ret)
+    }
+    #[cfg(target_os = "linux")]
+    {
+        if stat.st_mode & libc::S_IFMT == libc::S_IFBLK {
+            let range = [offset, len];
+            return res(unsafe {
+                libc::ioctl(fd, BLKDISCARD as _, range.as_ptr())
+            })
+        }
+        res(unsafe {
+            libc::fallocate(
+                fd,
+                libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                offset as libc::off_t,
+                len as libc::off_t,
+            )
+        })
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (offset, len);
+        -libc::EOPNOTSUPP as i64
+    }
+}
+
+/// Allocate the range of the file (`fallocate(2)` with no flags, or `posix_fallocate(3)` on
+/// the other systems), which may extend it.
+pub(crate) fn allocate(fd: RawFd, offset: u64, len: u64) -> i64 {
+    #[cfg(target_os = "linux")]
+    return res(unsafe {
+        libc::fallocate(fd, 0, offset as libc::off_t, len as libc::off_t)
+    });
+    // the errno is returned rather than set
+    #[cfg(not(target_os = "linux"))]
+    return -unsafe {
+        libc::posix_fallocate(fd, offset as libc::off_t, len as libc::off_t)
+    } as i64;
+}
+
+/// Truncate (or extend) the file to the length (`ftruncate(2)`).
+pub(crate) fn truncate(fd: RawFd, len: u64) -> i64 {
+    res(unsafe { libc::ftruncate(fd, len as libc::off_t) })
+}
+
+/// Advise the kernel of the access to the range (`posix_fadvise(2)`).
+pub(crate) fn advise(fd: RawFd, offset: u64, len: u64, advice: Advice) -> i64 {
+    // the errno is returned rather than set
+    let ret = unsafe {
+        libc::posix_fadvise(
+            fd,
+            offset as libc::off_t,
+            len as libc::off_t,
+            advice.to_raw(),
+        )
+    };
+    -ret as i64
+}
+
+/// Read the range ahead into the page cache (`readahead(2)`, or `POSIX_FADV_WILLNEED` on the
+/// other systems).
+pub(crate) fn readahead(fd: RawFd, offset: u64, len: u64) -> i64 {
+    #[cfg(target_os = "linux")]
+    return res(unsafe {
+        libc::readahead(fd, offset as libc::off64_t, len as libc::size_t)
+    } as libc::c_int);
+    #[cfg(not(target_os = "linux"))]
+    return advise(fd, offset, len, Advice::WillNeed);
+}
+
+/// Copy the range between the files, by sharing the extents (`FICLONERANGE`) where the file
+/// system supports it and the range is aligned to its blocks, otherwise by `copy_file_range(2)`
+/// until the range is copied or the end of the source is reached. Returns the bytes copied.
+pub(crate) fn copy_range(
+    src_fd: RawFd,
+    src_offset: u64,
+    dst_fd: RawFd,
+    dst_offset: u64,
+    len: u64,
+) -> i64 {
+    if len == 0 {
+        return 0
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let range = FileCloneRange {
+            src_fd: src_fd as i64,
+            src_offset,
+            src_length: len,
+            dest_offset: dst_offset,
+        };
+        if unsafe { libc::ioctl(dst_fd, FICLONERANGE as _, &range) } == 0 {
+            return len as i64
+        }
+    }
+    // loff_t on Linux, off_t elsewhere
+    let mut src_off = src_offset as _;
+    let mut dst_off = dst_offset as _;
+    let mut copied = 0;
+    while copied < len {
+        let ret = unsafe {
+            libc::copy_file_range(
+                src_fd,
+                &mut src_off,
+                dst_fd,
+                &mut dst_off,
+                (len - copied).min(isize::MAX as u64) as libc::size_t,
+                0,
+            )
+        };
+        if ret < 0 {
+            // report the bytes copied so far, as a short write does
+            return if copied > 0 { copied as i64 } else { res(-1) }
+        }
+        if ret == 0 {
+            break
+        }
+        copied += ret as u64;
+    }
+    copied as i64
+}
+
+/// The path as passed to the syscalls, unless it contains a NUL byte.
+pub(crate) fn c_path(path: &Path) -> Option<CString> {
+    CString::new(path.as_os_str().as_bytes()).ok()
+}
+
+/// Open the file relative to the directory (`openat(2)`, always with `O_CLOEXEC`).
+pub(crate) fn open_at(
+    dirfd: RawFd,
+    path: &CStr,
+    flags: libc::c_int,
+    mode: libc::mode_t,
+) -> Result<OwnedFd, i64> {
+    let ret = unsafe {
+        libc::openat(
+            dirfd,
+            path.as_ptr(),
+            flags | libc::O_CLOEXEC,
+            mode as libc::c_uint,
+        )
+    };
+    if ret < 0 {
+        return Err(res(ret))
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(ret) })
+}
+
+/// The metadata of the open file (`fstat(2)`).
+pub(crate) fn metadata(fd: RawFd) -> Result<std::fs::Metadata, i64> {
+    // only borrowed, so never closed here
+    let file = ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(fd) });
+    file.metadata()
+        .map_err(|e| -e.raw_os_error().unwrap_or(libc::EIO) as i64)
+}
+
+/// Close the file (`close(2)`), which may block, e.g., to flush it on a network file system.
+pub(crate) fn close(fd: OwnedFd) -> Result<(), i64> {
+    match res(unsafe { libc::close(fd.into_raw_fd()) }) {
+        0 => Ok(()),
+        e => Err(e),
+    }
+}
+
+/// Rename the file relative to the directories (`renameat(2)`), replacing the new one
+/// atomically if it exists.
+pub(crate) fn rename(
+    old_dirfd: RawFd,
+    old_path: &CStr,
+    new_dirfd: RawFd,
+    new_path: &CStr,
+) -> Result<(), i64> {
+    match res(unsafe {
+        libc::renameat(
+            old_dirfd,
+            old_path.as_ptr(),
+            new_dirfd,
+            new_path.as_ptr(),
+        )
+    }) {
+        0 => Ok(()),
+        e => Err(e),
+    }
+}
+
+/// Remove the file relative to the directory (`unlinkat(2)`).
+pub(crate) fn unlink(dirfd: RawFd, path: &CStr) -> Result<(), i64> {
+    match res(unsafe { libc::unlinkat(dirfd, path.as_ptr(), 0) }) {
+        0 => Ok(()),
+        e => Err(e),
+    }
+}
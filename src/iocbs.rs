@@ -0,0 +1,55 @@
+//! A fixed set of iocbs reused by the AIOs, to take the allocator off the IO path.
+
+use crate::abi;
+use crossbeam_channel::{Receiver, Sender};
+use std::cell::UnsafeCell;
+
+pub(crate) struct IocbSlab {
+    iocbs: Box<[UnsafeCell<abi::IOCb>]>,
+    // the indices of the iocbs not taken
+    free_in: Sender<usize>,
+    free_out: Receiver<usize>,
+}
+
+// an iocb is only accessed by the AIO it is taken by
+unsafe impl Send for IocbSlab {}
+unsafe impl Sync for IocbSlab {}
+
+impl IocbSlab {
+    pub(crate) fn new(n: usize) -> Self {
+        let (free_in, free_out) = crossbeam_channel::bounded(n);
+        for i in 0..n {
+            free_in.send(i).unwrap()
+        }
+        IocbSlab {
+            iocbs: (0..n).map(|_| UnsafeCell::default()).collect(),
+            free_in,
+            free_out,
+        }
+    }
+
+    /// A blank iocb, allocated if all of those of the slab are taken (e.g., by the AIOs waiting
+    /// to be submitted).
+    pub(crate) fn get(&self) -> *mut abi::IOCb {
+        match self.free_out.try_recv() {
+            Ok(i) => {
+                let iocb = self.iocbs[i].get();
+                unsafe { *iocb = abi::IOCb::default() };
+                iocb
+            }
+            Err(_) => Box::into_raw(Box::default()),
+        }
+    }
+
+    /// Give back an iocb taken by `get`.
+    pub(crate) fn put(&self, iocb: *mut abi::IOCb) {
+        let base = self.iocbs.as_ptr() as usize;
+        let offset = (iocb as usize).wrapping_sub(base);
+        let i = offset / std::mem::size_of::<abi::IOCb>();
+        if i < self.iocbs.len() {
+            self.free_in.send(i).unwrap()
+        } else {
+            drop(unsafe { Box::from_raw(iocb) })
+        }
+    }
+}
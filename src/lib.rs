@@ -5,9 +5,7 @@
 //! Use aiofut to schedule writes to a file:
 //!
 //! ```rust
-//! use futures::{executor::LocalPool, future::FutureExt, task::LocalSpawnExt};
 //! use aiofut::AIOBuilder;
-//! use std::os::unix::io::AsRawFd;
 //! let mut aiomgr = AIOBuilder::default().build().unwrap();
 //! let file = std::fs::OpenOptions::new()
 //!     .read(true)
@@ -16,606 +14,4443 @@
 //!     .truncate(true)
 //!     .open("test")
 //!     .unwrap();
-//! let fd = file.as_raw_fd();
-//! // keep all returned futures in a vector
+//! // keep all returned futures (which borrow the file) in a vector
 //! let ws = vec![(0, "hello"), (5, "world"), (2, "xxxx")]
 //!     .into_iter()
-//!     .map(|(off, s)| aiomgr.write(fd, off, s.as_bytes().into(), None))
+//!     .map(|(off, s)| aiomgr.write(&file, off, s.as_bytes().to_vec(), None))
 //!     .collect::<Vec<_>>();
-//! // here we use futures::executor::LocalPool to poll all futures
-//! let mut pool = LocalPool::new();
-//! let spawner = pool.spawner();
-//! for w in ws.into_iter() {
-//!     let h = spawner.spawn_local_with_handle(w).unwrap().map(|r| {
-//!         println!("wrote {} bytes", r.0.unwrap());
-//!     });
-//!     spawner.spawn_local(h).unwrap();
+//! // here we use futures::executor::block_on to poll all futures
+//! for r in futures::executor::block_on(futures::future::join_all(ws)) {
+//!     println!("wrote {} bytes", r.0.unwrap());
 //! }
-//! pool.run();
 //! ```
 
-mod abi;
+// log the diagnostics with the `log` feature, or compile them out
+#[cfg(feature = "log")]
+macro_rules! diag {
+    ($lvl:ident, $($arg:tt)+) => { log::$lvl!($($arg)+) };
+}
+#[cfg(not(feature = "log"))]
+macro_rules! diag {
+    ($lvl:ident, $($arg:tt)+) => {{ let _ = format_args!($($arg)+); }};
+}
+
+#[cfg(feature = "raw-abi")] pub mod abi;
+#[cfg(not(feature = "raw-abi"))] mod abi;
+mod aligned;
+mod backend;
+mod barrier;
+mod batch;
+#[cfg(feature = "bytes")] mod bytes_buf;
+mod cursor;
+mod elevator;
+mod error;
+#[cfg(feature = "prometheus")] mod exporter;
+mod fair;
+#[cfg(feature = "fault-injection")] mod fault;
+mod file;
+mod flags;
+mod geometry;
+mod group;
+mod grow;
+mod helper;
+mod iocbs;
+mod merge;
+#[cfg(feature = "mock")] mod mock;
+mod options;
+mod permits;
+mod pool;
+#[cfg(feature = "posix-aio")] mod posix;
+mod prio;
+mod reader;
+mod retry;
+mod shard;
+#[cfg(feature = "futures-sink")] mod sink;
+mod slab;
+mod split;
+mod stats;
+#[cfg(any(feature = "futures-io", feature = "tokio-io"))] mod stream;
+mod tenant;
+mod threadpool;
+mod throttle;
+#[cfg(feature = "tokio")] mod tokio_driver;
+#[cfg(feature = "uring")] mod uring;
+mod waker;
+mod writer;
+pub use abi::{IOCb, IOCmd};
+pub use aligned::AlignedBuf;
+use backend::AioBackend;
+pub use backend::Backend;
+use barrier::Barriers;
+pub use barrier::FdOrder;
+pub use batch::Request;
+pub use cursor::AIOCursor;
+use elevator::Elevator;
+pub use error::Error;
+use fair::{Enqueued, FairQueue, TagWeights};
+#[cfg(feature = "fault-injection")] pub use fault::{FaultInjector, Faults};
+pub use file::AIOFile;
+pub use flags::RWFlags;
+pub use geometry::Geometry;
+pub use group::IoGroup;
+use grow::GrowingContext;
+use helper::Helper;
+pub use helper::{Advice, BlockingFuture};
+use iocbs::IocbSlab;
+use libc::time_t;
+use merge::{Merged, MERGED_ID};
+#[cfg(feature = "mock")] pub use mock::{MockDisk, MockFile, MockOp};
+pub use options::IoOptions;
 use parking_lot::Mutex;
-use std::collections::{hash_map, HashMap};
-use std::os::unix::io::RawFd;
+use permits::Permits;
+use pool::BufferPool;
+pub use prio::IoPriority;
+pub use reader::BufferedReader;
+pub use retry::RetryPolicy;
+use shard::ShardedContext;
+pub use shard::Sharding;
+#[cfg(feature = "futures-sink")] pub use sink::AIOSink;
+use slab::Slab;
+use split::{Splits, SPLIT_ID};
+pub use stable_deref_trait::StableDeref;
+use stats::Counters;
+pub use stats::{FdStats, Stats, TenantStats};
+use std::any::Any;
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::{BinaryHeap, HashMap};
+use std::marker::PhantomData;
+use std::ops::DerefMut;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::os::unix::thread::JoinHandleExt;
 use std::pin::Pin;
-use std::os::raw::c_long;
-use libc::time_t;
 use std::sync::{
-    atomic::{AtomicPtr, AtomicUsize, Ordering},
+    atomic::{
+        AtomicBool, AtomicI32, AtomicPtr, AtomicU64, AtomicUsize, Ordering,
+    },
     Arc,
 };
+use std::time::Instant;
+#[cfg(any(feature = "futures-io", feature = "tokio-io"))]
+pub use stream::AIOStream;
+use tenant::Tenants;
+pub use tenant::{Tenant, TenantQuota};
+pub use throttle::RateLimit;
+use throttle::Throttle;
+use waker::Wakers;
+pub use writer::BufferedWriter;
 
 const LIBAIO_EAGAIN: libc::c_int = -libc::EAGAIN;
+#[cfg(target_os = "linux")]
 const LIBAIO_ENOMEM: libc::c_int = -libc::ENOMEM;
+#[cfg(target_os = "linux")]
 const LIBAIO_ENOSYS: libc::c_int = -libc::ENOSYS;
 
-#[derive(Debug)]
-pub enum Error {
-    MaxEventsTooLarge,
-    LowKernelRes,
-    NotSupported,
-    OtherError,
+/// A non-blocking eventfd that is closed on drop.
+struct EventFd(RawFd);
+
+impl EventFd {
+    fn new() -> Result<Self, Error> {
+        let fd =
+            unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            Err(Error::last_os_error())
+        } else {
+            Ok(EventFd(fd))
+        }
+    }
+}
+
+impl Drop for EventFd {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.0);
+        }
+    }
+}
+
+/// A buffer owned by an AIO for as long as the kernel may access it.
+trait AIOBuffer: Send {
+    /// The `(aio_buf, aio_nbytes)` pair to be filled into the iocb.
+    fn iocb_buf(&self) -> (u64, u64);
+    fn to_vec(&self) -> Vec<u8>;
+    fn into_any(self: Box<Self>) -> Box<dyn Any + Send>;
+    /// Cut the buffer down to the `n` bytes transferred by the finished AIO (only done by the
+    /// buffers of `AIOManager::read_trimmed`).
+    fn trim(&mut self, _n: usize) {}
 }
 
-// NOTE: I assume it io_context_t is thread-safe, no?
-struct AIOContext(abi::IOContextPtr);
-unsafe impl Sync for AIOContext {}
-unsafe impl Send for AIOContext {}
+impl AIOBuffer for Box<[u8]> {
+    fn iocb_buf(&self) -> (u64, u64) {
+        // the kernel rejects non-null buffers for commands that carry no
+        // data (e.g. fsync), while an empty boxed slice has a dangling pointer
+        if self.is_empty() {
+            (0, 0)
+        } else {
+            (self.as_ptr() as u64, self.len() as u64)
+        }
+    }
+
+    fn to_vec(&self) -> Vec<u8> {
+        self[..].to_vec()
+    }
 
-impl std::ops::Deref for AIOContext {
-    type Target = abi::IOContextPtr;
-    fn deref(&self) -> &abi::IOContextPtr {
-        &self.0
+    fn into_any(self: Box<Self>) -> Box<dyn Any + Send> {
+        Box::new(*self)
     }
 }
 
-impl AIOContext {
-    fn new(maxevents: u32) -> Result<Self, Error> {
-        let mut ctx = std::ptr::null_mut();
-        unsafe {
-            match abi::io_setup(maxevents as libc::c_int, &mut ctx) {
-                0 => Ok(()),
-                LIBAIO_EAGAIN => Err(Error::MaxEventsTooLarge),
-                LIBAIO_ENOMEM => Err(Error::LowKernelRes),
-                LIBAIO_ENOSYS => Err(Error::NotSupported),
-                _ => Err(Error::OtherError),
-            }
-            .and_then(|_| Ok(AIOContext(ctx)))
+/// The source of a write, which only needs to be readable and stay in place.
+struct WriteBuf<B>(B);
+
+impl<B: StableDeref<Target = [u8]> + Send + 'static> AIOBuffer for WriteBuf<B> {
+    fn iocb_buf(&self) -> (u64, u64) {
+        if self.0.is_empty() {
+            (0, 0)
+        } else {
+            (self.0.as_ptr() as u64, self.0.len() as u64)
         }
     }
+
+    fn to_vec(&self) -> Vec<u8> {
+        self.0.to_vec()
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any + Send> {
+        Box::new(self.0)
+    }
 }
 
-impl Drop for AIOContext {
-    fn drop(&mut self) {
-        unsafe {
-            assert_eq!(abi::io_destroy(self.0), 0);
+/// The rest of a write still to be done after a short write.
+struct WriteTail<B> {
+    buf: B,
+    start: usize,
+}
+
+impl<B: StableDeref<Target = [u8]> + Send + 'static> AIOBuffer
+    for WriteTail<B>
+{
+    fn iocb_buf(&self) -> (u64, u64) {
+        let tail = &self.buf[self.start..];
+        if tail.is_empty() {
+            (0, 0)
+        } else {
+            (tail.as_ptr() as u64, tail.len() as u64)
+        }
+    }
+
+    fn to_vec(&self) -> Vec<u8> {
+        self.buf[self.start..].to_vec()
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any + Send> {
+        Box::new(self.buf)
+    }
+}
+
+/// The destination of a read provided by the user.
+struct ReadBuf<B> {
+    buf: B,
+    // taken once through DerefMut, as the kernel writes into it
+    ptr: *mut u8,
+    len: usize,
+}
+
+// ptr points into the buffer owned by buf
+unsafe impl<B: Send> Send for ReadBuf<B> {}
+
+impl<B: StableDeref<Target = [u8]> + DerefMut> ReadBuf<B> {
+    fn new(buf: B) -> Self {
+        Self::tail(buf, 0)
+    }
+
+    /// Only the bytes of the buffer from `start` on are read into.
+    fn tail(mut buf: B, start: usize) -> Self {
+        let tail = &mut buf[start..];
+        let (ptr, len) = (tail.as_mut_ptr(), tail.len());
+        ReadBuf { buf, ptr, len }
+    }
+}
+
+impl<B: StableDeref<Target = [u8]> + Send + 'static> AIOBuffer for ReadBuf<B> {
+    fn iocb_buf(&self) -> (u64, u64) {
+        if self.len == 0 {
+            (0, 0)
+        } else {
+            (self.ptr as u64, self.len as u64)
+        }
+    }
+
+    fn to_vec(&self) -> Vec<u8> {
+        self.buf.to_vec()
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any + Send> {
+        Box::new(self.buf)
+    }
+}
+
+/// The destination of a read that is returned with only the bytes actually read.
+struct TrimmedBuf(Vec<u8>);
+
+impl AIOBuffer for TrimmedBuf {
+    fn iocb_buf(&self) -> (u64, u64) {
+        if self.0.is_empty() {
+            (0, 0)
+        } else {
+            (self.0.as_ptr() as u64, self.0.len() as u64)
         }
     }
+
+    fn to_vec(&self) -> Vec<u8> {
+        self.0.clone()
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any + Send> {
+        Box::new(self.0)
+    }
+
+    fn trim(&mut self, n: usize) {
+        self.0.truncate(n)
+    }
+}
+
+/// Buffers of a vectored operation, together with the iovec array that points into them.
+struct IOVecBuffer {
+    bufs: Vec<Box<[u8]>>,
+    iovs: Box<[abi::IOVector]>,
+}
+
+// iovs only point into the heap memory owned by bufs
+unsafe impl Send for IOVecBuffer {}
+
+impl IOVecBuffer {
+    fn new(mut bufs: Vec<Box<[u8]>>) -> Self {
+        let iovs = bufs
+            .iter_mut()
+            .map(|b| abi::IOVector {
+                iov_base: b.as_mut_ptr(),
+                iov_len: b.len(),
+            })
+            .collect();
+        IOVecBuffer { bufs, iovs }
+    }
+}
+
+impl AIOBuffer for IOVecBuffer {
+    fn iocb_buf(&self) -> (u64, u64) {
+        (self.iovs.as_ptr() as u64, self.iovs.len() as u64)
+    }
+
+    fn to_vec(&self) -> Vec<u8> {
+        self.bufs.concat()
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any + Send> {
+        Box::new(self.bufs)
+    }
+}
+
+/// The events a poll waits for, carried in place of a buffer.
+struct PollEvents(i16);
+
+impl AIOBuffer for PollEvents {
+    fn iocb_buf(&self) -> (u64, u64) {
+        (self.0 as u16 as u64, 0)
+    }
+
+    fn to_vec(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any + Send> {
+        Box::new(Box::<[u8]>::default())
+    }
+}
+
+/// The memory of a raw iocb, owned by the caller (see `AIOManager::submit_raw`).
+struct RawBuf(u64, u64);
+
+impl AIOBuffer for RawBuf {
+    fn iocb_buf(&self) -> (u64, u64) {
+        (self.0, self.1)
+    }
+
+    fn to_vec(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any + Send> {
+        Box::new(Box::<[u8]>::default())
+    }
 }
 
 /// Represent the necessary data for an AIO operation. Memory-safe when moved.
 pub struct AIO {
     // hold the buffer used by iocb
-    data: Option<Box<[u8]>>,
+    data: Option<Box<dyn AIOBuffer>>,
     iocb: AtomicPtr<abi::IOCb>,
+    // where the iocb goes back to
+    iocbs: Arc<IocbSlab>,
     id: u64,
+    cancelled: bool,
+    // accepted by the context (or run on the helper threads), and not yet to be retried
+    submitted: bool,
+    // keep the file open until the operation is finished
+    file: Option<Arc<std::fs::File>>,
+    deadline: Option<Instant>,
+    // only kept for the per-fd statistics and the slow AIOs
+    scheduled: Option<Instant>,
+    retry: RetryPolicy,
+    // the submissions so far
+    attempts: u32,
+    tag: u32,
+    // the epoch among the barriers on the fd, if enabled by `AIOBuilder::barriers`
+    barrier: Option<(Arc<Barriers>, u64)>,
+    // the same for the order of the AIOs on the fd, if enabled by `AIOBuilder::fd_order`
+    order: Option<(Arc<Barriers>, u64)>,
+    // from the scheduling to the completion (when the AIO is dropped)
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
 }
 
 impl AIO {
     fn new(
+        iocbs: &Arc<IocbSlab>,
         id: u64,
         fd: RawFd,
         off: u64,
-        data: Box<[u8]>,
-        priority: u16,
-        flags: u32,
+        data: Box<dyn AIOBuffer>,
         opcode: abi::IOCmd,
     ) -> Self {
-        let mut iocb = Box::new(abi::IOCb::default());
+        let ptr = iocbs.get();
+        let iocb = unsafe { &mut *ptr };
+        let (buf, nbytes) = data.iocb_buf();
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "aio",
+            id,
+            fd,
+            offset = off,
+            len = nbytes,
+            opcode = ?opcode,
+            res = tracing::field::Empty,
+        );
         iocb.aio_fildes = fd as u32;
         iocb.aio_lio_opcode = opcode as u16;
-        iocb.aio_reqprio = priority;
-        iocb.aio_buf = data.as_ptr() as u64;
-        iocb.aio_nbytes = data.len() as u64;
+        iocb.aio_buf = buf;
+        iocb.aio_nbytes = nbytes;
         iocb.aio_offset = off;
-        iocb.aio_flags = flags;
         iocb.aio_data = id;
-        let iocb = AtomicPtr::new(Box::into_raw(iocb));
+        let iocb = AtomicPtr::new(ptr);
         let data = Some(data);
-        AIO { iocb, id, data }
+        AIO {
+            iocb,
+            iocbs: iocbs.clone(),
+            id,
+            data,
+            cancelled: false,
+            submitted: false,
+            file: None,
+            deadline: None,
+            scheduled: None,
+            retry: RetryPolicy::never(),
+            attempts: 1,
+            tag: 0,
+            barrier: None,
+            order: None,
+            #[cfg(feature = "tracing")]
+            span,
+        }
+    }
+}
+
+impl AIO {
+    /// Whether some AIOs behind a barrier are waiting for this one.
+    fn is_blocking(&self) -> bool {
+        let fd = unsafe { (*self.iocb.load(Ordering::Acquire)).aio_fildes };
+        self.barrier
+            .iter()
+            .chain(self.order.iter())
+            .any(|(barriers, epoch)| barriers.is_blocking(fd as RawFd, *epoch))
     }
 }
 
 impl Drop for AIO {
     fn drop(&mut self) {
-        unsafe {
-            drop(Box::from_raw(self.iocb.load(Ordering::Acquire)));
+        let iocb = self.iocb.load(Ordering::Acquire);
+        let fd = unsafe { (*iocb).aio_fildes as RawFd };
+        self.iocbs.put(iocb);
+        for (barriers, epoch) in
+            self.barrier.take().into_iter().chain(self.order.take())
+        {
+            barriers.leave(fd, epoch)
         }
     }
 }
 
 /// The result of an AIO operation: the number of bytes written on success,
 /// or the errno on failure.
-pub type AIOResult = (Result<usize, i32>, Box<[u8]>);
+pub type AIOResult<B = Box<[u8]>> = (Result<usize, i32>, B);
+
+/// Convert the result of an AIO operation into an `io::Result`, which drops the buffer upon
+/// failure.
+pub fn into_io_result<B>(r: AIOResult<B>) -> std::io::Result<(usize, B)> {
+    match r {
+        (Ok(n), data) => Ok((n, data)),
+        (Err(errno), _) => Err(std::io::Error::from_raw_os_error(errno)),
+    }
+}
 
 /// Represents a scheduled (future) asynchronous I/O operation, which gets executed (resolved)
 /// automatically.
-pub struct AIOFuture {
+///
+/// The future borrows the file descriptor for `'a`, so the file cannot be closed before the
+/// future is resolved or dropped (`'static` for the operations on raw file descriptors). It is
+/// `Send` whatever the buffer type, so the `'static` ones can be moved to another
+/// task, e.g., by `tokio::spawn`. Once resolved, it stays pending if polled again (and it is a
+/// `FusedFuture` with the `futures-core` feature, e.g., for `futures::select!`).
+pub struct AIOFuture<'a, B = Box<[u8]>> {
     notifier: Arc<AIONotifier>,
     aio_id: u64,
+    // not yet scheduled for the lack of a permit (see `AIOBuilder::max_pending`)
+    queued: Option<Box<Queued>>,
+    // set once resolved, as the AIO is then gone
+    done: bool,
+    _buf: PhantomData<fn() -> B>,
+    _fd: PhantomData<BorrowedFd<'a>>,
 }
 
-impl AIOFuture {
+impl<B> AIOFuture<'_, B> {
+    fn new(notifier: Arc<AIONotifier>, aio_id: u64) -> Self {
+        AIOFuture {
+            notifier,
+            aio_id,
+            queued: None,
+            done: false,
+            _buf: PhantomData,
+            _fd: PhantomData,
+        }
+    }
+
+    /// The id of the AIO, e.g., for `AIOManager::status` or `AIOManager::cancel`.
+    pub fn id(&self) -> u64 {
+        self.aio_id
+    }
+
+    /// Same as `id`.
     pub fn get_id(&self) -> u64 {
         self.aio_id
     }
 }
 
-impl std::future::Future for AIOFuture {
-    type Output = AIOResult;
+impl<'a, B> AIOFuture<'a, B> {
+    /// Resolve to an `io::Result` instead (see `into_io_result`).
+    pub fn into_io(self) -> IoFuture<'a, B> {
+        IoFuture(self)
+    }
+}
+
+impl<B: 'static> std::future::Future for AIOFuture<'_, B> {
+    type Output = AIOResult<B>;
     fn poll(
         self: Pin<&mut Self>,
         cx: &mut std::task::Context,
     ) -> std::task::Poll<Self::Output> {
-        if let Some(ret) = self.notifier.poll(self.aio_id, cx.waker()) {
-            std::task::Poll::Ready(ret)
+        let this = self.get_mut();
+        if this.done {
+            return std::task::Poll::Pending
+        }
+        if let Some(q) = this.queued.take() {
+            let permits = this.notifier.permits.as_ref().unwrap();
+            if !permits.acquire(Some(cx.waker())) {
+                // the AIOs behind a barrier may hold all the permits
+                if q.aio.is_blocking() {
+                    permits.force(1)
+                } else {
+                    this.queued = Some(q);
+                    return std::task::Poll::Pending
+                }
+            }
+            q.queues.enqueue(q.aio, q.lane, &this.notifier);
+        }
+        if let Some((res, data)) = this.notifier.poll(this.aio_id, cx.waker()) {
+            this.done = true;
+            std::task::Poll::Ready((res, *data.into_any().downcast().unwrap()))
         } else {
             std::task::Poll::Pending
         }
     }
 }
 
-impl Drop for AIOFuture {
+#[cfg(feature = "futures-core")]
+impl<B: 'static> futures_core::future::FusedFuture for AIOFuture<'_, B> {
+    fn is_terminated(&self) -> bool {
+        self.done
+    }
+}
+
+impl<B> Drop for AIOFuture<'_, B> {
     fn drop(&mut self) {
-        self.notifier.dropped(self.aio_id)
+        // nothing to do for an AIO never scheduled, or already resolved
+        if self.queued.is_none() && !self.done {
+            self.notifier.dropped(self.aio_id)
+        }
+    }
+}
+
+/// An AIOFuture resolving to an `io::Result` (see `AIOFuture::into_io`).
+pub struct IoFuture<'a, B = Box<[u8]>>(AIOFuture<'a, B>);
+
+impl<B: 'static> std::future::Future for IoFuture<'_, B> {
+    type Output = std::io::Result<(usize, B)>;
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context,
+    ) -> std::task::Poll<Self::Output> {
+        Pin::new(&mut self.get_mut().0).poll(cx).map(into_io_result)
+    }
+}
+
+#[cfg(feature = "futures-core")]
+impl<B: 'static> futures_core::future::FusedFuture for IoFuture<'_, B> {
+    fn is_terminated(&self) -> bool {
+        self.0.done
     }
 }
 
+impl<B> IoFuture<'_, B> {
+    /// The id of the underlying AIO (see `AIOFuture::id`).
+    pub fn id(&self) -> u64 {
+        self.0.aio_id
+    }
+}
+
+/// The progress of an operation (see `AIOManager::status`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpStatus {
+    /// Scheduled but not yet submitted, e.g., for the lack of room in the context, behind a
+    /// barrier, or until it is retried.
+    Queued,
+    /// Submitted (or running on the helper threads for a blocking operation), and not yet
+    /// finished.
+    Submitted,
+    /// Finished, but its future is yet to resolve.
+    Done,
+    /// Never scheduled, still waiting for a permit (see `AIOBuilder::max_pending`), or finished
+    /// with its future resolved or dropped.
+    Unknown,
+}
+
+/// An AIO waiting for a permit to be scheduled.
+struct Queued {
+    aio: AIO,
+    lane: usize,
+    queues: Arc<Queues>,
+}
+
 enum AIOState {
-    FutureInit(AIO, bool),
-    FuturePending(AIO, std::task::Waker, bool),
-    FutureDone(AIOResult),
+    // whether the future is dropped (its waker is kept by the slab)
+    Pending(AIO, bool),
+    Done(AIOResult<Box<dyn AIOBuffer>>),
 }
 
 /// The state machine for finished AIO operations and wakes up the futures.
 pub struct AIONotifier {
-    waiting: Mutex<HashMap<u64, AIOState>>,
+    // dropped first, so the kernel is done with the buffers of the in-flight AIOs before they
+    // are freed
+    io_ctx: Box<dyn AioBackend>,
+    waiting: Slab<AIOState>,
+    // those of the AIOs (see `IocbSlab`)
+    iocbs: Arc<IocbSlab>,
+    // held (shared) while scheduling an AIO, so either it sees closed, or it is drained by
+    // close()
+    intake: parking_lot::RwLock<()>,
+    // notified whenever an AIO is finished (see `wait_finished`)
+    finished: parking_lot::Condvar,
+    finishing: Mutex<()>,
     npending: AtomicUsize,
-    io_ctx: AIOContext,
+    // no more AIO is taken by the driver (see `AIOBatchSchedulerOut::close`)
+    closed: AtomicBool,
+    // no AIO is submitted until resumed (see `AIOManager::pause`), which is signalled to the
+    // driver waiting for it
+    paused: AtomicBool,
+    resume_in: crossbeam_channel::Sender<()>,
+    resume_out: crossbeam_channel::Receiver<()>,
+    // the errno of the fatal error the context failed with, or 0
+    fatal: AtomicI32,
+    // woken up once there is no pending AIO
+    drained: Mutex<Vec<std::task::Waker>>,
+    permits: Option<Permits>,
+    counters: Counters,
+    fd_stats: Option<Mutex<HashMap<RawFd, FdStats>>>,
+    slow_op_threshold: Option<std::time::Duration>,
+    // the AIOs to be submitted again once their backoffs are over (or once within the rate
+    // limits, see `throttle`)
+    retries: Mutex<BinaryHeap<Deadlined>>,
+    throttle: Throttle,
+    tag_weights: TagWeights,
+    tenants: Tenants,
+    barriers: Option<Arc<Barriers>>,
+    order: Option<(Arc<Barriers>, FdOrder)>,
+    // the in-flight writes merged by the scheduler, if enabled by `AIOBuilder::merge_writes`
+    merged: Option<Mutex<HashMap<u64, Merged>>>,
+    // the chunks of the in-flight AIOs split by the scheduler, if enabled by
+    // `AIOBuilder::max_io_size`
+    splits: Option<Mutex<Splits>>,
+    eventfd: Option<EventFd>,
     #[cfg(feature = "emulated-failure")]
     emul_fail: Option<EmulatedFailureShared>,
 }
 
 impl AIONotifier {
     fn register_notify(&self, id: u64, state: AIOState) {
-        let mut waiting = self.waiting.lock();
-        assert!(waiting.insert(id, state).is_none());
+        let ready = matches!(state, AIOState::Done(_));
+        assert!(self.waiting.insert(id, state, ready));
     }
 
     fn dropped(&self, id: u64) {
-        let mut waiting = self.waiting.lock();
-        match waiting.entry(id) {
-            hash_map::Entry::Occupied(mut e) => match e.get_mut() {
-                AIOState::FutureInit(_, dropped) => *dropped = true,
-                AIOState::FuturePending(_, _, dropped) => *dropped = true,
-                AIOState::FutureDone(_) => {
-                    e.remove();
+        if let Some(mut e) = self.waiting.get(id) {
+            let aio = match &mut *e {
+                AIOState::Pending(aio, dropped) => {
+                    *dropped = true;
+                    aio
                 }
-            },
-            _ => (),
-        }
-    }
-
-    fn poll(&self, id: u64, waker: &std::task::Waker) -> Option<AIOResult> {
-        let mut waiting = self.waiting.lock();
-        match waiting.entry(id) {
-            hash_map::Entry::Occupied(e) => {
-                let v = e.remove();
-                match v {
-                    AIOState::FutureInit(aio, _) => {
-                        waiting.insert(
-                            id,
-                            AIOState::FuturePending(aio, waker.clone(), false),
-                        );
-                        None
-                    }
-                    AIOState::FuturePending(aio, waker, dropped) => {
-                        waiting.insert(
-                            id,
-                            AIOState::FuturePending(aio, waker, dropped),
-                        );
-                        None
-                    }
-                    AIOState::FutureDone(res) => Some(res),
+                AIOState::Done(_) => {
+                    e.remove();
+                    return
                 }
-            }
-            _ => unreachable!(),
+            };
+            // try to stop the in-flight operation (its completion still shows up), or do
+            // nothing if it is not submitted yet, as it will then be discarded by the scheduler
+            self.io_ctx.cancel(aio.iocb.load(Ordering::Acquire));
         }
     }
 
-    fn finish(&self, id: u64, res: i64) {
-        let mut w = self.waiting.lock();
-        self.npending.fetch_sub(1, Ordering::Relaxed);
-        match w.entry(id) {
-            hash_map::Entry::Occupied(e) => match e.remove() {
-                AIOState::FutureInit(mut aio, dropped) => {
-                    if !dropped {
-                        let data = aio.data.take().unwrap();
-                        w.insert(
-                            id,
-                            AIOState::FutureDone(if res >= 0 {
-                                (Ok(res as usize), data)
-                            } else {
-                                (Err(-res as i32), data)
-                            }),
-                        );
-                    }
-                }
-                AIOState::FuturePending(mut aio, waker, dropped) => {
-                    if !dropped {
-                        let data = aio.data.take().unwrap();
-                        w.insert(
-                            id,
-                            AIOState::FutureDone(if res >= 0 {
-                                (Ok(res as usize), data)
-                            } else {
-                                (Err(-res as i32), data)
-                            }),
-                        );
-                        waker.wake();
-                    }
+    /// Remove the not-yet-submitted iocbs whose futures are already dropped, or that are
+    /// cancelled (which resolves their futures).
+    fn discard(&self, iocbs: &mut Vec<*mut abi::IOCb>) {
+        let mut wakers = Wakers::default();
+        iocbs.retain(|iocb| {
+            let id = unsafe { (**iocb).aio_data };
+            let discarded = match self.waiting.get(id) {
+                Some(e) if matches!(*e, AIOState::Pending(_, true)) => {
+                    e.remove();
+                    true
                 }
-                AIOState::FutureDone(ret) => {
-                    w.insert(id, AIOState::FutureDone(ret));
+                Some(e) if matches!(&*e, AIOState::Pending(aio, _) if aio.cancelled) => {
+                    Self::resolve(e, -libc::ECANCELED as i64, &mut wakers);
+                    self.counters.discarded();
+                    true
                 }
-            },
-            _ => unreachable!(),
+                _ => false,
+            };
+            if discarded {
+                self.retire();
+            }
+            !discarded
+        });
+    }
+
+    /// Finish the no-ops among the iocbs right away, as if they were submitted and reaped, for
+    /// an engine not carrying them out.
+    fn complete_noops(&self, iocbs: &mut Vec<*mut abi::IOCb>) {
+        let mut noops = Vec::new();
+        iocbs.retain(|iocb| {
+            let iocb = unsafe { &**iocb };
+            let noop = iocb.aio_lio_opcode == abi::IOCmd::Noop as u16;
+            if noop {
+                noops.push((iocb.aio_fildes as RawFd, iocb.aio_data))
+            }
+            !noop
+        });
+        if noops.is_empty() {
+            return
+        }
+        self.counters.submitted(noops.len());
+        self.release_order(&noops);
+        let mut wakers = Wakers::default();
+        for (_, id) in noops {
+            self.finish_with(id, 0, &mut wakers)
         }
     }
-}
 
-pub struct AIOBuilder {
-    max_events: u32,
-    max_nwait: u16,
-    max_nbatched: usize,
-    timeout: Option<u32>,
-    #[cfg(feature = "emulated-failure")]
-    emul_fail: Option<EmulatedFailureShared>,
-}
+    /// Fail the AIOs of an iocb rejected by `io_submit` (e.g., with `EBADF`) with the errno, as
+    /// if it was submitted and reaped.
+    fn reject(&self, iocb: *mut abi::IOCb, aios: &[(RawFd, u64)], errno: i32) {
+        let id = unsafe { (*iocb).aio_data };
+        diag!(debug, "io_submit: aio {} rejected: errno {}", id, errno);
+        self.counters.submitted(aios.len());
+        self.release_order(aios);
+        self.complete(id, -errno as i64, &mut Wakers::default());
+    }
 
-impl Default for AIOBuilder {
-    fn default() -> Self {
-        AIOBuilder {
-            max_events: 128,
-            max_nwait: 128,
-            max_nbatched: 128,
-            timeout: None,
-            #[cfg(feature = "emulated-failure")]
-            emul_fail: None,
+    fn poll(
+        &self,
+        id: u64,
+        waker: &std::task::Waker,
+    ) -> Option<AIOResult<Box<dyn AIOBuffer>>> {
+        // without locking until finished, so the reapers are not held up
+        if !self.waiting.register(id, waker) {
+            return None
+        }
+        match self.waiting.get(id).unwrap().remove() {
+            AIOState::Done(res) => Some(res),
+            AIOState::Pending(..) => unreachable!(),
         }
     }
-}
 
-impl AIOBuilder {
-    /// Maximum concurrent async IO operations.
-    pub fn max_events(&mut self, v: u32) -> &mut Self {
-        self.max_events = v;
-        self
+    /// Reap the finished AIOs (waiting for at least `min_nr` of them) and resolve their futures.
+    /// Returns the number of reaped AIOs, or the negated errno.
+    fn reap(
+        &self,
+        min_nr: usize,
+        max_nwait: usize,
+        timeout: Option<&mut libc::timespec>,
+    ) -> libc::c_int {
+        self.reap_shard(None, min_nr, max_nwait, timeout)
     }
 
-    /// Maximum complete IOs per poll.
-    pub fn max_nwait(&mut self, v: u16) -> &mut Self {
-        self.max_nwait = v;
-        self
+    /// Same as `reap`, but only from the given context when sharded.
+    fn reap_shard(
+        &self,
+        shard: Option<usize>,
+        min_nr: usize,
+        max_nwait: usize,
+        timeout: Option<&mut libc::timespec>,
+    ) -> libc::c_int {
+        let mut events = vec![abi::IOEvent::default(); max_nwait];
+        let ret = match shard {
+            Some(shard) => {
+                self.io_ctx.reap_shard(shard, min_nr, &mut events, timeout)
+            }
+            None => self.io_ctx.reap(min_nr, &mut events, timeout),
+        };
+        if ret == -libc::EINTR {
+            return 0
+        }
+        if ret < 0 {
+            diag!(error, "io_getevents failed: errno {}", -ret);
+        }
+        if ret <= 0 {
+            return ret
+        }
+        let mut nreaped = 0;
+        // woken up once all of them are finished
+        let mut wakers = Wakers::default();
+        for ev in events[..ret as usize].iter() {
+            #[cfg(not(feature = "emulated-failure"))]
+            {
+                nreaped += self.complete(ev.data, ev.res, &mut wakers);
+            }
+            #[cfg(feature = "emulated-failure")]
+            {
+                let mut res = ev.res;
+                if let Some(emul_fail) = self.emul_fail.as_ref() {
+                    let mut ef = emul_fail.lock();
+                    if let Some(e) = ef.tick() {
+                        res = e
+                    }
+                }
+                nreaped += self.complete(ev.data, res, &mut wakers);
+            }
+        }
+        nreaped as libc::c_int
     }
 
-    /// Maximum number of IOs per submission.
-    pub fn max_nbatched(&mut self, v: usize) -> &mut Self {
-        self.max_nbatched = v;
-        self
+    /// Finish the AIO(s) of a completion, splitting the result of a merged write, or once all
+    /// the chunks of a split AIO are finished. Returns the number of finished AIOs.
+    fn complete(&self, id: u64, res: i64, wakers: &mut Wakers) -> usize {
+        if id & MERGED_ID != 0 {
+            let m = self.merged.as_ref().and_then(|m| m.lock().remove(&id));
+            let parts = m.map(|m| m.split(res)).unwrap_or_default();
+            return parts
+                .iter()
+                .map(|(id, res)| self.complete(*id, *res, wakers))
+                .sum()
+        }
+        match self.unsplit(id, res) {
+            Some((id, res)) => {
+                self.finish_with(id, res, wakers);
+                1
+            }
+            None => 0,
+        }
     }
 
-    /// Timeout for a polling iteration (default is None).
-    pub fn timeout(&mut self, sec: u32) -> &mut Self {
-        self.timeout = Some(sec);
-        self
+    /// The AIO with its result once the chunk is the last one of it to finish, or the AIO
+    /// itself if not a chunk.
+    fn unsplit(&self, id: u64, res: i64) -> Option<(u64, i64)> {
+        match self.splits.as_ref() {
+            Some(splits) if id & SPLIT_ID != 0 => splits.lock().finish(id, res),
+            _ => Some((id, res)),
+        }
     }
 
-    #[cfg(feature = "emulated-failure")]
-    pub fn emulated_failure(&mut self, ef: EmulatedFailureShared) -> &mut Self {
-        self.emul_fail = Some(ef);
-        self
+    fn finish(&self, id: u64, res: i64) {
+        self.finish_with(id, res, &mut Wakers::default())
     }
 
-    /// Build an AIOManager object based on the configuration (and auto-start the background IO
-    /// scheduling thread).
-    pub fn build(&mut self) -> Result<AIOManager, Error> {
-        let (scheduler_in, scheduler_out) =
-            new_batch_scheduler(self.max_nbatched);
-        let (exit_s, exit_r) = crossbeam_channel::bounded(0);
+    /// Same as `finish`, leaving the waker of the AIO to those finished together.
+    fn finish_with(&self, id: u64, res: i64, wakers: &mut Wakers) {
+        let mut e = match self.waiting.get(id) {
+            Some(e) => e,
+            None => return,
+        };
+        if let AIOState::Pending(aio, false) = &mut *e {
+            if RetryPolicy::is_transient(res) && !aio.cancelled {
+                if let Some(backoff) = aio.retry.backoff(aio.attempts) {
+                    aio.attempts += 1;
+                    aio.submitted = false;
+                    diag!(
+                        debug,
+                        "retrying aio {} in {:?} after errno {}",
+                        id,
+                        backoff,
+                        -res
+                    );
+                    self.counters.retried();
+                    self.retries.lock().push(Deadlined {
+                        deadline: Instant::now() + backoff,
+                        id,
+                        iocb: AtomicPtr::new(aio.iocb.load(Ordering::Acquire)),
+                    });
+                    return
+                }
+            }
+        }
+        if let AIOState::Pending(aio, _) = &*e {
+            self.retire();
+            let iocb = unsafe { &*aio.iocb.load(Ordering::Acquire) };
+            self.counters.reaped(iocb.aio_lio_opcode, res);
+            let latency = aio.scheduled.map(|t| t.elapsed());
+            if let (Some(fd_stats), Some(latency)) =
+                (self.fd_stats.as_ref(), latency)
+            {
+                let mut fd_stats = fd_stats.lock();
+                let s = fd_stats.entry(iocb.aio_fildes as RawFd).or_default();
+                s.reaped(res, latency);
+            }
+            if let Some(latency) = latency {
+                self.tenants.finished(aio.tag, res, latency)
+            }
+            if let (Some(threshold), Some(latency)) =
+                (self.slow_op_threshold, latency)
+            {
+                if latency > threshold {
+                    self.counters.slow_op();
+                    diag!(
+                        warn,
+                        "slow aio {} on fd {}: {:?}",
+                        id,
+                        iocb.aio_fildes,
+                        latency
+                    );
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(parent: &aio.span, ?latency, "slow aio");
+                }
+            }
+            if aio.deadline.is_some_and(|d| Instant::now() > d) {
+                self.counters.deadline_missed();
+            }
+            Self::resolve(e, res, wakers);
+            let _finishing = self.finishing.lock();
+            self.finished.notify_all();
+        }
+        // otherwise already given up upon a fatal error
+    }
 
-        let notifier = Arc::new(AIONotifier {
-            io_ctx: AIOContext::new(self.max_events)?,
-            waiting: Mutex::new(HashMap::new()),
-            npending: AtomicUsize::new(0),
-            #[cfg(feature = "emulated-failure")]
-            emul_fail: self.emul_fail.as_ref().map(|ef| ef.clone()),
-        });
-        let mut aiomgr = AIOManager {
-            notifier,
-            listener: None,
-            scheduler_in,
-            exit_s,
+    /// The pending AIOs on the fd which overlap the range (up to the end of the file if `len`
+    /// is 0), or that are not on a range (e.g., fsync).
+    fn pending_on(&self, fd: RawFd, offset: u64, len: u64) -> Vec<u64> {
+        let end = match len {
+            0 => u64::MAX,
+            len => offset.saturating_add(len),
         };
-        aiomgr.start(scheduler_out, exit_r, self.max_nwait, self.timeout)?;
-        Ok(aiomgr)
+        let mut ids = Vec::new();
+        self.waiting.for_each(|id, state| {
+            if let AIOState::Pending(aio, _) = state {
+                let iocb = unsafe { &*aio.iocb.load(Ordering::Acquire) };
+                let op = iocb.aio_lio_opcode;
+                let ranged = op == abi::IOCmd::PRead as u16 ||
+                    op == abi::IOCmd::PWrite as u16;
+                let overlaps = !ranged ||
+                    (iocb.aio_offset < end &&
+                        offset < iocb.aio_offset + iocb.aio_nbytes);
+                if iocb.aio_fildes as RawFd == fd && overlaps {
+                    ids.push(id)
+                }
+            }
+        });
+        ids
     }
-}
 
-pub trait EmulatedFailure: Send {
-    fn tick(&mut self) -> Option<i64>;
+    /// Block until none of the AIOs is pending.
+    fn wait_finished(&self, ids: &[u64]) {
+        let mut finishing = self.finishing.lock();
+        while ids.iter().any(|id| {
+            self.waiting
+                .get(*id)
+                .is_some_and(|e| matches!(*e, AIOState::Pending(..)))
+        }) {
+            // also checked regularly, as those given up on (e.g., by close()) are not notified
+            self.finished
+                .wait_for(&mut finishing, std::time::Duration::from_millis(10));
+        }
+    }
+
+    /// Move the iocbs of the AIOs still behind a barrier into `held`.
+    fn hold_back(&self, iocbs: &mut Vec<*mut abi::IOCb>, held: &mut Vec<Held>) {
+        if self.barriers.is_none() && self.order.is_none() {
+            return
+        }
+        // the AIOs per fd to be submitted ahead with the same io_submit
+        let mut ahead = HashMap::<RawFd, Vec<u64>>::new();
+        iocbs.retain(|iocb| {
+            let (fd, id) =
+                unsafe { ((**iocb).aio_fildes as RawFd, (**iocb).aio_data) };
+            let (barrier, order) = match self.waiting.get(id).as_deref() {
+                Some(AIOState::Pending(aio, _)) => (
+                    aio.barrier.as_ref().map(|(_, epoch)| *epoch),
+                    aio.order.as_ref().map(|(_, epoch)| *epoch),
+                ),
+                _ => (None, None),
+            };
+            let h = Held {
+                iocb: AtomicPtr::new(*iocb),
+                fd,
+                barrier,
+                order,
+            };
+            let ahead = ahead.entry(fd).or_default();
+            if self.is_ready_after(&h, ahead) {
+                ahead.extend(order);
+                true
+            } else {
+                held.push(h);
+                false
+            }
+        });
+    }
+
+    /// Put off the reads and writes beyond their rate limits until they are within them, as
+    /// the retries.
+    fn throttle(&self, iocbs: &mut Vec<*mut abi::IOCb>) {
+        if !self.throttle.is_active() {
+            return
+        }
+        let now = Instant::now();
+        iocbs.retain(|iocb| {
+            let iocb = *iocb;
+            match self.throttle.admit(unsafe { &*iocb }, now) {
+                Err(deadline) => {
+                    self.retries.lock().push(Deadlined {
+                        deadline,
+                        id: unsafe { (*iocb).aio_data },
+                        iocb: AtomicPtr::new(iocb),
+                    });
+                    false
+                }
+                Ok(()) => true,
+            }
+        })
+    }
+
+    /// Whether the held AIO is no longer behind a barrier.
+    fn is_ready(&self, held: &Held) -> bool {
+        self.is_ready_after(held, &[])
+    }
+
+    /// Same as `is_ready`, with the given AIOs on the fd (by their order) submitted just before.
+    fn is_ready_after(&self, held: &Held, ahead: &[u64]) -> bool {
+        let barrier = match (held.barrier, self.barriers.as_ref()) {
+            (Some(epoch), Some(barriers)) => {
+                barriers.is_ready(held.fd, epoch, &[])
+            }
+            _ => true,
+        };
+        let order = match (held.order, self.order.as_ref()) {
+            (Some(epoch), Some((order, FdOrder::Submission))) => {
+                order.is_ready(held.fd, epoch, ahead)
+            }
+            (Some(epoch), Some((order, FdOrder::Completion))) => {
+                order.is_ready(held.fd, epoch, &[])
+            }
+            _ => true,
+        };
+        barrier && order
+    }
+
+    /// Let the AIOs on the same fds after the submitted ones go, with `FdOrder::Submission`.
+    /// Mark the AIOs as submitted (see `AIOManager::status`).
+    fn mark_submitted(&self, aios: &[(RawFd, u64)]) {
+        for (_, id) in aios {
+            // unless already finished
+            if let Some(AIOState::Pending(aio, _)) =
+                self.waiting.get(*id).as_deref_mut()
+            {
+                aio.submitted = true
+            }
+        }
+    }
+
+    fn release_order(&self, aios: &[(RawFd, u64)]) {
+        if !matches!(self.order, Some((_, FdOrder::Submission))) {
+            return
+        }
+        for (fd, id) in aios {
+            // unless already finished
+            if let Some(AIOState::Pending(aio, _)) =
+                self.waiting.get(*id).as_deref_mut()
+            {
+                if let Some((order, epoch)) = aio.order.take() {
+                    order.leave(*fd, epoch)
+                }
+            }
+        }
+    }
+
+    /// Split the oversized reads and writes among the iocbs (see `AIOBuilder::max_io_size`).
+    fn split(&self, iocbs: &mut Vec<*mut abi::IOCb>) {
+        if let Some(splits) = self.splits.as_ref() {
+            splits.lock().split(iocbs)
+        }
+    }
+
+    /// Merge the contiguous writes among the iocbs (see `AIOBuilder::merge_writes`).
+    fn merge(&self, iocbs: &mut Vec<*mut abi::IOCb>, last_id: &mut u64) {
+        if let Some(merged) = self.merged.as_ref() {
+            let max_bytes =
+                self.splits.as_ref().map_or(u64::MAX, |s| s.lock().max());
+            let m = merge::merge(iocbs, last_id, max_bytes);
+            if !m.is_empty() {
+                diag!(trace, "merged {} runs of writes", m.len());
+                merged.lock().extend(m)
+            }
+        }
+    }
+
+    /// The fd and the id of the AIOs of the iocbs, along with the index of their iocb (several
+    /// AIOs share that of a merged write, and a split AIO goes with its first chunk).
+    fn aios(&self, iocbs: &[*mut abi::IOCb]) -> Vec<(usize, RawFd, u64)> {
+        let merged = self.merged.as_ref().map(|m| m.lock());
+        let splits = self.splits.as_ref().map(|s| s.lock());
+        let mut aios = Vec::with_capacity(iocbs.len());
+        for (i, iocb) in iocbs.iter().enumerate() {
+            let (fd, id) =
+                unsafe { ((**iocb).aio_fildes as RawFd, (**iocb).aio_data) };
+            let ids = match merged.as_ref().and_then(|m| m.get(&id)) {
+                Some(m) => m.ids().collect(),
+                None => vec![id],
+            };
+            for id in ids {
+                match splits.as_ref().and_then(|s| s.aio_of(id)) {
+                    Some((aio, true)) => aios.push((i, fd, aio)),
+                    Some((_, false)) => (),
+                    None => aios.push((i, fd, id)),
+                }
+            }
+        }
+        aios
+    }
+
+    /// The ids of the AIOs of a never submitted iocb, dropping it if it is a merged write.
+    fn unmerge(&self, id: u64) -> Vec<u64> {
+        match self.merged.as_ref() {
+            Some(merged) if id & MERGED_ID != 0 => merged
+                .lock()
+                .remove(&id)
+                .map(|m| m.ids().collect())
+                .unwrap_or_default(),
+            _ => vec![id],
+        }
+    }
+
+    /// Have the completion of the AIO signal the eventfd, if any.
+    fn set_resfd(&self, iocb: *mut abi::IOCb) {
+        if let Some(efd) = self.eventfd.as_ref() {
+            unsafe {
+                (*iocb).aio_flags |= abi::IOCB_FLAG_RESFD;
+                (*iocb).aio_resfd = efd.0 as u32;
+            }
+        }
+    }
+
+    /// When the first of the AIOs to be retried is due (or that of a tenant held back for the
+    /// lack of bytes, see `Tenants::next_due`).
+    fn next_retry(&self) -> Option<Instant> {
+        let retry = self.retries.lock().peek().map(|d| d.deadline);
+        match (retry, self.tenants.next_due()) {
+            (Some(retry), Some(due)) => Some(retry.min(due)),
+            (retry, due) => retry.or(due),
+        }
+    }
+
+    /// The (negated) errno the AIOs no longer taken by the driver are resolved with.
+    fn shutdown_res(&self) -> i64 {
+        match self.fatal.load(Ordering::Acquire) {
+            0 => -libc::ESHUTDOWN as i64,
+            errno => -errno as i64,
+        }
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Acquire)
+    }
+
+    /// Account for an AIO no longer pending.
+    fn retire(&self) {
+        if self.npending.fetch_sub(1, Ordering::AcqRel) == 1 {
+            for w in self.drained.lock().drain(..) {
+                w.wake()
+            }
+        }
+        if let Some(permits) = self.permits.as_ref() {
+            permits.release()
+        }
+    }
+
+    /// Wait for all the pending AIOs to finish.
+    fn poll_drained(&self, cx: &mut std::task::Context) -> std::task::Poll<()> {
+        let mut drained = self.drained.lock();
+        if self.npending.load(Ordering::Acquire) == 0 {
+            return std::task::Poll::Ready(())
+        }
+        drained.push(cx.waker().clone());
+        std::task::Poll::Pending
+    }
+
+    fn resolve(
+        mut e: slab::Entry<'_, AIOState>,
+        res: i64,
+        wakers: &mut Wakers,
+    ) {
+        let aio = match &mut *e {
+            AIOState::Pending(_, true) => {
+                e.remove();
+                return
+            }
+            AIOState::Pending(aio, false) => aio,
+            AIOState::Done(_) => return,
+        };
+        #[cfg(feature = "tracing")]
+        aio.span.record("res", res);
+        let mut data = aio.data.take().unwrap();
+        if res >= 0 {
+            data.trim(res as usize)
+        }
+        *e = AIOState::Done(if res >= 0 {
+            (Ok(res as usize), data)
+        } else {
+            (Err(-res as i32), data)
+        });
+        wakers.push(e.finish())
+    }
+
+    /// Give back the AIO events of the context once the driver is done with it, rather than
+    /// when the last future goes, unless some AIOs are still in flight (e.g., given up upon a
+    /// fatal error).
+    fn destroy(&self) {
+        if self.counters.in_flight() == 0 {
+            self.io_ctx.destroy()
+        }
+    }
+
+    /// Cancel all the AIOs (as `cancel` does).
+    fn cancel_all(&self) {
+        self.waiting.for_each(|_, state| {
+            if let AIOState::Pending(aio, _) = state {
+                aio.cancelled = true;
+                self.io_ctx.cancel(aio.iocb.load(Ordering::Acquire));
+            }
+        })
+    }
+
+    fn cancel(&self, id: u64) -> bool {
+        match self.waiting.get(id).as_deref_mut() {
+            Some(AIOState::Pending(aio, _)) => {
+                aio.cancelled = true;
+                self.io_ctx.cancel(aio.iocb.load(Ordering::Acquire));
+                true
+            }
+            _ => false,
+        }
+    }
 }
 
-pub type EmulatedFailureShared = Arc<Mutex<dyn EmulatedFailure>>;
+pub struct AIOBuilder {
+    max_events: u32,
+    max_nwait: u16,
+    max_nbatched: usize,
+    timeout: Option<std::time::Duration>,
+    submit_delays: [std::time::Duration; prio::NLANES],
+    backend: Backend,
+    allow_fallback: bool,
+    eventfd: bool,
+    reaper_threads: usize,
+    threads: ThreadConfig,
+    alignment: usize,
+    pool_size: usize,
+    weights: [usize; prio::NLANES],
+    max_pending: usize,
+    fd_stats: bool,
+    slow_op_threshold: Option<std::time::Duration>,
+    retry: RetryPolicy,
+    barriers: bool,
+    fd_order: Option<FdOrder>,
+    merge_writes: bool,
+    elevator: bool,
+    max_io_size: usize,
+    validate_alignment: bool,
+    clamp_max_events: bool,
+    grow_max_events: u32,
+    shards: usize,
+    sharding: Sharding,
+    sigmask: Option<libc::sigset_t>,
+    #[cfg(feature = "emulated-failure")]
+    emul_fail: Option<EmulatedFailureShared>,
+    #[cfg(feature = "mock")]
+    mock: Option<MockDisk>,
+    #[cfg(feature = "fault-injection")]
+    faults: Option<FaultInjector>,
+}
 
-/// Manager all AIOs.
-pub struct AIOManager {
-    notifier: Arc<AIONotifier>,
-    scheduler_in: AIOBatchSchedulerIn,
-    listener: Option<std::thread::JoinHandle<()>>,
-    exit_s: crossbeam_channel::Sender<()>,
+impl Default for AIOBuilder {
+    fn default() -> Self {
+        AIOBuilder {
+            max_events: 128,
+            max_nwait: 128,
+            max_nbatched: 128,
+            timeout: None,
+            submit_delays: [std::time::Duration::ZERO; prio::NLANES],
+            backend: Backend::default(),
+            allow_fallback: false,
+            eventfd: false,
+            reaper_threads: 1,
+            threads: ThreadConfig::default(),
+            alignment: 4096,
+            pool_size: 0,
+            weights: [4, 2, 1],
+            max_pending: 0,
+            fd_stats: false,
+            slow_op_threshold: None,
+            retry: RetryPolicy::never(),
+            barriers: false,
+            fd_order: None,
+            merge_writes: false,
+            elevator: false,
+            max_io_size: 0,
+            validate_alignment: false,
+            clamp_max_events: false,
+            grow_max_events: 0,
+            shards: 1,
+            sharding: Sharding::Fd,
+            sigmask: None,
+            #[cfg(feature = "emulated-failure")]
+            emul_fail: None,
+            #[cfg(feature = "mock")]
+            mock: None,
+            #[cfg(feature = "fault-injection")]
+            faults: None,
+        }
+    }
 }
 
-impl AIOManager {
-    fn start(
+impl AIOBuilder {
+    /// Maximum concurrent async IO operations.
+    pub fn max_events(&mut self, v: u32) -> &mut Self {
+        self.max_events = v;
+        self
+    }
+
+    /// Maximum complete IOs per poll.
+    pub fn max_nwait(&mut self, v: u16) -> &mut Self {
+        self.max_nwait = v;
+        self
+    }
+
+    /// Maximum number of IOs per submission.
+    pub fn max_nbatched(&mut self, v: usize) -> &mut Self {
+        self.max_nbatched = v;
+        self
+    }
+
+    /// Once idle, wait up to the given time (default 0, i.e., submit right away; e.g., 50µs)
+    /// for more IOs to be scheduled after the first one, unless `max_nbatched` of them are
+    /// sooner, so they are submitted together rather than one at a time under a light load. The
+    /// IOs scheduled while others are in flight are not delayed, as they are already submitted
+    /// together once some of those finish. Only applies to the background threads (see `build`).
+    pub fn submit_delay(&mut self, v: std::time::Duration) -> &mut Self {
+        self.submit_delays = [v; prio::NLANES];
+        self
+    }
+
+    /// Same as `submit_delay`, but for the realtime, best-effort (including those without a
+    /// priority) and idle IOs respectively, e.g., 0 for the latency-critical reads while the
+    /// background writes wait longer. The shortest delay among the IOs scheduled goes, and
+    /// those with a deadline are never delayed.
+    pub fn priority_submit_delays(
         &mut self,
-        mut scheduler_out: AIOBatchSchedulerOut,
-        exit_r: crossbeam_channel::Receiver<()>,
-        max_nwait: u16,
-        timeout: Option<u32>,
-    ) -> Result<(), Error> {
-        let n = self.notifier.clone();
-        self.listener = Some(std::thread::spawn(move || {
-            let mut timespec = timeout.and_then(|sec: u32| {
-                Some(libc::timespec {
-                    tv_sec: sec as time_t,
-                    tv_nsec: 0,
-                })
-            });
-            let mut ongoing = 0;
-            loop {
-                // try to quiesce
-                if ongoing == 0 && scheduler_out.is_empty() {
-                    let mut sel = crossbeam_channel::Select::new();
-                    sel.recv(&exit_r);
-                    sel.recv(&scheduler_out.get_receiver());
-                    if sel.ready() == 0 {
-                        exit_r.recv().unwrap();
-                        break
-                    }
-                }
-                // submit as many aios as possible
-                loop {
-                    let nacc = scheduler_out.submit(&n);
-                    ongoing += nacc;
-                    if nacc == 0 {
-                        break
-                    }
-                }
-                // no need to wait if there is no progress
-                if ongoing == 0 {
-                    continue
-                }
-                // then block on any finishing aios
-                let mut events =
-                    vec![abi::IOEvent::default(); max_nwait as usize];
-                let ret = unsafe {
-                    abi::io_getevents(
-                        *n.io_ctx,
-                        1,
-                        max_nwait as c_long,
-                        events.as_mut_ptr(),
-                        timespec
-                            .as_mut()
-                            .and_then(|t| Some(t as *mut libc::timespec))
-                            .unwrap_or(std::ptr::null_mut()),
-                    )
-                };
-                // TODO: AIO fatal error handling
-                // avoid empty slice
-                if ret == 0 {
-                    continue
-                }
-                assert!(ret > 0);
-                ongoing -= ret as usize;
-                for ev in events[..ret as usize].iter() {
-                    #[cfg(not(feature = "emulated-failure"))]
-                    n.finish(ev.data as u64, ev.res);
-                    #[cfg(feature = "emulated-failure")]
-                    {
-                        let mut res = ev.res;
-                        if let Some(emul_fail) = n.emul_fail.as_ref() {
-                            let mut ef = emul_fail.lock();
-                            if let Some(e) = ef.tick() {
-                                res = e
-                            }
-                        }
-                        n.finish(ev.data as u64, res);
-                    }
+        rt: std::time::Duration,
+        be: std::time::Duration,
+        idle: std::time::Duration,
+    ) -> &mut Self {
+        self.submit_delays = [rt, be, idle];
+        self
+    }
+
+    /// Timeout for a polling iteration in seconds (default is None, see `poll_timeout`).
+    pub fn timeout(&mut self, sec: u32) -> &mut Self {
+        self.poll_timeout(std::time::Duration::from_secs(sec as u64))
+    }
+
+    /// Timeout for a polling iteration (default is None), with up to nanosecond precision. The
+    /// reapers of `reaper_threads` (or `shards`) poll at least every 100ms regardless.
+    pub fn poll_timeout(&mut self, v: std::time::Duration) -> &mut Self {
+        self.timeout = Some(v);
+        self
+    }
+
+    /// The engine that carries out the IOs (default is io_uring when the `uring` feature is
+    /// enabled, Linux native AIO otherwise).
+    pub fn backend(&mut self, v: Backend) -> &mut Self {
+        self.backend = v;
+        self
+    }
+
+    /// Fall back to `Backend::ThreadPool` (or `Backend::PosixAio` with the `posix-aio` feature)
+    /// when the chosen backend is not supported by the system (default is false).
+    pub fn allow_fallback(&mut self, v: bool) -> &mut Self {
+        self.allow_fallback = v;
+        self
+    }
+
+    /// Signal an eventfd upon every completed IO, so completions can be detected by other
+    /// pollers (default is false). See `AIOManager::eventfd`.
+    pub fn eventfd(&mut self, v: bool) -> &mut Self {
+        self.eventfd = v;
+        self
+    }
+
+    #[cfg(feature = "emulated-failure")]
+    pub fn emulated_failure(&mut self, ef: EmulatedFailureShared) -> &mut Self {
+        self.emul_fail = Some(ef);
+        self
+    }
+
+    /// Carry out the IOs on the in-memory files of the disk instead of the chosen backend (and
+    /// without growing, see `grow_max_events`), for the tests. The IOs on any other fd fail
+    /// with `EBADF`.
+    #[cfg(feature = "mock")]
+    pub fn mock(&mut self, disk: &MockDisk) -> &mut Self {
+        self.mock = Some(disk.clone());
+        self
+    }
+
+    /// Inject the faults of the injector into the IOs (on top of the chosen backend, or of the
+    /// mock disk), for testing the error handling.
+    #[cfg(feature = "fault-injection")]
+    pub fn fault_injection(&mut self, faults: &FaultInjector) -> &mut Self {
+        self.faults = Some(faults.clone());
+        self
+    }
+
+    /// Number of background threads waiting for and finishing the completed IOs (default 1). With
+    /// more than one, they share the context while a separate thread submits the IOs, so a
+    /// single thread no longer bounds the completion rate.
+    pub fn reaper_threads(&mut self, v: usize) -> &mut Self {
+        self.reaper_threads = v;
+        self
+    }
+
+    /// Name of the background threads (for `top`/`perf`), additional reaper threads get an index
+    /// appended. Linux truncates it to 15 bytes.
+    pub fn thread_name(&mut self, name: &str) -> &mut Self {
+        self.threads.name = Some(name.to_string());
+        self
+    }
+
+    /// Pin the background threads to the given CPU cores (only on Linux, building fails with
+    /// `ENOSYS` elsewhere).
+    pub fn affinity(&mut self, cpus: &[usize]) -> &mut Self {
+        self.threads.affinity = Some(cpus.to_vec());
+        self
+    }
+
+    /// Alignment of the buffers allocated by `AIOManager::read_aligned` and the chunks of
+    /// `BufferedWriter` (default 4096, which suits `O_DIRECT` on most devices). Panics if it is
+    /// not a power of two and a multiple of the pointer size.
+    pub fn alignment(&mut self, v: usize) -> &mut Self {
+        assert!(v.is_power_of_two() && v >= std::mem::size_of::<*const u8>());
+        self.alignment = v;
+        self
+    }
+
+    /// Keep up to the given number of bytes of buffers returned by `AIOManager::recycle` for the
+    /// subsequent reads (default 0, i.e., no pool). The recycled buffers are not zeroed, so the
+    /// bytes beyond what a read returns are unspecified.
+    pub fn buffer_pool(&mut self, max_bytes: usize) -> &mut Self {
+        self.pool_size = max_bytes;
+        self
+    }
+
+    /// The number of operations taken in turn from the realtime, best-effort (including those
+    /// without a priority) and idle ones queued for the submission (default 4, 2 and 1). So the
+    /// more urgent operations get ahead of the queued ones, while the others still progress.
+    pub fn priority_weights(
+        &mut self,
+        rt: usize,
+        be: usize,
+        idle: usize,
+    ) -> &mut Self {
+        assert!(
+            rt > 0 && be > 0 && idle > 0,
+            "the weights should be positive"
+        );
+        self.weights = [rt, be, idle];
+        self
+    }
+
+    /// Bound the number of pending AIOs (default 0, i.e., unbounded). Beyond that, the futures
+    /// of the newly scheduled AIOs first wait (when polled) for others to finish, before their
+    /// AIOs are scheduled. So only the AIOs scheduled can be cancelled with `AIOManager::cancel`.
+    pub fn max_pending(&mut self, v: usize) -> &mut Self {
+        self.max_pending = v;
+        self
+    }
+
+    /// Keep the counters of the AIOs per file descriptor (see `AIOManager::fd_stats`).
+    pub fn fd_stats(&mut self, v: bool) -> &mut Self {
+        self.fd_stats = v;
+        self
+    }
+
+    /// Count (in `Stats::slow_ops`) the AIOs taking longer than the given time from their
+    /// scheduling to their completion, which are also logged and traced with the `log` and
+    /// `tracing` features.
+    pub fn slow_op_threshold(&mut self, v: std::time::Duration) -> &mut Self {
+        self.slow_op_threshold = Some(v);
+        self
+    }
+
+    /// Retry the AIOs failed with a transient error, unless overridden by the operation (e.g.,
+    /// `AIOManager::read_with_retry`).
+    pub fn retry_policy(&mut self, v: RetryPolicy) -> &mut Self {
+        self.retry = v;
+        self
+    }
+
+    /// Keep track of the AIOs per file descriptor, as required by `AIOManager::barrier`.
+    pub fn barriers(&mut self, v: bool) -> &mut Self {
+        self.barriers = v;
+        self
+    }
+
+    /// Keep the operations on the same file descriptor in the order of their scheduling,
+    /// regardless of their priorities and deadlines (e.g., for the appends to a log). A retried
+    /// operation (see `retry_policy`) may still be submitted after the following ones with
+    /// `FdOrder::Submission`.
+    pub fn fd_order(&mut self, v: FdOrder) -> &mut Self {
+        self.fd_order = Some(v);
+        self
+    }
+
+    /// Merge the contiguous writes to the same file descriptor submitted together into a single
+    /// vectored write, which saves the queue depth for many small sequential appends. The
+    /// merged writes get their share of the bytes written (the first ones are filled first),
+    /// or the error of the vectored write, and can no longer be cancelled once submitted.
+    pub fn merge_writes(&mut self, v: bool) -> &mut Self {
+        self.merge_writes = v;
+        self
+    }
+
+    /// Sort the reads and writes on each file descriptor submitted together by their offsets,
+    /// sweeping upwards from where the previous submission on the fd ended and wrapping around
+    /// (C-SCAN), to reduce the seeks on rotational devices. Not done with `fd_order`, nor for
+    /// the batches of `AIOManager::submit_batch`.
+    pub fn elevator(&mut self, v: bool) -> &mut Self {
+        self.elevator = v;
+        self
+    }
+
+    /// Split the reads and writes longer than the given number of bytes (default 0, i.e., no
+    /// limit) into chunks submitted separately, as the device or the kernel may not take them
+    /// at once. The operation still finishes with a single result once all its chunks are
+    /// finished: the bytes transferred up to the first short chunk, or the error of the first
+    /// chunk. With `O_DIRECT`, the size must be a multiple of the alignment.
+    pub fn max_io_size(&mut self, v: usize) -> &mut Self {
+        self.max_io_size = v;
+        self
+    }
+
+    /// Check the reads and writes on the file descriptors opened with `O_DIRECT` (or given an
+    /// alignment by `AIOManager::set_fd_alignment`) before scheduling them: those whose offset,
+    /// buffer or length is not aligned as configured by `alignment` fail with `EINVAL` right
+    /// away (or `Error::Misaligned` for `AIOManager::try_read` and `try_write`), rather than
+    /// deep in the kernel. Costs an `fcntl` per operation.
+    pub fn validate_alignment(&mut self, v: bool) -> &mut Self {
+        self.validate_alignment = v;
+        self
+    }
+
+    /// Lower `max_events` to what the system-wide limit on the AIO events still allows (see
+    /// `Error::MaxEventsTooLarge`), rather than failing to build, e.g., when many processes set
+    /// up contexts. The limit may still be reached down to a single event.
+    pub fn clamp_max_events(&mut self, v: bool) -> &mut Self {
+        self.clamp_max_events = v;
+        self
+    }
+
+    /// Grow the context up to the given number of events (default 0, i.e., never), doubling
+    /// its size whenever the submissions keep finding it full for a while, rather than holding
+    /// them back until some AIOs finish, so `max_events` need not be guessed upfront. The AIOs
+    /// in the previous context are still reaped, and it is released once they are finished.
+    /// The limits derived from `max_events` (e.g., for `AIOManager::try_read` and the batches
+    /// of `AIOManager::submit_batch`) stay as configured.
+    pub fn grow_max_events(&mut self, v: u32) -> &mut Self {
+        self.grow_max_events = v;
+        self
+    }
+
+    /// Spread the AIOs over the given number of contexts (default 1), each of `max_events`
+    /// events and with a reaper thread of its own (at least, see `reaper_threads`), so the
+    /// completions of a busy system are not all collected by a single thread. The AIOs are
+    /// routed by their fd (`Sharding::Fd`, so those on the same file share a context), or in
+    /// turn (`Sharding::RoundRobin`). The other settings of the context (e.g.,
+    /// `grow_max_events`) apply to each of them. Without the reapers (`build_manual` and
+    /// `build_tokio`), all the contexts are reaped together.
+    pub fn shards(&mut self, n: usize, by: Sharding) -> &mut Self {
+        self.shards = n;
+        self.sharding = by;
+        self
+    }
+
+    /// Wait for the completions with the signals in the mask blocked instead of those of the
+    /// waiting thread (`io_pgetevents(2)`, Linux 4.18 or later, while `Backend::Uring` swaps the
+    /// mask of the thread around the waits), e.g., all of them, so the signals meant for a
+    /// specific thread of the application are never delivered to the listener, nor cut its
+    /// waits short. Applies to the waits in `AIOManager::drive` as well. `Backend::ThreadPool`
+    /// is never interrupted by signals, so it ignores the mask.
+    pub fn sigmask(&mut self, v: libc::sigset_t) -> &mut Self {
+        self.sigmask = Some(v);
+        self
+    }
+
+    /// Build an AIOManager object based on the configuration (and auto-start the background IO
+    /// scheduling thread).
+    pub fn build(&mut self) -> Result<AIOManager, Error> {
+        let (mut aiomgr, scheduler_out) = self.create(self.eventfd)?;
+        if self.reaper_threads > 1 || self.shards > 1 {
+            aiomgr.start_reapers(
+                scheduler_out,
+                self.max_nwait,
+                self.timeout,
+                self.reaper_threads.max(self.shards),
+                &self.threads,
+            )?;
+        } else {
+            aiomgr.start(
+                scheduler_out,
+                self.max_nwait,
+                self.timeout,
+                &self.threads,
+            )?;
+        }
+        Ok(aiomgr)
+    }
+
+    /// Build an AIOManager object whose IOs are driven by a task spawned onto the current tokio
+    /// runtime (which must have IO enabled), instead of a background thread. The completions
+    /// are detected through the eventfd, so `eventfd(true)` is implied.
+    #[cfg(feature = "tokio")]
+    pub fn build_tokio(&mut self) -> Result<AIOManager, Error> {
+        let (mut aiomgr, scheduler_out) = self.create(true)?;
+        let ctl = tokio_driver::spawn(
+            aiomgr.notifier.clone(),
+            scheduler_out,
+            self.max_nwait,
+        )?;
+        Arc::get_mut(&mut aiomgr.handle.scheduler_in.queues)
+            .unwrap()
+            .kick = Some(ctl.clone());
+        aiomgr.driver = Some(Driver::Tokio(ctl));
+        Ok(aiomgr)
+    }
+
+    /// Build an AIOManager object without any background thread: the IOs only progress when the
+    /// user calls `AIOManager::drive`, e.g., from the event loop of each core.
+    pub fn build_manual(&mut self) -> Result<AIOManager, Error> {
+        let (mut aiomgr, scheduler_out) = self.create(self.eventfd)?;
+        aiomgr.driver =
+            Some(Driver::Manual(Box::new(Mutex::new(ManualDriver {
+                scheduler_out,
+                ongoing: 0,
+            }))));
+        Ok(aiomgr)
+    }
+
+    fn create(
+        &mut self,
+        eventfd: bool,
+    ) -> Result<(AIOManager, AIOBatchSchedulerOut), Error> {
+        let mut shard_events = self.max_events;
+        let mut max_events = 0;
+        let mut shards = Vec::with_capacity(self.shards.max(1));
+        for _ in 0..self.shards.max(1) {
+            let (ctx, n) = self.new_context(shard_events)?;
+            #[cfg(feature = "fault-injection")]
+            let ctx: Box<dyn AioBackend> = match self.faults.as_ref() {
+                Some(faults) => Box::new(faults.wrap(ctx)),
+                None => ctx,
+            };
+            // the next ones need not try the larger size again
+            shard_events = n;
+            max_events += n;
+            shards.push(ctx)
+        }
+        let io_ctx: Box<dyn AioBackend> = if shards.len() > 1 {
+            Box::new(ShardedContext::new(shards, self.sharding))
+        } else {
+            shards.pop().unwrap()
+        };
+        let (scheduler_in, scheduler_out) = new_batch_scheduler(
+            self.max_nbatched,
+            max_events as usize,
+            self.weights,
+            self.submit_delays,
+            self.elevator,
+        );
+        let eventfd = if eventfd { Some(EventFd::new()?) } else { None };
+        let (resume_in, resume_out) = crossbeam_channel::bounded(1);
+        let notifier = Arc::new(AIONotifier {
+            io_ctx,
+            eventfd,
+            waiting: Slab::new(2 * (max_events as usize).max(self.max_pending)),
+            iocbs: Arc::new(IocbSlab::new(max_events as usize)),
+            intake: parking_lot::RwLock::new(()),
+            finished: parking_lot::Condvar::new(),
+            finishing: Mutex::new(()),
+            npending: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            paused: AtomicBool::new(false),
+            resume_in,
+            resume_out,
+            fatal: AtomicI32::new(0),
+            drained: Mutex::new(Vec::new()),
+            permits: match self.max_pending {
+                0 => None,
+                n => Some(Permits::new(n)),
+            },
+            counters: Counters::default(),
+            fd_stats: if self.fd_stats {
+                Some(Mutex::new(HashMap::new()))
+            } else {
+                None
+            },
+            slow_op_threshold: self.slow_op_threshold,
+            retries: Mutex::new(BinaryHeap::new()),
+            throttle: Throttle::new(),
+            tag_weights: TagWeights::default(),
+            tenants: Tenants::new(),
+            barriers: if self.barriers {
+                Some(Arc::new(Barriers::default()))
+            } else {
+                None
+            },
+            order: self.fd_order.map(|o| (Arc::new(Barriers::default()), o)),
+            merged: if self.merge_writes {
+                Some(Mutex::new(HashMap::new()))
+            } else {
+                None
+            },
+            splits: match self.max_io_size {
+                0 => None,
+                n => Some(Mutex::new(Splits::new(n))),
+            },
+            #[cfg(feature = "emulated-failure")]
+            emul_fail: self.emul_fail.as_ref().map(|ef| ef.clone()),
+        });
+        let handle = AIOManagerHandle {
+            notifier,
+            scheduler_in,
+            alignment: self.alignment,
+            pool: Arc::new(BufferPool::new(self.pool_size)),
+            max_events: max_events as usize,
+            retry: self.retry,
+            fd_alignment: if self.validate_alignment {
+                Some(Arc::new(Mutex::new(HashMap::new())))
+            } else {
+                None
+            },
+            helper: Arc::new(Mutex::new(None)),
+        };
+        let aiomgr = AIOManager {
+            handle,
+            driver: None,
+        };
+        Ok((aiomgr, scheduler_out))
+    }
+
+    /// Set up a context of `max_events` events (or fewer, see `clamp_max_events`), returning
+    /// it with its actual size.
+    fn new_context(
+        &self,
+        mut max_events: u32,
+    ) -> Result<(Box<dyn AioBackend>, u32), Error> {
+        #[cfg(feature = "mock")]
+        if let Some(disk) = self.mock.as_ref() {
+            return Ok((Box::new(disk.context(max_events)), max_events))
+        }
+        let mut backend = self.backend;
+        let mut io_ctx = loop {
+            match backend.create(max_events, self.sigmask) {
+                Err(Error::MaxEventsTooLarge {
+                    in_use: Some(in_use),
+                    limit: Some(limit),
+                    ..
+                }) if self.clamp_max_events && max_events > 1 => {
+                    // others may take some meanwhile
+                    let left = limit.saturating_sub(in_use) as u32;
+                    let clamped = if left < max_events {
+                        left.max(1)
+                    } else {
+                        max_events / 2
+                    };
+                    diag!(
+                        warn,
+                        "max_events {} clamped to {} ({} of {} aio events in use)",
+                        max_events,
+                        clamped,
+                        in_use,
+                        limit
+                    );
+                    max_events = clamped
+                }
+                Err(Error::NotSupported) if self.allow_fallback => {
+                    backend = Backend::fallback();
+                    break backend.create(max_events, None)?
+                }
+                r => break r?,
+            }
+        };
+        if self.grow_max_events > max_events {
+            io_ctx = Box::new(GrowingContext::new(
+                io_ctx,
+                backend,
+                max_events,
+                self.grow_max_events,
+                self.sigmask,
+            ))
+        }
+        Ok((io_ctx, max_events))
+    }
+}
+
+pub trait EmulatedFailure: Send {
+    fn tick(&mut self) -> Option<i64>;
+}
+
+pub type EmulatedFailureShared = Arc<Mutex<dyn EmulatedFailure>>;
+
+/// How the submission and the completion of AIOs are driven.
+enum Driver {
+    /// By the background listener thread(s).
+    Listener(
+        Vec<std::thread::JoinHandle<()>>,
+        crossbeam_channel::Sender<()>,
+    ),
+    /// By a task in the tokio runtime.
+    #[cfg(feature = "tokio")]
+    Tokio(Arc<tokio_driver::Control>),
+    /// By the user through `AIOManager::drive`.
+    Manual(Box<Mutex<ManualDriver>>),
+}
+
+/// How the background threads are spawned.
+#[derive(Default)]
+struct ThreadConfig {
+    name: Option<String>,
+    affinity: Option<Vec<usize>>,
+}
+
+impl ThreadConfig {
+    fn spawn<F: FnOnce() + Send + 'static>(
+        &self,
+        threads: &mut Vec<std::thread::JoinHandle<()>>,
+        index: Option<usize>,
+        f: F,
+    ) -> Result<(), Error> {
+        let mut builder = std::thread::Builder::new();
+        if let Some(name) = self.name.as_ref() {
+            builder = builder.name(match index {
+                Some(i) => format!("{}-{}", name, i),
+                None => name.clone(),
+            });
+        }
+        let handle = builder.spawn(f).map_err(|e| {
+            e.raw_os_error().map_or(Error::OtherError, Error::Sys)
+        })?;
+        let thread = handle.as_pthread_t();
+        threads.push(handle);
+        #[cfg(not(target_os = "linux"))]
+        if self.affinity.is_some() {
+            let _ = thread;
+            return Err(Error::Sys(libc::ENOSYS))
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(cpus) = self.affinity.as_ref() {
+            let ret = unsafe {
+                let mut set: libc::cpu_set_t = std::mem::zeroed();
+                for cpu in cpus {
+                    if *cpu >= libc::CPU_SETSIZE as usize {
+                        return Err(Error::Sys(libc::EINVAL))
+                    }
+                    libc::CPU_SET(*cpu, &mut set);
+                }
+                libc::pthread_setaffinity_np(
+                    thread,
+                    std::mem::size_of::<libc::cpu_set_t>(),
+                    &set,
+                )
+            };
+            if ret != 0 {
+                return Err(Error::Sys(ret))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The state shared by the submitting thread and the reapers.
+struct Reaping {
+    ongoing: Mutex<usize>,
+    progress: parking_lot::Condvar,
+    exit: AtomicBool,
+    // the errno of the fatal error a reaper got, or 0
+    failed: AtomicI32,
+}
+
+/// How long a reaper (or the blocked submitter) waits before checking again.
+const REAPER_POLL: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// How often the scheduled AIOs are counted while gathering them (see `AIOBuilder::submit_delay`).
+const GATHER_POLL: std::time::Duration = std::time::Duration::from_micros(10);
+
+/// The first and the longest waits before submitting again after finding the context full,
+/// unless some AIOs finish sooner (see `AIOBatchSchedulerOut::is_stalled`).
+const EAGAIN_BACKOFF: std::time::Duration =
+    std::time::Duration::from_micros(100);
+const MAX_EAGAIN_BACKOFF: std::time::Duration =
+    std::time::Duration::from_millis(100);
+
+/// The length of the reads of `read_file`, and how many of them are in flight at once.
+const READ_FILE_CHUNK: usize = 1 << 20;
+const READ_FILE_DEPTH: usize = 4;
+
+struct ManualDriver {
+    scheduler_out: AIOBatchSchedulerOut,
+    ongoing: usize,
+}
+
+/// The less common settings of an operation.
+#[derive(Default)]
+struct OpOptions {
+    // keep the file open until the operation is finished
+    file: Option<Arc<std::fs::File>>,
+    rw_flags: RWFlags,
+    deadline: Option<Instant>,
+    // a permit is already taken
+    admitted: bool,
+    // overrides that of the AIOManager
+    retry: Option<RetryPolicy>,
+    // shares the lane with the other tags (see `AIOManager::read_with_tag`)
+    tag: u32,
+}
+
+impl OpOptions {
+    fn file(file: Arc<std::fs::File>) -> Self {
+        OpOptions {
+            file: Some(file),
+            ..Default::default()
+        }
+    }
+
+    fn with(opts: &IoOptions) -> Self {
+        OpOptions {
+            rw_flags: opts.rw_flags,
+            deadline: opts.deadline,
+            retry: opts.retry,
+            tag: opts.tag,
+            ..Default::default()
+        }
+    }
+
+    fn admitted() -> Self {
+        OpOptions {
+            admitted: true,
+            ..Default::default()
+        }
+    }
+
+    fn retry(retry: RetryPolicy) -> Self {
+        OpOptions {
+            retry: Some(retry),
+            ..Default::default()
+        }
+    }
+}
+
+/// Manager all AIOs. The AIOs are scheduled through its `AIOManagerHandle`, whose methods it
+/// dereferences to.
+pub struct AIOManager {
+    handle: AIOManagerHandle,
+    driver: Option<Driver>,
+}
+
+/// Schedules the AIOs of an AIOManager, from any thread or task (see `AIOManager::handle`). It
+/// is cheap to clone, and it does not keep the AIOManager running: once the AIOManager is shut
+/// down (or dropped), the AIOs scheduled through it fail with `ESHUTDOWN` (or the fatal error
+/// of the AIOManager).
+#[derive(Clone)]
+pub struct AIOManagerHandle {
+    notifier: Arc<AIONotifier>,
+    scheduler_in: AIOBatchSchedulerIn,
+    alignment: usize,
+    pool: Arc<BufferPool>,
+    max_events: usize,
+    retry: RetryPolicy,
+    // the alignments set by `set_fd_alignment`, if enabled by `AIOBuilder::validate_alignment`
+    fd_alignment: Option<Arc<Mutex<HashMap<RawFd, usize>>>>,
+    // started upon the first blocking operation (see `run_blocking`)
+    helper: Arc<Mutex<Option<Helper>>>,
+}
+
+impl AIOManager {
+    fn start(
+        &mut self,
+        mut scheduler_out: AIOBatchSchedulerOut,
+        max_nwait: u16,
+        timeout: Option<std::time::Duration>,
+        config: &ThreadConfig,
+    ) -> Result<(), Error> {
+        let (exit_s, exit_r) = crossbeam_channel::bounded(0);
+        self.driver = Some(Driver::Listener(Vec::new(), exit_s));
+        let n = self.notifier.clone();
+        config.spawn(self.listener_threads(), None, move || {
+            diag!(debug, "aio listener started");
+            let timespec = timeout.map(to_timespec);
+            let mut ongoing = 0;
+            loop {
+                // try to quiesce
+                if ongoing == 0 && scheduler_out.is_empty(&n) {
+                    let mut sel = crossbeam_channel::Select::new();
+                    sel.recv(&exit_r);
+                    scheduler_out.watch(&n, &mut sel);
+                    // or until the next retry is due
+                    let due = scheduler_out.next_due(&n);
+                    let ready = match due {
+                        Some(due) => sel.ready_deadline(due).ok(),
+                        None => Some(sel.ready()),
+                    };
+                    if ready == Some(0) {
+                        // or disconnected by shutdown_now()
+                        let _ = exit_r.recv();
+                        break
+                    }
+                    scheduler_out.gather();
+                }
+                // submit as many aios as possible
+                loop {
+                    let nacc = scheduler_out.submit(&n);
+                    ongoing += nacc;
+                    if nacc == 0 {
+                        break
+                    }
+                }
+                // no need to wait if there is no progress
+                if ongoing == 0 {
+                    continue
+                }
+                // then block on any finishing aios
+                let mut wait =
+                    retry_timeout(timespec, scheduler_out.next_due(&n));
+                let ret = n.reap(1, max_nwait as usize, wait.as_mut());
+                if ret < 0 {
+                    scheduler_out.fail(&n, -ret);
+                    break
+                }
+                ongoing -= ret as usize;
+            }
+            scheduler_out.close(&n);
+            diag!(debug, "aio listener exited");
+        })
+    }
+
+    fn listener_threads(&mut self) -> &mut Vec<std::thread::JoinHandle<()>> {
+        match self.driver.as_mut() {
+            Some(Driver::Listener(threads, _)) => threads,
+            _ => unreachable!(),
+        }
+    }
+
+    fn start_reapers(
+        &mut self,
+        mut scheduler_out: AIOBatchSchedulerOut,
+        max_nwait: u16,
+        timeout: Option<std::time::Duration>,
+        nreapers: usize,
+        config: &ThreadConfig,
+    ) -> Result<(), Error> {
+        let (exit_s, exit_r) = crossbeam_channel::bounded(0);
+        // register the threads right away, so they are stopped by drop() upon any failure
+        self.driver = Some(Driver::Listener(Vec::new(), exit_s));
+        let reaping = Arc::new(Reaping {
+            ongoing: Mutex::new(0),
+            progress: parking_lot::Condvar::new(),
+            exit: AtomicBool::new(false),
+            failed: AtomicI32::new(0),
+        });
+        let n = self.notifier.clone();
+        let r = reaping.clone();
+        config.spawn(self.listener_threads(), None, move || {
+            diag!(debug, "aio submitter started");
+            loop {
+                let failed = r.failed.load(Ordering::Acquire);
+                if failed != 0 {
+                    scheduler_out.fail(&n, failed);
+                    // let the other reapers go
+                    *r.ongoing.lock() = 0;
+                    break
+                }
+                if scheduler_out.is_empty(&n) {
+                    let mut sel = crossbeam_channel::Select::new();
+                    sel.recv(&exit_r);
+                    scheduler_out.watch(&n, &mut sel);
+                    // bounded, to notice a failed reaper or a retry that is due
+                    let poll = Instant::now() + REAPER_POLL;
+                    let due = scheduler_out
+                        .next_due(&n)
+                        .map_or(poll, |due| due.min(poll));
+                    if let Ok(0) = sel.ready_deadline(due) {
+                        // or disconnected by shutdown_now()
+                        let _ = exit_r.recv();
+                        break
+                    }
+                    let idle = *r.ongoing.lock() == 0;
+                    if idle {
+                        scheduler_out.gather()
+                    }
+                } else {
+                    // the context is full, wait for some aios to finish
+                    let mut ongoing = r.ongoing.lock();
+                    r.progress.wait_for(&mut ongoing, REAPER_POLL);
+                }
+                // count the aios before any reaper gets to finish them
+                let mut ongoing = r.ongoing.lock();
+                loop {
+                    let nacc = scheduler_out.submit(&n);
+                    *ongoing += nacc;
+                    if nacc == 0 {
+                        break
+                    }
+                }
+                if *ongoing > 0 {
+                    r.progress.notify_all();
+                }
+            }
+            let _ongoing = r.ongoing.lock();
+            r.exit.store(true, Ordering::Release);
+            r.progress.notify_all();
+            scheduler_out.close(&n);
+            diag!(debug, "aio submitter exited");
+        })?;
+        for i in 0..nreapers {
+            let n = self.notifier.clone();
+            let r = reaping.clone();
+            config.spawn(self.listener_threads(), Some(i), move || {
+                diag!(debug, "aio reaper {} started", i);
+                // bounded, as the aios another reaper has taken may never come
+                let mut timespec = to_timespec(
+                    timeout.map_or(REAPER_POLL, |t| t.min(REAPER_POLL)),
+                );
+                loop {
+                    {
+                        let mut ongoing = r.ongoing.lock();
+                        while *ongoing == 0 {
+                            if r.exit.load(Ordering::Acquire) {
+                                diag!(debug, "aio reaper {} exited", i);
+                                return
+                            }
+                            r.progress.wait(&mut ongoing);
+                        }
+                    }
+                    let ret = n.reap_shard(
+                        Some(i),
+                        1,
+                        max_nwait as usize,
+                        Some(&mut timespec),
+                    );
+                    if ret < 0 {
+                        // leave it to the submitter to fail the aios
+                        r.failed.store(-ret, Ordering::Release);
+                        diag!(debug, "aio reaper {} exited", i);
+                        return
+                    }
+                    if ret > 0 {
+                        let mut ongoing = r.ongoing.lock();
+                        // already zeroed if the aios are failed meanwhile
+                        *ongoing = ongoing.saturating_sub(ret as usize);
+                        r.progress.notify_all();
+                    }
+                }
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl AIOManagerHandle {
+    pub fn read<'a>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        length: usize,
+        priority: Option<IoPriority>,
+    ) -> AIOFuture<'a> {
+        self.read_raw(fd.as_fd().as_raw_fd(), offset, length, priority)
+    }
+
+    /// Same as `read`, but the returned buffer only holds the bytes actually read (e.g., fewer
+    /// than `length` at the end of the file), instead of being zero-padded to `length`.
+    pub fn read_trimmed<'a>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        length: usize,
+        priority: Option<IoPriority>,
+    ) -> AIOFuture<'a, Vec<u8>> {
+        self.read_trimmed_raw(fd.as_fd().as_raw_fd(), offset, length, priority)
+    }
+
+    /// Read into the buffer provided by the caller (e.g., `Box<[u8]>`, `Vec<u8>`, `AlignedBuf`),
+    /// which controls the allocation and the alignment. Up to `buf.len()` bytes are read.
+    pub fn read_into<'a, B>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        buf: B,
+        priority: Option<IoPriority>,
+    ) -> AIOFuture<'a, B>
+    where
+        B: StableDeref<Target = [u8]> + DerefMut + Send + 'static,
+    {
+        self.read_into_raw(fd.as_fd().as_raw_fd(), offset, buf, priority)
+    }
+
+    /// Write the data of any buffer type whose content stays in place when moved (e.g.,
+    /// `Box<[u8]>`, `Vec<u8>`, `Arc<[u8]>`), which is handed back in the result.
+    pub fn write<'a, B>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        data: B,
+        priority: Option<IoPriority>,
+    ) -> AIOFuture<'a, B>
+    where
+        B: StableDeref<Target = [u8]> + Send + 'static,
+    {
+        self.write_raw(fd.as_fd().as_raw_fd(), offset, data, priority)
+    }
+
+    /// Same as `write`, but the rest of the data is written again upon a short write, until all
+    /// of it is written. Resolves to the length of the data, or the errno of the first failed
+    /// write (`EIO` if a write makes no progress), which leaves the data partially written.
+    /// Named after `std::os::unix::fs::FileExt::write_all_at`, to go with `read_exact_at`.
+    pub async fn write_all_at<B>(
+        &self,
+        fd: &impl AsFd,
+        offset: u64,
+        data: B,
+        priority: Option<IoPriority>,
+    ) -> AIOResult<B>
+    where
+        B: StableDeref<Target = [u8]> + Send + 'static,
+    {
+        let fd = fd.as_fd().as_raw_fd();
+        let len = data.len();
+        let (mut buf, mut start) = (data, 0);
+        while start < len {
+            let tail = WriteTail { buf, start };
+            let (res, b) = self
+                .schedule::<B>(
+                    fd,
+                    offset + start as u64,
+                    Box::new(tail),
+                    priority,
+                    abi::IOCmd::PWrite,
+                    OpOptions::default(),
+                )
+                .await;
+            buf = b;
+            match res {
+                Ok(0) => return (Err(libc::EIO), buf),
+                Ok(n) => start += n,
+                Err(e) => return (Err(e), buf),
+            }
+        }
+        (Ok(len), buf)
+    }
+
+    /// Same as `write_all_at`, under its former name.
+    #[deprecated(note = "renamed to `write_all_at`")]
+    pub async fn write_all<B>(
+        &self,
+        fd: &impl AsFd,
+        offset: u64,
+        data: B,
+        priority: Option<IoPriority>,
+    ) -> AIOResult<B>
+    where
+        B: StableDeref<Target = [u8]> + Send + 'static,
+    {
+        self.write_all_at(fd, offset, data, priority).await
+    }
+
+    /// Same as `read_into`, but the rest of the buffer is read again upon a short read, until all
+    /// of it is filled (like `std::os::unix::fs::FileExt::read_exact_at`). Resolves to the
+    /// length of the buffer, or the errno of the first failed read (`EIO` if the end of the file
+    /// comes first), which leaves the buffer partially filled. See `write_all_at` for the writes.
+    pub async fn read_exact_at<B>(
+        &self,
+        fd: &impl AsFd,
+        offset: u64,
+        buf: B,
+        priority: Option<IoPriority>,
+    ) -> AIOResult<B>
+    where
+        B: StableDeref<Target = [u8]> + DerefMut + Send + 'static,
+    {
+        let fd = fd.as_fd().as_raw_fd();
+        let len = buf.len();
+        let (mut buf, mut start) = (buf, 0);
+        while start < len {
+            let tail = ReadBuf::tail(buf, start);
+            let (res, b) = self
+                .schedule::<B>(
+                    fd,
+                    offset + start as u64,
+                    Box::new(tail),
+                    priority,
+                    abi::IOCmd::PRead,
+                    OpOptions::default(),
+                )
+                .await;
+            buf = b;
+            match res {
+                Ok(0) => return (Err(libc::EIO), buf),
+                Ok(n) => start += n,
+                Err(e) => return (Err(e), buf),
+            }
+        }
+        (Ok(len), buf)
+    }
+
+    /// Read the whole file (like `std::fs::read`) in chunks of 1 MiB, up to 4 of them in flight
+    /// at once, sized by `fstat(2)` upfront. Reading goes on past that size until the end of the
+    /// file, in case it grew meanwhile. The chunks are aligned as configured by
+    /// `AIOBuilder::alignment` (so `O_DIRECT` file descriptors work), and reused for the next
+    /// reads once copied out. Resolves to the contents, or the error of the first failed read.
+    pub async fn read_file(&self, fd: &impl AsFd) -> std::io::Result<Vec<u8>> {
+        let fd = fd.as_fd().as_raw_fd();
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstat(fd, &mut stat) } < 0 {
+            return Err(std::io::Error::last_os_error())
+        }
+        let size = stat.st_size as u64;
+        let mut data = Vec::with_capacity(size as usize);
+        let mut inflight = std::collections::VecDeque::new();
+        let mut spare = Vec::new();
+        let mut next = 0;
+        loop {
+            // past the size, one read at a time
+            while inflight.len() < READ_FILE_DEPTH &&
+                (next < size || inflight.is_empty())
+            {
+                let buf = spare
+                    .pop()
+                    .unwrap_or_else(|| self.aligned_buf(READ_FILE_CHUNK));
+                let read = self.read_into_raw(fd, next, buf, None);
+                inflight.push_back((next, read));
+                next += READ_FILE_CHUNK as u64;
+            }
+            // the reads still in flight are dropped along with the future
+            let (off, read) = inflight.pop_front().unwrap();
+            let (res, buf) = read.await;
+            let mut got = res.map_err(std::io::Error::from_raw_os_error)?;
+            data.extend_from_slice(&buf[..got]);
+            spare.push(buf);
+            // short of the size, the rest of the chunk is read again (as by read_exact_at)
+            while got > 0 && got < READ_FILE_CHUNK && off + (got as u64) < size
+            {
+                let rest = self.aligned_buf(READ_FILE_CHUNK - got);
+                let (res, rest) =
+                    self.read_into_raw(fd, off + got as u64, rest, None).await;
+                let n = res.map_err(std::io::Error::from_raw_os_error)?;
+                if n == 0 {
+                    break
+                }
+                data.extend_from_slice(&rest[..n]);
+                got += n;
+            }
+            // otherwise a short read is the end of the file
+            if got < READ_FILE_CHUNK {
+                return Ok(data)
+            }
+        }
+    }
+
+    /// Same as `read`, but fails with `Error::QueueFull` instead of queueing the AIO when there is
+    /// no room for it right away: no permit left (see `AIOBuilder::max_pending`), or otherwise
+    /// as many pending AIOs as `AIOBuilder::max_events`.
+    /// Fails with `Error::Sys` once the AIO context has failed (see `fatal_error`).
+    pub fn try_read<'a>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        length: usize,
+        priority: Option<IoPriority>,
+    ) -> Result<AIOFuture<'a>, Error> {
+        let data = self.pool.get(length);
+        self.try_schedule(
+            fd.as_fd().as_raw_fd(),
+            offset,
+            Box::new(data),
+            priority,
+            abi::IOCmd::PRead,
+        )
+    }
+
+    /// Same as `write`, but fails with `Error::QueueFull` instead of queueing the AIO (see
+    /// `try_read`).
+    pub fn try_write<'a, B>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        data: B,
+        priority: Option<IoPriority>,
+    ) -> Result<AIOFuture<'a, B>, Error>
+    where
+        B: StableDeref<Target = [u8]> + Send + 'static,
+    {
+        self.try_schedule(
+            fd.as_fd().as_raw_fd(),
+            offset,
+            Box::new(WriteBuf(data)),
+            priority,
+            abi::IOCmd::PWrite,
+        )
+    }
+
+    /// Write the shared data, e.g., to replicate the same block to many offsets or files, where
+    /// all the concurrent writes are backed by the same allocation.
+    pub fn write_shared<'a>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        data: &Arc<[u8]>,
+        priority: Option<IoPriority>,
+    ) -> AIOFuture<'a, Arc<[u8]>> {
+        self.write(fd, offset, data.clone(), priority)
+    }
+
+    /// Same as `read`, with the given per-operation settings, e.g., flags, a deadline, a tag and
+    /// a retry policy at once.
+    pub fn read_with_options<'a>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        length: usize,
+        priority: Option<IoPriority>,
+        opts: &IoOptions,
+    ) -> AIOFuture<'a> {
+        let data = self.pool.get(length);
+        self.schedule(
+            fd.as_fd().as_raw_fd(),
+            offset,
+            Box::new(data),
+            priority,
+            abi::IOCmd::PRead,
+            OpOptions::with(opts),
+        )
+    }
+
+    /// Same as `write`, with the given per-operation settings (see `read_with_options`).
+    pub fn write_with_options<'a, B>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        data: B,
+        priority: Option<IoPriority>,
+        opts: &IoOptions,
+    ) -> AIOFuture<'a, B>
+    where
+        B: StableDeref<Target = [u8]> + Send + 'static,
+    {
+        self.schedule(
+            fd.as_fd().as_raw_fd(),
+            offset,
+            Box::new(WriteBuf(data)),
+            priority,
+            abi::IOCmd::PWrite,
+            OpOptions::with(opts),
+        )
+    }
+
+    /// Same as `read`, with the given per-operation flags (e.g., `RWFlags::NOWAIT`).
+    pub fn read_with_flags<'a>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        length: usize,
+        priority: Option<IoPriority>,
+        flags: RWFlags,
+    ) -> AIOFuture<'a> {
+        self.read_with_options(
+            fd,
+            offset,
+            length,
+            priority,
+            IoOptions::default().flags(flags),
+        )
+    }
+
+    /// Same as `write`, with the given per-operation flags (e.g., `RWFlags::DSYNC`).
+    pub fn write_with_flags<'a, B>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        data: B,
+        priority: Option<IoPriority>,
+        flags: RWFlags,
+    ) -> AIOFuture<'a, B>
+    where
+        B: StableDeref<Target = [u8]> + Send + 'static,
+    {
+        self.write_with_options(
+            fd,
+            offset,
+            data,
+            priority,
+            IoOptions::default().flags(flags),
+        )
+    }
+
+    /// Same as `read`, but to be done by the given time: the operations with a deadline are
+    /// submitted before any other, the earliest deadline first. Those finishing late are counted
+    /// by `get_deadline_misses`.
+    pub fn read_with_deadline<'a>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        length: usize,
+        priority: Option<IoPriority>,
+        deadline: Instant,
+    ) -> AIOFuture<'a> {
+        self.read_with_options(
+            fd,
+            offset,
+            length,
+            priority,
+            IoOptions::default().deadline(deadline),
+        )
+    }
+
+    /// Same as `write`, but to be done by the given time (see `read_with_deadline`).
+    pub fn write_with_deadline<'a, B>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        data: B,
+        priority: Option<IoPriority>,
+        deadline: Instant,
+    ) -> AIOFuture<'a, B>
+    where
+        B: StableDeref<Target = [u8]> + Send + 'static,
+    {
+        self.write_with_options(
+            fd,
+            offset,
+            data,
+            priority,
+            IoOptions::default().deadline(deadline),
+        )
+    }
+
+    /// Same as `read`, but tagged (e.g., by the subsystem scheduling it), so the AIOs of each
+    /// priority are submitted in turn by their tags, sharing the bytes in proportion to the
+    /// weights of the tags (see `set_tag_weight`), rather than in the order of scheduling. The
+    /// AIOs without a tag go with the tag 0.
+    pub fn read_with_tag<'a>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        length: usize,
+        priority: Option<IoPriority>,
+        tag: u32,
+    ) -> AIOFuture<'a> {
+        self.read_with_options(
+            fd,
+            offset,
+            length,
+            priority,
+            IoOptions::default().tag(tag),
+        )
+    }
+
+    /// Same as `write`, but tagged (see `read_with_tag`).
+    pub fn write_with_tag<'a, B>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        data: B,
+        priority: Option<IoPriority>,
+        tag: u32,
+    ) -> AIOFuture<'a, B>
+    where
+        B: StableDeref<Target = [u8]> + Send + 'static,
+    {
+        self.write_with_options(
+            fd,
+            offset,
+            data,
+            priority,
+            IoOptions::default().tag(tag),
+        )
+    }
+
+    /// Same as `read`, but retried as told by the given policy, instead of that of the
+    /// AIOManager (see `AIOBuilder::retry_policy`).
+    pub fn read_with_retry<'a>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        length: usize,
+        priority: Option<IoPriority>,
+        retry: RetryPolicy,
+    ) -> AIOFuture<'a> {
+        self.read_with_options(
+            fd,
+            offset,
+            length,
+            priority,
+            IoOptions::default().retry(retry),
+        )
+    }
+
+    /// Same as `write`, but retried as told by the given policy (see `read_with_retry`).
+    pub fn write_with_retry<'a, B>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        data: B,
+        priority: Option<IoPriority>,
+        retry: RetryPolicy,
+    ) -> AIOFuture<'a, B>
+    where
+        B: StableDeref<Target = [u8]> + Send + 'static,
+    {
+        self.write_with_options(
+            fd,
+            offset,
+            data,
+            priority,
+            IoOptions::default().retry(retry),
+        )
+    }
+
+    /// Read into multiple buffers (of the given lengths) from consecutive file data with a single
+    /// operation (like `preadv(2)`).
+    pub fn read_vectored<'a>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        lengths: &[usize],
+        priority: Option<IoPriority>,
+    ) -> AIOFuture<'a, Vec<Box<[u8]>>> {
+        self.read_vectored_raw(
+            fd.as_fd().as_raw_fd(),
+            offset,
+            lengths,
+            priority,
+        )
+    }
+
+    /// Write multiple buffers to consecutive file data with a single operation (like
+    /// `pwritev(2)`).
+    pub fn write_vectored<'a>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        data: Vec<Box<[u8]>>,
+        priority: Option<IoPriority>,
+    ) -> AIOFuture<'a, Vec<Box<[u8]>>> {
+        self.write_vectored_raw(fd.as_fd().as_raw_fd(), offset, data, priority)
+    }
+
+    /// Flush both the data and the metadata of the file to the device (like `fsync(2)`).
+    pub fn fsync<'a>(&self, fd: &'a impl AsFd) -> AIOFuture<'a> {
+        self.fsync_raw(fd.as_fd().as_raw_fd())
+    }
+
+    /// Flush the data of the file to the device, without flushing the metadata unless it is
+    /// required to retrieve the data (like `fdatasync(2)`).
+    pub fn fdatasync<'a>(&self, fd: &'a impl AsFd) -> AIOFuture<'a> {
+        self.fdatasync_raw(fd.as_fd().as_raw_fd())
+    }
+
+    /// Same as `read`, but into a buffer aligned as configured by `AIOBuilder::alignment`, for
+    /// the files opened with `O_DIRECT`.
+    pub fn read_aligned<'a>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        length: usize,
+        priority: Option<IoPriority>,
+    ) -> AIOFuture<'a, AlignedBuf> {
+        self.read_into(fd, offset, self.aligned_buf(length), priority)
+    }
+
+    /// Allocate a buffer aligned as configured by `AIOBuilder::alignment`.
+    pub(crate) fn aligned_buf(&self, length: usize) -> AlignedBuf {
+        AlignedBuf::new(length, self.alignment).unwrap_or_else(|_| {
+            std::alloc::handle_alloc_error(
+                std::alloc::Layout::from_size_align(length, self.alignment)
+                    .unwrap(),
+            )
+        })
+    }
+
+    /// Same as `read_trimmed`, but for any offset and length on the files opened with
+    /// `O_DIRECT`: the range is widened to the alignment configured by `AIOBuilder::alignment`
+    /// and read into an aligned bounce buffer, from which the requested bytes are copied.
+    pub async fn read_unaligned(
+        &self,
+        fd: &impl AsFd,
+        offset: u64,
+        length: usize,
+        priority: Option<IoPriority>,
+    ) -> AIOResult<Vec<u8>> {
+        let align = self.alignment as u64;
+        let start = offset & !(align - 1);
+        let end = match offset
+            .checked_add(length as u64)
+            .and_then(|end| end.checked_add(align - 1))
+        {
+            Some(end) => end & !(align - 1),
+            None => return (Err(libc::EINVAL), Vec::new()),
+        };
+        let skip = (offset - start) as usize;
+        let (res, buf) = self
+            .read_aligned(fd, start, (end - start) as usize, priority)
+            .await;
+        let n = match res {
+            Ok(n) => n.saturating_sub(skip).min(length),
+            Err(_) => length,
+        };
+        // the requested part of the bounce buffer, as `read` gives back its buffer on an error
+        (res.map(|_| n), buf[skip..skip + n].to_vec())
+    }
+
+    /// Same as `write`, but from an aligned buffer, for the files opened with `O_DIRECT`.
+    pub fn write_aligned<'a>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        data: AlignedBuf,
+        priority: Option<IoPriority>,
+    ) -> AIOFuture<'a, AlignedBuf> {
+        self.write(fd, offset, data, priority)
+    }
+
+    /// Same as `read`, but on a raw file descriptor, which the caller has to keep open until the
+    /// operation is finished.
+    pub fn read_raw(
+        &self,
+        fd: RawFd,
+        offset: u64,
+        length: usize,
+        priority: Option<IoPriority>,
+    ) -> AIOFuture<'static> {
+        let data = self.pool.get(length);
+        self.schedule(
+            fd,
+            offset,
+            Box::new(data),
+            priority,
+            abi::IOCmd::PRead,
+            OpOptions::default(),
+        )
+    }
+
+    /// Same as `read_trimmed`, but on a raw file descriptor, which the caller has to keep open
+    /// until the operation is finished.
+    pub fn read_trimmed_raw(
+        &self,
+        fd: RawFd,
+        offset: u64,
+        length: usize,
+        priority: Option<IoPriority>,
+    ) -> AIOFuture<'static, Vec<u8>> {
+        self.schedule(
+            fd,
+            offset,
+            Box::new(TrimmedBuf(vec![0; length])),
+            priority,
+            abi::IOCmd::PRead,
+            OpOptions::default(),
+        )
+    }
+
+    /// Same as `read_into`, but on a raw file descriptor, which the caller has to keep open until
+    /// the operation is finished.
+    pub fn read_into_raw<B>(
+        &self,
+        fd: RawFd,
+        offset: u64,
+        buf: B,
+        priority: Option<IoPriority>,
+    ) -> AIOFuture<'static, B>
+    where
+        B: StableDeref<Target = [u8]> + DerefMut + Send + 'static,
+    {
+        self.schedule(
+            fd,
+            offset,
+            Box::new(ReadBuf::new(buf)),
+            priority,
+            abi::IOCmd::PRead,
+            OpOptions::default(),
+        )
+    }
+
+    /// Same as `write`, but on a raw file descriptor, which the caller has to keep open until the
+    /// operation is finished.
+    pub fn write_raw<B>(
+        &self,
+        fd: RawFd,
+        offset: u64,
+        data: B,
+        priority: Option<IoPriority>,
+    ) -> AIOFuture<'static, B>
+    where
+        B: StableDeref<Target = [u8]> + Send + 'static,
+    {
+        self.schedule(
+            fd,
+            offset,
+            Box::new(WriteBuf(data)),
+            priority,
+            abi::IOCmd::PWrite,
+            OpOptions::default(),
+        )
+    }
+
+    /// Same as `read_vectored`, but on a raw file descriptor, which the caller has to keep open
+    /// until the operation is finished.
+    pub fn read_vectored_raw(
+        &self,
+        fd: RawFd,
+        offset: u64,
+        lengths: &[usize],
+        priority: Option<IoPriority>,
+    ) -> AIOFuture<'static, Vec<Box<[u8]>>> {
+        let bufs = lengths.iter().map(|len| self.pool.get(*len)).collect();
+        let data = Box::new(IOVecBuffer::new(bufs));
+        self.schedule(
+            fd,
+            offset,
+            data,
+            priority,
+            abi::IOCmd::PReadV,
+            OpOptions::default(),
+        )
+    }
+
+    /// Same as `write_vectored`, but on a raw file descriptor, which the caller has to keep open
+    /// until the operation is finished.
+    pub fn write_vectored_raw(
+        &self,
+        fd: RawFd,
+        offset: u64,
+        data: Vec<Box<[u8]>>,
+        priority: Option<IoPriority>,
+    ) -> AIOFuture<'static, Vec<Box<[u8]>>> {
+        let data = Box::new(IOVecBuffer::new(data));
+        self.schedule(
+            fd,
+            offset,
+            data,
+            priority,
+            abi::IOCmd::PWriteV,
+            OpOptions::default(),
+        )
+    }
+
+    /// Same as `fsync`, but on a raw file descriptor.
+    pub fn fsync_raw(&self, fd: RawFd) -> AIOFuture<'static> {
+        self.sync(fd, abi::IOCmd::FSync, OpOptions::default())
+    }
+
+    /// Same as `fdatasync`, but on a raw file descriptor.
+    pub fn fdatasync_raw(&self, fd: RawFd) -> AIOFuture<'static> {
+        self.sync(fd, abi::IOCmd::FdSync, OpOptions::default())
+    }
+
+    /// Wait for the fd (e.g., an eventfd, a pipe or a socket) to become ready for any of the
+    /// events (`libc::POLLIN`, `libc::POLLOUT`, ...) through the same context as the other
+    /// operations (`IOCB_CMD_POLL`, Linux 4.18 or later), resolving to the events it is ready
+    /// for. Dropping the future cancels the wait, except with `Backend::ThreadPool`, where it
+    /// takes up a worker thread until the fd becomes ready.
+    pub fn poll_fd<'a>(&self, fd: &'a impl AsFd, events: i16) -> AIOFuture<'a> {
+        self.poll_fd_raw(fd.as_fd().as_raw_fd(), events)
+    }
+
+    /// Same as `poll_fd`, but on a raw file descriptor.
+    pub fn poll_fd_raw(&self, fd: RawFd, events: i16) -> AIOFuture<'static> {
+        let data = Box::new(PollEvents(events));
+        let opts = OpOptions::default();
+        self.schedule(fd, 0, data, None, abi::IOCmd::Poll, opts)
+    }
+
+    /// Schedule an operation doing nothing (`IOCB_CMD_NOOP`), which resolves to 0 once it has
+    /// gone through the scheduling, the submission and the reaping, to measure the latency of
+    /// the framework (and the queueing in the engine) apart from that of the device, e.g., for
+    /// health checks. The kernel AIO rejects it, so with `Backend::Libaio` it is finished at
+    /// the submission instead.
+    pub fn noop(&self) -> AIOFuture<'static> {
+        self.sync(-1, abi::IOCmd::Noop, OpOptions::default())
+    }
+
+    /// Schedule a copy of the iocb as is, for the fields (e.g., `aio_rw_flags`, `aio_flags` or
+    /// `aio_resfd`) or the opcodes the other operations do not cover, its `aio_data` being
+    /// replaced by the id of the AIO. Its priority (`aio_reqprio`) is replaced as well if
+    /// `priority` is given, and its `aio_resfd` by the eventfd enabled by `AIOBuilder::eventfd`,
+    /// if any. It resolves to the `res` of its completion, with an empty buffer.
+    ///
+    /// # Safety
+    ///
+    /// The file descriptor and the memory the iocb refers to (the buffer at `aio_buf`, or the
+    /// iovecs there and their buffers) must stay valid until the future resolves, even if it is
+    /// dropped meanwhile, as the operation may then still be in flight.
+    pub unsafe fn submit_raw(
+        &self,
+        iocb: &IOCb,
+        priority: Option<IoPriority>,
+    ) -> AIOFuture<'static> {
+        let data = Box::new(RawBuf(iocb.aio_buf, iocb.aio_nbytes));
+        let fd = iocb.aio_fildes as RawFd;
+        let opts = OpOptions::default();
+        // the opcode is filled in below, without checking the alignment
+        let mut aio = match self.new_aio(
+            fd,
+            iocb.aio_offset,
+            data,
+            priority,
+            abi::IOCmd::Noop,
+            opts,
+        ) {
+            Ok(aio) => aio,
+            Err(data) => return self.fail(data, libc::EINVAL),
+        };
+        let raw = unsafe { &mut **aio.iocb.get_mut() };
+        raw.aio_lio_opcode = iocb.aio_lio_opcode;
+        raw.aio_rw_flags = iocb.aio_rw_flags;
+        if priority.is_none() {
+            raw.aio_reqprio = iocb.aio_reqprio
+        }
+        raw.aio_flags |= iocb.aio_flags;
+        raw.aio_resfd = iocb.aio_resfd;
+        self.scheduler_in.schedule(
+            aio,
+            prio::lane(priority),
+            false,
+            &self.notifier,
+        )
+    }
+
+    /// Release the range of the block device (`BLKDISCARD`), or punch a hole into that of the
+    /// file (`fallocate(2)` with `FALLOC_FL_PUNCH_HOLE`, keeping its size), on a helper thread.
+    /// Unlike an AIO, it is not ordered after the operations on the file descriptor scheduled
+    /// before it, so they should be awaited first.
+    pub fn discard<'a>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        len: u64,
+    ) -> AIOFuture<'a> {
+        self.discard_raw(fd.as_fd().as_raw_fd(), offset, len)
+    }
+
+    /// Same as `discard`, but on a raw file descriptor.
+    pub fn discard_raw(
+        &self,
+        fd: RawFd,
+        offset: u64,
+        len: u64,
+    ) -> AIOFuture<'static> {
+        self.run_blocking(
+            fd,
+            Vec::new(),
+            Box::new(move || helper::discard(fd, offset, len)),
+        )
+    }
+
+    /// Allocate the range of the file (`fallocate(2)`, which extends it if needed) on a helper
+    /// thread, e.g., to preallocate a segment file, so the writes into it never fail for the
+    /// lack of space. Not ordered after the operations scheduled before it (see `discard`).
+    pub fn allocate<'a>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        len: u64,
+    ) -> AIOFuture<'a> {
+        self.allocate_raw(fd.as_fd().as_raw_fd(), offset, len)
+    }
+
+    /// Same as `allocate`, but on a raw file descriptor.
+    pub fn allocate_raw(
+        &self,
+        fd: RawFd,
+        offset: u64,
+        len: u64,
+    ) -> AIOFuture<'static> {
+        self.run_blocking(
+            fd,
+            Vec::new(),
+            Box::new(move || helper::allocate(fd, offset, len)),
+        )
+    }
+
+    /// Truncate (or extend) the file to the length (`ftruncate(2)`) on a helper thread, e.g.,
+    /// to drop the tail of a log after a checkpoint. Not ordered after the operations scheduled
+    /// before it (see `discard`).
+    pub fn truncate<'a>(&self, fd: &'a impl AsFd, len: u64) -> AIOFuture<'a> {
+        self.truncate_raw(fd.as_fd().as_raw_fd(), len)
+    }
+
+    /// Same as `truncate`, but on a raw file descriptor.
+    pub fn truncate_raw(&self, fd: RawFd, len: u64) -> AIOFuture<'static> {
+        self.run_blocking(
+            fd,
+            Vec::new(),
+            Box::new(move || helper::truncate(fd, len)),
+        )
+    }
+
+    /// Advise the kernel of how the range of the file (up to its end if `len` is 0) is to be
+    /// accessed (`posix_fadvise(2)`), e.g., to drop the pages cached by streaming writes, or to
+    /// read ahead. Carried out on a helper thread once the AIOs on the range pending by then
+    /// are finished (so the written pages are dropped after they are written).
+    pub fn advise<'a>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        len: u64,
+        advice: Advice,
+    ) -> AIOFuture<'a> {
+        self.advise_raw(fd.as_fd().as_raw_fd(), offset, len, advice)
+    }
+
+    /// Same as `advise`, but on a raw file descriptor.
+    pub fn advise_raw(
+        &self,
+        fd: RawFd,
+        offset: u64,
+        len: u64,
+        advice: Advice,
+    ) -> AIOFuture<'static> {
+        let after = self.notifier.pending_on(fd, offset, len);
+        let op = move || helper::advise(fd, offset, len, advice);
+        self.run_blocking(fd, after, Box::new(op))
+    }
+
+    /// Read the range of the file ahead into the page cache (`readahead(2)`) on a helper thread,
+    /// e.g., to warm up the ranges a scan reads next, without any buffer.
+    pub fn readahead<'a>(
+        &self,
+        fd: &'a impl AsFd,
+        offset: u64,
+        len: u64,
+    ) -> AIOFuture<'a> {
+        self.readahead_raw(fd.as_fd().as_raw_fd(), offset, len)
+    }
+
+    /// Same as `readahead`, but on a raw file descriptor.
+    pub fn readahead_raw(
+        &self,
+        fd: RawFd,
+        offset: u64,
+        len: u64,
+    ) -> AIOFuture<'static> {
+        let op = move || helper::readahead(fd, offset, len);
+        self.run_blocking(fd, Vec::new(), Box::new(op))
+    }
+
+    /// Copy `len` bytes from the source file at `src_offset` to the destination file at
+    /// `dst_offset` within the kernel (`copy_file_range(2)`, sharing the extents instead where
+    /// the file system supports reflinks) on a helper thread, e.g., to move the live data of
+    /// segments during compaction. Carried out once the AIOs on both ranges pending by then are
+    /// finished. Resolves to the bytes copied, which are fewer than `len` if the source ends
+    /// before the range does.
+    pub fn copy_range<'a>(
+        &self,
+        src: &'a impl AsFd,
+        src_offset: u64,
+        dst: &'a impl AsFd,
+        dst_offset: u64,
+        len: u64,
+    ) -> AIOFuture<'a> {
+        self.copy_range_raw(
+            src.as_fd().as_raw_fd(),
+            src_offset,
+            dst.as_fd().as_raw_fd(),
+            dst_offset,
+            len,
+        )
+    }
+
+    /// Same as `copy_range`, but on raw file descriptors.
+    pub fn copy_range_raw(
+        &self,
+        src_fd: RawFd,
+        src_offset: u64,
+        dst_fd: RawFd,
+        dst_offset: u64,
+        len: u64,
+    ) -> AIOFuture<'static> {
+        let mut after = self.notifier.pending_on(src_fd, src_offset, len);
+        after.extend(self.notifier.pending_on(dst_fd, dst_offset, len));
+        let op = move || {
+            helper::copy_range(src_fd, src_offset, dst_fd, dst_offset, len)
+        };
+        self.run_blocking(dst_fd, after, Box::new(op))
+    }
+
+    /// Open the file at the path relative to the directory (`openat(2)` with the flags, e.g.,
+    /// `libc::O_RDWR | libc::O_DIRECT`, always adding `O_CLOEXEC`, and the mode for a file it
+    /// creates) on a helper thread, so the async code never blocks on the file system. Counts as
+    /// pending on the manager, and fails with `ESHUTDOWN` once it is shut down, like an AIO.
+    pub fn open_at<'a>(
+        &self,
+        dir: &'a impl AsFd,
+        path: impl AsRef<std::path::Path>,
+        flags: i32,
+        mode: u32,
+    ) -> BlockingFuture<'a, OwnedFd> {
+        self.open_at_raw(dir.as_fd().as_raw_fd(), path, flags, mode)
+    }
+
+    /// Same as `open_at`, but relative to a raw directory file descriptor (`libc::AT_FDCWD` for
+    /// the current directory).
+    pub fn open_at_raw(
+        &self,
+        dirfd: RawFd,
+        path: impl AsRef<std::path::Path>,
+        flags: i32,
+        mode: u32,
+    ) -> BlockingFuture<'static, OwnedFd> {
+        let path = match helper::c_path(path.as_ref()) {
+            Some(path) => path,
+            None => return self.fail_blocking(libc::EINVAL),
+        };
+        self.run_blocking_with(dirfd, move || {
+            helper::open_at(dirfd, &path, flags, mode as libc::mode_t)
+        })
+    }
+
+    /// Rename the file at the old path relative to the old directory to the new path relative
+    /// to the new directory (`renameat(2)`) on a helper thread, atomically replacing the file
+    /// there if any, e.g., to commit a file written and synced under a temporary name (then
+    /// `fsync` the directory to make the rename durable).
+    pub fn rename<'a>(
+        &self,
+        old_dir: &'a impl AsFd,
+        old_path: impl AsRef<std::path::Path>,
+        new_dir: &'a impl AsFd,
+        new_path: impl AsRef<std::path::Path>,
+    ) -> BlockingFuture<'a, ()> {
+        self.rename_raw(
+            old_dir.as_fd().as_raw_fd(),
+            old_path,
+            new_dir.as_fd().as_raw_fd(),
+            new_path,
+        )
+    }
+
+    /// Same as `rename`, but relative to raw directory file descriptors (see `open_at_raw`).
+    pub fn rename_raw(
+        &self,
+        old_dirfd: RawFd,
+        old_path: impl AsRef<std::path::Path>,
+        new_dirfd: RawFd,
+        new_path: impl AsRef<std::path::Path>,
+    ) -> BlockingFuture<'static, ()> {
+        let (old_path, new_path) = match (
+            helper::c_path(old_path.as_ref()),
+            helper::c_path(new_path.as_ref()),
+        ) {
+            (Some(old_path), Some(new_path)) => (old_path, new_path),
+            _ => return self.fail_blocking(libc::EINVAL),
+        };
+        self.run_blocking_with(new_dirfd, move || {
+            helper::rename(old_dirfd, &old_path, new_dirfd, &new_path)
+        })
+    }
+
+    /// Remove the file at the path relative to the directory (`unlinkat(2)`) on a helper thread.
+    pub fn unlink<'a>(
+        &self,
+        dir: &'a impl AsFd,
+        path: impl AsRef<std::path::Path>,
+    ) -> BlockingFuture<'a, ()> {
+        self.unlink_raw(dir.as_fd().as_raw_fd(), path)
+    }
+
+    /// Same as `unlink`, but relative to a raw directory file descriptor (see `open_at_raw`).
+    pub fn unlink_raw(
+        &self,
+        dirfd: RawFd,
+        path: impl AsRef<std::path::Path>,
+    ) -> BlockingFuture<'static, ()> {
+        let path = match helper::c_path(path.as_ref()) {
+            Some(path) => path,
+            None => return self.fail_blocking(libc::EINVAL),
+        };
+        self.run_blocking_with(dirfd, move || helper::unlink(dirfd, &path))
+    }
+
+    /// Query the metadata of the open file (`fstat(2)`) on a helper thread.
+    pub fn metadata<'a>(
+        &self,
+        fd: &'a impl AsFd,
+    ) -> BlockingFuture<'a, std::fs::Metadata> {
+        self.metadata_raw(fd.as_fd().as_raw_fd())
+    }
+
+    /// Same as `metadata`, but on a raw file descriptor.
+    pub fn metadata_raw(
+        &self,
+        fd: RawFd,
+    ) -> BlockingFuture<'static, std::fs::Metadata> {
+        self.run_blocking_with(fd, move || helper::metadata(fd))
+    }
+
+    /// Close the file (`close(2)`) on a helper thread, as closing may block (e.g., to flush it
+    /// on a network file system), resolving to the error reported, if any. The operations on it
+    /// should be finished by then. The file is closed even if the future is dropped.
+    pub fn close(&self, fd: OwnedFd) -> BlockingFuture<'static, ()> {
+        let raw = fd.as_raw_fd();
+        self.run_blocking_with(raw, move || helper::close(fd))
+    }
+
+    /// Carry out the blocking operation resolving to a value (or to a negative errno) on the
+    /// helper threads.
+    fn run_blocking_with<T: Send + 'static>(
+        &self,
+        fd: RawFd,
+        op: impl FnOnce() -> Result<T, i64> + Send + 'static,
+    ) -> BlockingFuture<'static, T> {
+        let slot = Arc::new(Mutex::new(None));
+        let out = slot.clone();
+        let op = move || match op() {
+            Ok(v) => {
+                *out.lock() = Some(v);
+                0
+            }
+            Err(res) => res,
+        };
+        BlockingFuture::new(
+            self.run_blocking(fd, Vec::new(), Box::new(op)),
+            slot,
+        )
+    }
+
+    fn fail_blocking<T>(&self, errno: i32) -> BlockingFuture<'static, T> {
+        let aio = self.fail(Box::new(Box::<[u8]>::default()), errno);
+        BlockingFuture::new(aio, Default::default())
+    }
+
+    /// Carry out the blocking operation on the fd on the helper threads once the AIOs `after`
+    /// are finished, as an AIO.
+    fn run_blocking(
+        &self,
+        fd: RawFd,
+        after: Vec<u64>,
+        op: Box<dyn FnOnce() -> i64 + Send>,
+    ) -> AIOFuture<'static> {
+        let data = Box::new(Box::<[u8]>::default());
+        // never submitted again, as it is never submitted to the kernel
+        let opts = OpOptions::retry(RetryPolicy::never());
+        let aio = match self.new_aio(fd, 0, data, None, abi::IOCmd::Noop, opts)
+        {
+            Ok(aio) => aio,
+            Err(data) => return self.fail(data, libc::EINVAL),
+        };
+        let id = aio.id;
+        let fut = AIOFuture::new(self.notifier.clone(), id);
+        let intake = self.notifier.intake.read();
+        self.notifier
+            .register_notify(id, AIOState::Pending(aio, false));
+        self.notifier.npending.fetch_add(1, Ordering::Relaxed);
+        if self.notifier.closed.load(Ordering::Acquire) {
+            self.notifier.retire();
+            let res = self.notifier.shutdown_res();
+            let e = self.notifier.waiting.get(id).unwrap();
+            AIONotifier::resolve(e, res, &mut Wakers::default());
+            return fut
+        }
+        drop(intake);
+        let mut helper = self.helper.lock();
+        if helper.is_none() {
+            match Helper::spawn(self.notifier.clone()) {
+                Ok(h) => *helper = Some(h),
+                Err(e) => {
+                    diag!(error, "failed to spawn the helper threads: {}", e);
+                    let errno = e.raw_os_error().unwrap_or(libc::EAGAIN);
+                    self.notifier.finish(id, -errno as i64);
+                    return fut
+                }
+            }
+        }
+        self.notifier.counters.submitted(1);
+        self.notifier.mark_submitted(&[(fd, id)]);
+        helper.as_ref().unwrap().run(id, after, op);
+        fut
+    }
+
+    fn sync(
+        &self,
+        fd: RawFd,
+        opcode: abi::IOCmd,
+        opts: OpOptions,
+    ) -> AIOFuture<'static> {
+        let data = Box::new(Box::<[u8]>::default());
+        self.schedule(fd, 0, data, None, opcode, opts)
+    }
+
+    fn schedule<B>(
+        &self,
+        fd: RawFd,
+        offset: u64,
+        data: Box<dyn AIOBuffer>,
+        priority: Option<IoPriority>,
+        opcode: abi::IOCmd,
+        opts: OpOptions,
+    ) -> AIOFuture<'static, B> {
+        let admitted = opts.admitted;
+        match self.new_aio(fd, offset, data, priority, opcode, opts) {
+            Ok(aio) => self.scheduler_in.schedule(
+                aio,
+                prio::lane(priority),
+                admitted,
+                &self.notifier,
+            ),
+            Err(data) => {
+                // give back the permit taken by try_schedule
+                if let (true, Some(permits)) =
+                    (admitted, self.notifier.permits.as_ref())
+                {
+                    permits.release()
                 }
+                self.fail(data, libc::EINVAL)
             }
-        }));
-        Ok(())
+        }
     }
 
-    pub fn read(
+    /// Set up the AIO of an operation, or give back its buffer if the priority is invalid.
+    fn new_aio(
         &self,
         fd: RawFd,
         offset: u64,
-        length: usize,
-        priority: Option<u16>,
-    ) -> AIOFuture {
-        let priority = priority.unwrap_or(0);
-        let mut data = Vec::new();
-        data.resize(length, 0);
-        let data = data.into_boxed_slice();
-        let aio = AIO::new(
+        data: Box<dyn AIOBuffer>,
+        priority: Option<IoPriority>,
+        opcode: abi::IOCmd,
+        opts: OpOptions,
+    ) -> Result<AIO, Box<dyn AIOBuffer>> {
+        if let Err(e) = self.check_alignment(fd, offset, &*data, &opcode) {
+            diag!(debug, "aio on fd {} rejected: {}", fd, e);
+            return Err(data)
+        }
+        // the kernel ignores aio_reqprio unless told otherwise
+        let (prio, flags) = match priority.map(IoPriority::to_raw) {
+            Some(Some(prio)) => (prio, abi::IOCB_FLAG_IOPRIO),
+            Some(None) => return Err(data),
+            None => (0, 0),
+        };
+        let mut aio = AIO::new(
+            &self.notifier.iocbs,
             self.scheduler_in.next_id(),
             fd,
             offset,
             data,
-            priority,
-            0,
-            abi::IOCmd::PRead,
+            opcode,
         );
-        self.scheduler_in.schedule(aio, &self.notifier)
+        aio.file = opts.file;
+        aio.deadline = opts.deadline;
+        aio.retry = opts.retry.unwrap_or(self.retry);
+        aio.tag = opts.tag;
+        if self.notifier.fd_stats.is_some() ||
+            self.notifier.slow_op_threshold.is_some() ||
+            Tenants::is_tenant(opts.tag)
+        {
+            aio.scheduled = Some(Instant::now());
+        }
+        let iocb = unsafe { &mut **aio.iocb.get_mut() };
+        iocb.aio_reqprio = prio;
+        iocb.aio_flags = flags;
+        iocb.aio_rw_flags = opts.rw_flags.bits();
+        if let Some(barriers) = self.notifier.barriers.as_ref() {
+            aio.barrier = Some((barriers.clone(), barriers.enter(fd)));
+        }
+        if let Some((order, _)) = self.notifier.order.as_ref() {
+            aio.order = Some((order.clone(), order.enter_last(fd)));
+        }
+        Ok(aio)
     }
 
-    pub fn write(
+    fn try_schedule<B>(
         &self,
         fd: RawFd,
         offset: u64,
-        data: Box<[u8]>,
-        priority: Option<u16>,
-    ) -> AIOFuture {
-        let priority = priority.unwrap_or(0);
-        let aio = AIO::new(
-            self.scheduler_in.next_id(),
-            fd,
-            offset,
-            data,
-            priority,
-            0,
-            abi::IOCmd::PWrite,
-        );
-        self.scheduler_in.schedule(aio, &self.notifier)
+        data: Box<dyn AIOBuffer>,
+        priority: Option<IoPriority>,
+        opcode: abi::IOCmd,
+    ) -> Result<AIOFuture<'static, B>, Error> {
+        if let Some(errno) = self.fatal_error() {
+            return Err(Error::Sys(errno))
+        }
+        self.check_alignment(fd, offset, &*data, &opcode)?;
+        let admitted = match self.notifier.permits.as_ref() {
+            Some(permits) => permits.acquire(None),
+            None => self.get_npending() < self.max_events,
+        };
+        if !admitted {
+            return Err(Error::QueueFull)
+        }
+        let opts = OpOptions::admitted();
+        Ok(self.schedule(fd, offset, data, priority, opcode, opts))
+    }
+
+    /// Check a read or write against the alignment required on the fd, if any (see
+    /// `AIOBuilder::validate_alignment`).
+    fn check_alignment(
+        &self,
+        fd: RawFd,
+        offset: u64,
+        data: &dyn AIOBuffer,
+        opcode: &abi::IOCmd,
+    ) -> Result<(), Error> {
+        let fd_alignment = match self.fd_alignment.as_ref() {
+            Some(fd_alignment) => fd_alignment,
+            None => return Ok(()),
+        };
+        if !matches!(opcode, abi::IOCmd::PRead | abi::IOCmd::PWrite) {
+            return Ok(())
+        }
+        let required = match fd_alignment.lock().get(&fd) {
+            Some(v) => *v,
+            None => {
+                let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+                if flags < 0 || flags & libc::O_DIRECT == 0 {
+                    return Ok(())
+                }
+                self.alignment
+            }
+        };
+        let (buf, len) = data.iocb_buf();
+        match [offset, buf, len]
+            .iter()
+            .copied()
+            .find(|v| v % required as u64 != 0)
+        {
+            Some(got) => Err(Error::Misaligned { required, got }),
+            None => Ok(()),
+        }
+    }
+
+    /// Resolve the operation with the error right away, without submitting it.
+    fn fail<B>(
+        &self,
+        data: Box<dyn AIOBuffer>,
+        errno: i32,
+    ) -> AIOFuture<'static, B> {
+        let id = self.scheduler_in.next_id();
+        self.notifier
+            .register_notify(id, AIOState::Done((Err(errno), data)));
+        AIOFuture::new(self.notifier.clone(), id)
+    }
+
+    /// Return a buffer (e.g., of a finished read) to the pool enabled by
+    /// `AIOBuilder::buffer_pool`, for the subsequent reads.
+    pub fn recycle(&self, buf: Box<[u8]>) {
+        self.pool.put(buf)
     }
 
     /// Get a copy of the current data in the buffer.
     pub fn copy_data(&self, aio_id: u64) -> Option<Vec<u8>> {
-        let w = self.notifier.waiting.lock();
-        w.get(&aio_id).and_then(|state| {
-            Some(
-                match state {
-                    AIOState::FutureInit(aio, _) => {
-                        &**aio.data.as_ref().unwrap()
-                    }
-                    AIOState::FuturePending(aio, _, _) => {
-                        &**aio.data.as_ref().unwrap()
+        self.notifier.waiting.get(aio_id).map(|state| {
+            match &*state {
+                AIOState::Pending(aio, _) => aio.data.as_deref().unwrap(),
+                AIOState::Done(res) => res.1.as_ref(),
+            }
+            .to_vec()
+        })
+    }
+
+    /// Cancel an operation by its id: a queued operation is never submitted, while the kernel is
+    /// asked to abort an in-flight one. The future then resolves to `ECANCELED`, unless the
+    /// operation has already completed (many files do not support aborting in-flight IOs).
+    /// Returns false if the operation is unknown or already finished.
+    pub fn cancel(&self, aio_id: u64) -> bool {
+        self.notifier.cancel(aio_id)
+    }
+
+    /// The progress of an operation by its id (see `AIOFuture::id`).
+    pub fn status(&self, aio_id: u64) -> OpStatus {
+        match self.notifier.waiting.get(aio_id).as_deref() {
+            Some(AIOState::Pending(aio, _)) if aio.submitted => {
+                OpStatus::Submitted
+            }
+            Some(AIOState::Pending(..)) => OpStatus::Queued,
+            Some(AIOState::Done(_)) => OpStatus::Done,
+            None => OpStatus::Unknown,
+        }
+    }
+
+    /// The eventfd signaled upon every completed IO, if enabled by `AIOBuilder::eventfd`. Its
+    /// counter is increased by one per completion.
+    pub fn eventfd(&self) -> Option<RawFd> {
+        self.notifier.eventfd.as_ref().map(|efd| efd.0)
+    }
+}
+
+impl AIOManager {
+    /// A handle to schedule the AIOs from other threads or tasks, which does not keep the
+    /// AIOManager running.
+    pub fn handle(&self) -> AIOManagerHandle {
+        self.handle.clone()
+    }
+
+    /// Submit the scheduled AIOs and reap up to `max` finished ones, waiting for at least one of
+    /// them for at most `timeout` (forever if `None`). Returns the number of reaped AIOs. Only
+    /// available to an AIOManager built by `AIOBuilder::build_manual` (otherwise it does nothing
+    /// and returns 0), which should be driven until `get_npending()` drops to 0 before dropping
+    /// it.
+    pub fn drive(
+        &self,
+        max: usize,
+        timeout: Option<std::time::Duration>,
+    ) -> usize {
+        let mut m = match self.driver.as_ref() {
+            Some(Driver::Manual(m)) => m.lock(),
+            _ => return 0,
+        };
+        loop {
+            let nacc = m.scheduler_out.submit(&self.notifier);
+            m.ongoing += nacc;
+            if nacc == 0 {
+                break
+            }
+        }
+        if m.ongoing == 0 || max == 0 {
+            return 0
+        }
+        let mut timespec = timeout.map(to_timespec);
+        let ret = self.notifier.reap(1, max, timespec.as_mut());
+        if ret < 0 {
+            m.scheduler_out.fail(&self.notifier, -ret);
+            m.ongoing = 0;
+            return 0
+        }
+        m.ongoing -= ret as usize;
+        ret as usize
+    }
+
+    /// Stop the AIOManager gracefully: wait for all the scheduled AIOs to finish (resuming the
+    /// submission if paused), then stop the background threads (or task). An AIOManager built
+    /// by `AIOBuilder::build_manual` is driven (blocking) by the call until then. Fails if any of
+    /// the threads has panicked.
+    pub async fn shutdown(mut self) -> Result<(), Error> {
+        self.resume();
+        if let Some(Driver::Manual(_)) = self.driver {
+            while self.get_npending() > 0 {
+                self.drive(self.max_events, None);
+            }
+        }
+        std::future::poll_fn(|cx| self.notifier.poll_drained(cx)).await;
+        self.stop()
+    }
+
+    /// Stop the AIOManager right away, cancelling all the scheduled AIOs: their futures resolve
+    /// with `ECANCELED`, immediately for those not yet submitted, or as soon as the kernel
+    /// cancels the in-flight ones (which just finish if the kernel cannot cancel them, as for
+    /// most files). The background threads are not waited for, and quit on their own once there
+    /// is no in-flight AIO left. An AIOManager built by `AIOBuilder::build_manual` is driven
+    /// (blocking) by the call until then.
+    pub fn shutdown_now(mut self) {
+        self.notifier.cancel_all();
+        // detached, to finish the blocking operations by itself
+        self.helper.lock().take();
+        match self.driver.take() {
+            // disconnect the threads instead of waiting for them
+            Some(Driver::Listener(threads, exit_s)) => drop((threads, exit_s)),
+            #[cfg(feature = "tokio")]
+            Some(Driver::Tokio(ctl)) => ctl.exit(),
+            Some(driver @ Driver::Manual(_)) => {
+                self.driver = Some(driver);
+                while self.get_npending() > 0 {
+                    self.drive(self.max_events, None);
+                }
+            }
+            None => (),
+        }
+    }
+
+    fn stop(&mut self) -> Result<(), Error> {
+        let helper = match self.helper.lock().take() {
+            Some(h) => h.join().map_err(|_| Error::OtherError),
+            None => Ok(()),
+        };
+        let driver = match self.driver.take() {
+            Some(Driver::Listener(threads, exit_s)) => {
+                // fails if the threads are gone
+                let _ = exit_s.send(());
+                let mut res = Ok(());
+                for t in threads {
+                    if t.join().is_err() {
+                        res = Err(Error::OtherError)
                     }
-                    AIOState::FutureDone(res) => &res.1,
                 }
-                .to_vec(),
-            )
-        })
+                self.notifier.destroy();
+                res
+            }
+            // the task quits by itself after finishing the outstanding IOs
+            #[cfg(feature = "tokio")]
+            Some(Driver::Tokio(ctl)) => {
+                ctl.exit();
+                Ok(())
+            }
+            Some(Driver::Manual(m)) => {
+                (*m).into_inner().scheduler_out.close(&self.notifier);
+                self.notifier.destroy();
+                Ok(())
+            }
+            None => Ok(()),
+        };
+        driver.and(helper)
+    }
+}
+
+impl AIOManagerHandle {
+    /// Stop submitting the scheduled AIOs, e.g., to quiesce the IOs during a snapshot, while the
+    /// in-flight ones are still reaped (see `in_flight`). Those scheduled meanwhile are queued
+    /// until `resume`, unlike the blocking operations, which are not submitted to the kernel.
+    pub fn pause(&self) {
+        self.notifier.paused.store(true, Ordering::Release)
+    }
+
+    /// Submit the scheduled AIOs again after `pause`.
+    pub fn resume(&self) {
+        if self.notifier.paused.swap(false, Ordering::AcqRel) {
+            // wake up the driver, unless there is already a pending signal
+            let _ = self.notifier.resume_in.try_send(());
+            #[cfg(feature = "tokio")]
+            if let Some(ctl) = self.scheduler_in.queues.kick.as_ref() {
+                ctl.kick()
+            }
+        }
+    }
+
+    /// Limit the rate of the reads submitted from now on (see `RateLimit`), those beyond it being
+    /// held back in the queue (see `queued`). Those held back already still wait until they are
+    /// within the previous limit.
+    pub fn set_read_rate_limit(&self, v: RateLimit) {
+        self.notifier.throttle.set(false, v)
+    }
+
+    /// Same as `set_read_rate_limit`, for the writes.
+    pub fn set_write_rate_limit(&self, v: RateLimit) {
+        self.notifier.throttle.set(true, v)
+    }
+
+    /// Set the share of the tag (see `read_with_tag`) relative to the others, 1 by default. Panics
+    /// if the weight is 0.
+    pub fn set_tag_weight(&self, tag: u32, weight: u32) {
+        assert!(weight > 0, "the weight must be positive");
+        self.notifier.tag_weights.set(tag, weight)
+    }
+
+    /// Register a tenant of the AIOManager (e.g., a database of a multi-tenant node), whose AIOs
+    /// are those scheduled with its tag (see `Tenant::tag` and `read_with_tag`): they are held
+    /// to its quota and counted apart (see `tenant_stats`). The tags of the tenants start from
+    /// 2^31, leaving those below to the other uses of the tags.
+    pub fn register_tenant(&self, name: &str, quota: TenantQuota) -> Tenant {
+        self.notifier.tenants.register(name, quota)
+    }
+
+    /// Replace the quota of the tenant.
+    pub fn set_tenant_quota(&self, tenant: Tenant, quota: TenantQuota) {
+        self.notifier.tenants.set_quota(tenant, quota)
+    }
+
+    /// Get the counters of the AIOs per tenant, by their names.
+    pub fn tenant_stats(&self) -> Vec<(String, TenantStats)> {
+        self.notifier.tenants.stats()
     }
 
     /// Get the number of pending AIOs (approximation).
     pub fn get_npending(&self) -> usize {
         self.notifier.npending.load(Ordering::Relaxed)
     }
+
+    /// Get the number of AIOs submitted but not yet finished (approximation).
+    pub fn in_flight(&self) -> usize {
+        self.notifier.counters.in_flight() as usize
+    }
+
+    /// Get the number of AIOs scheduled but not yet submitted, e.g., for the lack of room in the
+    /// context or waiting for a retry (approximation).
+    pub fn queued(&self) -> usize {
+        self.get_npending().saturating_sub(self.in_flight())
+    }
+
+    /// Get the number of AIOs the context takes at once (`AIOBuilder::max_events`, possibly
+    /// clamped, and summed over the shards), beyond which they stay queued.
+    pub fn capacity(&self) -> usize {
+        self.max_events
+    }
+
+    /// The errno of the fatal error the AIO context failed with (e.g., `io_getevents` failing),
+    /// if any. The AIOManager is then unusable: all the pending AIOs, as well as those scheduled
+    /// afterwards, are resolved with this errno.
+    pub fn fatal_error(&self) -> Option<i32> {
+        match self.notifier.fatal.load(Ordering::Acquire) {
+            0 => None,
+            errno => Some(errno),
+        }
+    }
+
+    /// Require the given alignment of the reads and writes on the file descriptor, instead of
+    /// probing it for `O_DIRECT` (or no longer with `None`, which should be done before the
+    /// file descriptor is closed and reused). Only effective with
+    /// `AIOBuilder::validate_alignment`. Panics if the alignment is 0.
+    pub fn set_fd_alignment(&self, fd: &impl AsFd, v: Option<usize>) {
+        assert!(v != Some(0), "the alignment must be positive");
+        if let Some(fd_alignment) = self.fd_alignment.as_ref() {
+            let fd = fd.as_fd().as_raw_fd();
+            match v {
+                Some(v) => fd_alignment.lock().insert(fd, v),
+                None => fd_alignment.lock().remove(&fd),
+            };
+        }
+    }
+
+    /// Put a barrier after the operations scheduled so far on the file descriptor: those
+    /// scheduled afterwards are only submitted once all of them are finished (without waiting
+    /// for the barrier to be reached by the caller). This orders the writes of a journal without
+    /// syncing the file, but only orders the submissions to the kernel, not their persistence.
+    /// A batch (see `submit_batch`) across a barrier is not submitted at once. Panics if not
+    /// enabled by `AIOBuilder::barriers`.
+    pub fn barrier(&self, fd: &impl AsFd) {
+        self.barrier_raw(fd.as_fd().as_raw_fd())
+    }
+
+    /// Same as `barrier`, but on a raw file descriptor.
+    pub fn barrier_raw(&self, fd: RawFd) {
+        let barriers = self.notifier.barriers.as_ref();
+        barriers.expect("barriers are not enabled").barrier(fd)
+    }
+
+    /// Get the number of AIOs finished after their deadlines so far.
+    pub fn get_deadline_misses(&self) -> u64 {
+        self.notifier.counters.deadline_misses()
+    }
+
+    /// Get a snapshot of the counters of the AIOs.
+    pub fn stats(&self) -> Stats {
+        self.notifier.counters.snapshot()
+    }
+
+    /// Get the counters of the AIOs per file descriptor, if enabled by `AIOBuilder::fd_stats`
+    /// (otherwise empty).
+    pub fn fd_stats(&self) -> Vec<(RawFd, FdStats)> {
+        match self.notifier.fd_stats.as_ref() {
+            Some(fd_stats) => {
+                fd_stats.lock().iter().map(|(fd, s)| (*fd, *s)).collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Start over the counters of the AIOs per file descriptor, e.g., after a file is closed so
+    /// its descriptor can be reused.
+    pub fn reset_fd_stats(&self) {
+        if let Some(fd_stats) = self.notifier.fd_stats.as_ref() {
+            fd_stats.lock().clear()
+        }
+    }
+}
+
+impl std::ops::Deref for AIOManager {
+    type Target = AIOManagerHandle;
+
+    fn deref(&self) -> &AIOManagerHandle {
+        &self.handle
+    }
 }
 
 impl Drop for AIOManager {
     fn drop(&mut self) {
-        self.exit_s.send(()).unwrap();
-        self.listener.take().unwrap().join().unwrap();
+        self.stop().unwrap()
     }
 }
 
+#[derive(Clone)]
 pub struct AIOBatchSchedulerIn {
-    queue_in: crossbeam_channel::Sender<AtomicPtr<abi::IOCb>>,
-    last_id: std::cell::Cell<u64>,
+    queues: Arc<Queues>,
+}
+
+/// The sending ends of the scheduler, shared with the futures waiting for a permit.
+struct Queues {
+    // one queue per lane, from the most urgent
+    queue_in: Vec<crossbeam_channel::Sender<Enqueued>>,
+    deadline_in: crossbeam_channel::Sender<Deadlined>,
+    batch_in: crossbeam_channel::Sender<Vec<AtomicPtr<abi::IOCb>>>,
+    #[cfg(feature = "tokio")]
+    kick: Option<Arc<tokio_driver::Control>>,
+    // the id of the next AIO
+    next_id: AtomicU64,
 }
 
 pub struct AIOBatchSchedulerOut {
-    queue_out: crossbeam_channel::Receiver<AtomicPtr<abi::IOCb>>,
+    queue_out: Vec<crossbeam_channel::Receiver<Enqueued>>,
+    // the AIOs taken from the queues, by their tags
+    fair: Vec<FairQueue>,
+    weights: [usize; prio::NLANES],
+    // how long the AIOs of each lane may wait for others once idle (see `gather`)
+    submit_delays: [std::time::Duration; prio::NLANES],
+    deadline_out: crossbeam_channel::Receiver<Deadlined>,
+    // the AIOs with a deadline, taken before those in the lanes
+    deadlined: BinaryHeap<Deadlined>,
+    batch_out: crossbeam_channel::Receiver<Vec<AtomicPtr<abi::IOCb>>>,
+    // the batch waiting for the room in the context
+    batch: Option<Vec<AtomicPtr<abi::IOCb>>>,
     max_nbatched: usize,
+    max_events: usize,
     leftover: Vec<AtomicPtr<abi::IOCb>>,
+    // the AIOs behind a barrier, in order
+    held: Vec<Held>,
+    // the id of the last merged write (see `AIONotifier::merge`)
+    last_merged: u64,
+    // sorts the submissions by their offsets, if enabled by `AIOBuilder::elevator`
+    elevator: Option<Elevator>,
+    // since the context was found full (see `is_stalled`)
+    backoff: Option<Backoff>,
+}
+
+/// The wait after the context was found full.
+struct Backoff {
+    until: Instant,
+    wait: std::time::Duration,
+    // the AIOs in flight back then, so it is over once any of them finishes
+    in_flight: u64,
+}
+
+/// An AIO taken from the queues but behind a barrier.
+struct Held {
+    iocb: AtomicPtr<abi::IOCb>,
+    fd: RawFd,
+    // the epochs of the AIO (see `AIO::barrier` and `AIO::order`)
+    barrier: Option<u64>,
+    order: Option<u64>,
+}
+
+/// A scheduled AIO with a deadline, the earliest deadline being the greatest (i.e., the first out
+/// of a `BinaryHeap`).
+struct Deadlined {
+    deadline: Instant,
+    id: u64,
+    iocb: AtomicPtr<abi::IOCb>,
+}
+
+impl PartialEq for Deadlined {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for Deadlined {}
+
+impl PartialOrd for Deadlined {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Deadlined {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        // the ties go in the order of scheduling
+        other
+            .deadline
+            .cmp(&self.deadline)
+            .then_with(|| other.id.cmp(&self.id))
+    }
 }
 
 impl AIOBatchSchedulerIn {
-    fn schedule(&self, aio: AIO, notifier: &Arc<AIONotifier>) -> AIOFuture {
-        let fut = AIOFuture {
-            notifier: notifier.clone(),
-            aio_id: aio.id,
-        };
-        let iocb = aio.iocb.load(Ordering::Acquire);
-        notifier.register_notify(aio.id, AIOState::FutureInit(aio, false));
-        self.queue_in.send(AtomicPtr::new(iocb)).unwrap();
-        notifier.npending.fetch_add(1, Ordering::Relaxed);
+    fn schedule<B>(
+        &self,
+        aio: AIO,
+        lane: usize,
+        admitted: bool,
+        notifier: &Arc<AIONotifier>,
+    ) -> AIOFuture<'static, B> {
+        let mut fut = AIOFuture::new(notifier.clone(), aio.id);
+        match notifier.permits.as_ref() {
+            Some(permits) if !admitted && !permits.acquire(None) => {
+                fut.queued = Some(Box::new(Queued {
+                    aio,
+                    lane,
+                    queues: self.queues.clone(),
+                }))
+            }
+            _ => self.queues.enqueue(aio, lane, notifier),
+        }
         fut
     }
 
     fn next_id(&self) -> u64 {
-        let id = self.last_id.get();
-        self.last_id.set(id.wrapping_add(1));
-        id
+        self.queues.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+impl Queues {
+    fn enqueue(&self, aio: AIO, lane: usize, notifier: &AIONotifier) {
+        let iocb = aio.iocb.load(Ordering::Acquire);
+        notifier.set_resfd(iocb);
+        let (id, deadline, tag) = (aio.id, aio.deadline, aio.tag);
+        let intake = notifier.intake.read();
+        notifier.register_notify(id, AIOState::Pending(aio, false));
+        notifier.npending.fetch_add(1, Ordering::Relaxed);
+        // sent while holding the lock, so either the AIO is drained by close(), or it sees closed
+        let sent = !notifier.closed.load(Ordering::Acquire) &&
+            match deadline {
+                Some(deadline) => self
+                    .deadline_in
+                    .send(Deadlined {
+                        deadline,
+                        id,
+                        iocb: AtomicPtr::new(iocb),
+                    })
+                    .is_ok(),
+                None => self.queue_in[lane]
+                    .send(Enqueued {
+                        iocb: AtomicPtr::new(iocb),
+                        tag,
+                    })
+                    .is_ok(),
+            };
+        if !sent {
+            diag!(warn, "aio {} scheduled after the shutdown", id);
+            notifier.retire();
+            let e = notifier.waiting.get(id).unwrap();
+            AIONotifier::resolve(
+                e,
+                notifier.shutdown_res(),
+                &mut Wakers::default(),
+            );
+            return
+        }
+        drop(intake);
+        #[cfg(feature = "tokio")]
+        if let Some(ctl) = self.kick.as_ref() {
+            ctl.kick()
+        }
+    }
+
+    /// Schedule the AIOs to be submitted together, in order.
+    fn enqueue_batch(&self, aios: Vec<AIO>, notifier: &AIONotifier) {
+        let iocbs = aios
+            .iter()
+            .map(|aio| {
+                let iocb = aio.iocb.load(Ordering::Acquire);
+                notifier.set_resfd(iocb);
+                AtomicPtr::new(iocb)
+            })
+            .collect::<Vec<_>>();
+        let ids = aios.iter().map(|aio| aio.id).collect::<Vec<_>>();
+        let intake = notifier.intake.read();
+        for aio in aios {
+            notifier.register_notify(aio.id, AIOState::Pending(aio, false));
+        }
+        notifier.npending.fetch_add(ids.len(), Ordering::Relaxed);
+        let sent = !notifier.closed.load(Ordering::Acquire) &&
+            self.batch_in.send(iocbs).is_ok();
+        if !sent {
+            diag!(
+                warn,
+                "batch of {} aios scheduled after the shutdown",
+                ids.len()
+            );
+            let mut wakers = Wakers::default();
+            for id in ids {
+                notifier.retire();
+                let e = notifier.waiting.get(id).unwrap();
+                AIONotifier::resolve(e, notifier.shutdown_res(), &mut wakers);
+            }
+            return
+        }
+        drop(intake);
+        #[cfg(feature = "tokio")]
+        if let Some(ctl) = self.kick.as_ref() {
+            ctl.kick()
+        }
     }
 }
 
 impl AIOBatchSchedulerOut {
-    fn get_receiver(
-        &self,
-    ) -> &crossbeam_channel::Receiver<AtomicPtr<abi::IOCb>> {
-        &self.queue_out
+    /// Wake up the selection upon any newly scheduled AIO.
+    fn watch<'a>(
+        &'a self,
+        notifier: &'a AIONotifier,
+        sel: &mut crossbeam_channel::Select<'a>,
+    ) {
+        // any signal left by an earlier resume
+        let _ = notifier.resume_out.try_recv();
+        // or only upon resuming, as the AIOs are left where they are until then
+        if notifier.is_paused() {
+            sel.recv(&notifier.resume_out);
+            return
+        }
+        // or once the backoff is over (see `next_due`)
+        if self.is_stalled(notifier) {
+            return
+        }
+        sel.recv(&self.batch_out);
+        sel.recv(&self.deadline_out);
+        for q in self.queue_out.iter() {
+            sel.recv(q);
+        }
+    }
+
+    /// Stop taking AIOs: those left and any scheduled afterwards (e.g., by the futures waiting
+    /// for a permit) are resolved with `ESHUTDOWN` (or the fatal error, see `fail`).
+    fn close(&mut self, notifier: &AIONotifier) {
+        {
+            let _intake = notifier.intake.write();
+            notifier.closed.store(true, Ordering::Release);
+        }
+        let mut iocbs = self
+            .leftover
+            .drain(..)
+            .map(|p| p.load(Ordering::Acquire))
+            .collect::<Vec<_>>();
+        self.deadlined.extend(self.deadline_out.try_iter());
+        iocbs.extend(
+            self.deadlined
+                .drain()
+                .chain(notifier.retries.lock().drain())
+                .map(|d| d.iocb.load(Ordering::Acquire)),
+        );
+        for (q, fair) in self.queue_out.iter().zip(self.fair.iter_mut()) {
+            iocbs.extend(fair.drain());
+            iocbs.extend(q.try_iter().map(|e| e.iocb.load(Ordering::Acquire)));
+        }
+        iocbs.extend(
+            self.held.drain(..).map(|h| h.iocb.load(Ordering::Acquire)),
+        );
+        iocbs.extend(
+            self.batch
+                .take()
+                .into_iter()
+                .chain(self.batch_out.try_iter())
+                .flatten()
+                .map(|p| p.load(Ordering::Acquire)),
+        );
+        // a split AIO is given up on once none of its chunks is in flight
+        let aios = iocbs
+            .into_iter()
+            .flat_map(|iocb| notifier.unmerge(unsafe { (*iocb).aio_data }))
+            .filter_map(|id| notifier.unsplit(id, notifier.shutdown_res()))
+            .collect::<Vec<_>>();
+        let mut wakers = Wakers::default();
+        for (id, res) in aios {
+            notifier.retire();
+            let e = notifier.waiting.get(id).unwrap();
+            AIONotifier::resolve(e, res, &mut wakers);
+        }
     }
-    fn is_empty(&self) -> bool {
-        self.leftover.len() == 0
+
+    /// Give up upon a fatal error of the context: besides closing, the in-flight AIOs, whose
+    /// completions would never come, are resolved with `errno` as well.
+    fn fail(&mut self, notifier: &AIONotifier, errno: i32) {
+        diag!(error, "aio context failed: errno {}", errno);
+        notifier.fatal.store(errno, Ordering::Release);
+        self.close(notifier);
+        let mut ids = Vec::new();
+        notifier.waiting.for_each(|id, state| {
+            if let AIOState::Pending(..) = state {
+                ids.push(id)
+            }
+        });
+        let mut wakers = Wakers::default();
+        for id in ids {
+            // unless finished meanwhile
+            if let Some(e) = notifier.waiting.get(id) {
+                if let AIOState::Pending(..) = *e {
+                    notifier.retire();
+                    AIONotifier::resolve(e, -errno as i64, &mut wakers);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    fn is_drained(&self) -> bool {
+        self.batch_out.is_empty() &&
+            self.deadline_out.is_empty() &&
+            self.queue_out.iter().all(|q| q.is_empty()) &&
+            self.fair.iter().all(|f| f.len() == 0)
+    }
+
+    /// Wait for more AIOs to be scheduled up to the shortest delay among those already, unless
+    /// a whole batch of them is sooner (see `AIOBuilder::submit_delay`).
+    fn gather(&self) {
+        if self.submit_delays.iter().all(|d| d.is_zero()) {
+            return
+        }
+        let start = Instant::now();
+        loop {
+            let queued = self
+                .queue_out
+                .iter()
+                .zip(self.fair.iter())
+                .map(|(q, f)| q.len() + f.len());
+            let delay = queued
+                .clone()
+                .zip(self.submit_delays)
+                .filter(|(n, _)| *n > 0)
+                .map(|(_, d)| d)
+                .min()
+                .unwrap_or_default();
+            let nscheduled = self.deadline_out.len() + queued.sum::<usize>();
+            let elapsed = start.elapsed();
+            if nscheduled >= self.max_nbatched ||
+                !self.batch_out.is_empty() ||
+                !self.deadline_out.is_empty() ||
+                elapsed >= delay
+            {
+                break
+            }
+            std::thread::sleep((delay - elapsed).min(GATHER_POLL))
+        }
+    }
+
+    /// Whether the context was found full lately, and none of the AIOs in flight back then has
+    /// finished since.
+    fn is_stalled(&self, notifier: &AIONotifier) -> bool {
+        self.backoff.as_ref().is_some_and(|b| {
+            Instant::now() < b.until &&
+                notifier.counters.in_flight() >= b.in_flight
+        })
+    }
+
+    /// When to try submitting again at the latest, for a retry or a tenant that is due, or the
+    /// end of the backoff, unless paused.
+    fn next_due(&self, notifier: &AIONotifier) -> Option<Instant> {
+        if notifier.is_paused() {
+            return None
+        }
+        let stalled = match self.backoff.as_ref() {
+            Some(b) if self.is_stalled(notifier) => Some(b.until),
+            _ => None,
+        };
+        match (notifier.next_retry(), stalled) {
+            (Some(due), Some(until)) => Some(due.min(until)),
+            (due, until) => due.or(until),
+        }
+    }
+
+    fn is_empty(&self, notifier: &AIONotifier) -> bool {
+        // nothing to submit for now
+        if notifier.is_paused() || self.is_stalled(notifier) {
+            return true
+        }
+        self.leftover.len() == 0 &&
+            self.deadlined.is_empty() &&
+            self.fair.iter().all(|f| !f.is_ready(&notifier.tenants)) &&
+            self.batch.is_none() &&
+            !self.held.iter().any(|h| notifier.is_ready(h))
     }
     fn submit(&mut self, notifier: &AIONotifier) -> usize {
+        if notifier.is_paused() || self.is_stalled(notifier) {
+            return 0
+        }
+        if self.leftover.is_empty() {
+            if self.batch.is_none() {
+                self.batch = self.batch_out.try_recv().ok();
+            }
+            if let Some(batch) = self.batch.as_ref() {
+                // submitted on its own once the context has room for all of it, so it is
+                // accepted by a single io_submit
+                let in_flight = notifier.counters.in_flight() as usize;
+                if in_flight + batch.len() > self.max_events {
+                    return 0
+                }
+                let pending = self
+                    .batch
+                    .take()
+                    .unwrap()
+                    .iter()
+                    .map(|p| p.load(Ordering::Acquire))
+                    .collect::<Vec<_>>();
+                // counted against the rate limits, without holding back part of it
+                if notifier.throttle.is_active() {
+                    let now = Instant::now();
+                    for iocb in pending.iter() {
+                        notifier.throttle.force(unsafe { &**iocb }, now)
+                    }
+                }
+                return self.submit_pending(notifier, pending, true)
+            }
+        }
         let mut quota = self.max_nbatched;
         let mut pending = self
             .leftover
             .iter()
             .map(|p| p.load(Ordering::Acquire))
             .collect::<Vec<_>>();
+        // those past their barriers go first
+        let (ready, held): (Vec<_>, Vec<_>) = std::mem::take(&mut self.held)
+            .into_iter()
+            .partition(|h| notifier.is_ready(h));
+        self.held = held;
+        pending.extend(ready.iter().map(|h| h.iocb.load(Ordering::Acquire)));
+        // those taken before are within the rate limits already
+        let nadmitted = pending.len();
         if pending.len() < quota {
             quota -= pending.len();
-            while let Ok(iocb) = self.queue_out.try_recv() {
-                pending.push(iocb.load(Ordering::Acquire));
-                quota -= 1;
-                if quota == 0 {
-                    break
+            // the retries go first once their backoffs are over
+            {
+                let mut retries = notifier.retries.lock();
+                let now = Instant::now();
+                while quota > 0 &&
+                    retries.peek().is_some_and(|d| d.deadline <= now)
+                {
+                    let d = retries.pop().unwrap();
+                    pending.push(d.iocb.load(Ordering::Acquire));
+                    quota -= 1;
+                }
+            }
+            // then the earliest deadlines
+            self.deadlined.extend(self.deadline_out.try_iter());
+            while quota > 0 {
+                match self.deadlined.pop() {
+                    Some(d) => {
+                        pending.push(d.iocb.load(Ordering::Acquire));
+                        quota -= 1;
+                    }
+                    None => break,
+                }
+            }
+            // then take from the lanes in turn, as many as their weights each time
+            for (q, fair) in self.queue_out.iter().zip(self.fair.iter_mut()) {
+                for e in q.try_iter() {
+                    fair.push(e)
+                }
+            }
+            let mut more = true;
+            while quota > 0 && more {
+                more = false;
+                for (fair, w) in self.fair.iter_mut().zip(self.weights.iter()) {
+                    for _ in 0..quota.min(*w) {
+                        match fair.pop(&notifier.tag_weights, &notifier.tenants)
+                        {
+                            Some(iocb) => {
+                                pending.push(iocb);
+                                quota -= 1;
+                                more = true;
+                            }
+                            None => break,
+                        }
+                    }
                 }
             }
         }
-        if pending.len() == 0 {
+        let mut taken = pending.split_off(nadmitted);
+        notifier.throttle(&mut taken);
+        pending.append(&mut taken);
+        // unless the order on each fd is to be kept anyway
+        if let (Some(elevator), None) =
+            (self.elevator.as_mut(), notifier.order.as_ref())
+        {
+            elevator.sort(&mut pending)
+        }
+        self.submit_pending(notifier, pending, false)
+    }
+
+    /// Submit the iocbs, those of a batch as they are, as the room for them is made by counting
+    /// one iocb per AIO.
+    fn submit_pending(
+        &mut self,
+        notifier: &AIONotifier,
+        mut pending: Vec<*mut abi::IOCb>,
+        batch: bool,
+    ) -> usize {
+        notifier.discard(&mut pending);
+        notifier.hold_back(&mut pending, &mut self.held);
+        if !notifier.io_ctx.supports_noop() {
+            notifier.complete_noops(&mut pending);
+        }
+        if pending.is_empty() {
+            self.leftover.clear();
             return 0
         }
-        let mut ret = unsafe {
-            abi::io_submit(
-                *notifier.io_ctx,
-                pending.len() as c_long,
-                (&mut pending).as_mut_ptr(),
-            )
-        };
-        if ret < 0 && ret == LIBAIO_EAGAIN {
+        if !batch {
+            notifier.split(&mut pending);
+            notifier.merge(&mut pending, &mut self.last_merged);
+        }
+        // read before any of them may finish
+        let aios = notifier.aios(&pending);
+        diag!(trace, "submitting {} aios", pending.len());
+        let mut ret = notifier.io_ctx.submit(&mut pending);
+        // the first iocb is rejected, which fails its AIOs without holding up the others
+        let mut nrejected = 0;
+        while ret < 0 && ret != LIBAIO_EAGAIN {
+            let rejected = aios
+                .iter()
+                .filter(|(i, _, _)| *i == nrejected)
+                .map(|(_, fd, id)| (*fd, *id))
+                .collect::<Vec<_>>();
+            notifier.reject(pending[nrejected], &rejected, -ret);
+            nrejected += 1;
+            ret = match pending.len() - nrejected {
+                0 => 0,
+                _ => notifier.io_ctx.submit(&mut pending[nrejected..]),
+            };
+        }
+        if ret == LIBAIO_EAGAIN {
+            notifier.counters.submit_eagain();
+            let wait = match self.backoff.as_ref() {
+                Some(b) => (b.wait * 2).min(MAX_EAGAIN_BACKOFF),
+                None => EAGAIN_BACKOFF,
+            };
+            diag!(
+                debug,
+                "io_submit: EAGAIN, retrying {} aios in {:?}",
+                pending.len() - nrejected,
+                wait
+            );
+            self.backoff = Some(Backoff {
+                until: Instant::now() + wait,
+                wait,
+                in_flight: notifier.counters.in_flight(),
+            });
             ret = 0
+        } else if (ret as usize) < pending.len() - nrejected {
+            diag!(
+                debug,
+                "io_submit: {}/{} aios accepted",
+                ret,
+                pending.len() - nrejected
+            );
         }
         let nacc = ret as usize;
-        self.leftover = (&pending[nacc..])
+        if nacc > 0 {
+            self.backoff = None
+        }
+        let accepted = aios
+            .iter()
+            .skip_while(|(i, _, _)| *i < nrejected)
+            .take_while(|(i, _, _)| *i < nrejected + nacc)
+            .map(|(_, fd, id)| (*fd, *id))
+            .collect::<Vec<_>>();
+        notifier.counters.submitted(accepted.len());
+        notifier.mark_submitted(&accepted);
+        notifier.release_order(&accepted);
+        self.leftover = pending[nrejected + nacc..]
             .iter()
             .map(|p| AtomicPtr::new(*p))
             .collect::<Vec<_>>();
-        nacc
+        // counted by the AIOs (not the iocbs), as the reaped ones are
+        accepted.len()
+    }
+}
+
+pub(crate) fn to_timespec(d: std::time::Duration) -> libc::timespec {
+    libc::timespec {
+        tv_sec: d.as_secs() as time_t,
+        tv_nsec: d.subsec_nanos() as libc::c_long,
+    }
+}
+
+/// Bound the wait for the completions by the time the next retry is due.
+fn retry_timeout(
+    timeout: Option<libc::timespec>,
+    due: Option<Instant>,
+) -> Option<libc::timespec> {
+    let due = match due {
+        Some(due) => to_timespec(due.saturating_duration_since(Instant::now())),
+        None => return timeout,
+    };
+    match timeout {
+        Some(t) if (t.tv_sec, t.tv_nsec) < (due.tv_sec, due.tv_nsec) => Some(t),
+        _ => Some(due),
     }
 }
 
 /// Create the scheduler that submits AIOs in batches.
 fn new_batch_scheduler(
     max_nbatched: usize,
+    max_events: usize,
+    weights: [usize; prio::NLANES],
+    submit_delays: [std::time::Duration; prio::NLANES],
+    elevator: bool,
 ) -> (AIOBatchSchedulerIn, AIOBatchSchedulerOut) {
-    let (queue_in, queue_out) = crossbeam_channel::unbounded();
+    let (queue_in, queue_out) = (0..prio::NLANES)
+        .map(|_| crossbeam_channel::unbounded())
+        .unzip();
+    let (deadline_in, deadline_out) = crossbeam_channel::unbounded();
+    let (batch_in, batch_out) = crossbeam_channel::unbounded();
     let bin = AIOBatchSchedulerIn {
-        queue_in,
-        last_id: std::cell::Cell::new(0),
+        queues: Arc::new(Queues {
+            queue_in,
+            deadline_in,
+            batch_in,
+            #[cfg(feature = "tokio")]
+            kick: None,
+            next_id: AtomicU64::new(0),
+        }),
     };
     let bout = AIOBatchSchedulerOut {
         queue_out,
+        fair: (0..prio::NLANES).map(|_| FairQueue::default()).collect(),
+        weights,
+        submit_delays,
+        deadline_out,
+        deadlined: BinaryHeap::new(),
+        batch_out,
+        batch: None,
         max_nbatched,
+        max_events,
         leftover: Vec::new(),
+        held: Vec::new(),
+        last_merged: 0,
+        backoff: None,
+        elevator: if elevator {
+            Some(Elevator::default())
+        } else {
+            None
+        },
     };
     (bin, bout)
 }
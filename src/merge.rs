@@ -0,0 +1,141 @@
+//! Merging the contiguous writes to the same file descriptor into vectored writes.
+
+use crate::abi;
+
+/// The ids of the merged writes have this bit set, so they never clash with those of the AIOs.
+pub(crate) const MERGED_ID: u64 = 1 << 63;
+
+/// The most writes merged together (`IOV_MAX`).
+const MAX_MERGED: usize = 1024;
+
+/// A vectored write submitted instead of the contiguous writes merged into it.
+pub(crate) struct Merged {
+    iocb: Box<abi::IOCb>,
+    // point into the buffers of the merged AIOs, which are kept until they are finished
+    _iovs: Box<[abi::IOVector]>,
+    // the ids and the lengths of the merged AIOs, in the order of their offsets
+    parts: Vec<(u64, u64)>,
+}
+
+// iovs only point into the buffers of the merged AIOs
+unsafe impl Send for Merged {}
+
+impl Merged {
+    fn new(id: u64, run: &[*mut abi::IOCb]) -> Self {
+        let first = unsafe { &*run[0] };
+        let iovs = run
+            .iter()
+            .map(|iocb| unsafe {
+                abi::IOVector {
+                    iov_base: (**iocb).aio_buf as *mut u8,
+                    iov_len: (**iocb).aio_nbytes as libc::size_t,
+                }
+            })
+            .collect::<Box<[_]>>();
+        let iocb = Box::new(abi::IOCb {
+            aio_data: MERGED_ID | id,
+            aio_rw_flags: first.aio_rw_flags,
+            aio_lio_opcode: abi::IOCmd::PWriteV as u16,
+            aio_reqprio: first.aio_reqprio,
+            aio_fildes: first.aio_fildes,
+            aio_buf: iovs.as_ptr() as u64,
+            aio_nbytes: iovs.len() as u64,
+            aio_offset: first.aio_offset,
+            aio_flags: first.aio_flags,
+            aio_resfd: first.aio_resfd,
+            ..Default::default()
+        });
+        let parts = run
+            .iter()
+            .map(|iocb| unsafe { ((**iocb).aio_data, (**iocb).aio_nbytes) })
+            .collect();
+        Merged {
+            iocb,
+            _iovs: iovs,
+            parts,
+        }
+    }
+
+    /// The ids of the merged AIOs.
+    pub(crate) fn ids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.parts.iter().map(|(id, _)| *id)
+    }
+
+    /// Split the result of the vectored write among the merged AIOs: each gets the error if it
+    /// failed, otherwise the bytes written are accounted to the first ones.
+    pub(crate) fn split(&self, res: i64) -> Vec<(u64, i64)> {
+        let mut left = res;
+        self.parts
+            .iter()
+            .map(|(id, len)| {
+                if res < 0 {
+                    return (*id, res)
+                }
+                let n = left.min(*len as i64);
+                left -= n;
+                (*id, n)
+            })
+            .collect()
+    }
+}
+
+/// Whether the write can be merged after the one before it.
+fn is_contiguous(prev: &abi::IOCb, iocb: &abi::IOCb) -> bool {
+    iocb.aio_fildes == prev.aio_fildes &&
+        iocb.aio_offset == prev.aio_offset + prev.aio_nbytes &&
+        iocb.aio_rw_flags == prev.aio_rw_flags &&
+        iocb.aio_reqprio == prev.aio_reqprio &&
+        iocb.aio_flags == prev.aio_flags &&
+        iocb.aio_resfd == prev.aio_resfd
+}
+
+fn is_write(iocb: &abi::IOCb) -> bool {
+    iocb.aio_lio_opcode == abi::IOCmd::PWrite as u16 && iocb.aio_nbytes > 0
+}
+
+/// Replace each run of contiguous writes to the same fd among the iocbs by a vectored write of
+/// up to `max_bytes` (keeping the order of the iocbs otherwise), returning the merged writes by
+/// their ids.
+pub(crate) fn merge(
+    iocbs: &mut Vec<*mut abi::IOCb>,
+    last_id: &mut u64,
+    max_bytes: u64,
+) -> Vec<(u64, Merged)> {
+    let mut merged = Vec::new();
+    let mut out = Vec::with_capacity(iocbs.len());
+    let mut run: Vec<*mut abi::IOCb> = Vec::new();
+    let mut run_bytes = 0;
+    let mut flush = |run: &mut Vec<*mut abi::IOCb>, out: &mut Vec<_>| {
+        if run.len() > 1 {
+            *last_id = last_id.wrapping_add(1) & !MERGED_ID;
+            let mut m = Merged::new(*last_id, run);
+            out.push(&mut *m.iocb as *mut abi::IOCb);
+            merged.push((MERGED_ID | *last_id, m));
+        } else {
+            out.extend(run.iter().copied());
+        }
+        run.clear();
+    };
+    for iocb in iocbs.drain(..) {
+        let cur = unsafe { &*iocb };
+        let extends = run.last().is_some_and(|prev| {
+            run.len() < MAX_MERGED &&
+                run_bytes + cur.aio_nbytes <= max_bytes &&
+                is_write(cur) &&
+                is_contiguous(unsafe { &**prev }, cur)
+        });
+        if !extends {
+            flush(&mut run, &mut out);
+            run_bytes = 0;
+        }
+        if extends || is_write(cur) {
+            run.push(iocb);
+            run_bytes += cur.aio_nbytes;
+        } else {
+            out.push(iocb)
+        }
+    }
+    flush(&mut run, &mut out);
+    *iocbs = out;
+    merged
+}
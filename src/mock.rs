@@ -0,0 +1,448 @@
+//! An engine whose files are byte vectors in memory and whose operations only finish when the
+//! test says so, for unit-testing the ordering and the crash handling of an application without
+//! a disk (see `AIOBuilder::mock`).
+
+use crate::abi;
+use crate::backend::AioBackend;
+use crate::IOCmd;
+use parking_lot::{Condvar, Mutex};
+use std::collections::HashMap;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A file of a `MockDisk`.
+#[derive(Default)]
+struct Data {
+    bytes: Vec<u8>,
+    // as of the last fsync, which is what is left after a crash
+    durable: Vec<u8>,
+}
+
+/// A submitted operation, yet to be finished by the test.
+struct Pending {
+    op: MockOp,
+    iocb: *mut abi::IOCb,
+    // the engine it was submitted to
+    engine: u64,
+    done_in: crossbeam_channel::Sender<abi::IOEvent>,
+}
+// the iocb is owned by its AIO, which outlives the operation
+unsafe impl Send for Pending {}
+
+#[derive(Default)]
+struct State {
+    files: HashMap<String, Data>,
+    // the names of the files behind the open handles
+    fds: HashMap<RawFd, String>,
+    // in the order of submission
+    pending: Vec<Pending>,
+    next_id: u64,
+    next_engine: u64,
+    auto_complete: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    // signaled upon every submission
+    submitted: Condvar,
+}
+
+/// A disk of named in-memory files, shared by the AIOManagers it is given to (see
+/// `AIOBuilder::mock`). The submitted operations stay pending until finished by `complete` or
+/// `fail`, in any order, unless `auto_complete` is set. Reads see the contents as of their
+/// completion, and writes change them upon their completion. Only the reads, the writes
+/// (vectored or not) and the syncs are carried out, the polls fail with `EINVAL`.
+#[derive(Clone, Default)]
+pub struct MockDisk(Arc<Shared>);
+
+/// A handle to a file of a `MockDisk`, to pass to the AIOManager like any file. It is backed by
+/// `/dev/null`, so it only stands for the file.
+pub struct MockFile {
+    file: std::fs::File,
+    disk: MockDisk,
+}
+
+/// A pending operation (see `MockDisk::pending`).
+#[derive(Clone, Debug)]
+pub struct MockOp {
+    /// Unique among the operations of the disk, in the order of submission.
+    pub id: u64,
+    /// The name of the file.
+    pub name: String,
+    pub cmd: IOCmd,
+    pub offset: u64,
+    /// The bytes read or written.
+    pub len: u64,
+}
+
+impl MockDisk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open the file of the given name, creating it empty if it does not exist.
+    pub fn open(&self, name: &str) -> std::io::Result<MockFile> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/null")?;
+        let mut s = self.0.state.lock();
+        s.files.entry(name.to_string()).or_default();
+        s.fds.insert(file.as_raw_fd(), name.to_string());
+        Ok(MockFile {
+            file,
+            disk: self.clone(),
+        })
+    }
+
+    /// The current contents of the file, if it exists.
+    pub fn contents(&self, name: &str) -> Option<Vec<u8>> {
+        self.0.state.lock().files.get(name).map(|d| d.bytes.clone())
+    }
+
+    /// Replace the contents of the file (creating it if needed), as if synced.
+    pub fn set_contents(&self, name: &str, bytes: Vec<u8>) {
+        let mut s = self.0.state.lock();
+        s.files.insert(
+            name.to_string(),
+            Data {
+                durable: bytes.clone(),
+                bytes,
+            },
+        );
+    }
+
+    /// The pending operations, in the order of submission.
+    pub fn pending(&self) -> Vec<MockOp> {
+        let s = self.0.state.lock();
+        s.pending.iter().map(|p| p.op.clone()).collect()
+    }
+
+    /// Wait for at least `n` pending operations for at most `timeout`, returning them all.
+    pub fn wait_pending(&self, n: usize, timeout: Duration) -> Vec<MockOp> {
+        let deadline = Instant::now() + timeout;
+        let mut s = self.0.state.lock();
+        while s.pending.len() < n {
+            if self.0.submitted.wait_until(&mut s, deadline).timed_out() {
+                break
+            }
+        }
+        s.pending.iter().map(|p| p.op.clone()).collect()
+    }
+
+    /// Carry out the pending operation and finish it. Returns false if there is no such
+    /// operation.
+    pub fn complete(&self, id: u64) -> bool {
+        let mut s = self.0.state.lock();
+        match s.take(id) {
+            Some(p) => {
+                let res = s.apply(&p);
+                finish(&p, res);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Fail the pending operation with the errno, without carrying it out. Returns false if
+    /// there is no such operation.
+    pub fn fail(&self, id: u64, errno: i32) -> bool {
+        let mut s = self.0.state.lock();
+        match s.take(id) {
+            Some(p) => {
+                finish(&p, -errno as i64);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Complete all the pending operations in the order of submission, returning their number.
+    pub fn complete_all(&self) -> usize {
+        let mut s = self.0.state.lock();
+        let pending = std::mem::take(&mut s.pending);
+        for p in pending.iter() {
+            let res = s.apply(p);
+            finish(p, res)
+        }
+        pending.len()
+    }
+
+    /// Complete the operations as soon as they are submitted (default is false), so the disk
+    /// behaves like a (very fast) real one. The operations already pending stay so.
+    pub fn auto_complete(&self, v: bool) {
+        self.0.state.lock().auto_complete = v
+    }
+
+    /// Lose what was written to the files since they were last synced, and fail the pending
+    /// operations with `EIO`, as after a power loss.
+    pub fn crash(&self) {
+        let mut s = self.0.state.lock();
+        for d in s.files.values_mut() {
+            d.bytes = d.durable.clone()
+        }
+        for p in std::mem::take(&mut s.pending) {
+            finish(&p, -libc::EIO as i64)
+        }
+    }
+
+    /// A new engine on the disk.
+    pub(crate) fn context(&self, maxevents: u32) -> MockContext {
+        let (done_in, done_out) = crossbeam_channel::unbounded();
+        let mut s = self.0.state.lock();
+        s.next_engine += 1;
+        MockContext {
+            disk: self.clone(),
+            engine: s.next_engine,
+            done_in,
+            done_out,
+            inflight: AtomicUsize::new(0),
+            maxevents: maxevents as usize,
+        }
+    }
+}
+
+impl State {
+    fn take(&mut self, id: u64) -> Option<Pending> {
+        let i = self.pending.iter().position(|p| p.op.id == id)?;
+        Some(self.pending.remove(i))
+    }
+
+    /// Carry out the operation, returning the result in the same form as `io_event.res`.
+    fn apply(&mut self, p: &Pending) -> i64 {
+        let iocb = unsafe { &*p.iocb };
+        let data = self.files.entry(p.op.name.clone()).or_default();
+        let single = [libc::iovec {
+            iov_base: iocb.aio_buf as *mut libc::c_void,
+            iov_len: iocb.aio_nbytes as usize,
+        }];
+        let iov = match p.op.cmd {
+            IOCmd::PRead | IOCmd::PWrite => &single[..],
+            IOCmd::PReadV | IOCmd::PWriteV => unsafe {
+                std::slice::from_raw_parts(
+                    iocb.aio_buf as *const libc::iovec,
+                    iocb.aio_nbytes as usize,
+                )
+            },
+            _ => {
+                data.durable = data.bytes.clone();
+                return 0
+            }
+        };
+        let mut off = p.op.offset as usize;
+        let mut n = 0;
+        for v in iov.iter() {
+            let buf = unsafe {
+                std::slice::from_raw_parts_mut(v.iov_base as *mut u8, v.iov_len)
+            };
+            match p.op.cmd {
+                IOCmd::PRead | IOCmd::PReadV => {
+                    let avail =
+                        data.bytes.len().saturating_sub(off).min(buf.len());
+                    // nothing to copy past the end, where the offset may be out of the bytes
+                    if avail > 0 {
+                        buf[..avail]
+                            .copy_from_slice(&data.bytes[off..off + avail]);
+                    }
+                    n += avail;
+                    if avail < buf.len() {
+                        break
+                    }
+                }
+                _ => {
+                    let end = off + buf.len();
+                    if data.bytes.len() < end {
+                        data.bytes.resize(end, 0)
+                    }
+                    data.bytes[off..end].copy_from_slice(buf);
+                    n += buf.len();
+                }
+            }
+            off += buf.len();
+        }
+        n as i64
+    }
+}
+
+/// Report the result of the operation to its engine.
+fn finish(p: &Pending, res: i64) {
+    let iocb = unsafe { &*p.iocb };
+    let ev = abi::IOEvent {
+        data: iocb.aio_data,
+        obj: p.iocb as u64,
+        res,
+        res2: 0,
+    };
+    let _ = p.done_in.send(ev);
+    if iocb.aio_flags & abi::IOCB_FLAG_RESFD != 0 {
+        let one = 1u64;
+        unsafe {
+            libc::write(
+                iocb.aio_resfd as libc::c_int,
+                &one as *const u64 as *const libc::c_void,
+                8,
+            );
+        }
+    }
+}
+
+impl MockFile {
+    /// The name of the file.
+    pub fn name(&self) -> String {
+        let s = self.disk.0.state.lock();
+        s.fds[&self.file.as_raw_fd()].clone()
+    }
+}
+
+impl AsFd for MockFile {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.file.as_fd()
+    }
+}
+
+impl AsRawFd for MockFile {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl Drop for MockFile {
+    fn drop(&mut self) {
+        self.disk.0.state.lock().fds.remove(&self.file.as_raw_fd());
+    }
+}
+
+pub(crate) struct MockContext {
+    disk: MockDisk,
+    engine: u64,
+    done_in: crossbeam_channel::Sender<abi::IOEvent>,
+    done_out: crossbeam_channel::Receiver<abi::IOEvent>,
+    // the number of submitted iocbs that are yet to be reaped
+    inflight: AtomicUsize,
+    maxevents: usize,
+}
+
+impl MockContext {
+    /// The operation described by the iocb, or the errno to reject it with.
+    fn op(s: &mut State, iocb: &abi::IOCb) -> Result<MockOp, libc::c_int> {
+        let name = match s.fds.get(&(iocb.aio_fildes as RawFd)) {
+            Some(name) => name.clone(),
+            None => return Err(libc::EBADF),
+        };
+        let cmd = match iocb.aio_lio_opcode {
+            x if x == IOCmd::PRead as u16 => IOCmd::PRead,
+            x if x == IOCmd::PWrite as u16 => IOCmd::PWrite,
+            x if x == IOCmd::FSync as u16 => IOCmd::FSync,
+            x if x == IOCmd::FdSync as u16 => IOCmd::FdSync,
+            x if x == IOCmd::PReadV as u16 => IOCmd::PReadV,
+            x if x == IOCmd::PWriteV as u16 => IOCmd::PWriteV,
+            _ => return Err(libc::EINVAL),
+        };
+        let len = crate::throttle::rw_bytes(iocb).map_or(0, |(_, n)| n);
+        s.next_id += 1;
+        Ok(MockOp {
+            id: s.next_id,
+            name,
+            cmd,
+            offset: iocb.aio_offset,
+            len,
+        })
+    }
+}
+
+impl AioBackend for MockContext {
+    fn submit(&self, iocbs: &mut [*mut abi::IOCb]) -> libc::c_int {
+        let inflight = self.inflight.load(Ordering::Acquire);
+        // behave like a full libaio context when reaching maxevents
+        let n = iocbs.len().min(self.maxevents.saturating_sub(inflight));
+        if n == 0 {
+            return -libc::EAGAIN
+        }
+        let mut s = self.disk.0.state.lock();
+        let mut nacc = 0;
+        for iocb in iocbs[..n].iter() {
+            let op = match Self::op(&mut s, unsafe { &**iocb }) {
+                Ok(op) => op,
+                // the first one is rejected as by io_submit(2), otherwise the next submission
+                // rejects it
+                Err(e) if nacc == 0 => return -e,
+                Err(_) => break,
+            };
+            let p = Pending {
+                op,
+                iocb: *iocb,
+                engine: self.engine,
+                done_in: self.done_in.clone(),
+            };
+            if s.auto_complete {
+                let res = s.apply(&p);
+                finish(&p, res)
+            } else {
+                s.pending.push(p)
+            }
+            self.inflight.fetch_add(1, Ordering::AcqRel);
+            nacc += 1;
+        }
+        self.disk.0.submitted.notify_all();
+        nacc as libc::c_int
+    }
+
+    fn reap(
+        &self,
+        min_nr: usize,
+        events: &mut [abi::IOEvent],
+        timeout: Option<&mut libc::timespec>,
+    ) -> libc::c_int {
+        let deadline = timeout.map(|t| {
+            Instant::now() + Duration::new(t.tv_sec as u64, t.tv_nsec as u32)
+        });
+        let mut nev = 0;
+        while nev < events.len() {
+            let ev = if nev < min_nr {
+                match deadline {
+                    Some(d) => self.done_out.recv_deadline(d).ok(),
+                    None => self.done_out.recv().ok(),
+                }
+            } else {
+                self.done_out.try_recv().ok()
+            };
+            match ev {
+                Some(ev) => events[nev] = ev,
+                None => break,
+            }
+            nev += 1;
+        }
+        self.inflight.fetch_sub(nev, Ordering::AcqRel);
+        nev as libc::c_int
+    }
+
+    fn cancel(&self, iocb: *mut abi::IOCb) -> libc::c_int {
+        let mut s = self.disk.0.state.lock();
+        let i = s
+            .pending
+            .iter()
+            .position(|p| p.engine == self.engine && p.iocb == iocb);
+        match i {
+            Some(i) => {
+                finish(&s.pending.remove(i), -libc::ECANCELED as i64);
+                0
+            }
+            None => -libc::EINVAL,
+        }
+    }
+
+    fn supports_noop(&self) -> bool {
+        false
+    }
+}
+
+impl Drop for MockContext {
+    fn drop(&mut self) {
+        // their iocbs go away with the AIOManager
+        let mut s = self.disk.0.state.lock();
+        s.pending.retain(|p| p.engine != self.engine)
+    }
+}
@@ -0,0 +1,42 @@
+//! The per-operation settings of a read or a write, combined in one value.
+
+use crate::{RWFlags, RetryPolicy};
+use std::time::Instant;
+
+/// The less common settings of a read or a write (see `AIOManager::read_with_options`), all of
+/// them optional and combined freely, e.g.,
+/// `IoOptions::default().flags(RWFlags::DSYNC).deadline(t).tag(1)`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IoOptions {
+    pub(crate) rw_flags: RWFlags,
+    pub(crate) deadline: Option<Instant>,
+    pub(crate) tag: u32,
+    pub(crate) retry: Option<RetryPolicy>,
+}
+
+impl IoOptions {
+    /// The per-operation flags (e.g., `RWFlags::NOWAIT`), see `AIOManager::read_with_flags`.
+    pub fn flags(&mut self, v: RWFlags) -> &mut Self {
+        self.rw_flags = v;
+        self
+    }
+
+    /// The time to be done by, see `AIOManager::read_with_deadline`.
+    pub fn deadline(&mut self, v: Instant) -> &mut Self {
+        self.deadline = Some(v);
+        self
+    }
+
+    /// The tag to share the lane with the others by, see `AIOManager::read_with_tag`.
+    pub fn tag(&mut self, v: u32) -> &mut Self {
+        self.tag = v;
+        self
+    }
+
+    /// The policy to retry by instead of that of the AIOManager, see
+    /// `AIOManager::read_with_retry`.
+    pub fn retry(&mut self, v: RetryPolicy) -> &mut Self {
+        self.retry = Some(v);
+        self
+    }
+}
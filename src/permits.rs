@@ -0,0 +1,58 @@
+//! A counting semaphore bounding the number of outstanding AIOs, waited on by their futures.
+
+use parking_lot::Mutex;
+use std::task::Waker;
+
+pub(crate) struct Permits {
+    max: usize,
+    state: Mutex<State>,
+}
+
+struct State {
+    used: usize,
+    waiters: Vec<Waker>,
+}
+
+impl Permits {
+    pub(crate) fn new(max: usize) -> Self {
+        Permits {
+            max,
+            state: Mutex::new(State {
+                used: 0,
+                waiters: Vec::new(),
+            }),
+        }
+    }
+
+    /// Take a permit if any is left, otherwise have the waker (if any) woken up upon a release.
+    pub(crate) fn acquire(&self, waker: Option<&Waker>) -> bool {
+        let mut s = self.state.lock();
+        if s.used < self.max {
+            s.used += 1;
+            return true
+        }
+        if let Some(waker) = waker {
+            if !s.waiters.iter().any(|w| w.will_wake(waker)) {
+                s.waiters.push(waker.clone())
+            }
+        }
+        false
+    }
+
+    /// Take `n` permits even beyond the maximum, for AIOs that cannot wait (e.g., a batch).
+    pub(crate) fn force(&self, n: usize) {
+        self.state.lock().used += n
+    }
+
+    pub(crate) fn release(&self) {
+        // all the waiters try again, as some of them may be gone
+        let waiters = {
+            let mut s = self.state.lock();
+            s.used -= 1;
+            std::mem::take(&mut s.waiters)
+        };
+        for w in waiters {
+            w.wake()
+        }
+    }
+}
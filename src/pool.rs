@@ -0,0 +1,51 @@
+//! Recycling of the read buffers, to take the allocator off the IO path.
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+
+/// Buffers of the same length are kept together, and the pool holds no more than `max_bytes` in
+/// total.
+pub(crate) struct BufferPool {
+    classes: Mutex<Classes>,
+    max_bytes: usize,
+}
+
+#[derive(Default)]
+struct Classes {
+    bufs: HashMap<usize, Vec<Box<[u8]>>>,
+    nbytes: usize,
+}
+
+impl BufferPool {
+    pub(crate) fn new(max_bytes: usize) -> Self {
+        BufferPool {
+            classes: Mutex::new(Classes::default()),
+            max_bytes,
+        }
+    }
+
+    /// Get a buffer of the given length, which is not zeroed if it is recycled.
+    pub(crate) fn get(&self, len: usize) -> Box<[u8]> {
+        if len > 0 {
+            let mut c = self.classes.lock();
+            if let Some(buf) = c.bufs.get_mut(&len).and_then(|v| v.pop()) {
+                c.nbytes -= len;
+                return buf
+            }
+        }
+        vec![0; len].into_boxed_slice()
+    }
+
+    /// Return a buffer to the pool, which drops it if already full.
+    pub(crate) fn put(&self, buf: Box<[u8]>) {
+        let len = buf.len();
+        if len == 0 {
+            return
+        }
+        let mut c = self.classes.lock();
+        if c.nbytes + len <= self.max_bytes {
+            c.nbytes += len;
+            c.bufs.entry(len).or_default().push(buf);
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! An engine that carries out the iocbs with the POSIX AIO (`aio_read(3)`, `aio_write(3)`,
+//! `aio_fsync(3)`), for the systems without the Linux AIO syscalls. A thread waits for the
+//! operations with `aio_suspend(3)`, so their completions are reaped as with the other engines.
+
+use crate::abi;
+use crate::backend::AioBackend;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The longest wait for the operations in flight, after which those submitted meanwhile are
+/// waited for as well.
+const SUSPEND_POLL: Duration = Duration::from_millis(1);
+
+/// An operation in flight, whose aiocb stays at the same place until it is finished.
+struct Op {
+    cb: libc::aiocb,
+    iocb: *mut abi::IOCb,
+}
+// the iocb is owned by its AIO, which outlives the operation
+unsafe impl Send for Op {}
+
+/// The operations in flight, by the address of their iocbs.
+type Ops = Arc<Mutex<HashMap<usize, Box<Op>>>>;
+
+pub struct PosixContext {
+    ops: Ops,
+    done_out: crossbeam_channel::Receiver<abi::IOEvent>,
+    // wakes up the waiter once an operation is submitted while there was none
+    kick_in: crossbeam_channel::Sender<()>,
+    waiter: Option<std::thread::JoinHandle<()>>,
+    // set when dropped, so the waiter cancels the operations in flight and quits
+    closing: Arc<AtomicBool>,
+    // the number of submitted iocbs that are yet to be reaped
+    inflight: AtomicUsize,
+    maxevents: usize,
+}
+
+impl PosixContext {
+    pub fn new(maxevents: u32) -> Self {
+        let ops: Ops = Arc::new(Mutex::new(HashMap::new()));
+        let (done_in, done_out) = crossbeam_channel::unbounded();
+        let (kick_in, kick_out) = crossbeam_channel::bounded(1);
+        let closing = Arc::new(AtomicBool::new(false));
+        let waiter = {
+            let ops = ops.clone();
+            let closing = closing.clone();
+            std::thread::spawn(move || wait(ops, done_in, kick_out, closing))
+        };
+        PosixContext {
+            ops,
+            done_out,
+            kick_in,
+            waiter: Some(waiter),
+            closing,
+            inflight: AtomicUsize::new(0),
+            maxevents: maxevents as usize,
+        }
+    }
+
+    /// Start the operation described by the iocb, returning 0 or the errno.
+    fn start(&self, iocb: *mut abi::IOCb) -> libc::c_int {
+        let i = unsafe { &*iocb };
+        let mut op = Box::new(Op {
+            cb: unsafe { std::mem::zeroed() },
+            iocb,
+        });
+        op.cb.aio_fildes = i.aio_fildes as libc::c_int;
+        op.cb.aio_buf = i.aio_buf as *mut libc::c_void;
+        op.cb.aio_nbytes = i.aio_nbytes as usize;
+        op.cb.aio_offset = i.aio_offset as libc::off_t;
+        op.cb.aio_sigevent.sigev_notify = libc::SIGEV_NONE;
+        // the vectored operations, the polls and the per-operation flags are beyond POSIX AIO
+        let ret = unsafe {
+            match i.aio_lio_opcode {
+                _ if i.aio_rw_flags != 0 => return libc::EINVAL,
+                x if x == abi::IOCmd::PRead as u16 => {
+                    libc::aio_read(&mut op.cb)
+                }
+                x if x == abi::IOCmd::PWrite as u16 => {
+                    libc::aio_write(&mut op.cb)
+                }
+                x if x == abi::IOCmd::FSync as u16 => {
+                    libc::aio_fsync(libc::O_SYNC, &mut op.cb)
+                }
+                x if x == abi::IOCmd::FdSync as u16 => {
+                    libc::aio_fsync(libc::O_DSYNC, &mut op.cb)
+                }
+                _ => return libc::EINVAL,
+            }
+        };
+        if ret < 0 {
+            return std::io::Error::last_os_error()
+                .raw_os_error()
+                .unwrap_or(libc::EIO)
+        }
+        self.ops.lock().insert(iocb as usize, op);
+        0
+    }
+}
+
+/// Wait for the operations in flight and pass on their completions, until dropped.
+fn wait(
+    ops: Ops,
+    done_in: crossbeam_channel::Sender<abi::IOEvent>,
+    kick_out: crossbeam_channel::Receiver<()>,
+    closing: Arc<AtomicBool>,
+) {
+    let timeout = crate::to_timespec(SUSPEND_POLL);
+    loop {
+        // only removed by this thread, so they stay in place while waited for
+        let cbs = ops
+            .lock()
+            .values()
+            .map(|op| &op.cb as *const libc::aiocb)
+            .collect::<Vec<_>>();
+        if cbs.is_empty() {
+            if closing.load(Ordering::Acquire) {
+                break
+            }
+            let _ = kick_out.recv_timeout(SUSPEND_POLL);
+            continue
+        }
+        unsafe {
+            libc::aio_suspend(cbs.as_ptr(), cbs.len() as libc::c_int, &timeout)
+        };
+        let mut ops = ops.lock();
+        let done = ops
+            .iter()
+            .filter(|(_, op)| {
+                let errno = unsafe { libc::aio_error(&op.cb) };
+                errno != libc::EINPROGRESS
+            })
+            .map(|(k, _)| *k)
+            .collect::<Vec<_>>();
+        for k in done {
+            let mut op = ops.remove(&k).unwrap();
+            // aio_return(3) is called once, even upon an error
+            let (errno, ret) = unsafe {
+                (libc::aio_error(&op.cb), libc::aio_return(&mut op.cb))
+            };
+            let res = match errno {
+                0 => ret as i64,
+                errno => -errno as i64,
+            };
+            let iocb = unsafe { &*op.iocb };
+            let ev = abi::IOEvent {
+                data: iocb.aio_data,
+                obj: op.iocb as u64,
+                res,
+                res2: 0,
+            };
+            if iocb.aio_flags & abi::IOCB_FLAG_RESFD != 0 {
+                let one = 1u64;
+                unsafe {
+                    libc::write(
+                        iocb.aio_resfd as libc::c_int,
+                        &one as *const u64 as *const libc::c_void,
+                        8,
+                    );
+                }
+            }
+            let _ = done_in.send(ev);
+        }
+        if closing.load(Ordering::Acquire) {
+            for op in ops.values_mut() {
+                unsafe { libc::aio_cancel(op.cb.aio_fildes, &mut op.cb) };
+            }
+        }
+    }
+}
+
+impl AioBackend for PosixContext {
+    fn submit(&self, iocbs: &mut [*mut abi::IOCb]) -> libc::c_int {
+        let inflight = self.inflight.load(Ordering::Acquire);
+        // behave like a full libaio context when reaching maxevents
+        let n = iocbs.len().min(self.maxevents.saturating_sub(inflight));
+        if n == 0 {
+            return -libc::EAGAIN
+        }
+        let mut nacc = 0;
+        for iocb in iocbs[..n].iter() {
+            let errno = self.start(*iocb);
+            if errno != 0 {
+                // the first one is rejected as by io_submit(2), otherwise the next submission
+                // rejects it
+                if nacc == 0 {
+                    return -errno
+                }
+                break
+            }
+            self.inflight.fetch_add(1, Ordering::AcqRel);
+            nacc += 1;
+        }
+        let _ = self.kick_in.try_send(());
+        nacc as libc::c_int
+    }
+
+    fn reap(
+        &self,
+        min_nr: usize,
+        events: &mut [abi::IOEvent],
+        timeout: Option<&mut libc::timespec>,
+    ) -> libc::c_int {
+        let deadline = timeout.map(|t| {
+            std::time::Instant::now() +
+                Duration::new(t.tv_sec as u64, t.tv_nsec as u32)
+        });
+        let mut nev = 0;
+        while nev < events.len() {
+            let ev = if nev < min_nr {
+                match deadline {
+                    Some(d) => self.done_out.recv_deadline(d).ok(),
+                    None => self.done_out.recv().ok(),
+                }
+            } else {
+                self.done_out.try_recv().ok()
+            };
+            match ev {
+                Some(ev) => events[nev] = ev,
+                None => break,
+            }
+            nev += 1;
+        }
+        self.inflight.fetch_sub(nev, Ordering::AcqRel);
+        nev as libc::c_int
+    }
+
+    fn cancel(&self, iocb: *mut abi::IOCb) -> libc::c_int {
+        let mut ops = self.ops.lock();
+        let op = match ops.get_mut(&(iocb as usize)) {
+            Some(op) => op,
+            None => return -libc::EINVAL,
+        };
+        // the completion then reports ECANCELED
+        match unsafe { libc::aio_cancel(op.cb.aio_fildes, &mut op.cb) } {
+            libc::AIO_CANCELED => 0,
+            libc::AIO_NOTCANCELED => -libc::EINPROGRESS,
+            _ => -libc::EINVAL,
+        }
+    }
+
+    fn supports_noop(&self) -> bool {
+        false
+    }
+}
+
+impl Drop for PosixContext {
+    fn drop(&mut self) {
+        self.closing.store(true, Ordering::Release);
+        if let Some(w) = self.waiter.take() {
+            w.join().unwrap();
+        }
+    }
+}
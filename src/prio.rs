@@ -0,0 +1,59 @@
+//! The I/O priorities, encoded as in `linux/ioprio.h`.
+
+const IOPRIO_CLASS_SHIFT: u16 = 13;
+const IOPRIO_CLASS_RT: u16 = 1;
+const IOPRIO_CLASS_BE: u16 = 2;
+const IOPRIO_CLASS_IDLE: u16 = 3;
+
+const fn ioprio(class: u16, level: u8) -> u16 {
+    (class << IOPRIO_CLASS_SHIFT) | level as u16
+}
+
+/// The I/O priority of an operation: a scheduling class and, for the realtime and best-effort
+/// ones, a level within it from 0 (the highest) to 7 (the lowest).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IoPriority {
+    /// Always gets the first access to the disk (requires `CAP_SYS_ADMIN`).
+    Rt(u8),
+    /// The class of the processes by default.
+    Be(u8),
+    /// Only gets the disk when no one else needs it.
+    Idle,
+}
+
+impl IoPriority {
+    /// The number of levels within the realtime and best-effort classes.
+    pub const NLEVELS: u8 = 8;
+
+    pub fn is_valid(self) -> bool {
+        match self {
+            IoPriority::Rt(l) | IoPriority::Be(l) => l < Self::NLEVELS,
+            IoPriority::Idle => true,
+        }
+    }
+
+    /// The value for `aio_reqprio`, or `None` if the level is out of range.
+    pub fn to_raw(self) -> Option<u16> {
+        if !self.is_valid() {
+            return None
+        }
+        Some(match self {
+            IoPriority::Rt(l) => ioprio(IOPRIO_CLASS_RT, l),
+            IoPriority::Be(l) => ioprio(IOPRIO_CLASS_BE, l),
+            IoPriority::Idle => ioprio(IOPRIO_CLASS_IDLE, 0),
+        })
+    }
+}
+
+/// The number of submission lanes, one per class.
+pub(crate) const NLANES: usize = 3;
+
+/// The submission lane of an operation, from 0 (the most urgent). The operations without a
+/// priority go with the best-effort ones.
+pub(crate) fn lane(priority: Option<IoPriority>) -> usize {
+    match priority {
+        Some(IoPriority::Rt(_)) => 0,
+        Some(IoPriority::Be(_)) | None => 1,
+        Some(IoPriority::Idle) => 2,
+    }
+}
@@ -0,0 +1,169 @@
+//! Sequential reads with pipelined read-ahead.
+
+use crate::{AIOFuture, AIOManagerHandle};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// A sequential reader that keeps the reads of the next `readahead` blocks in flight while the
+/// current block is consumed. A short read is taken as the end of the file. Implements
+/// `AsyncRead`/`AsyncBufRead` of `futures::io` and `tokio::io` (with the `futures-io` and
+/// `tokio-io` features).
+pub struct BufferedReader<'a> {
+    aiomgr: &'a AIOManagerHandle,
+    fd: BorrowedFd<'a>,
+    // the file offset of the next block to read
+    next: u64,
+    // the file offset of the next byte to consume
+    pos: u64,
+    block_size: usize,
+    readahead: usize,
+    inflight: VecDeque<AIOFuture<'a>>,
+    block: Box<[u8]>,
+    consumed: usize,
+    eof: bool,
+}
+
+impl<'a> BufferedReader<'a> {
+    pub fn new(
+        aiomgr: &'a AIOManagerHandle,
+        fd: &'a impl AsFd,
+        offset: u64,
+        block_size: usize,
+        readahead: usize,
+    ) -> Self {
+        assert!(block_size > 0 && readahead > 0);
+        BufferedReader {
+            aiomgr,
+            fd: fd.as_fd(),
+            next: offset,
+            pos: offset,
+            block_size,
+            readahead,
+            inflight: VecDeque::new(),
+            block: Box::default(),
+            consumed: 0,
+            eof: false,
+        }
+    }
+
+    /// The file offset of the next byte to be read.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Read up to `buf.len()` bytes, returning 0 at the end of the file.
+    pub async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        std::future::poll_fn(|cx| self.poll_read_buf(cx, buf)).await
+    }
+
+    fn poll_fill(&mut self, cx: &mut Context) -> Poll<io::Result<&[u8]>> {
+        while self.consumed == self.block.len() && !self.eof {
+            // the fd is borrowed by the reader for 'a, which outlives the futures
+            let fd = self.fd.as_raw_fd();
+            while self.inflight.len() < self.readahead {
+                let r =
+                    self.aiomgr.read_raw(fd, self.next, self.block_size, None);
+                self.next += self.block_size as u64;
+                self.inflight.push_back(r);
+            }
+            let front = self.inflight.front_mut().unwrap();
+            let (res, mut block) = match Pin::new(front).poll(cx) {
+                Poll::Ready(r) => r,
+                Poll::Pending => return Poll::Pending,
+            };
+            self.inflight.pop_front();
+            let n = res.map_err(io::Error::from_raw_os_error)?;
+            if n < self.block_size {
+                // nothing is there for the blocks read ahead
+                self.eof = true;
+                self.inflight.clear();
+                block = block[..n].into();
+            }
+            self.aiomgr
+                .recycle(std::mem::replace(&mut self.block, block));
+            self.consumed = 0;
+        }
+        Poll::Ready(Ok(&self.block[self.consumed..]))
+    }
+
+    fn consume_buf(&mut self, amt: usize) {
+        let amt = amt.min(self.block.len() - self.consumed);
+        self.consumed += amt;
+        self.pos += amt as u64;
+    }
+
+    fn poll_read_buf(
+        &mut self,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let data = match self.poll_fill(cx) {
+            Poll::Ready(r) => r?,
+            Poll::Pending => return Poll::Pending,
+        };
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        self.consume_buf(n);
+        Poll::Ready(Ok(n))
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncRead for BufferedReader<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_read_buf(cx, buf)
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncBufRead for BufferedReader<'_> {
+    fn poll_fill_buf(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<io::Result<&[u8]>> {
+        self.get_mut().poll_fill(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().consume_buf(amt)
+    }
+}
+
+#[cfg(feature = "tokio-io")]
+impl tokio::io::AsyncRead for BufferedReader<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut tokio::io::ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        let n =
+            match self.get_mut().poll_read_buf(cx, buf.initialize_unfilled()) {
+                Poll::Ready(r) => r?,
+                Poll::Pending => return Poll::Pending,
+            };
+        buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio-io")]
+impl tokio::io::AsyncBufRead for BufferedReader<'_> {
+    fn poll_fill_buf(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<io::Result<&[u8]>> {
+        self.get_mut().poll_fill(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().consume_buf(amt)
+    }
+}
@@ -0,0 +1,48 @@
+//! Retrying the AIOs failed with a transient error.
+
+use std::time::Duration;
+
+/// How the AIOs failed with a transient error (`EAGAIN` or `EINTR`) are submitted again,
+/// instead of resolving their futures with the error: up to `max_attempts` attempts in total,
+/// waiting `backoff` before the first retry and twice as long before every following one. The
+/// default never retries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Panics if `max_attempts` is 0.
+    pub fn new(max_attempts: u32, backoff: Duration) -> Self {
+        assert!(max_attempts > 0, "max_attempts must be positive");
+        RetryPolicy {
+            max_attempts,
+            backoff,
+        }
+    }
+
+    /// Fail the AIOs with their first error.
+    pub fn never() -> Self {
+        Self::new(1, Duration::ZERO)
+    }
+
+    pub(crate) fn is_transient(res: i64) -> bool {
+        res == -libc::EAGAIN as i64 || res == -libc::EINTR as i64
+    }
+
+    /// The wait before the next attempt after `attempts` of them, if any is left.
+    pub(crate) fn backoff(&self, attempts: u32) -> Option<Duration> {
+        if attempts >= self.max_attempts {
+            return None
+        }
+        let factor = 1u32.checked_shl(attempts - 1).unwrap_or(u32::MAX);
+        Some(self.backoff.saturating_mul(factor))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::never()
+    }
+}
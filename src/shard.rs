@@ -0,0 +1,160 @@
+//! Spreading the AIOs over several contexts, each reaped by its own thread.
+
+use crate::abi;
+use crate::backend::AioBackend;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// The longest wait on a context while reaping all of them (see `ShardedContext::reap`).
+const SHARD_POLL: Duration = Duration::from_millis(1);
+
+/// How the AIOs are routed among the contexts (see `AIOBuilder::shards`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sharding {
+    /// By the file descriptor, so the AIOs on the same fd share a context.
+    Fd,
+    /// Each submission to the next context in turn, skipping the full ones.
+    RoundRobin,
+}
+
+pub(crate) struct ShardedContext {
+    shards: Vec<Box<dyn AioBackend>>,
+    by: Sharding,
+    // the next shard to submit to (or to wait on when reaping all of them)
+    next: AtomicUsize,
+}
+
+impl ShardedContext {
+    pub(crate) fn new(shards: Vec<Box<dyn AioBackend>>, by: Sharding) -> Self {
+        ShardedContext {
+            shards,
+            by,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    fn shard_of(&self, iocb: *mut abi::IOCb) -> usize {
+        let fd = unsafe { (*iocb).aio_fildes };
+        fd as usize % self.shards.len()
+    }
+
+    /// Submit the consecutive iocbs on the same shard together, until one is not accepted.
+    fn submit_by_fd(&self, iocbs: &mut [*mut abi::IOCb]) -> libc::c_int {
+        let mut nacc = 0;
+        while nacc < iocbs.len() {
+            let shard = self.shard_of(iocbs[nacc]);
+            let run = iocbs[nacc..]
+                .iter()
+                .take_while(|iocb| self.shard_of(**iocb) == shard)
+                .count();
+            let ret = self.shards[shard].submit(&mut iocbs[nacc..nacc + run]);
+            if ret < 0 {
+                return if nacc > 0 { nacc as libc::c_int } else { ret }
+            }
+            nacc += ret as usize;
+            if (ret as usize) < run {
+                break
+            }
+        }
+        nacc as libc::c_int
+    }
+
+    fn submit_round_robin(&self, iocbs: &mut [*mut abi::IOCb]) -> libc::c_int {
+        let n = self.shards.len();
+        let first = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut ret = -libc::EAGAIN;
+        for k in 0..n {
+            ret = self.shards[(first + k) % n].submit(iocbs);
+            if ret != -libc::EAGAIN {
+                break
+            }
+        }
+        ret
+    }
+}
+
+impl AioBackend for ShardedContext {
+    fn submit(&self, iocbs: &mut [*mut abi::IOCb]) -> libc::c_int {
+        match self.by {
+            Sharding::Fd => self.submit_by_fd(iocbs),
+            Sharding::RoundRobin => self.submit_round_robin(iocbs),
+        }
+    }
+
+    /// Collect the completions of all the shards, waiting on one of them in turn for a short
+    /// while, so the others are not left behind.
+    fn reap(
+        &self,
+        min_nr: usize,
+        events: &mut [abi::IOEvent],
+        timeout: Option<&mut libc::timespec>,
+    ) -> libc::c_int {
+        let mut nev = 0;
+        for shard in self.shards.iter() {
+            let mut no_wait = crate::to_timespec(Duration::ZERO);
+            let ret = shard.reap(0, &mut events[nev..], Some(&mut no_wait));
+            if ret < 0 {
+                return if nev > 0 { nev as libc::c_int } else { ret }
+            }
+            nev += ret as usize;
+            if nev == events.len() {
+                break
+            }
+        }
+        if nev >= min_nr || nev == events.len() {
+            return nev as libc::c_int
+        }
+        let t = match timeout {
+            Some(t) => {
+                Duration::new(t.tv_sec as u64, t.tv_nsec as u32).min(SHARD_POLL)
+            }
+            None => SHARD_POLL,
+        };
+        let mut bounded = crate::to_timespec(t);
+        let shard =
+            self.next.fetch_add(1, Ordering::Relaxed) % self.shards.len();
+        let ret = self.shards[shard].reap(
+            min_nr - nev,
+            &mut events[nev..],
+            Some(&mut bounded),
+        );
+        if ret < 0 {
+            return if nev > 0 { nev as libc::c_int } else { ret }
+        }
+        (nev + ret as usize) as libc::c_int
+    }
+
+    fn cancel(&self, iocb: *mut abi::IOCb) -> libc::c_int {
+        if self.by == Sharding::Fd {
+            return self.shards[self.shard_of(iocb)].cancel(iocb)
+        }
+        // io_uring reports a missing target only through the completion of the cancel, so
+        // all the shards are tried
+        self.shards.iter().fold(-libc::EINVAL, |res, shard| {
+            match shard.cancel(iocb) {
+                ret if res == -libc::EINVAL => ret,
+                _ => res,
+            }
+        })
+    }
+
+    fn destroy(&self) {
+        for shard in self.shards.iter() {
+            shard.destroy()
+        }
+    }
+
+    fn supports_noop(&self) -> bool {
+        self.shards[0].supports_noop()
+    }
+
+    fn reap_shard(
+        &self,
+        shard: usize,
+        min_nr: usize,
+        events: &mut [abi::IOEvent],
+        timeout: Option<&mut libc::timespec>,
+    ) -> libc::c_int {
+        self.shards[shard % self.shards.len()].reap(min_nr, events, timeout)
+    }
+}
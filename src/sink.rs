@@ -0,0 +1,115 @@
+//! Positioned writes fed through `futures::Sink`.
+
+use crate::{AIOFuture, AIOManagerHandle};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// A `futures::Sink` of `(offset, data)` pairs, each written at its offset, with up to `depth`
+/// writes in flight: beyond them, the sink is not ready until one of them finishes, which holds
+/// back a faster producer (e.g., `SinkExt::send_all`). The writes may finish in any order, so
+/// overlapping ones leave either data. A failed write (a short write fails with `EIO`) fails
+/// the next call on the sink, and the data of the writes still in flight is written regardless.
+pub struct AIOSink<'a> {
+    aiomgr: &'a AIOManagerHandle,
+    fd: BorrowedFd<'a>,
+    depth: usize,
+    inflight: VecDeque<(AIOFuture<'a>, usize)>,
+}
+
+impl<'a> AIOSink<'a> {
+    pub fn new(
+        aiomgr: &'a AIOManagerHandle,
+        fd: &'a impl AsFd,
+        depth: usize,
+    ) -> Self {
+        assert!(depth > 0);
+        AIOSink {
+            aiomgr,
+            fd: fd.as_fd(),
+            depth,
+            inflight: VecDeque::new(),
+        }
+    }
+
+    /// The number of writes in flight.
+    pub fn in_flight(&self) -> usize {
+        self.inflight.len()
+    }
+
+    /// Drop the finished writes, failing with the error of the first failed one.
+    fn poll_inflight(&mut self, cx: &mut Context) -> io::Result<()> {
+        let mut i = 0;
+        while i < self.inflight.len() {
+            let (w, len) = &mut self.inflight[i];
+            let len = *len;
+            match Pin::new(w).poll(cx) {
+                Poll::Ready((res, _)) => {
+                    self.inflight.remove(i);
+                    match res {
+                        Ok(n) if n == len => (),
+                        Ok(_) => {
+                            return Err(io::Error::from_raw_os_error(libc::EIO))
+                        }
+                        Err(e) => return Err(io::Error::from_raw_os_error(e)),
+                    }
+                }
+                Poll::Pending => i += 1,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl futures_sink::Sink<(u64, Box<[u8]>)> for AIOSink<'_> {
+    type Error = io::Error;
+
+    fn poll_ready(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.poll_inflight(cx)?;
+        if this.inflight.len() < this.depth {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+
+    fn start_send(
+        self: Pin<&mut Self>,
+        (offset, data): (u64, Box<[u8]>),
+    ) -> io::Result<()> {
+        let this = self.get_mut();
+        let len = data.len();
+        // the fd is borrowed by the sink for 'a, which outlives the futures
+        let fd = this.fd.as_raw_fd();
+        let w = this.aiomgr.write_raw(fd, offset, data, None);
+        this.inflight.push_back((w, len));
+        Ok(())
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.poll_inflight(cx)?;
+        if this.inflight.is_empty() {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
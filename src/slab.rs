@@ -0,0 +1,213 @@
+//! The states of the AIOs by their ids, each behind a lock of its own, so finishing an AIO
+//! does not hold up the others. Its future learns that it is finished without taking the
+//! lock, so it never contends with the thread finishing it.
+
+use crate::waker::AtomicWaker;
+use parking_lot::{Mutex, MutexGuard};
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::task::Waker;
+
+/// The smallest number of slots.
+const MIN_SLOTS: usize = 256;
+
+pub(crate) struct Slot<T> {
+    entry: Mutex<Option<(u64, T)>>,
+    // the id of the entry plus one (0 if none), and whether it is finished, only changed
+    // while holding the lock
+    owner: AtomicU64,
+    ready: AtomicBool,
+    waker: AtomicWaker,
+}
+
+/// An entry which spilled over, with what the slot keeps otherwise.
+pub(crate) struct Spilled<T> {
+    v: T,
+    ready: bool,
+    waker: Option<Waker>,
+}
+
+pub(crate) struct Slab<T> {
+    // as the ids are taken in sequence, that at `id & mask` is most likely free
+    slots: Box<[Slot<T>]>,
+    mask: u64,
+    // the entries whose slot is taken by another one (e.g., a long-lived AIO)
+    overflow: Mutex<HashMap<u64, Spilled<T>>>,
+    noverflow: AtomicUsize,
+}
+
+/// An entry, locked until dropped.
+pub(crate) enum Entry<'a, T> {
+    Slot(MutexGuard<'a, Option<(u64, T)>>, &'a Slot<T>),
+    Overflow(
+        MutexGuard<'a, HashMap<u64, Spilled<T>>>,
+        u64,
+        &'a AtomicUsize,
+    ),
+}
+
+impl<T> Slab<T> {
+    /// Room for about `n` entries before any spills over (into a map).
+    pub(crate) fn new(n: usize) -> Self {
+        let n = n.max(MIN_SLOTS).next_power_of_two();
+        Slab {
+            slots: (0..n)
+                .map(|_| Slot {
+                    entry: Mutex::new(None),
+                    owner: AtomicU64::new(0),
+                    ready: AtomicBool::new(false),
+                    waker: AtomicWaker::new(),
+                })
+                .collect(),
+            mask: n as u64 - 1,
+            overflow: Mutex::new(HashMap::new()),
+            noverflow: AtomicUsize::new(0),
+        }
+    }
+
+    fn slot(&self, id: u64) -> &Slot<T> {
+        &self.slots[(id & self.mask) as usize]
+    }
+
+    /// Add the entry (finished already if `ready`), unless there is already one with the id.
+    pub(crate) fn insert(&self, id: u64, v: T, ready: bool) -> bool {
+        let slot = self.slot(id);
+        let mut entry = slot.entry.lock();
+        if entry.as_ref().is_some_and(|(i, _)| *i == id) {
+            return false
+        }
+        if entry.is_none() && self.noverflow.load(Ordering::Acquire) == 0 {
+            slot.fill(&mut entry, id, v, ready);
+            return true
+        }
+        let mut overflow = self.overflow.lock();
+        if overflow.contains_key(&id) {
+            return false
+        }
+        if entry.is_none() {
+            slot.fill(&mut entry, id, v, ready)
+        } else {
+            let waker = None;
+            overflow.insert(id, Spilled { v, ready, waker });
+            self.noverflow.fetch_add(1, Ordering::Release);
+        }
+        true
+    }
+
+    pub(crate) fn get(&self, id: u64) -> Option<Entry<'_, T>> {
+        let slot = self.slot(id);
+        let entry = slot.entry.lock();
+        if entry.as_ref().is_some_and(|(i, _)| *i == id) {
+            return Some(Entry::Slot(entry, slot))
+        }
+        drop(entry);
+        if self.noverflow.load(Ordering::Acquire) == 0 {
+            return None
+        }
+        let overflow = self.overflow.lock();
+        overflow
+            .contains_key(&id)
+            .then(|| Entry::Overflow(overflow, id, &self.noverflow))
+    }
+
+    /// Whether the (existing) entry is finished (see `Entry::finish`), otherwise have the waker
+    /// woken up once it is.
+    pub(crate) fn register(&self, id: u64, waker: &Waker) -> bool {
+        let slot = self.slot(id);
+        // stays the same as long as the entry is there
+        if slot.owner.load(Ordering::Acquire) == id.wrapping_add(1) {
+            if slot.ready.load(Ordering::Acquire) {
+                return true
+            }
+            slot.waker.register(waker);
+            return slot.ready.load(Ordering::Acquire)
+        }
+        let mut overflow = self.overflow.lock();
+        let s = overflow.get_mut(&id).unwrap();
+        if !s.ready && !s.waker.as_ref().is_some_and(|w| w.will_wake(waker)) {
+            s.waker = Some(waker.clone())
+        }
+        s.ready
+    }
+
+    /// Visit all the entries, locking one at a time.
+    pub(crate) fn for_each(&self, mut f: impl FnMut(u64, &mut T)) {
+        for slot in self.slots.iter() {
+            if let Some((id, v)) = slot.entry.lock().as_mut() {
+                f(*id, v)
+            }
+        }
+        if self.noverflow.load(Ordering::Acquire) > 0 {
+            for (id, s) in self.overflow.lock().iter_mut() {
+                f(*id, &mut s.v)
+            }
+        }
+    }
+}
+
+impl<T> Slot<T> {
+    fn fill(&self, entry: &mut Option<(u64, T)>, id: u64, v: T, ready: bool) {
+        *entry = Some((id, v));
+        self.owner.store(id.wrapping_add(1), Ordering::Release);
+        self.ready.store(ready, Ordering::Release);
+    }
+}
+
+impl<T> Entry<'_, T> {
+    pub(crate) fn remove(self) -> T {
+        match self {
+            Entry::Slot(mut entry, slot) => {
+                slot.owner.store(0, Ordering::Release);
+                slot.ready.store(false, Ordering::Release);
+                slot.waker.take();
+                entry.take().unwrap().1
+            }
+            Entry::Overflow(mut overflow, id, noverflow) => {
+                noverflow.fetch_sub(1, Ordering::Release);
+                overflow.remove(&id).unwrap().v
+            }
+        }
+    }
+
+    /// Mark the entry as finished, returning the waker registered for it, to be woken up once
+    /// unlocked.
+    pub(crate) fn finish(self) -> Option<Waker> {
+        // taken before unlocking, as the slot may then go to another entry
+        match self {
+            Entry::Slot(entry, slot) => {
+                slot.ready.store(true, Ordering::Release);
+                let waker = slot.waker.take();
+                drop(entry);
+                waker
+            }
+            Entry::Overflow(mut overflow, id, _) => {
+                let s = overflow.get_mut(&id).unwrap();
+                s.ready = true;
+                s.waker.take()
+            }
+        }
+    }
+}
+
+impl<T> Deref for Entry<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match self {
+            Entry::Slot(entry, _) => &entry.as_ref().unwrap().1,
+            Entry::Overflow(overflow, id, _) => &overflow[id].v,
+        }
+    }
+}
+
+impl<T> DerefMut for Entry<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        match self {
+            Entry::Slot(entry, _) => &mut entry.as_mut().unwrap().1,
+            Entry::Overflow(overflow, id, _) => {
+                &mut overflow.get_mut(id).unwrap().v
+            }
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! Splitting the oversized reads and writes into chunks submitted separately.
+
+use crate::abi;
+use std::collections::HashMap;
+
+/// The ids of the chunks have this bit set, so they never clash with those of the AIOs (nor
+/// with those of the merged writes, see `merge::MERGED_ID`).
+pub(crate) const SPLIT_ID: u64 = 1 << 62;
+
+pub(crate) struct Splits {
+    // the most bytes per chunk
+    max: u64,
+    // the chunks by the id of the AIO they are split from
+    split: HashMap<u64, Split>,
+    // the AIO of each chunk, with the index of the chunk
+    chunks: HashMap<u64, (u64, usize)>,
+    last_id: u64,
+}
+
+struct Split {
+    iocbs: Box<[abi::IOCb]>,
+    // the results of the chunks finished so far
+    results: Vec<Option<i64>>,
+}
+
+impl Splits {
+    /// Panics if `max` is 0.
+    pub(crate) fn new(max: usize) -> Self {
+        assert!(max > 0, "max_io_size must be positive");
+        Splits {
+            max: max as u64,
+            split: HashMap::new(),
+            chunks: HashMap::new(),
+            last_id: 0,
+        }
+    }
+
+    pub(crate) fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Replace each read or write longer than the maximum among the iocbs by its chunks, in
+    /// the order of their offsets.
+    pub(crate) fn split(&mut self, iocbs: &mut Vec<*mut abi::IOCb>) {
+        if !iocbs
+            .iter()
+            .any(|iocb| self.is_oversized(unsafe { &**iocb }))
+        {
+            return
+        }
+        let mut out = Vec::with_capacity(iocbs.len());
+        for iocb in iocbs.drain(..) {
+            let parent = unsafe { &*iocb };
+            if !self.is_oversized(parent) {
+                out.push(iocb);
+                continue
+            }
+            let n = parent.aio_nbytes.div_ceil(self.max) as usize;
+            let mut chunks = (0..n as u64)
+                .map(|k| {
+                    self.last_id =
+                        self.last_id.wrapping_add(1) & (SPLIT_ID - 1);
+                    let id = SPLIT_ID | self.last_id;
+                    self.chunks.insert(id, (parent.aio_data, k as usize));
+                    let skip = k * self.max;
+                    abi::IOCb {
+                        aio_data: id,
+                        aio_rw_flags: parent.aio_rw_flags,
+                        aio_lio_opcode: parent.aio_lio_opcode,
+                        aio_reqprio: parent.aio_reqprio,
+                        aio_fildes: parent.aio_fildes,
+                        aio_buf: parent.aio_buf + skip,
+                        aio_nbytes: self.max.min(parent.aio_nbytes - skip),
+                        aio_offset: parent.aio_offset + skip,
+                        aio_flags: parent.aio_flags,
+                        aio_resfd: parent.aio_resfd,
+                        ..Default::default()
+                    }
+                })
+                .collect::<Box<[_]>>();
+            out.extend(chunks.iter_mut().map(|c| c as *mut abi::IOCb));
+            self.split.insert(
+                parent.aio_data,
+                Split {
+                    iocbs: chunks,
+                    results: vec![None; n],
+                },
+            );
+        }
+        *iocbs = out;
+    }
+
+    fn is_oversized(&self, iocb: &abi::IOCb) -> bool {
+        let op = iocb.aio_lio_opcode;
+        (op == abi::IOCmd::PRead as u16 || op == abi::IOCmd::PWrite as u16) &&
+            iocb.aio_nbytes > self.max
+    }
+
+    /// The id of the AIO the chunk is split from, along with whether it is the first chunk.
+    pub(crate) fn aio_of(&self, id: u64) -> Option<(u64, bool)> {
+        self.chunks.get(&id).map(|(aio, k)| (*aio, *k == 0))
+    }
+
+    /// Record the result of a chunk. Once all the chunks are finished, returns the id of the
+    /// AIO with its result: the bytes transferred up to the first short (or failed) chunk, or
+    /// the error of the first chunk.
+    pub(crate) fn finish(&mut self, id: u64, res: i64) -> Option<(u64, i64)> {
+        let (aio, k) = self.chunks.remove(&id)?;
+        let split = self.split.get_mut(&aio).unwrap();
+        split.results[k] = Some(res);
+        if split.results.iter().any(|r| r.is_none()) {
+            return None
+        }
+        let split = self.split.remove(&aio).unwrap();
+        let mut total = 0;
+        for (iocb, r) in split.iocbs.iter().zip(split.results) {
+            let r = r.unwrap();
+            if r < 0 {
+                return Some((aio, if total == 0 { r } else { total }))
+            }
+            total += r;
+            if (r as u64) < iocb.aio_nbytes {
+                break
+            }
+        }
+        Some((aio, total))
+    }
+}
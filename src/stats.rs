@@ -0,0 +1,177 @@
+//! The counters of the AIOs, maintained with atomics on the submit and finish paths.
+
+use crate::abi;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// A snapshot of the counters of an AIOManager, since it was built.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The AIOs accepted by the kernel (or the backend).
+    pub submitted: u64,
+    /// The AIOs finished successfully.
+    pub completed: u64,
+    /// The AIOs finished with an error other than `ECANCELED`.
+    pub failed: u64,
+    /// The AIOs cancelled, before or after their submission.
+    pub cancelled: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// The AIOs submitted but not yet finished.
+    pub in_flight: u64,
+    /// The AIOs finished after their deadlines.
+    pub deadline_misses: u64,
+    /// The AIOs slower than `AIOBuilder::slow_op_threshold`.
+    pub slow_ops: u64,
+    /// The AIOs submitted again after a transient error (see `RetryPolicy`).
+    pub retries: u64,
+    /// The submissions which found the context full (`EAGAIN`), each followed by a backoff. A
+    /// steady growth means the context is too small (see `AIOBuilder::max_events`), or the
+    /// system out of AIO events.
+    pub submit_eagain: u64,
+}
+
+/// The counters of the AIOs on a file descriptor, as enabled by `AIOBuilder::fd_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FdStats {
+    /// The finished AIOs.
+    pub ops: u64,
+    /// The bytes read or written.
+    pub bytes: u64,
+    /// The AIOs finished with an error (including `ECANCELED`).
+    pub errors: u64,
+    /// The sum of the times from the scheduling to the completion of the AIOs.
+    pub total_latency: Duration,
+}
+
+/// The counters of the AIOs of a tenant (see `AIOManager::register_tenant`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TenantStats {
+    /// The finished AIOs, counted as those on a file descriptor.
+    pub finished: FdStats,
+    /// The AIOs taken for submission but not yet finished.
+    pub in_flight: u64,
+}
+
+impl FdStats {
+    pub fn mean_latency(&self) -> Duration {
+        match self.ops {
+            0 => Duration::ZERO,
+            n => Duration::from_nanos(
+                (self.total_latency.as_nanos() / n as u128) as u64,
+            ),
+        }
+    }
+
+    pub(crate) fn reaped(&mut self, res: i64, latency: Duration) {
+        self.ops += 1;
+        if res < 0 {
+            self.errors += 1
+        } else {
+            self.bytes += res as u64
+        }
+        self.total_latency += latency;
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Counters {
+    submitted: AtomicU64,
+    completed: AtomicU64,
+    failed: AtomicU64,
+    cancelled: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    // the finished ones among the submitted
+    reaped: AtomicU64,
+    deadline_misses: AtomicU64,
+    slow_ops: AtomicU64,
+    retries: AtomicU64,
+    submit_eagain: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn submitted(&self, n: usize) {
+        self.submitted.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// Count a reaped AIO of the given opcode, with its result in the form of `io_event.res`.
+    pub(crate) fn reaped(&self, opcode: u16, res: i64) {
+        self.reaped.fetch_add(1, Ordering::Relaxed);
+        if res == -libc::ECANCELED as i64 {
+            self.cancelled.fetch_add(1, Ordering::Relaxed);
+        } else if res < 0 {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.completed.fetch_add(1, Ordering::Relaxed);
+            let bytes = match opcode {
+                x if x == abi::IOCmd::PRead as u16 ||
+                    x == abi::IOCmd::PReadV as u16 =>
+                {
+                    &self.bytes_read
+                }
+                x if x == abi::IOCmd::PWrite as u16 ||
+                    x == abi::IOCmd::PWriteV as u16 =>
+                {
+                    &self.bytes_written
+                }
+                _ => return,
+            };
+            bytes.fetch_add(res as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Count a submission which found the context full.
+    pub(crate) fn submit_eagain(&self) {
+        self.submit_eagain.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a reaped AIO to be submitted again.
+    pub(crate) fn retried(&self) {
+        self.reaped.fetch_add(1, Ordering::Relaxed);
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an AIO cancelled before its submission.
+    pub(crate) fn discarded(&self) {
+        self.cancelled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn deadline_missed(&self) {
+        self.deadline_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn slow_op(&self) {
+        self.slow_ops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn deadline_misses(&self) -> u64 {
+        self.deadline_misses.load(Ordering::Relaxed)
+    }
+
+    /// The AIOs submitted but not yet reaped, which is never less than those in the kernel.
+    pub(crate) fn in_flight(&self) -> u64 {
+        let reaped = self.reaped.load(Ordering::Relaxed);
+        let submitted = self.submitted.load(Ordering::Relaxed);
+        submitted.saturating_sub(reaped)
+    }
+
+    pub(crate) fn snapshot(&self) -> Stats {
+        let submitted = self.submitted.load(Ordering::Relaxed);
+        let reaped = self.reaped.load(Ordering::Relaxed);
+        Stats {
+            submitted,
+            completed: self.completed.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            cancelled: self.cancelled.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            // the counters may be momentarily off with respect to each other
+            in_flight: submitted.saturating_sub(reaped),
+            deadline_misses: self.deadline_misses(),
+            slow_ops: self.slow_ops.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            submit_eagain: self.submit_eagain.load(Ordering::Relaxed),
+        }
+    }
+}
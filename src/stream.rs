@@ -0,0 +1,235 @@
+//! Expose an AIOFile as a byte stream with a cursor, for the code written
+//! against the async IO traits.
+
+use crate::cursor::offset_by;
+use crate::{AIOFile, AIOFuture};
+use std::future::Future;
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// An AIOFile with a cursor, implementing `futures::io::{AsyncRead, AsyncWrite, AsyncSeek}` (with
+/// the `futures-io` feature) and `tokio::io::{AsyncRead, AsyncWrite, AsyncSeek}` (with the
+/// `tokio-io` feature).
+/// Each call on the stream is carried out by a single AIO at the cursor. Like with other
+/// streams, a pending write (or read) has to be polled again with the same buffer.
+pub struct AIOStream<'a> {
+    file: AIOFile<'a>,
+    pos: u64,
+    read: Option<AIOFuture<'static>>,
+    write: Option<AIOFuture<'static, Vec<u8>>>,
+    // the target of tokio's start_seek()
+    #[cfg(feature = "tokio-io")]
+    seek: Option<SeekFrom>,
+}
+
+impl<'a> AIOStream<'a> {
+    pub fn new(file: AIOFile<'a>) -> Self {
+        AIOStream {
+            file,
+            pos: 0,
+            read: None,
+            write: None,
+            #[cfg(feature = "tokio-io")]
+            seek: None,
+        }
+    }
+
+    /// The current position of the cursor.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    pub fn into_inner(self) -> AIOFile<'a> {
+        self.file
+    }
+
+    fn poll_read_at(
+        &mut self,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let (pos, file) = (self.pos, &self.file);
+        let read = self
+            .read
+            .get_or_insert_with(|| file.read_at(pos, buf.len(), None));
+        let (res, data) = match Pin::new(read).poll(cx) {
+            Poll::Ready(r) => r,
+            Poll::Pending => return Poll::Pending,
+        };
+        self.read = None;
+        let nread = res.map_err(io::Error::from_raw_os_error)?;
+        // the buffer could have shrunk since the read was issued
+        let n = nread.min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        self.pos += n as u64;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_write_at(
+        &mut self,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let (pos, file) = (self.pos, &self.file);
+        let write = self
+            .write
+            .get_or_insert_with(|| file.write_at(pos, buf.to_vec(), None));
+        let (res, _) = match Pin::new(write).poll(cx) {
+            Poll::Ready(r) => r,
+            Poll::Pending => return Poll::Pending,
+        };
+        self.write = None;
+        let n = res.map_err(io::Error::from_raw_os_error)?;
+        self.pos += n as u64;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush_write(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        if self.write.is_none() {
+            return Poll::Ready(Ok(()))
+        }
+        self.poll_write_at(cx, &[]).map_ok(|_| ())
+    }
+
+    fn seek_to(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let off = match pos {
+            SeekFrom::Start(off) => Some(off),
+            SeekFrom::Current(d) => offset_by(self.pos, d),
+            SeekFrom::End(d) => {
+                offset_by(self.file.file().metadata()?.len(), d)
+            }
+        }
+        .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))?;
+        // a read issued at the old position is no longer wanted
+        self.read = None;
+        self.pos = off;
+        Ok(off)
+    }
+
+    /// Seek once the pending write (issued at the old position) is done.
+    fn poll_seek_to(
+        &mut self,
+        cx: &mut Context,
+        pos: SeekFrom,
+    ) -> Poll<io::Result<u64>> {
+        match self.poll_flush_write(cx) {
+            Poll::Ready(r) => r?,
+            Poll::Pending => return Poll::Pending,
+        }
+        Poll::Ready(self.seek_to(pos))
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncRead for AIOStream<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_read_at(cx, buf)
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncWrite for AIOStream<'_> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_write_at(cx, buf)
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush_write(cx)
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush_write(cx)
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncSeek for AIOStream<'_> {
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        pos: SeekFrom,
+    ) -> Poll<io::Result<u64>> {
+        self.get_mut().poll_seek_to(cx, pos)
+    }
+}
+
+#[cfg(feature = "tokio-io")]
+impl tokio::io::AsyncRead for AIOStream<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut tokio::io::ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        let n = match self.get_mut().poll_read_at(cx, buf.initialize_unfilled())
+        {
+            Poll::Ready(r) => r?,
+            Poll::Pending => return Poll::Pending,
+        };
+        buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio-io")]
+impl tokio::io::AsyncWrite for AIOStream<'_> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_write_at(cx, buf)
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush_write(cx)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush_write(cx)
+    }
+}
+
+#[cfg(feature = "tokio-io")]
+impl tokio::io::AsyncSeek for AIOStream<'_> {
+    fn start_seek(self: Pin<&mut Self>, pos: SeekFrom) -> io::Result<()> {
+        self.get_mut().seek = Some(pos);
+        Ok(())
+    }
+
+    fn poll_complete(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+        let pos = match this.seek {
+            Some(pos) => pos,
+            None => return Poll::Ready(Ok(this.pos)),
+        };
+        let res = this.poll_seek_to(cx, pos);
+        if res.is_ready() {
+            this.seek = None;
+        }
+        res
+    }
+}
@@ -0,0 +1,165 @@
+//! The tenants sharing an AIOManager, each with its own tag, quotas and counters.
+
+use crate::abi;
+use crate::stats::TenantStats;
+use crate::throttle::{rw_bytes, Bucket};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+/// The tags from this one up are those of the tenants.
+const FIRST_TAG: u32 = 1 << 31;
+
+/// A tenant registered by `AIOManager::register_tenant`, whose AIOs are those with its tag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Tenant {
+    tag: u32,
+}
+
+impl Tenant {
+    /// The tag to schedule the AIOs of the tenant with (e.g., by `AIOManager::read_with_tag`).
+    pub fn tag(&self) -> u32 {
+        self.tag
+    }
+}
+
+/// The most AIOs of a tenant taken for submission but not yet finished, and the most bytes of
+/// its reads and writes submitted per second (0 for no limit). The AIOs beyond them stay
+/// queued, without holding back those of the other tenants. The default has no limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TenantQuota {
+    max_in_flight: u64,
+    bytes: u64,
+}
+
+impl TenantQuota {
+    pub fn new(max_in_flight: u64, bytes: u64) -> Self {
+        TenantQuota {
+            max_in_flight,
+            bytes,
+        }
+    }
+
+    /// No limit.
+    pub fn none() -> Self {
+        Self::default()
+    }
+}
+
+struct State {
+    name: String,
+    max_in_flight: u64,
+    bytes: Bucket,
+    stats: TenantStats,
+    // when the AIO held back for the lack of bytes may go
+    due: Option<Instant>,
+}
+
+pub(crate) struct Tenants {
+    next_tag: AtomicU32,
+    tenants: Mutex<HashMap<u32, State>>,
+}
+
+impl Tenants {
+    pub(crate) fn new() -> Self {
+        Tenants {
+            next_tag: AtomicU32::new(FIRST_TAG),
+            tenants: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn register(&self, name: &str, quota: TenantQuota) -> Tenant {
+        let tag = self.next_tag.fetch_add(1, Ordering::Relaxed);
+        assert!(tag >= FIRST_TAG, "too many tenants");
+        self.tenants.lock().insert(
+            tag,
+            State {
+                name: name.to_string(),
+                max_in_flight: quota.max_in_flight,
+                bytes: Bucket::new(quota.bytes, Instant::now()),
+                stats: TenantStats::default(),
+                due: None,
+            },
+        );
+        Tenant { tag }
+    }
+
+    /// Replace the quota of the tenant, starting with a full bucket of bytes.
+    pub(crate) fn set_quota(&self, tenant: Tenant, quota: TenantQuota) {
+        if let Some(s) = self.tenants.lock().get_mut(&tenant.tag) {
+            s.max_in_flight = quota.max_in_flight;
+            s.bytes = Bucket::new(quota.bytes, Instant::now());
+            s.due = None;
+        }
+    }
+
+    pub(crate) fn is_tenant(tag: u32) -> bool {
+        tag >= FIRST_TAG
+    }
+
+    /// Whether the AIO with the tag is within the quotas of its tenant, if any.
+    pub(crate) fn may_submit(&self, tag: u32, iocb: *mut abi::IOCb) -> bool {
+        if !Self::is_tenant(tag) {
+            return true
+        }
+        let mut tenants = self.tenants.lock();
+        let s = match tenants.get_mut(&tag) {
+            Some(s) => s,
+            None => return true,
+        };
+        if s.max_in_flight > 0 && s.stats.in_flight >= s.max_in_flight {
+            return false
+        }
+        let now = Instant::now();
+        s.bytes.refill(now);
+        s.due = s.bytes.due(Self::bytes(iocb));
+        s.due.is_none()
+    }
+
+    /// Account for the AIO with the tag taken for submission.
+    pub(crate) fn submitted(&self, tag: u32, iocb: *mut abi::IOCb) {
+        if !Self::is_tenant(tag) {
+            return
+        }
+        if let Some(s) = self.tenants.lock().get_mut(&tag) {
+            s.bytes.take(Self::bytes(iocb));
+            s.stats.in_flight += 1;
+        }
+    }
+
+    /// Account for the finished AIO with the tag.
+    pub(crate) fn finished(&self, tag: u32, res: i64, latency: Duration) {
+        if !Self::is_tenant(tag) {
+            return
+        }
+        if let Some(s) = self.tenants.lock().get_mut(&tag) {
+            // not taken for submission if failed by the shutdown
+            s.stats.in_flight = s.stats.in_flight.saturating_sub(1);
+            s.stats.finished.reaped(res, latency)
+        }
+    }
+
+    /// When the first of the tenants held back for the lack of bytes may go.
+    pub(crate) fn next_due(&self) -> Option<Instant> {
+        let now = Instant::now();
+        let tenants = self.tenants.lock();
+        tenants
+            .values()
+            .filter_map(|s| s.due)
+            .filter(|due| *due > now)
+            .min()
+    }
+
+    pub(crate) fn stats(&self) -> Vec<(String, TenantStats)> {
+        let tenants = self.tenants.lock();
+        tenants
+            .values()
+            .map(|s| (s.name.clone(), s.stats))
+            .collect()
+    }
+
+    fn bytes(iocb: *mut abi::IOCb) -> u64 {
+        rw_bytes(unsafe { &*iocb }).map_or(0, |(_, n)| n)
+    }
+}
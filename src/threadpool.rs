@@ -0,0 +1,301 @@
+//! A portable engine that carries out the iocbs with blocking syscalls on a
+//! small pool of threads, for systems where the kernel AIO is unavailable.
+
+use crate::abi;
+use crate::backend::AioBackend;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+const NWORKERS: usize = 4;
+// how often a poll checks whether the pool is dropped
+const POLL_INTERVAL_MS: libc::c_int = 100;
+
+struct IOCbPtr(*mut abi::IOCb);
+// the iocb is owned by its AIO, which outlives the operation
+unsafe impl Send for IOCbPtr {}
+
+pub struct ThreadPoolContext {
+    queue_in: Option<crossbeam_channel::Sender<IOCbPtr>>,
+    done_out: crossbeam_channel::Receiver<abi::IOEvent>,
+    workers: Vec<std::thread::JoinHandle<()>>,
+    // set when dropped, so the polls waiting for their fds give up
+    closing: Arc<AtomicBool>,
+    // the number of submitted iocbs that are yet to be reaped
+    inflight: AtomicUsize,
+    maxevents: usize,
+}
+
+impl ThreadPoolContext {
+    pub fn new(maxevents: u32) -> Self {
+        let (queue_in, queue_out) = crossbeam_channel::unbounded::<IOCbPtr>();
+        let (done_in, done_out) = crossbeam_channel::unbounded();
+        let closing = Arc::new(AtomicBool::new(false));
+        let workers = (0..NWORKERS)
+            .map(|_| {
+                let queue_out = queue_out.clone();
+                let done_in = done_in.clone();
+                let closing = closing.clone();
+                std::thread::spawn(move || {
+                    // the I/O priority of this worker thread
+                    let mut prio = 0;
+                    while let Ok(iocb) = queue_out.recv() {
+                        let iocb = unsafe { &*iocb.0 };
+                        let want =
+                            if iocb.aio_flags & abi::IOCB_FLAG_IOPRIO != 0 {
+                                iocb.aio_reqprio
+                            } else {
+                                0
+                            };
+                        let mut res = 0;
+                        if want != prio {
+                            res = set_ioprio(want);
+                            if res == 0 {
+                                prio = want
+                            }
+                        }
+                        if res == 0 {
+                            res = execute(iocb, &closing)
+                        }
+                        let ev = abi::IOEvent {
+                            data: iocb.aio_data,
+                            obj: iocb as *const abi::IOCb as u64,
+                            res,
+                            res2: 0,
+                        };
+                        if done_in.send(ev).is_err() {
+                            break
+                        }
+                        if iocb.aio_flags & abi::IOCB_FLAG_RESFD != 0 {
+                            let one = 1u64;
+                            unsafe {
+                                libc::write(
+                                    iocb.aio_resfd as libc::c_int,
+                                    &one as *const u64 as *const libc::c_void,
+                                    8,
+                                );
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        ThreadPoolContext {
+            queue_in: Some(queue_in),
+            done_out,
+            workers,
+            closing,
+            inflight: AtomicUsize::new(0),
+            maxevents: maxevents as usize,
+        }
+    }
+}
+
+/// Set the I/O priority of the calling thread (0 for the default one of its scheduling class),
+/// returning 0 or a negative errno.
+#[cfg(target_os = "linux")]
+fn set_ioprio(prio: u16) -> i64 {
+    const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            0,
+            prio as libc::c_long,
+        )
+    };
+    if ret < 0 {
+        -std::io::Error::last_os_error()
+            .raw_os_error()
+            .unwrap_or(libc::EINVAL) as i64
+    } else {
+        0
+    }
+}
+
+/// The other systems have no I/O priorities, so only the default one is taken.
+#[cfg(not(target_os = "linux"))]
+fn set_ioprio(prio: u16) -> i64 {
+    if prio == 0 {
+        0
+    } else {
+        -libc::EINVAL as i64
+    }
+}
+
+/// `preadv2(2)`, or `preadv(2)` on the other systems, which take no per-operation flags.
+unsafe fn preadv2(
+    fd: libc::c_int,
+    iov: *const libc::iovec,
+    iovcnt: libc::c_int,
+    off: libc::off_t,
+    flags: libc::c_int,
+) -> i64 {
+    #[cfg(target_os = "linux")]
+    return libc::preadv2(fd, iov, iovcnt, off, flags) as i64;
+    #[cfg(not(target_os = "linux"))]
+    return {
+        let _ = flags;
+        libc::preadv(fd, iov, iovcnt, off) as i64
+    };
+}
+
+/// `pwritev2(2)`, or `pwritev(2)` on the other systems (see `preadv2`).
+unsafe fn pwritev2(
+    fd: libc::c_int,
+    iov: *const libc::iovec,
+    iovcnt: libc::c_int,
+    off: libc::off_t,
+    flags: libc::c_int,
+) -> i64 {
+    #[cfg(target_os = "linux")]
+    return libc::pwritev2(fd, iov, iovcnt, off, flags) as i64;
+    #[cfg(not(target_os = "linux"))]
+    return {
+        let _ = flags;
+        libc::pwritev(fd, iov, iovcnt, off) as i64
+    };
+}
+
+/// Carry out the operation described by the iocb, returning the result in the same form as
+/// `io_event.res`.
+fn execute(iocb: &abi::IOCb, closing: &AtomicBool) -> i64 {
+    let fd = iocb.aio_fildes as libc::c_int;
+    let buf = iocb.aio_buf as *mut libc::c_void;
+    let nbytes = iocb.aio_nbytes as usize;
+    let off = iocb.aio_offset as libc::off_t;
+    let flags = iocb.aio_rw_flags as libc::c_int;
+    #[cfg(not(target_os = "linux"))]
+    if flags != 0 {
+        return -libc::EINVAL as i64
+    }
+    // only the *v2 syscalls take the per-operation flags
+    let single = libc::iovec {
+        iov_base: buf,
+        iov_len: nbytes,
+    };
+    let ret = unsafe {
+        match iocb.aio_lio_opcode {
+            x if x == abi::IOCmd::PRead as u16 && flags == 0 => {
+                libc::pread(fd, buf, nbytes, off) as i64
+            }
+            x if x == abi::IOCmd::PWrite as u16 && flags == 0 => {
+                libc::pwrite(fd, buf, nbytes, off) as i64
+            }
+            x if x == abi::IOCmd::PRead as u16 => {
+                preadv2(fd, &single, 1, off, flags)
+            }
+            x if x == abi::IOCmd::PWrite as u16 => {
+                pwritev2(fd, &single, 1, off, flags)
+            }
+            x if x == abi::IOCmd::PReadV as u16 => {
+                let iov = buf as *const libc::iovec;
+                preadv2(fd, iov, nbytes as libc::c_int, off, flags)
+            }
+            x if x == abi::IOCmd::PWriteV as u16 => {
+                let iov = buf as *const libc::iovec;
+                pwritev2(fd, iov, nbytes as libc::c_int, off, flags)
+            }
+            x if x == abi::IOCmd::FSync as u16 => libc::fsync(fd) as i64,
+            x if x == abi::IOCmd::FdSync as u16 => libc::fdatasync(fd) as i64,
+            x if x == abi::IOCmd::Noop as u16 => 0,
+            x if x == abi::IOCmd::Poll as u16 => {
+                return poll(fd, iocb.aio_buf as u16 as libc::c_short, closing)
+            }
+            _ => return -libc::EINVAL as i64,
+        }
+    };
+    if ret < 0 {
+        -std::io::Error::last_os_error()
+            .raw_os_error()
+            .unwrap_or(libc::EIO) as i64
+    } else {
+        ret
+    }
+}
+
+/// Wait for the fd to become ready for the events, returning those it is ready for, or
+/// `-ECANCELED` once the pool is dropped.
+fn poll(fd: libc::c_int, events: libc::c_short, closing: &AtomicBool) -> i64 {
+    let mut pfd = libc::pollfd {
+        fd,
+        events,
+        revents: 0,
+    };
+    while !closing.load(Ordering::Acquire) {
+        match unsafe { libc::poll(&mut pfd, 1, POLL_INTERVAL_MS) } {
+            0 => continue,
+            n if n > 0 => return pfd.revents as u16 as i64,
+            _ => {
+                let errno = std::io::Error::last_os_error()
+                    .raw_os_error()
+                    .unwrap_or(libc::EIO);
+                if errno != libc::EINTR {
+                    return -errno as i64
+                }
+            }
+        }
+    }
+    -libc::ECANCELED as i64
+}
+
+impl AioBackend for ThreadPoolContext {
+    fn submit(&self, iocbs: &mut [*mut abi::IOCb]) -> libc::c_int {
+        let queue_in = self.queue_in.as_ref().unwrap();
+        let inflight = self.inflight.load(Ordering::Acquire);
+        // behave like a full libaio context when reaching maxevents
+        let nacc = iocbs.len().min(self.maxevents.saturating_sub(inflight));
+        if nacc == 0 {
+            return -libc::EAGAIN
+        }
+        self.inflight.fetch_add(nacc, Ordering::AcqRel);
+        for iocb in iocbs[..nacc].iter() {
+            queue_in.send(IOCbPtr(*iocb)).unwrap();
+        }
+        nacc as libc::c_int
+    }
+
+    fn reap(
+        &self,
+        min_nr: usize,
+        events: &mut [abi::IOEvent],
+        timeout: Option<&mut libc::timespec>,
+    ) -> libc::c_int {
+        let deadline = timeout.map(|t| {
+            std::time::Instant::now() +
+                std::time::Duration::new(t.tv_sec as u64, t.tv_nsec as u32)
+        });
+        let mut nev = 0;
+        while nev < events.len() {
+            let ev = if nev < min_nr {
+                match deadline {
+                    Some(d) => self.done_out.recv_deadline(d).ok(),
+                    None => self.done_out.recv().ok(),
+                }
+            } else {
+                self.done_out.try_recv().ok()
+            };
+            match ev {
+                Some(ev) => events[nev] = ev,
+                None => break,
+            }
+            nev += 1;
+        }
+        self.inflight.fetch_sub(nev, Ordering::AcqRel);
+        nev as libc::c_int
+    }
+
+    fn cancel(&self, _iocb: *mut abi::IOCb) -> libc::c_int {
+        // a blocking syscall cannot be interrupted once it is picked up
+        -libc::EINVAL
+    }
+}
+
+impl Drop for ThreadPoolContext {
+    fn drop(&mut self) {
+        self.queue_in.take();
+        self.closing.store(true, Ordering::Release);
+        for w in self.workers.drain(..) {
+            w.join().unwrap();
+        }
+    }
+}
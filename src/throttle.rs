@@ -0,0 +1,169 @@
+//! Limiting the rates of the reads and writes submitted, with a token bucket for the operations
+//! and another for the bytes, each filled up to a second worth of its rate.
+
+use crate::abi;
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// The most operations and bytes submitted per second (0 for no limit), as set by
+/// `AIOManager::set_read_rate_limit` and `AIOManager::set_write_rate_limit`. An operation larger
+/// than a second worth of bytes goes once the bucket is full, and holds back the following ones
+/// accordingly. The default has no limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    ops: u64,
+    bytes: u64,
+}
+
+impl RateLimit {
+    pub fn new(ops: u64, bytes: u64) -> Self {
+        RateLimit { ops, bytes }
+    }
+
+    /// No limit.
+    pub fn none() -> Self {
+        Self::default()
+    }
+}
+
+/// The tokens of a rate, up to a second worth of it.
+pub(crate) struct Bucket {
+    // per second, or 0 for no limit
+    rate: u64,
+    // negative after an operation larger than the bucket
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    pub(crate) fn new(rate: u64, now: Instant) -> Self {
+        Bucket {
+            rate,
+            tokens: rate as f64,
+            last: now,
+        }
+    }
+
+    pub(crate) fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+        self.last = now;
+    }
+
+    /// When there will be enough tokens for `n`, or `None` if there are already.
+    pub(crate) fn due(&self, n: u64) -> Option<Instant> {
+        let need = n.min(self.rate) as f64;
+        if self.rate == 0 || self.tokens >= need {
+            return None
+        }
+        let wait = (need - self.tokens) / self.rate as f64;
+        Some(self.last + Duration::from_secs_f64(wait))
+    }
+
+    pub(crate) fn take(&mut self, n: u64) {
+        if self.rate > 0 {
+            self.tokens -= n as f64
+        }
+    }
+}
+
+struct Limiter {
+    ops: Bucket,
+    bytes: Bucket,
+}
+
+impl Limiter {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        Limiter {
+            ops: Bucket::new(limit.ops, now),
+            bytes: Bucket::new(limit.bytes, now),
+        }
+    }
+
+    fn is_none(&self) -> bool {
+        self.ops.rate == 0 && self.bytes.rate == 0
+    }
+}
+
+pub(crate) struct Throttle {
+    // whether any limit is set, so the submissions do not take the lock otherwise
+    active: AtomicBool,
+    // the reads, then the writes
+    limiters: Mutex<[Limiter; 2]>,
+}
+
+impl Throttle {
+    pub(crate) fn new() -> Self {
+        let now = Instant::now();
+        let none = || Limiter::new(RateLimit::none(), now);
+        Throttle {
+            active: AtomicBool::new(false),
+            limiters: Mutex::new([none(), none()]),
+        }
+    }
+
+    /// Replace the limit of the reads (or the writes), starting with full buckets.
+    pub(crate) fn set(&self, write: bool, limit: RateLimit) {
+        let mut limiters = self.limiters.lock();
+        limiters[write as usize] = Limiter::new(limit, Instant::now());
+        let active = limiters.iter().any(|l| !l.is_none());
+        self.active.store(active, Ordering::Release);
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.active.load(Ordering::Acquire)
+    }
+
+    /// Take the tokens for the iocb, unless it is to wait for them until the returned time.
+    pub(crate) fn admit(
+        &self,
+        iocb: &abi::IOCb,
+        now: Instant,
+    ) -> Result<(), Instant> {
+        let (write, bytes) = match rw_bytes(iocb) {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+        let l = &mut self.limiters.lock()[write as usize];
+        l.ops.refill(now);
+        l.bytes.refill(now);
+        if let Some(due) = l.ops.due(1).max(l.bytes.due(bytes)) {
+            return Err(due)
+        }
+        l.ops.take(1);
+        l.bytes.take(bytes);
+        Ok(())
+    }
+
+    /// Take the tokens for the iocb even if there are not enough (e.g., for a batch, which
+    /// cannot wait in part).
+    pub(crate) fn force(&self, iocb: &abi::IOCb, now: Instant) {
+        if let Some((write, bytes)) = rw_bytes(iocb) {
+            let l = &mut self.limiters.lock()[write as usize];
+            l.ops.refill(now);
+            l.bytes.refill(now);
+            l.ops.take(1);
+            l.bytes.take(bytes)
+        }
+    }
+}
+
+/// Whether the iocb is a write, and its bytes, if a read or a write.
+pub(crate) fn rw_bytes(iocb: &abi::IOCb) -> Option<(bool, u64)> {
+    let vectored = || {
+        let iov = iocb.aio_buf as *const libc::iovec;
+        let iov = unsafe {
+            std::slice::from_raw_parts(iov, iocb.aio_nbytes as usize)
+        };
+        iov.iter().map(|v| v.iov_len as u64).sum()
+    };
+    match iocb.aio_lio_opcode {
+        x if x == abi::IOCmd::PRead as u16 => Some((false, iocb.aio_nbytes)),
+        x if x == abi::IOCmd::PWrite as u16 => Some((true, iocb.aio_nbytes)),
+        x if x == abi::IOCmd::PReadV as u16 => Some((false, vectored())),
+        x if x == abi::IOCmd::PWriteV as u16 => Some((true, vectored())),
+        _ => None,
+    }
+}
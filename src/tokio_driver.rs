@@ -0,0 +1,130 @@
+//! Drive the AIOs from a task in the tokio runtime: the completions are
+//! detected by polling the eventfd with the reactor, so no thread is blocked
+//! in waiting.
+
+use crate::{AIOBatchSchedulerOut, AIONotifier, Error};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+use tokio::sync::Notify;
+
+/// The handle used by the AIOManager to control its driving task.
+pub(crate) struct Control {
+    kick: Notify,
+    exit: AtomicBool,
+}
+
+impl Control {
+    /// Wake up the task to submit the newly scheduled AIOs.
+    pub(crate) fn kick(&self) {
+        self.kick.notify_one()
+    }
+
+    /// Let the task quit once all outstanding AIOs are finished.
+    pub(crate) fn exit(&self) {
+        self.exit.store(true, Ordering::Release);
+        self.kick.notify_one()
+    }
+}
+
+// the eventfd is owned (and closed) by the notifier
+struct Fd(RawFd);
+
+impl AsRawFd for Fd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+/// Wait until the next submission is due (see `AIOBatchSchedulerOut::next_due`), if any.
+async fn retry_due(due: Option<std::time::Instant>) {
+    match due {
+        Some(due) => {
+            tokio::time::sleep_until(tokio::time::Instant::from_std(due)).await
+        }
+        None => std::future::pending().await,
+    }
+}
+
+pub(crate) fn spawn(
+    n: Arc<AIONotifier>,
+    mut scheduler_out: AIOBatchSchedulerOut,
+    max_nwait: u16,
+) -> Result<Arc<Control>, Error> {
+    let handle =
+        tokio::runtime::Handle::try_current().map_err(|_| Error::OtherError)?;
+    let fd = Fd(n.eventfd.as_ref().unwrap().0);
+    let efd = {
+        let _guard = handle.enter();
+        AsyncFd::with_interest(fd, Interest::READABLE).map_err(|e| {
+            e.raw_os_error().map_or(Error::OtherError, Error::Sys)
+        })?
+    };
+    let ctl = Arc::new(Control {
+        kick: Notify::new(),
+        exit: AtomicBool::new(false),
+    });
+    let c = ctl.clone();
+    handle.spawn(async move {
+        diag!(debug, "aio driving task started");
+        let mut no_wait = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        let mut ongoing = 0;
+        'drive: loop {
+            // submit as many aios as possible
+            loop {
+                let nacc = scheduler_out.submit(&n);
+                ongoing += nacc;
+                if nacc == 0 {
+                    break
+                }
+            }
+            if ongoing == 0 && scheduler_out.is_empty(&n) {
+                if c.exit.load(Ordering::Acquire) && scheduler_out.is_drained()
+                {
+                    break
+                }
+                tokio::select! {
+                    _ = c.kick.notified() => (),
+                    _ = retry_due(scheduler_out.next_due(&n)) => (),
+                }
+                continue
+            }
+            tokio::select! {
+                r = efd.readable() => if let Ok(mut guard) = r {
+                    // reset the counter, all completions are reaped below
+                    let mut cnt = 0u64;
+                    unsafe {
+                        libc::read(
+                            efd.as_raw_fd(),
+                            &mut cnt as *mut u64 as *mut libc::c_void,
+                            8,
+                        );
+                    }
+                    guard.clear_ready();
+                },
+                _ = c.kick.notified() => (),
+                _ = retry_due(scheduler_out.next_due(&n)) => (),
+            }
+            loop {
+                let ret = n.reap(0, max_nwait as usize, Some(&mut no_wait));
+                if ret < 0 {
+                    scheduler_out.fail(&n, -ret);
+                    break 'drive
+                }
+                ongoing -= ret as usize;
+                if (ret as usize) < max_nwait as usize {
+                    break
+                }
+            }
+        }
+        scheduler_out.close(&n);
+        n.destroy();
+        diag!(debug, "aio driving task exited");
+    });
+    Ok(ctl)
+}
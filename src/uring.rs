@@ -0,0 +1,313 @@
+//! An io_uring-based engine that understands the libaio iocbs, so the rest of
+//! the crate (and its users) stay the same when switching the backend.
+
+use crate::abi;
+use crate::backend::{setup_error, AioBackend};
+use crate::Error;
+use io_uring::{opcode, types, IoUring};
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+// user_data of the entries issued by cancel(), whose completions are not reported
+const CANCEL_USER_DATA: u64 = u64::MAX;
+
+pub struct UringContext {
+    uring: IoUring,
+    // serialize the accesses to the submission/completion queues, while still
+    // allowing a thread blocked in waiting to not hold up the others
+    sq_lock: Mutex<()>,
+    cq_lock: Mutex<()>,
+    // the number of submitted entries that are yet to be reaped
+    inflight: AtomicUsize,
+    maxevents: usize,
+    // set when entries were left in the submission queue by a failed enter, for the next one
+    unflushed: AtomicBool,
+    // the errno the next enter fails with, or 0
+    #[cfg(test)]
+    fail_enter: std::sync::atomic::AtomicI32,
+    // io_uring signals an eventfd per ring instead of per request
+    eventfd_registered: AtomicBool,
+    // blocked while waiting, instead of those of the waiting thread
+    sigmask: Option<libc::sigset_t>,
+}
+
+impl UringContext {
+    pub fn new(
+        maxevents: u32,
+        sigmask: Option<libc::sigset_t>,
+    ) -> Result<Self, Error> {
+        let uring = IoUring::new(maxevents).map_err(|e| {
+            setup_error(-e.raw_os_error().unwrap_or(libc::EINVAL), maxevents)
+        })?;
+        Ok(UringContext {
+            uring,
+            sq_lock: Mutex::new(()),
+            cq_lock: Mutex::new(()),
+            inflight: AtomicUsize::new(0),
+            maxevents: maxevents as usize,
+            unflushed: AtomicBool::new(false),
+            #[cfg(test)]
+            fail_enter: std::sync::atomic::AtomicI32::new(0),
+            eventfd_registered: AtomicBool::new(false),
+            sigmask,
+        })
+    }
+
+    /// Translate an iocb into the equivalent submission queue entry, or `None` for an opcode
+    /// that has no equivalent.
+    fn to_sqe(iocb: &abi::IOCb) -> Option<io_uring::squeue::Entry> {
+        let fd = types::Fd(iocb.aio_fildes as i32);
+        let buf = iocb.aio_buf as *mut u8;
+        let len = iocb.aio_nbytes as u32;
+        let rw_flags = iocb.aio_rw_flags as i32;
+        let prio = if iocb.aio_flags & abi::IOCB_FLAG_IOPRIO != 0 {
+            iocb.aio_reqprio
+        } else {
+            0
+        };
+        let off = iocb.aio_offset;
+        let sqe = match iocb.aio_lio_opcode {
+            x if x == abi::IOCmd::PRead as u16 => {
+                opcode::Read::new(fd, buf, len)
+                    .offset(off)
+                    .ioprio(prio)
+                    .rw_flags(rw_flags)
+                    .build()
+            }
+            x if x == abi::IOCmd::PWrite as u16 => {
+                opcode::Write::new(fd, buf, len)
+                    .offset(off)
+                    .ioprio(prio)
+                    .rw_flags(rw_flags)
+                    .build()
+            }
+            x if x == abi::IOCmd::PReadV as u16 => {
+                opcode::Readv::new(fd, buf as *const libc::iovec, len)
+                    .offset(off)
+                    .ioprio(prio)
+                    .rw_flags(rw_flags)
+                    .build()
+            }
+            x if x == abi::IOCmd::PWriteV as u16 => {
+                opcode::Writev::new(fd, buf as *const libc::iovec, len)
+                    .offset(off)
+                    .ioprio(prio)
+                    .rw_flags(rw_flags)
+                    .build()
+            }
+            x if x == abi::IOCmd::FSync as u16 => {
+                opcode::Fsync::new(fd).build()
+            }
+            x if x == abi::IOCmd::FdSync as u16 => opcode::Fsync::new(fd)
+                .flags(types::FsyncFlags::DATASYNC)
+                .build(),
+            x if x == abi::IOCmd::Poll as u16 => {
+                opcode::PollAdd::new(fd, iocb.aio_buf as u32).build()
+            }
+            x if x == abi::IOCmd::Noop as u16 => opcode::Nop::new().build(),
+            _ => return None,
+        };
+        Some(sqe.user_data(iocb.aio_data))
+    }
+
+    /// Submit the entries in the submission queue, which are left there to submit again upon an
+    /// error (e.g., `EINTR` or `EAGAIN`).
+    fn enter(&self) -> libc::c_int {
+        #[cfg(test)]
+        let ret = match self.fail_enter.swap(0, Ordering::AcqRel) {
+            0 => self.uring.submit(),
+            e => Err(std::io::Error::from_raw_os_error(e)),
+        };
+        #[cfg(not(test))]
+        let ret = self.uring.submit();
+        self.unflushed.store(ret.is_err(), Ordering::Release);
+        match ret {
+            Ok(_) => 0,
+            Err(e) => -e.raw_os_error().unwrap_or(libc::EINVAL),
+        }
+    }
+}
+
+impl AioBackend for UringContext {
+    fn submit(&self, iocbs: &mut [*mut abi::IOCb]) -> libc::c_int {
+        if let Some(iocb) = iocbs.first() {
+            let iocb = unsafe { &**iocb };
+            if iocb.aio_flags & abi::IOCB_FLAG_RESFD != 0 &&
+                !self.eventfd_registered.swap(true, Ordering::AcqRel)
+            {
+                if let Err(e) = self
+                    .uring
+                    .submitter()
+                    .register_eventfd(iocb.aio_resfd as i32)
+                {
+                    self.eventfd_registered.store(false, Ordering::Release);
+                    return -e.raw_os_error().unwrap_or(libc::EINVAL)
+                }
+            }
+        }
+        let mut nacc = 0;
+        {
+            let _sq = self.sq_lock.lock();
+            let inflight = self.inflight.load(Ordering::Acquire);
+            let mut sq = unsafe { self.uring.submission_shared() };
+            for iocb in iocbs.iter() {
+                // behave like a full libaio context when reaching maxevents
+                if inflight + nacc >= self.maxevents {
+                    break
+                }
+                // like io_submit(2), reject an unknown opcode only when it comes first
+                let sqe = match Self::to_sqe(unsafe { &**iocb }) {
+                    Some(sqe) => sqe,
+                    None if nacc == 0 => return -libc::EINVAL,
+                    None => break,
+                };
+                if unsafe { sq.push(&sqe) }.is_err() {
+                    break
+                }
+                nacc += 1;
+            }
+            self.inflight.fetch_add(nacc, Ordering::AcqRel);
+        }
+        if nacc == 0 {
+            return -libc::EAGAIN
+        }
+        // the entries pushed are accepted anyway, as they are submitted by the next enter (or
+        // the wait of reap) at the latest
+        self.enter();
+        nacc as libc::c_int
+    }
+
+    fn reap(
+        &self,
+        min_nr: usize,
+        events: &mut [abi::IOEvent],
+        timeout: Option<&mut libc::timespec>,
+    ) -> libc::c_int {
+        let ts = timeout.map(|t| {
+            types::Timespec::new()
+                .sec(t.tv_sec as u64)
+                .nsec(t.tv_nsec as u32)
+        });
+        if self.unflushed.load(Ordering::Acquire) {
+            self.enter();
+        }
+        let mut nev = 0;
+        loop {
+            {
+                let _cq = self.cq_lock.lock();
+                for cqe in unsafe { self.uring.completion_shared() } {
+                    if cqe.user_data() == CANCEL_USER_DATA {
+                        continue
+                    }
+                    events[nev] = abi::IOEvent {
+                        data: cqe.user_data(),
+                        res: cqe.result() as i64,
+                        ..Default::default()
+                    };
+                    nev += 1;
+                    if nev == events.len() {
+                        break
+                    }
+                }
+            }
+            if nev >= min_nr {
+                break
+            }
+            let submitter = self.uring.submitter();
+            // SubmitArgs::sigmask passes the size of the libc sigset_t rather than that of the
+            // kernel, which is rejected, so the mask of the thread is swapped around the wait
+            let old = self.sigmask.as_ref().map(|sigmask| {
+                let mut old: libc::sigset_t = unsafe { std::mem::zeroed() };
+                unsafe {
+                    libc::pthread_sigmask(libc::SIG_SETMASK, sigmask, &mut old)
+                };
+                old
+            });
+            let ret = match ts.as_ref() {
+                Some(ts) => {
+                    let args = types::SubmitArgs::new().timespec(ts);
+                    submitter.submit_with_args(min_nr - nev, &args)
+                }
+                None => submitter.submit_and_wait(min_nr - nev),
+            };
+            if let Some(old) = old {
+                unsafe {
+                    libc::pthread_sigmask(
+                        libc::SIG_SETMASK,
+                        &old,
+                        std::ptr::null_mut(),
+                    )
+                };
+            }
+            match ret {
+                Ok(_) => self.unflushed.store(false, Ordering::Release),
+                Err(e) => match e.raw_os_error() {
+                    Some(libc::ETIME) => break,
+                    Some(libc::EINTR) => (),
+                    Some(e) => return -e,
+                    None => return -libc::EINVAL,
+                },
+            }
+        }
+        self.inflight.fetch_sub(nev, Ordering::AcqRel);
+        nev as libc::c_int
+    }
+
+    fn cancel(&self, iocb: *mut abi::IOCb) -> libc::c_int {
+        let target = unsafe { (*iocb).aio_data };
+        let sqe = opcode::AsyncCancel::new(target)
+            .build()
+            .user_data(CANCEL_USER_DATA);
+        {
+            let _sq = self.sq_lock.lock();
+            let mut sq = unsafe { self.uring.submission_shared() };
+            if unsafe { sq.push(&sqe) }.is_err() {
+                return -libc::EAGAIN
+            }
+        }
+        self.enter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_failed_enter() {
+        let ctx = UringContext::new(8, None).unwrap();
+        let mut iocb = abi::IOCb {
+            aio_data: 7,
+            ..Default::default()
+        };
+        // the entry stays queued, so it is accepted and not pushed again
+        ctx.fail_enter.store(libc::EBUSY, Ordering::Release);
+        let mut iocbs = [&mut iocb as *mut abi::IOCb];
+        assert_eq!(ctx.submit(&mut iocbs), 1);
+        assert_eq!(ctx.inflight.load(Ordering::Acquire), 1);
+        let mut events = [abi::IOEvent::default(), abi::IOEvent::default()];
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 50_000_000,
+        };
+        assert_eq!(ctx.reap(1, &mut events, Some(&mut ts)), 1);
+        assert_eq!((events[0].data, events[0].res), (7, 0));
+        assert_eq!(ctx.reap(1, &mut events, Some(&mut ts)), 0);
+        assert_eq!(ctx.inflight.load(Ordering::Acquire), 0);
+    }
+    #[test]
+    fn test_unknown_opcode() {
+        let ctx = UringContext::new(8, None).unwrap();
+        let mut noop = abi::IOCb::default();
+        let mut bad = abi::IOCb {
+            aio_lio_opcode: 4,
+            ..Default::default()
+        };
+        let mut iocbs = [&mut bad as *mut abi::IOCb];
+        assert_eq!(ctx.submit(&mut iocbs), -libc::EINVAL);
+        let mut iocbs =
+            [&mut noop as *mut abi::IOCb, &mut bad as *mut abi::IOCb];
+        assert_eq!(ctx.submit(&mut iocbs), 1);
+        assert_eq!(ctx.inflight.load(Ordering::Acquire), 1);
+    }
+}
@@ -0,0 +1,103 @@
+//! A waker registered by a future and taken by the thread finishing its AIO, without a lock
+//! (the same protocol as `futures::task::AtomicWaker`), and the wakers of the AIOs finished
+//! together.
+
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::Waker;
+
+const WAITING: usize = 0;
+const REGISTERING: usize = 1;
+const WAKING: usize = 2;
+
+pub(crate) struct AtomicWaker {
+    state: AtomicUsize,
+    // only accessed by whoever moves the state off WAITING
+    waker: UnsafeCell<Option<Waker>>,
+}
+
+unsafe impl Send for AtomicWaker {}
+unsafe impl Sync for AtomicWaker {}
+
+impl AtomicWaker {
+    pub(crate) fn new() -> Self {
+        AtomicWaker {
+            state: AtomicUsize::new(WAITING),
+            waker: UnsafeCell::new(None),
+        }
+    }
+
+    /// Have the waker woken up by the next `wake`. Never called concurrently with itself.
+    pub(crate) fn register(&self, waker: &Waker) {
+        match self
+            .state
+            .compare_exchange(
+                WAITING,
+                REGISTERING,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            )
+            .unwrap_or_else(|s| s)
+        {
+            WAITING => unsafe {
+                let cur = &mut *self.waker.get();
+                if !cur.as_ref().is_some_and(|w| w.will_wake(waker)) {
+                    *cur = Some(waker.clone())
+                }
+                if self
+                    .state
+                    .compare_exchange(
+                        REGISTERING,
+                        WAITING,
+                        Ordering::AcqRel,
+                        Ordering::Acquire,
+                    )
+                    .is_err()
+                {
+                    // woken up meanwhile, which is left to us
+                    let waker = cur.take().unwrap();
+                    self.state.swap(WAITING, Ordering::AcqRel);
+                    waker.wake()
+                }
+            },
+            // being woken up, possibly with an outdated waker
+            _ => waker.wake_by_ref(),
+        }
+    }
+
+    /// Take the registered waker, unless it is being registered (and thus woken up by
+    /// `register`).
+    pub(crate) fn take(&self) -> Option<Waker> {
+        match self.state.fetch_or(WAKING, Ordering::AcqRel) {
+            WAITING => {
+                let waker = unsafe { (*self.waker.get()).take() };
+                self.state.fetch_and(!WAKING, Ordering::Release);
+                waker
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The wakers of the AIOs finished together (e.g., reaped by the same `io_getevents`), woken up
+/// once all of them are finished, and only once per task.
+#[derive(Default)]
+pub(crate) struct Wakers(Vec<Waker>);
+
+impl Wakers {
+    pub(crate) fn push(&mut self, waker: Option<Waker>) {
+        if let Some(waker) = waker {
+            if !self.0.iter().any(|w| w.will_wake(&waker)) {
+                self.0.push(waker)
+            }
+        }
+    }
+}
+
+impl Drop for Wakers {
+    fn drop(&mut self) {
+        for waker in self.0.drain(..) {
+            waker.wake()
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Pipelined sequential writes of small pieces of data.
+
+use crate::{AIOFuture, AIOManagerHandle, AlignedBuf};
+use std::collections::VecDeque;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd};
+
+/// A sequential writer that accumulates the written data into chunks aligned to the chunk size
+/// (in terms of the file offset), and keeps up to `depth` chunks in flight. The chunks are
+/// allocated as configured by `AIOBuilder::alignment`, so they suit an `O_DIRECT` file
+/// descriptor as long as the starting offset, the chunk size and the total length written are
+/// multiples of the alignment: otherwise the first chunk or the last one (written by `flush`)
+/// is not aligned and fails with `EINVAL`, so a buffered file descriptor is needed. Call `flush`
+/// before dropping the writer, otherwise the data not yet written could be lost.
+pub struct BufferedWriter<'a> {
+    aiomgr: &'a AIOManagerHandle,
+    fd: BorrowedFd<'a>,
+    // the file offset of the chunk being filled
+    pos: u64,
+    chunk: AlignedBuf,
+    // the bytes of the chunk filled so far
+    filled: usize,
+    chunk_size: usize,
+    depth: usize,
+    inflight: VecDeque<(AIOFuture<'a, AlignedBuf>, usize)>,
+}
+
+impl<'a> BufferedWriter<'a> {
+    pub fn new(
+        aiomgr: &'a AIOManagerHandle,
+        fd: &'a impl AsFd,
+        offset: u64,
+        chunk_size: usize,
+        depth: usize,
+    ) -> Self {
+        assert!(chunk_size > 0 && depth > 0);
+        BufferedWriter {
+            aiomgr,
+            fd: fd.as_fd(),
+            pos: offset,
+            chunk: aiomgr.aligned_buf(chunk_size),
+            filled: 0,
+            chunk_size,
+            depth,
+            inflight: VecDeque::new(),
+        }
+    }
+
+    /// The file offset where the next written byte goes.
+    pub fn position(&self) -> u64 {
+        self.pos + self.filled as u64
+    }
+
+    /// Append the data, writing out every filled chunk. Returns the errno upon any failed write
+    /// (a short write fails with `EIO`).
+    pub async fn write(&mut self, mut data: &[u8]) -> Result<(), i32> {
+        while !data.is_empty() {
+            let room = self.chunk_room();
+            let n = room.min(data.len());
+            self.chunk[self.filled..self.filled + n]
+                .copy_from_slice(&data[..n]);
+            self.filled += n;
+            data = &data[n..];
+            if n == room {
+                self.submit_chunk();
+                while self.inflight.len() >= self.depth {
+                    self.wait_one().await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write out the partially filled chunk and wait for all writes to finish.
+    pub async fn flush(&mut self) -> Result<(), i32> {
+        if self.filled > 0 {
+            self.submit_chunk();
+        }
+        while !self.inflight.is_empty() {
+            self.wait_one().await?;
+        }
+        Ok(())
+    }
+
+    /// The number of bytes that still fit into the chunk before reaching the next aligned offset.
+    fn chunk_room(&self) -> usize {
+        self.chunk_size - (self.position() % self.chunk_size as u64) as usize
+    }
+
+    fn submit_chunk(&mut self) {
+        let mut chunk = std::mem::replace(
+            &mut self.chunk,
+            self.aiomgr.aligned_buf(self.chunk_size),
+        );
+        let len = std::mem::take(&mut self.filled);
+        chunk.truncate(len);
+        // the fd is borrowed by the writer for 'a, which outlives the futures
+        let fd = self.fd.as_raw_fd();
+        let w = self.aiomgr.write_raw(fd, self.pos, chunk, None);
+        self.pos += len as u64;
+        self.inflight.push_back((w, len));
+    }
+
+    async fn wait_one(&mut self) -> Result<(), i32> {
+        let (w, len) = self.inflight.pop_front().unwrap();
+        match w.await.0 {
+            Ok(n) if n == len => Ok(()),
+            Ok(_) => Err(libc::EIO),
+            Err(e) => Err(e),
+        }
+    }
+}
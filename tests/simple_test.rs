@@ -1,7 +1,10 @@
+use aiofut::{
+    AIOBuilder, AIOCursor, AIOFile, Backend, BufferedReader, BufferedWriter,
+    Sharding,
+};
 use futures::executor::LocalPool;
 use futures::future::FutureExt;
 use futures::task::LocalSpawnExt;
-use aiofut::AIOBuilder;
 use std::os::unix::io::AsRawFd;
 
 #[test]
@@ -17,7 +20,7 @@ fn simple1() {
     let fd = file.as_raw_fd();
     let ws = vec![(0, "hello"), (5, "world"), (2, "xxxx")]
         .into_iter()
-        .map(|(off, s)| aiomgr.write(fd, off, s.as_bytes().into(), None))
+        .map(|(off, s)| aiomgr.write_raw(fd, off, s.as_bytes().to_vec(), None))
         .collect::<Vec<_>>();
     let mut pool = LocalPool::new();
     let spawner = pool.spawner();
@@ -42,13 +45,12 @@ fn simple2() {
         .unwrap();
     let fd = file.as_raw_fd();
     let ws = (0..4000)
-        .into_iter()
         .map(|i| {
             let off = i * 128;
             let s = char::from((97 + i % 26) as u8)
                 .to_string()
                 .repeat((i + 1) as usize);
-            aiomgr.write(fd, off as u64, s.as_bytes().into(), None)
+            aiomgr.write_raw(fd, off as u64, s.as_bytes().to_vec(), None)
         })
         .collect::<Vec<_>>();
     let mut pool = LocalPool::new();
@@ -61,3 +63,3092 @@ fn simple2() {
     }
     pool.run();
 }
+
+#[test]
+fn sync1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test3")
+        .unwrap();
+    let fd = &file;
+    let w = aiomgr.write(fd, 0, "hello".as_bytes().to_vec(), None);
+    assert_eq!(futures::executor::block_on(w).0.unwrap(), 5);
+    let s = aiomgr.fsync(fd);
+    assert_eq!(futures::executor::block_on(s).0.unwrap(), 0);
+    let s = aiomgr.fdatasync(fd);
+    assert_eq!(futures::executor::block_on(s).0.unwrap(), 0);
+}
+
+#[test]
+fn vectored1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test4")
+        .unwrap();
+    let fd = &file;
+    let bufs = vec!["hello".as_bytes().into(), "world".as_bytes().into()];
+    let w = aiomgr.write_vectored(fd, 0, bufs, None);
+    assert_eq!(futures::executor::block_on(w).0.unwrap(), 10);
+    let r = aiomgr.read_vectored(fd, 0, &[3, 7], None);
+    let (res, bufs) = futures::executor::block_on(r);
+    assert_eq!(res.unwrap(), 10);
+    assert_eq!(&bufs[0][..], b"hel");
+    assert_eq!(&bufs[1][..], b"loworld");
+}
+
+#[test]
+fn backends1() {
+    let backends = [
+        Backend::Libaio,
+        #[cfg(feature = "uring")]
+        Backend::Uring,
+        Backend::ThreadPool,
+    ];
+    for (i, backend) in backends.iter().enumerate() {
+        let aiomgr = AIOBuilder::default().backend(*backend).build().unwrap();
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(format!("test5-{}", i))
+            .unwrap();
+        let fd = &file;
+        let w = aiomgr.write(fd, 0, "hello".as_bytes().to_vec(), None);
+        assert_eq!(futures::executor::block_on(w).0.unwrap(), 5);
+        let r = aiomgr.read(fd, 1, 3, None);
+        let (res, data) = futures::executor::block_on(r);
+        assert_eq!(res.unwrap(), 3);
+        assert_eq!(&data[..], b"ell");
+    }
+}
+
+#[test]
+fn dropped1() {
+    let aiomgr = AIOBuilder::default().max_nbatched(16).build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test6")
+        .unwrap();
+    let fd = &file;
+    // dropped futures are either discarded before submission or cancelled
+    for i in 0..1000 {
+        drop(aiomgr.write(fd, i * 4, "abcd".as_bytes().to_vec(), None));
+    }
+    let w = aiomgr.write(fd, 0, "hello".as_bytes().to_vec(), None);
+    assert_eq!(futures::executor::block_on(w).0.unwrap(), 5);
+    while aiomgr.get_npending() > 0 {
+        std::thread::yield_now();
+    }
+}
+
+#[test]
+fn cancel1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test7")
+        .unwrap();
+    let fd = &file;
+    let mut ws = (0..1000)
+        .map(|i| aiomgr.write(fd, i * 4, "abcd".as_bytes().to_vec(), None))
+        .collect::<Vec<_>>();
+    let w = ws.pop().unwrap();
+    let cancelled = aiomgr.cancel(w.get_id());
+    let (res, data) = futures::executor::block_on(w);
+    assert_eq!(&data[..], b"abcd");
+    match res {
+        Ok(n) => assert_eq!(n, 4),
+        Err(e) => assert!(cancelled && e == libc::ECANCELED),
+    }
+    assert!(!aiomgr.cancel(u64::MAX));
+}
+
+#[test]
+fn eventfd1() {
+    let backends = [
+        Backend::Libaio,
+        #[cfg(feature = "uring")]
+        Backend::Uring,
+        Backend::ThreadPool,
+    ];
+    for (i, backend) in backends.iter().enumerate() {
+        let aiomgr = AIOBuilder::default()
+            .backend(*backend)
+            .eventfd(true)
+            .build()
+            .unwrap();
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(format!("test8-{}", i))
+            .unwrap();
+        let fd = &file;
+        let w = aiomgr.write(fd, 0, "hello".as_bytes().to_vec(), None);
+        assert_eq!(futures::executor::block_on(w).0.unwrap(), 5);
+        let efd = aiomgr.eventfd().unwrap();
+        let mut cnt = 0u64;
+        // the eventfd could be signaled slightly after the future is woken up
+        loop {
+            let ret = unsafe {
+                libc::read(efd, &mut cnt as *mut u64 as *mut libc::c_void, 8)
+            };
+            if ret == 8 {
+                break
+            }
+            std::thread::yield_now();
+        }
+        assert_eq!(cnt, 1);
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn tokio1() {
+    let backends = [
+        Backend::Libaio,
+        #[cfg(feature = "uring")]
+        Backend::Uring,
+        Backend::ThreadPool,
+    ];
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()
+        .unwrap();
+    for (i, backend) in backends.iter().enumerate() {
+        let aiomgr = rt
+            .block_on(async {
+                AIOBuilder::default().backend(*backend).build_tokio()
+            })
+            .unwrap();
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(format!("test9-{}", i))
+            .unwrap();
+        let fd = &file;
+        rt.block_on(async {
+            let ws =
+                vec![(0, "hello"), (5, "world")]
+                    .into_iter()
+                    .map(|(off, s)| {
+                        aiomgr.write(fd, off, s.as_bytes().to_vec(), None)
+                    });
+            for w in futures::future::join_all(ws).await {
+                assert_eq!(w.0.unwrap(), 5);
+            }
+            let r = aiomgr.read(fd, 0, 10, None).await;
+            assert_eq!(&r.1[..], "helloworld".as_bytes());
+        });
+        drop(aiomgr);
+    }
+}
+
+#[test]
+fn manual1() {
+    let aiomgr = AIOBuilder::default().build_manual().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test10")
+        .unwrap();
+    let fd = &file;
+    let ws = vec![(0, "hello"), (5, "world")]
+        .into_iter()
+        .map(|(off, s)| aiomgr.write(fd, off, s.as_bytes().to_vec(), None))
+        .collect::<Vec<_>>();
+    let mut nreaped = 0;
+    while nreaped < ws.len() {
+        nreaped += aiomgr.drive(16, None);
+    }
+    for w in ws {
+        assert_eq!(futures::executor::block_on(w).0.unwrap(), 5);
+    }
+    let r = aiomgr.read(fd, 0, 10, None);
+    // nothing progresses without driving
+    assert_eq!(aiomgr.get_npending(), 1);
+    while aiomgr.drive(16, Some(std::time::Duration::from_millis(10))) == 0 {}
+    assert_eq!(
+        &futures::executor::block_on(r).1[..],
+        "helloworld".as_bytes()
+    );
+    assert_eq!(aiomgr.drive(16, None), 0);
+}
+
+#[test]
+fn reapers1() {
+    let aiomgr = AIOBuilder::default()
+        .max_events(16)
+        .reaper_threads(3)
+        .build()
+        .unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test11")
+        .unwrap();
+    let fd = &file;
+    // more than max_events to also go through a full context
+    let ws = (0..64u64)
+        .map(|i| aiomgr.write(fd, i * 4, "abcd".as_bytes().to_vec(), None))
+        .collect::<Vec<_>>();
+    for w in futures::executor::block_on(futures::future::join_all(ws)) {
+        assert_eq!(w.0.unwrap(), 4);
+    }
+    let r = futures::executor::block_on(aiomgr.read(fd, 0, 256, None));
+    assert_eq!(&r.1[..], "abcd".repeat(64).as_bytes());
+    assert_eq!(aiomgr.get_npending(), 0);
+}
+
+#[test]
+fn threads1() {
+    let aiomgr = AIOBuilder::default()
+        .thread_name("aio-test1")
+        .reaper_threads(2)
+        .affinity(&[0])
+        .build()
+        .unwrap();
+    let names = || {
+        std::fs::read_dir("/proc/self/task")
+            .unwrap()
+            .map(|t| {
+                let comm = t.unwrap().path().join("comm");
+                std::fs::read_to_string(comm).unwrap().trim().to_string()
+            })
+            .collect::<Vec<_>>()
+    };
+    // the threads name themselves once started
+    for name in ["aio-test1", "aio-test1-0", "aio-test1-1"].iter() {
+        while !names().iter().any(|n| n == name) {
+            std::thread::yield_now();
+        }
+    }
+    drop(aiomgr);
+    assert!(AIOBuilder::default().affinity(&[1 << 20]).build().is_err());
+}
+
+#[test]
+fn file1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test12")
+        .unwrap();
+    let file = AIOFile::new(file, &aiomgr);
+    let w = file.write_at(0, "hello".as_bytes().to_vec(), None);
+    assert_eq!(futures::executor::block_on(w).0.unwrap(), 5);
+    let w = file.write_vectored_at(5, vec!["world".as_bytes().into()], None);
+    assert_eq!(futures::executor::block_on(w).0.unwrap(), 5);
+    let s = file.sync();
+    let r = file.read_at(0, 10, None);
+    // the file stays open until the operations are finished
+    drop(file);
+    assert_eq!(futures::executor::block_on(s).0.unwrap(), 0);
+    let r = futures::executor::block_on(r);
+    assert_eq!(r.0.unwrap(), 10);
+    assert_eq!(&r.1[..], "helloworld".as_bytes());
+}
+
+#[cfg(feature = "futures-io")]
+#[test]
+fn stream1() {
+    use futures::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom};
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test13")
+        .unwrap();
+    let mut stream = aiofut::AIOStream::new(AIOFile::new(file, &aiomgr));
+    futures::executor::block_on(async {
+        stream.write_all("hello".as_bytes()).await.unwrap();
+        stream.write_all("world".as_bytes()).await.unwrap();
+        stream.flush().await.unwrap();
+        assert_eq!(stream.position(), 10);
+        assert_eq!(stream.seek(SeekFrom::End(-5)).await.unwrap(), 5);
+        let mut s = String::new();
+        stream.read_to_string(&mut s).await.unwrap();
+        assert_eq!(s, "world");
+        stream.seek(SeekFrom::Start(2)).await.unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, "llow".as_bytes());
+        assert!(stream.seek(SeekFrom::Current(-7)).await.is_err());
+        // a write left pending lands before the cursor moves
+        let _ = futures::poll!(stream.write("ab".as_bytes()));
+        assert_eq!(stream.seek(SeekFrom::Current(0)).await.unwrap(), 8);
+        stream.seek(SeekFrom::Start(0)).await.unwrap();
+        let mut s = String::new();
+        stream.read_to_string(&mut s).await.unwrap();
+        assert_eq!(s, "hellowabld");
+    });
+}
+
+#[cfg(feature = "tokio-io")]
+#[test]
+fn stream2() {
+    use std::io::SeekFrom;
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test14")
+        .unwrap();
+    let mut stream = aiofut::AIOStream::new(AIOFile::new(file, &aiomgr));
+    futures::executor::block_on(async {
+        stream.write_all("hello".as_bytes()).await.unwrap();
+        stream.write_all("world".as_bytes()).await.unwrap();
+        stream.flush().await.unwrap();
+        assert_eq!(stream.seek(SeekFrom::End(-5)).await.unwrap(), 5);
+        let mut s = String::new();
+        stream.read_to_string(&mut s).await.unwrap();
+        assert_eq!(s, "world");
+        stream.seek(SeekFrom::Start(2)).await.unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, "llow".as_bytes());
+    });
+}
+
+#[test]
+fn cursor1() {
+    use std::io::SeekFrom;
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test15")
+        .unwrap();
+    let mut cursor = AIOCursor::new(&aiomgr, &file);
+    futures::executor::block_on(async {
+        assert_eq!(cursor.write("hello".as_bytes().to_vec()).await.0, Ok(5));
+        assert_eq!(cursor.write("world".as_bytes().to_vec()).await.0, Ok(5));
+        assert_eq!(cursor.position(), 10);
+        assert_eq!(cursor.seek(SeekFrom::End(-7)).unwrap(), 3);
+        let r = cursor.read(4).await;
+        assert_eq!(&r.1[..], "lowo".as_bytes());
+        assert_eq!(cursor.seek(SeekFrom::Current(-2)).unwrap(), 5);
+        let r = cursor.read(10).await;
+        assert_eq!(r.0, Ok(5));
+        assert_eq!(&r.1[..5], "world".as_bytes());
+        assert!(cursor.seek(SeekFrom::Current(-11)).is_err());
+    });
+}
+
+#[test]
+fn writer1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test16")
+        .unwrap();
+    let mut expected = Vec::new();
+    futures::executor::block_on(async {
+        let mut writer = BufferedWriter::new(&aiomgr, &file, 3, 16, 2);
+        for i in 0..100u8 {
+            let piece = vec![i; (i % 7) as usize];
+            writer.write(&piece).await.unwrap();
+            expected.extend(piece);
+        }
+        writer.flush().await.unwrap();
+        assert_eq!(writer.position(), 3 + expected.len() as u64);
+    });
+    let content = std::fs::read("test16").unwrap();
+    assert_eq!(&content[3..], &expected[..]);
+}
+
+#[test]
+fn writer2() {
+    use std::os::unix::fs::OpenOptionsExt;
+    let aiomgr = AIOBuilder::default().alignment(4096).build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .custom_flags(libc::O_DIRECT)
+        .open("test90")
+        .unwrap();
+    let mut expected = Vec::new();
+    futures::executor::block_on(async {
+        // the chunks and the tail left to flush are aligned
+        let mut writer = BufferedWriter::new(&aiomgr, &file, 4096, 8192, 2);
+        for i in 0..40u8 {
+            let piece = vec![i; 512];
+            writer.write(&piece).await.unwrap();
+            expected.extend(piece);
+        }
+        writer.flush().await.unwrap();
+        assert_eq!(writer.position(), 4096 + expected.len() as u64);
+    });
+    let content = std::fs::read("test90").unwrap();
+    assert_eq!(&content[4096..], &expected[..]);
+    std::fs::remove_file("test90").unwrap();
+}
+
+#[test]
+fn reader1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let content = (0..1000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    std::fs::write("test17", &content).unwrap();
+    let file = std::fs::File::open("test17").unwrap();
+    let mut reader = BufferedReader::new(&aiomgr, &file, 5, 64, 3);
+    let mut data = Vec::new();
+    futures::executor::block_on(async {
+        let mut buf = [0; 50];
+        loop {
+            let n = reader.read(&mut buf).await.unwrap();
+            if n == 0 {
+                break
+            }
+            data.extend_from_slice(&buf[..n]);
+        }
+    });
+    assert_eq!(reader.position(), 1000);
+    assert_eq!(&data[..], &content[5..]);
+}
+
+#[test]
+fn aligned1() {
+    use std::os::unix::fs::OpenOptionsExt;
+    let aiomgr = AIOBuilder::default().alignment(4096).build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .custom_flags(libc::O_DIRECT)
+        .open("test18")
+        .unwrap();
+    let mut data = aiofut::AlignedBuf::new(8192, 4096).unwrap();
+    assert_eq!(data.as_ptr() as usize % 4096, 0);
+    data[4096..].copy_from_slice(&[42; 4096]);
+    let w = aiomgr.write_aligned(&file, 0, data, None);
+    assert_eq!(futures::executor::block_on(w).0.unwrap(), 8192);
+    let r = futures::executor::block_on(
+        aiomgr.read_aligned(&file, 4096, 4096, None),
+    );
+    assert_eq!(r.0.unwrap(), 4096);
+    assert_eq!(r.1.as_ptr() as usize % 4096, 0);
+    assert!(r.1.iter().all(|b| *b == 42));
+}
+
+#[test]
+fn pool1() {
+    let aiomgr = AIOBuilder::default().buffer_pool(16).build().unwrap();
+    std::fs::write("test19", "helloworld").unwrap();
+    let file = std::fs::File::open("test19").unwrap();
+    let r = futures::executor::block_on(aiomgr.read(&file, 0, 10, None));
+    assert_eq!(&r.1[..], "helloworld".as_bytes());
+    let ptr = r.1.as_ptr();
+    aiomgr.recycle(r.1);
+    // exceeds the pool size
+    aiomgr.recycle(vec![0; 10].into_boxed_slice());
+    let r = futures::executor::block_on(aiomgr.read(&file, 5, 10, None));
+    assert_eq!(r.0.unwrap(), 5);
+    assert_eq!(r.1.as_ptr(), ptr);
+    assert_eq!(&r.1[..5], "world".as_bytes());
+    let r2 = futures::executor::block_on(aiomgr.read(&file, 0, 10, None));
+    assert_ne!(r2.1.as_ptr(), ptr);
+}
+
+#[test]
+fn generic1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test20")
+        .unwrap();
+    let w = aiomgr.write(&file, 0, "hello".as_bytes(), None);
+    let (res, data): (_, &[u8]) = futures::executor::block_on(w);
+    assert_eq!((res.unwrap(), data), (5, "hello".as_bytes()));
+    let shared: std::sync::Arc<[u8]> = "world".as_bytes().into();
+    let w = aiomgr.write(&file, 5, shared.clone(), None);
+    let (res, data) = futures::executor::block_on(w);
+    assert_eq!(res.unwrap(), 5);
+    assert!(std::sync::Arc::ptr_eq(&data, &shared));
+    let w = aiomgr.write(&file, 10, Box::<[u8]>::from("!".as_bytes()), None);
+    assert_eq!(futures::executor::block_on(w).0.unwrap(), 1);
+    let r = futures::executor::block_on(aiomgr.read(&file, 0, 11, None));
+    assert_eq!(&r.1[..], "helloworld!".as_bytes());
+}
+
+#[test]
+fn shared1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let files = (0..3)
+        .map(|i| {
+            std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(format!("test21-{}", i))
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let block: std::sync::Arc<[u8]> = vec![7; 4096].into();
+    let ws = files
+        .iter()
+        .flat_map(|f| (0..4).map(move |i| (f, i * 4096)))
+        .map(|(f, off)| aiomgr.write_shared(f, off, &block, None))
+        .collect::<Vec<_>>();
+    for w in futures::executor::block_on(futures::future::join_all(ws)) {
+        assert_eq!(w.0.unwrap(), 4096);
+        assert!(std::sync::Arc::ptr_eq(&w.1, &block));
+    }
+    assert_eq!(std::sync::Arc::strong_count(&block), 1);
+    for i in 0..3 {
+        let content = std::fs::read(format!("test21-{}", i)).unwrap();
+        assert_eq!(content, vec![7; 4 * 4096]);
+    }
+}
+
+#[test]
+fn read_into1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    std::fs::write("test22", "helloworld").unwrap();
+    let file = std::fs::File::open("test22").unwrap();
+    let buf = vec![1u8; 8];
+    let ptr = buf.as_ptr();
+    let r = aiomgr.read_into(&file, 2, buf, None);
+    let (res, buf): (_, Vec<u8>) = futures::executor::block_on(r);
+    assert_eq!(res.unwrap(), 8);
+    assert_eq!((buf.as_ptr(), &buf[..]), (ptr, "lloworld".as_bytes()));
+    let buf = aiofut::AlignedBuf::new(512, 512).unwrap();
+    let r = aiomgr.read_into(&file, 5, buf, None);
+    let (res, buf) = futures::executor::block_on(r);
+    assert_eq!(res.unwrap(), 5);
+    assert_eq!(&buf[..5], "world".as_bytes());
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn bytes1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test23")
+        .unwrap();
+    let data = bytes::Bytes::from_static("helloworld".as_bytes());
+    let w = aiomgr.write_bytes(&file, 0, data.slice(5..), None);
+    let (res, data2) = futures::executor::block_on(w);
+    assert_eq!(res.unwrap(), 5);
+    assert_eq!(data2.as_ptr(), data[5..].as_ptr());
+    let r = aiomgr.read_bytes(&file, 0, 8, None);
+    let (res, buf) = futures::executor::block_on(r);
+    assert_eq!(res.unwrap(), 5);
+    assert_eq!(&buf.freeze()[..5], "world".as_bytes());
+}
+
+#[test]
+fn flags1() {
+    use aiofut::RWFlags;
+    let backends = [
+        Backend::Libaio,
+        #[cfg(feature = "uring")]
+        Backend::Uring,
+        Backend::ThreadPool,
+    ];
+    for (i, backend) in backends.iter().enumerate() {
+        let aiomgr = AIOBuilder::default().backend(*backend).build().unwrap();
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(format!("test24-{}", i))
+            .unwrap();
+        let w = aiomgr.write(&file, 0, "hello".as_bytes(), None);
+        assert_eq!(futures::executor::block_on(w).0.unwrap(), 5);
+        // the offset is ignored when appending
+        let flags = RWFlags::APPEND | RWFlags::DSYNC;
+        let w =
+            aiomgr.write_with_flags(&file, 0, "world".as_bytes(), None, flags);
+        assert_eq!(futures::executor::block_on(w).0.unwrap(), 5);
+        let r = aiomgr.read_with_flags(&file, 0, 10, None, RWFlags::empty());
+        assert_eq!(
+            &futures::executor::block_on(r).1[..],
+            "helloworld".as_bytes()
+        );
+    }
+}
+
+#[test]
+fn ioprio1() {
+    use aiofut::IoPriority;
+    let backends = [
+        Backend::Libaio,
+        #[cfg(feature = "uring")]
+        Backend::Uring,
+        Backend::ThreadPool,
+    ];
+    for (i, backend) in backends.iter().enumerate() {
+        let aiomgr = AIOBuilder::default().backend(*backend).build().unwrap();
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(format!("test25-{}", i))
+            .unwrap();
+        let prio = Some(IoPriority::Be(0));
+        let w = aiomgr.write(&file, 0, "hello".as_bytes(), prio);
+        assert_eq!(futures::executor::block_on(w).0.unwrap(), 5);
+        let r = aiomgr.read(&file, 0, 5, Some(IoPriority::Idle));
+        assert_eq!(&futures::executor::block_on(r).1[..], "hello".as_bytes());
+        // back to the default priority
+        let r = aiomgr.read(&file, 0, 5, None);
+        assert_eq!(&futures::executor::block_on(r).1[..], "hello".as_bytes());
+        // rejected without being submitted
+        let r = aiomgr.read(&file, 0, 5, Some(IoPriority::Be(8)));
+        assert_eq!(futures::executor::block_on(r).0, Err(libc::EINVAL));
+    }
+}
+
+#[test]
+fn lanes1() {
+    use aiofut::IoPriority;
+    use futures::FutureExt;
+    let aiomgr = AIOBuilder::default()
+        .priority_weights(2, 1, 1)
+        .build_manual()
+        .unwrap();
+    std::fs::write("test26", "helloworld").unwrap();
+    let file = std::fs::File::open("test26").unwrap();
+    let fd = &file;
+    let bulk = (0..4)
+        .map(|_| aiomgr.read(fd, 0, 10, Some(IoPriority::Idle)))
+        .collect::<Vec<_>>();
+    // scheduled last, but submitted (and finished) first
+    let r = aiomgr.read(fd, 5, 5, Some(IoPriority::Be(0)));
+    assert_eq!(aiomgr.drive(1, None), 1);
+    assert_eq!(&r.now_or_never().unwrap().1[..], "world".as_bytes());
+    let mut nreaped = 1;
+    while nreaped < 5 {
+        nreaped += aiomgr.drive(16, None);
+    }
+    for r in bulk {
+        assert_eq!(
+            &futures::executor::block_on(r).1[..],
+            "helloworld".as_bytes()
+        );
+    }
+}
+
+#[test]
+fn deadline1() {
+    use futures::FutureExt;
+    use std::time::{Duration, Instant};
+    let aiomgr = AIOBuilder::default().build_manual().unwrap();
+    std::fs::write("test27", "helloworld").unwrap();
+    let file = std::fs::File::open("test27").unwrap();
+    let fd = &file;
+    let now = Instant::now();
+    let hour = Duration::from_secs(3600);
+    let r1 = aiomgr.read(fd, 0, 10, None);
+    let r2 = aiomgr.read_with_deadline(fd, 0, 5, None, now + 2 * hour);
+    let r3 = aiomgr.read_with_deadline(fd, 5, 5, None, now + hour);
+    assert_eq!(aiomgr.drive(1, None), 1);
+    assert_eq!(&r3.now_or_never().unwrap().1[..], "world".as_bytes());
+    assert_eq!(aiomgr.drive(1, None), 1);
+    assert_eq!(&r2.now_or_never().unwrap().1[..], "hello".as_bytes());
+    assert_eq!(aiomgr.drive(1, None), 1);
+    assert_eq!(&r1.now_or_never().unwrap().1[..], "helloworld".as_bytes());
+    assert_eq!(aiomgr.get_deadline_misses(), 0);
+    // already missed
+    let r = aiomgr.read_with_deadline(fd, 0, 5, None, now);
+    assert_eq!(aiomgr.drive(1, None), 1);
+    assert_eq!(&r.now_or_never().unwrap().1[..], "hello".as_bytes());
+    assert_eq!(aiomgr.get_deadline_misses(), 1);
+}
+
+#[test]
+fn options1() {
+    use aiofut::{IoOptions, RWFlags, RetryPolicy};
+    use futures::FutureExt;
+    use std::time::{Duration, Instant};
+    let aiomgr = AIOBuilder::default().build_manual().unwrap();
+    std::fs::write("test92", "helloworld").unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("test92")
+        .unwrap();
+    let fd = &file;
+    let soon = Instant::now() + Duration::from_secs(3600);
+    // the flags, the deadline, the tag and the retry policy all apply
+    let w = aiomgr.write_with_options(
+        fd,
+        0,
+        "HELLO".as_bytes(),
+        None,
+        IoOptions::default()
+            .flags(RWFlags::DSYNC)
+            .deadline(soon)
+            .tag(1)
+            .retry(RetryPolicy::never()),
+    );
+    assert_eq!(aiomgr.drive(1, None), 1);
+    assert_eq!(w.now_or_never().unwrap().0.unwrap(), 5);
+    let r1 = aiomgr.read(fd, 0, 10, None);
+    let mut opts = IoOptions::default();
+    opts.deadline(soon).tag(2);
+    let r2 = aiomgr.read_with_options(fd, 5, 5, None, &opts);
+    assert_eq!(aiomgr.drive(1, None), 1);
+    assert_eq!(&r2.now_or_never().unwrap().1[..], "world".as_bytes());
+    assert_eq!(aiomgr.drive(1, None), 1);
+    assert_eq!(&r1.now_or_never().unwrap().1[..], "HELLOworld".as_bytes());
+    assert_eq!(aiomgr.get_deadline_misses(), 0);
+}
+
+#[test]
+fn bounded1() {
+    let aiomgr = AIOBuilder::default().max_pending(2).build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test28")
+        .unwrap();
+    let fd = &file;
+    let ws = (0..8)
+        .map(|i| aiomgr.write(fd, i * 5, "hello".as_bytes(), None))
+        .collect::<Vec<_>>();
+    // the rest wait to be polled
+    assert!(aiomgr.get_npending() <= 2);
+    for (res, _) in futures::executor::block_on(futures::future::join_all(ws)) {
+        assert_eq!(res.unwrap(), 5);
+    }
+    assert_eq!(
+        std::fs::read("test28").unwrap(),
+        "hello".repeat(8).as_bytes()
+    );
+}
+
+#[test]
+fn try1() {
+    let aiomgr = AIOBuilder::default().max_pending(1).build_manual().unwrap();
+    std::fs::write("test29", "helloworld").unwrap();
+    let file = std::fs::File::open("test29").unwrap();
+    let fd = &file;
+    let r = aiomgr.try_read(fd, 0, 10, None).unwrap();
+    assert!(matches!(
+        aiomgr.try_read(fd, 0, 10, None),
+        Err(aiofut::Error::QueueFull)
+    ));
+    while aiomgr.drive(1, None) == 0 {}
+    assert_eq!(
+        &futures::executor::block_on(r).1[..],
+        "helloworld".as_bytes()
+    );
+    // the room is back
+    let r = aiomgr.try_read(fd, 0, 5, None).unwrap();
+    while aiomgr.drive(1, None) == 0 {}
+    assert_eq!(&futures::executor::block_on(r).1[..], "hello".as_bytes());
+}
+
+#[test]
+fn stats1() {
+    let aiomgr = AIOBuilder::default().build_manual().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test30")
+        .unwrap();
+    let fd = &file;
+    let w = aiomgr.write(fd, 0, "helloworld".as_bytes(), None);
+    aiomgr.drive(0, None);
+    assert_eq!(aiomgr.stats().in_flight, 1);
+    while aiomgr.drive(1, None) == 0 {}
+    assert_eq!(futures::executor::block_on(w).0.unwrap(), 10);
+    let r = aiomgr.read(fd, 0, 5, None);
+    let c = aiomgr.read(fd, 0, 5, None);
+    assert!(aiomgr.cancel(c.get_id()));
+    while aiomgr.drive(1, None) == 0 {}
+    assert_eq!(futures::executor::block_on(r).0.unwrap(), 5);
+    assert_eq!(futures::executor::block_on(c).0, Err(libc::ECANCELED));
+    let stats = aiomgr.stats();
+    assert_eq!(stats.submitted, 2);
+    assert_eq!(stats.completed, 2);
+    assert_eq!(stats.cancelled, 1);
+    assert_eq!(stats.failed, 0);
+    assert_eq!(stats.bytes_read, 5);
+    assert_eq!(stats.bytes_written, 10);
+    assert_eq!(stats.in_flight, 0);
+}
+
+#[test]
+fn fdstats1() {
+    use std::os::unix::io::AsRawFd;
+    let aiomgr = AIOBuilder::default().fd_stats(true).build().unwrap();
+    std::fs::write("test31-0", "helloworld").unwrap();
+    std::fs::write("test31-1", "hello").unwrap();
+    let f0 = std::fs::File::open("test31-0").unwrap();
+    let f1 = std::fs::File::open("test31-1").unwrap();
+    let rs = vec![
+        aiomgr.read(&f0, 0, 10, None),
+        aiomgr.read(&f0, 5, 5, None),
+        aiomgr.read(&f1, 0, 10, None),
+    ];
+    futures::executor::block_on(futures::future::join_all(rs));
+    let stats = aiomgr.fd_stats();
+    assert_eq!(stats.len(), 2);
+    let get = |fd| stats.iter().find(|(f, _)| *f == fd).unwrap().1;
+    let s0 = get(f0.as_raw_fd());
+    assert_eq!((s0.ops, s0.bytes, s0.errors), (2, 15, 0));
+    assert!(s0.mean_latency() <= s0.total_latency);
+    let s1 = get(f1.as_raw_fd());
+    assert_eq!((s1.ops, s1.bytes, s1.errors), (1, 5, 0));
+    aiomgr.reset_fd_stats();
+    assert!(aiomgr.fd_stats().is_empty());
+    // more AIOs than a u32 counts
+    let many = aiofut::FdStats {
+        ops: 1 << 33,
+        total_latency: std::time::Duration::from_secs(1 << 34),
+        ..Default::default()
+    };
+    assert_eq!(many.mean_latency(), std::time::Duration::from_secs(2));
+}
+
+#[cfg(feature = "prometheus")]
+#[test]
+fn prometheus1() {
+    let aiomgr = AIOBuilder::default().fd_stats(true).build().unwrap();
+    let registry = prometheus::Registry::new();
+    aiomgr.register_metrics(&registry, "test").unwrap();
+    std::fs::write("test32", "helloworld").unwrap();
+    let file = std::fs::File::open("test32").unwrap();
+    let r = aiomgr.read(&file, 0, 10, None);
+    assert_eq!(futures::executor::block_on(r).0.unwrap(), 10);
+    let families = registry.gather();
+    let value = |name: &str| {
+        let f = families.iter().find(|f| f.get_name() == name).unwrap();
+        f.get_metric()[0].get_counter().get_value()
+    };
+    assert_eq!(value("test_aio_completed_total"), 1.0);
+    assert_eq!(value("test_aio_read_bytes_total"), 10.0);
+    assert_eq!(value("test_aio_fd_bytes_total"), 10.0);
+    // twice the same namespace
+    assert!(aiomgr.register_metrics(&registry, "test").is_err());
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn tracing1() {
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+
+    // collect the results recorded on the spans
+    struct Results(Arc<Mutex<Vec<i64>>>);
+    impl Visit for Results {
+        fn record_i64(&mut self, field: &Field, value: i64) {
+            if field.name() == "res" {
+                self.0.lock().unwrap().push(value)
+            }
+        }
+        fn record_debug(&mut self, _: &Field, _: &dyn std::fmt::Debug) {}
+    }
+    struct Subscriber(Arc<Mutex<Vec<i64>>>);
+    impl tracing::Subscriber for Subscriber {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            assert_eq!(span.metadata().name(), "aio");
+            Id::from_u64(1)
+        }
+        fn record(&self, _: &Id, values: &Record<'_>) {
+            values.record(&mut Results(self.0.clone()))
+        }
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &tracing::Event<'_>) {}
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let subscriber = Subscriber(results.clone());
+    tracing::subscriber::with_default(subscriber, || {
+        let aiomgr = AIOBuilder::default().build().unwrap();
+        std::fs::write("test33", "helloworld").unwrap();
+        let file = std::fs::File::open("test33").unwrap();
+        let r = aiomgr.read(&file, 0, 10, None);
+        assert_eq!(futures::executor::block_on(r).0.unwrap(), 10);
+    });
+    assert_eq!(*results.lock().unwrap(), vec![10]);
+}
+
+#[cfg(feature = "log")]
+#[test]
+fn log1() {
+    use std::sync::Mutex;
+    static MESSAGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    struct Logger;
+    impl log::Log for Logger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+        fn log(&self, record: &log::Record) {
+            MESSAGES.lock().unwrap().push(record.args().to_string())
+        }
+        fn flush(&self) {}
+    }
+    log::set_logger(&Logger).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    std::fs::write("test34", "helloworld").unwrap();
+    let file = std::fs::File::open("test34").unwrap();
+    let r = aiomgr.read(&file, 0, 10, None);
+    assert_eq!(futures::executor::block_on(r).0.unwrap(), 10);
+    drop(aiomgr);
+    let messages = MESSAGES.lock().unwrap();
+    for m in [
+        "aio listener started",
+        "submitting 1 aios",
+        "aio listener exited",
+    ] {
+        assert!(messages.iter().any(|s| s == m), "missing {}", m);
+    }
+}
+
+#[test]
+fn slow1() {
+    use std::time::Duration;
+    let aiomgr = AIOBuilder::default()
+        .slow_op_threshold(Duration::from_millis(50))
+        .build_manual()
+        .unwrap();
+    std::fs::write("test35", "helloworld").unwrap();
+    let file = std::fs::File::open("test35").unwrap();
+    let fd = &file;
+    // stalled before being driven
+    let r = aiomgr.read(fd, 0, 10, None);
+    std::thread::sleep(Duration::from_millis(100));
+    while aiomgr.drive(1, None) == 0 {}
+    assert_eq!(futures::executor::block_on(r).0.unwrap(), 10);
+    assert_eq!(aiomgr.stats().slow_ops, 1);
+    let r = aiomgr.read(fd, 0, 10, None);
+    while aiomgr.drive(1, None) == 0 {}
+    assert_eq!(futures::executor::block_on(r).0.unwrap(), 10);
+    assert_eq!(aiomgr.stats().slow_ops, 1);
+}
+
+#[test]
+fn shutdown1() {
+    use futures::FutureExt;
+    for manual in [false, true] {
+        let aiomgr = if manual {
+            AIOBuilder::default().build_manual().unwrap()
+        } else {
+            AIOBuilder::default().build().unwrap()
+        };
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(format!("test36-{}", manual as u8))
+            .unwrap();
+        let ws = (0..4)
+            .map(|i| aiomgr.write(&file, i * 5, "hello".as_bytes(), None))
+            .collect::<Vec<_>>();
+        futures::executor::block_on(aiomgr.shutdown()).unwrap();
+        // all finished already
+        for w in ws {
+            assert_eq!(w.now_or_never().unwrap().0.unwrap(), 5);
+        }
+    }
+}
+
+#[test]
+fn shutdown2() {
+    use futures::FutureExt;
+    std::fs::write("test37", "helloworld").unwrap();
+    let file = std::fs::File::open("test37").unwrap();
+    // never submitted
+    let aiomgr = AIOBuilder::default().build_manual().unwrap();
+    let rs = (0..4)
+        .map(|_| aiomgr.read(&file, 0, 10, None))
+        .collect::<Vec<_>>();
+    aiomgr.shutdown_now();
+    for r in rs {
+        assert_eq!(r.now_or_never().unwrap().0, Err(libc::ECANCELED));
+    }
+    // cancelled, or finished anyway
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let rs = (0..4)
+        .map(|_| aiomgr.read(&file, 0, 10, None))
+        .collect::<Vec<_>>();
+    aiomgr.shutdown_now();
+    for (res, _) in futures::executor::block_on(futures::future::join_all(rs)) {
+        assert!(res == Ok(10) || res == Err(libc::ECANCELED));
+    }
+}
+
+#[test]
+fn shutdown3() {
+    std::fs::write("test38", "helloworld").unwrap();
+    let file = std::fs::File::open("test38").unwrap();
+    let aiomgr = AIOBuilder::default().max_pending(1).build_manual().unwrap();
+    let r1 = aiomgr.read(&file, 0, 10, None);
+    // waiting for a permit, so only scheduled when polled
+    let r2 = aiomgr.read(&file, 0, 10, None);
+    aiomgr.shutdown_now();
+    assert_eq!(futures::executor::block_on(r1).0, Err(libc::ECANCELED));
+    assert_eq!(futures::executor::block_on(r2).0, Err(libc::ESHUTDOWN));
+}
+
+#[test]
+fn error1() {
+    let e = AIOBuilder::default().affinity(&[usize::MAX]).build().err();
+    assert!(matches!(e, Some(aiofut::Error::Sys(libc::EINVAL))));
+    let e: Box<dyn std::error::Error> =
+        Box::new(aiofut::Error::from_errno(libc::ECANCELED));
+    assert_eq!(e.to_string(), "the AIO is cancelled");
+    assert!(matches!(
+        aiofut::Error::from_errno(libc::ESHUTDOWN),
+        aiofut::Error::Shutdown
+    ));
+    let e = std::io::Error::from(aiofut::Error::QueueFull);
+    assert_eq!(e.kind(), std::io::ErrorKind::WouldBlock);
+    let e = std::io::Error::from(aiofut::Error::Sys(libc::ENOENT));
+    assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+}
+
+#[test]
+fn io1() {
+    std::fs::write("test40", "helloworld").unwrap();
+    let file = std::fs::File::open("test40").unwrap();
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let (n, data) =
+        futures::executor::block_on(aiomgr.read(&file, 0, 5, None).into_io())
+            .unwrap();
+    assert_eq!((n, &data[..]), (5, "hello".as_bytes()));
+    let e = aiofut::into_io_result((Err(libc::EBADF), ())).unwrap_err();
+    assert_eq!(e.raw_os_error(), Some(libc::EBADF));
+}
+
+#[test]
+fn trimmed1() {
+    std::fs::write("test41", "helloworld").unwrap();
+    let file = std::fs::File::open("test41").unwrap();
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let r1 = aiomgr.read_trimmed(&file, 5, 4096, None);
+    let r2 = aiomgr.read_trimmed(&file, 10, 4096, None);
+    let (res, data) = futures::executor::block_on(r1);
+    assert_eq!(res, Ok(5));
+    assert_eq!(data, "world".as_bytes());
+    let (res, data) = futures::executor::block_on(r2);
+    assert_eq!(res, Ok(0));
+    assert!(data.is_empty());
+}
+
+#[test]
+fn writeall1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test42")
+        .unwrap();
+    let data = (0..1 << 20).map(|i| i as u8).collect::<Vec<_>>();
+    let (res, data) = futures::executor::block_on(
+        aiomgr.write_all_at(&file, 4096, data, None),
+    );
+    assert_eq!(res, Ok(1 << 20));
+    assert_eq!(std::fs::read("test42").unwrap()[4096..], data[..]);
+}
+
+#[cfg(feature = "emulated-failure")]
+#[test]
+fn retry1() {
+    // fail the given number of the next completions with EAGAIN
+    struct Fails(usize);
+    impl aiofut::EmulatedFailure for Fails {
+        fn tick(&mut self) -> Option<i64> {
+            if self.0 == 0 {
+                return None
+            }
+            self.0 -= 1;
+            Some(-libc::EAGAIN as i64)
+        }
+    }
+    let fails = std::sync::Arc::new(parking_lot::Mutex::new(Fails(2)));
+    let aiomgr = AIOBuilder::default()
+        .retry_policy(aiofut::RetryPolicy::new(
+            3,
+            std::time::Duration::from_millis(1),
+        ))
+        .emulated_failure(fails.clone())
+        .build()
+        .unwrap();
+    std::fs::write("test43", "helloworld").unwrap();
+    let file = std::fs::File::open("test43").unwrap();
+    let (res, data) =
+        futures::executor::block_on(aiomgr.read(&file, 0, 10, None));
+    assert_eq!(res, Ok(10));
+    assert_eq!(&data[..], "helloworld".as_bytes());
+    assert_eq!(aiomgr.stats().retries, 2);
+    // overridden by the operation
+    fails.lock().0 = 1;
+    let r = aiomgr.read_with_retry(
+        &file,
+        0,
+        10,
+        None,
+        aiofut::RetryPolicy::never(),
+    );
+    assert_eq!(futures::executor::block_on(r).0, Err(libc::EAGAIN));
+}
+
+#[test]
+fn batch1() {
+    let aiomgr = AIOBuilder::default().max_events(16).build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test44")
+        .unwrap();
+    let fd = file.as_raw_fd();
+    let ws = aiomgr.submit_batch(
+        (0..8)
+            .map(|i| {
+                aiofut::Request::write(
+                    fd,
+                    i * 5,
+                    format!("rec{:02}", i).into_bytes().into(),
+                )
+            })
+            .collect(),
+    );
+    for (res, _) in futures::executor::block_on(futures::future::join_all(ws)) {
+        assert_eq!(res, Ok(5));
+    }
+    let rs = aiomgr.submit_batch(
+        (0..8)
+            .rev()
+            .map(|i| aiofut::Request::read(fd, i * 5, 5))
+            .collect(),
+    );
+    let rs = futures::executor::block_on(futures::future::join_all(rs));
+    for (i, (res, data)) in (0..8).rev().zip(rs) {
+        assert_eq!(res, Ok(5));
+        assert_eq!(&data[..], format!("rec{:02}", i).as_bytes());
+    }
+    // never fits into the context
+    let rs = aiomgr.submit_batch(
+        (0..17).map(|i| aiofut::Request::read(fd, i, 1)).collect(),
+    );
+    for r in rs {
+        assert_eq!(r.now_or_never().unwrap().0, Err(libc::EINVAL));
+    }
+}
+
+#[test]
+fn batch2() {
+    // as many AIOs as the context takes, each longer than max_io_size
+    let aiomgr = AIOBuilder::default()
+        .max_events(2)
+        .max_io_size(4)
+        .build()
+        .unwrap();
+    std::fs::write("test93", "helloworld").unwrap();
+    let file = std::fs::File::open("test93").unwrap();
+    let fd = file.as_raw_fd();
+    let rs = aiomgr.submit_batch(vec![
+        aiofut::Request::read(fd, 0, 10),
+        aiofut::Request::read(fd, 5, 5),
+    ]);
+    let rs = futures::executor::block_on(futures::future::join_all(rs));
+    assert_eq!(rs[0].0, Ok(10));
+    assert_eq!(&rs[0].1[..], "helloworld".as_bytes());
+    assert_eq!(rs[1].0, Ok(5));
+    assert_eq!(&rs[1].1[..], "world".as_bytes());
+}
+
+#[test]
+fn barrier1() {
+    let aiomgr = AIOBuilder::default().barriers(true).build_manual().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test45")
+        .unwrap();
+    let w1 = aiomgr.write(&file, 0, "hello".as_bytes(), None);
+    aiomgr.barrier(&file);
+    let w2 = aiomgr.write(&file, 0, "world".as_bytes(), None);
+    // another fd is not held back
+    let other = std::fs::File::open("Cargo.toml").unwrap();
+    let r = aiomgr.read(&other, 0, 1, None);
+    aiomgr.drive(0, None);
+    assert_eq!(aiomgr.stats().submitted, 2);
+    while aiomgr.get_npending() > 0 {
+        aiomgr.drive(1, None);
+    }
+    assert_eq!(aiomgr.stats().submitted, 3);
+    for (res, _) in
+        futures::executor::block_on(futures::future::join_all(vec![w1, w2]))
+    {
+        assert_eq!(res, Ok(5));
+    }
+    assert_eq!(futures::executor::block_on(r).0, Ok(1));
+    assert_eq!(std::fs::read("test45").unwrap(), "world".as_bytes());
+}
+
+#[test]
+fn group1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test46")
+        .unwrap();
+    let mut g = aiomgr.group();
+    for i in 0..4 {
+        g.write(
+            &file,
+            i * 5,
+            format!("rec{:02}", i).into_bytes().into(),
+            None,
+        );
+    }
+    assert_eq!(g.len(), 4);
+    let (res, bufs) = futures::executor::block_on(g);
+    assert_eq!(res, Ok(20));
+    assert_eq!(&bufs[3][..], "rec03".as_bytes());
+    let mut g = aiomgr.group();
+    g.read(&file, 0, 5, None).read(&file, 15, 5, None);
+    let (res, bufs) = futures::executor::block_on(g);
+    assert_eq!(res, Ok(10));
+    assert_eq!(&bufs[1][..], "rec03".as_bytes());
+    // the first error wins, while all the buffers come back
+    let ro = std::fs::File::open("test46").unwrap();
+    let mut g = aiomgr.group();
+    g.read(&ro, 0, 5, None)
+        .read(&ro, 0, 5, Some(aiofut::IoPriority::Be(8)));
+    let (res, bufs) = futures::executor::block_on(g);
+    assert_eq!(res, Err(libc::EINVAL));
+    assert_eq!(bufs.len(), 2);
+    assert_eq!(&bufs[0][..], "rec00".as_bytes());
+}
+
+#[test]
+fn fdorder1() {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test47")
+        .unwrap();
+    let idle = Some(aiofut::IoPriority::Idle);
+    let rt = Some(aiofut::IoPriority::Rt(0));
+    // the urgent write is held back until the earlier one is finished
+    let aiomgr = AIOBuilder::default()
+        .fd_order(aiofut::FdOrder::Completion)
+        .build_manual()
+        .unwrap();
+    let w1 = aiomgr.write(&file, 0, "hello".as_bytes(), idle);
+    let w2 = aiomgr.write(&file, 0, "world".as_bytes(), rt);
+    aiomgr.drive(0, None);
+    assert_eq!(aiomgr.stats().submitted, 1);
+    while aiomgr.get_npending() > 0 {
+        aiomgr.drive(1, None);
+    }
+    for (res, _) in
+        futures::executor::block_on(futures::future::join_all(vec![w1, w2]))
+    {
+        assert_eq!(res, Ok(5));
+    }
+    assert_eq!(std::fs::read("test47").unwrap(), "world".as_bytes());
+    // while only the submissions are ordered otherwise
+    let aiomgr = AIOBuilder::default()
+        .fd_order(aiofut::FdOrder::Submission)
+        .build_manual()
+        .unwrap();
+    let ws = (0..4)
+        .map(|i| aiomgr.write(&file, i * 5, "hello".as_bytes(), rt))
+        .collect::<Vec<_>>();
+    aiomgr.drive(0, None);
+    assert_eq!(aiomgr.stats().submitted, 4);
+    while aiomgr.get_npending() > 0 {
+        aiomgr.drive(1, None);
+    }
+    for (res, _) in futures::executor::block_on(futures::future::join_all(ws)) {
+        assert_eq!(res, Ok(5));
+    }
+}
+
+#[test]
+fn merge1() {
+    let aiomgr = AIOBuilder::default()
+        .max_events(16)
+        .merge_writes(true)
+        .build()
+        .unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test48")
+        .unwrap();
+    let fd = file.as_raw_fd();
+    // two runs of contiguous writes, with a read in between
+    let mut reqs = (0..4)
+        .map(|i| {
+            aiofut::Request::write(
+                fd,
+                i * 5,
+                format!("rec{:02}", i).into_bytes().into(),
+            )
+        })
+        .collect::<Vec<_>>();
+    reqs.push(aiofut::Request::read(fd, 100, 5));
+    reqs.extend((4..8).map(|i| {
+        aiofut::Request::write(
+            fd,
+            i * 5,
+            format!("rec{:02}", i).into_bytes().into(),
+        )
+    }));
+    let rs = futures::executor::block_on(futures::future::join_all(
+        aiomgr.submit_batch(reqs),
+    ));
+    for (i, (res, _)) in rs.into_iter().enumerate() {
+        assert_eq!(res, Ok(if i == 4 { 0 } else { 5 }));
+    }
+    let (res, data) =
+        futures::executor::block_on(aiomgr.read(&file, 0, 40, None));
+    assert_eq!(res, Ok(40));
+    let expected = (0..8).map(|i| format!("rec{:02}", i)).collect::<String>();
+    assert_eq!(&data[..], expected.as_bytes());
+    let stats = aiomgr.stats();
+    assert_eq!(stats.submitted, 10);
+    assert_eq!(stats.completed, 10);
+    assert_eq!(stats.bytes_written, 40);
+    assert_eq!(stats.in_flight, 0);
+}
+
+#[test]
+fn elevator1() {
+    let aiomgr = AIOBuilder::default()
+        .elevator(true)
+        .merge_writes(true)
+        .build_manual()
+        .unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test49")
+        .unwrap();
+    let fd = &file;
+    // scheduled backwards, but sorted into a single run of contiguous writes
+    let ws = (0..8)
+        .rev()
+        .map(|i| {
+            aiomgr.write(fd, i * 5, format!("rec{:02}", i).into_bytes(), None)
+        })
+        .collect::<Vec<_>>();
+    let mut nreaped = 0;
+    while nreaped < ws.len() {
+        nreaped += aiomgr.drive(16, None);
+    }
+    for w in ws {
+        assert_eq!(w.now_or_never().unwrap().0, Ok(5));
+    }
+    let r = aiomgr.read(fd, 0, 40, None);
+    while aiomgr.drive(16, None) == 0 {}
+    let expected = (0..8).map(|i| format!("rec{:02}", i)).collect::<String>();
+    assert_eq!(&r.now_or_never().unwrap().1[..], expected.as_bytes());
+}
+
+#[test]
+fn split1() {
+    let aiomgr = AIOBuilder::default().max_io_size(4096).build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test50")
+        .unwrap();
+    let fd = &file;
+    let data = (0..10000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let (res, _) =
+        futures::executor::block_on(aiomgr.write(fd, 0, data.clone(), None));
+    assert_eq!(res, Ok(10000));
+    let (res, buf) =
+        futures::executor::block_on(aiomgr.read(fd, 0, 10000, None));
+    assert_eq!(res, Ok(10000));
+    assert_eq!(&buf[..], &data[..]);
+    // the chunks past the end of the file are short
+    let (res, buf) =
+        futures::executor::block_on(aiomgr.read(fd, 100, 16384, None));
+    assert_eq!(res, Ok(9900));
+    assert_eq!(&buf[..9900], &data[100..]);
+    let stats = aiomgr.stats();
+    assert_eq!(stats.submitted, 3);
+    assert_eq!(stats.bytes_written, 10000);
+    assert_eq!(stats.bytes_read, 19900);
+    assert_eq!(stats.in_flight, 0);
+}
+
+#[test]
+fn align1() {
+    let aiomgr = AIOBuilder::default()
+        .alignment(512)
+        .validate_alignment(true)
+        .build()
+        .unwrap();
+    std::fs::write("test51", vec![1; 4096]).unwrap();
+    let file = std::fs::File::open("test51").unwrap();
+    let fd = &file;
+    // not opened with O_DIRECT
+    let (res, _) = futures::executor::block_on(aiomgr.read(fd, 100, 10, None));
+    assert_eq!(res, Ok(10));
+    aiomgr.set_fd_alignment(fd, Some(512));
+    match aiomgr.try_read(fd, 100, 512, None) {
+        Err(aiofut::Error::Misaligned { required, got }) => {
+            assert_eq!((required, got), (512, 100))
+        }
+        _ => panic!("misaligned read accepted"),
+    }
+    let (res, _) = futures::executor::block_on(aiomgr.read(fd, 512, 100, None));
+    assert_eq!(res, Err(libc::EINVAL));
+    let (res, buf) =
+        futures::executor::block_on(aiomgr.read_aligned(fd, 512, 1024, None));
+    assert_eq!(res, Ok(1024));
+    assert_eq!(&buf[..], &[1; 1024][..]);
+    aiomgr.set_fd_alignment(fd, None);
+    let (res, _) = futures::executor::block_on(aiomgr.read(fd, 100, 10, None));
+    assert_eq!(res, Ok(10));
+    // probed, unless the file system does not support O_DIRECT
+    use std::os::unix::fs::OpenOptionsExt;
+    if let Ok(direct) = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECT)
+        .open("test51")
+    {
+        assert!(matches!(
+            aiomgr.try_read(&direct, 100, 512, None),
+            Err(aiofut::Error::Misaligned {
+                required: 512,
+                got: 100
+            })
+        ));
+    }
+}
+
+#[test]
+fn unaligned1() {
+    let aiomgr = AIOBuilder::default().alignment(512).build().unwrap();
+    let data = (0..3000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    std::fs::write("test52", &data).unwrap();
+    // falls back to a buffered file where O_DIRECT is not supported
+    use std::os::unix::fs::OpenOptionsExt;
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECT)
+        .open("test52")
+        .unwrap_or_else(|_| std::fs::File::open("test52").unwrap());
+    let (res, buf) = futures::executor::block_on(
+        aiomgr.read_unaligned(&file, 700, 1000, None),
+    );
+    assert_eq!(res, Ok(1000));
+    assert_eq!(&buf[..], &data[700..1700]);
+    // short at the end of the file
+    let (res, buf) = futures::executor::block_on(
+        aiomgr.read_unaligned(&file, 2900, 1000, None),
+    );
+    assert_eq!(res, Ok(100));
+    assert_eq!(&buf[..], &data[2900..]);
+    // the range widened past the end of the offsets
+    let (res, _) = futures::executor::block_on(aiomgr.read_unaligned(
+        &file,
+        u64::MAX - 100,
+        1000,
+        None,
+    ));
+    assert_eq!(res, Err(libc::EINVAL));
+}
+
+#[test]
+fn geometry1() {
+    std::fs::write("test53", vec![0; 10000]).unwrap();
+    let file = std::fs::File::open("test53").unwrap();
+    let g = aiofut::Geometry::of(&file).unwrap();
+    assert_eq!(g.size, 10000);
+    assert!(g.logical_block_size.is_power_of_two());
+    assert!(g.physical_block_size >= g.logical_block_size);
+}
+
+#[test]
+fn discard1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    std::fs::write("test54", vec![1; 8192]).unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("test54")
+        .unwrap();
+    let fd = &file;
+    let (res, _) = futures::executor::block_on(aiomgr.discard(fd, 4096, 4096));
+    assert_eq!(res, Ok(0));
+    let (res, data) =
+        futures::executor::block_on(aiomgr.read(fd, 0, 8192, None));
+    assert_eq!(res, Ok(8192));
+    assert_eq!(&data[..4096], &[1; 4096][..]);
+    assert_eq!(&data[4096..], &[0; 4096][..]);
+    // not writable
+    let file = std::fs::File::open("test54").unwrap();
+    let (res, _) = futures::executor::block_on(aiomgr.discard(&file, 0, 4096));
+    assert_eq!(res, Err(libc::EBADF));
+}
+
+#[test]
+fn allocate1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test55")
+        .unwrap();
+    let fd = &file;
+    let (res, _) = futures::executor::block_on(aiomgr.allocate(fd, 0, 1 << 20));
+    assert_eq!(res, Ok(0));
+    assert_eq!(file.metadata().unwrap().len(), 1 << 20);
+    let (res, _) = futures::executor::block_on(aiomgr.allocate(fd, 0, 0));
+    assert_eq!(res, Err(libc::EINVAL));
+}
+
+#[test]
+fn truncate1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    std::fs::write("test56", "helloworld").unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("test56")
+        .unwrap();
+    let fd = &file;
+    let (res, _) = futures::executor::block_on(aiomgr.truncate(fd, 5));
+    assert_eq!(res, Ok(0));
+    assert_eq!(std::fs::read("test56").unwrap(), "hello".as_bytes());
+    let file = std::fs::File::open("test56").unwrap();
+    let (res, _) = futures::executor::block_on(aiomgr.truncate(&file, 0));
+    assert_eq!(res, Err(libc::EINVAL));
+}
+
+#[test]
+fn advise1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test57")
+        .unwrap();
+    let fd = &file;
+    let w = aiomgr.write(fd, 0, vec![1; 8192], None);
+    // after the write
+    let a = aiomgr.advise(fd, 0, 0, aiofut::Advice::DontNeed);
+    let (res, _) = futures::executor::block_on(a);
+    assert_eq!(res, Ok(0));
+    assert_eq!(w.now_or_never().unwrap().0, Ok(8192));
+    let (res, _) = futures::executor::block_on(aiomgr.advise(
+        fd,
+        0,
+        4096,
+        aiofut::Advice::WillNeed,
+    ));
+    assert_eq!(res, Ok(0));
+    let mut pipe = [0; 2];
+    assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+    let (res, _) = futures::executor::block_on(aiomgr.advise_raw(
+        pipe[0],
+        0,
+        0,
+        aiofut::Advice::Random,
+    ));
+    assert_eq!(res, Err(libc::ESPIPE));
+    unsafe {
+        libc::close(pipe[0]);
+        libc::close(pipe[1]);
+    }
+}
+
+#[test]
+fn readahead1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    std::fs::write("test58", vec![1; 65536]).unwrap();
+    let file = std::fs::File::open("test58").unwrap();
+    let (res, _) =
+        futures::executor::block_on(aiomgr.readahead(&file, 0, 65536));
+    assert_eq!(res, Ok(0));
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open("test58")
+        .unwrap();
+    let (res, _) = futures::executor::block_on(aiomgr.readahead(&file, 0, 1));
+    assert_eq!(res, Err(libc::EBADF));
+}
+
+#[test]
+fn copy_range1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let src = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test59")
+        .unwrap();
+    let dst = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test60")
+        .unwrap();
+    let data: Vec<u8> = (0..8192).map(|i| i as u8).collect();
+    let w = aiomgr.write(&src, 0, data.clone(), None);
+    // after the write
+    let c = aiomgr.copy_range(&src, 4096, &dst, 100, 4096);
+    let (res, _) = futures::executor::block_on(c);
+    assert_eq!(res, Ok(4096));
+    assert_eq!(w.now_or_never().unwrap().0, Ok(8192));
+    assert_eq!(&std::fs::read("test60").unwrap()[100..], &data[4096..]);
+    // past the end of the source
+    let c = aiomgr.copy_range(&src, 8000, &dst, 0, 4096);
+    assert_eq!(futures::executor::block_on(c).0, Ok(192));
+    let c = aiomgr.copy_range(&src, 0, &dst, 0, 0);
+    assert_eq!(futures::executor::block_on(c).0, Ok(0));
+}
+
+#[test]
+fn open1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let dir = std::fs::File::open(".").unwrap();
+    let fd = futures::executor::block_on(aiomgr.open_at(
+        &dir,
+        "test61",
+        libc::O_RDWR | libc::O_CREAT | libc::O_TRUNC,
+        0o644,
+    ))
+    .unwrap();
+    let (res, _) =
+        futures::executor::block_on(aiomgr.write(&fd, 0, vec![1; 4096], None));
+    assert_eq!(res, Ok(4096));
+    let meta = futures::executor::block_on(aiomgr.metadata(&fd)).unwrap();
+    assert!(meta.is_file());
+    assert_eq!(meta.len(), 4096);
+    futures::executor::block_on(aiomgr.close(fd)).unwrap();
+    let err = futures::executor::block_on(aiomgr.open_at_raw(
+        libc::AT_FDCWD,
+        "test61/nonexistent",
+        libc::O_RDONLY,
+        0,
+    ))
+    .unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENOTDIR));
+    let err = futures::executor::block_on(aiomgr.open_at_raw(
+        libc::AT_FDCWD,
+        "test\0",
+        libc::O_RDONLY,
+        0,
+    ))
+    .unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+    assert_eq!(aiomgr.get_npending(), 0);
+}
+
+#[test]
+fn rename1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let dir = std::fs::File::open(".").unwrap();
+    std::fs::write("test62.tmp", b"committed").unwrap();
+    futures::executor::block_on(aiomgr.rename(
+        &dir,
+        "test62.tmp",
+        &dir,
+        "test62",
+    ))
+    .unwrap();
+    assert_eq!(std::fs::read("test62").unwrap(), b"committed");
+    assert!(!std::path::Path::new("test62.tmp").exists());
+    futures::executor::block_on(aiomgr.unlink(&dir, "test62")).unwrap();
+    assert!(!std::path::Path::new("test62").exists());
+    let err = futures::executor::block_on(
+        aiomgr.unlink_raw(libc::AT_FDCWD, "test62"),
+    )
+    .unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENOENT));
+}
+
+#[test]
+fn noop1() {
+    let backends = [
+        Backend::Libaio,
+        #[cfg(feature = "uring")]
+        Backend::Uring,
+        Backend::ThreadPool,
+    ];
+    for backend in backends.iter() {
+        let aiomgr = AIOBuilder::default().backend(*backend).build().unwrap();
+        let ns = (0..4).map(|_| aiomgr.noop()).collect::<Vec<_>>();
+        for (res, _) in
+            futures::executor::block_on(futures::future::join_all(ns))
+        {
+            assert_eq!(res, Ok(0));
+        }
+        let stats = aiomgr.stats();
+        assert_eq!(stats.submitted, 4);
+        assert_eq!(stats.completed, 4);
+        assert_eq!(aiomgr.get_npending(), 0);
+    }
+}
+
+#[test]
+fn poll1() {
+    let backends = [
+        Backend::Libaio,
+        #[cfg(feature = "uring")]
+        Backend::Uring,
+        Backend::ThreadPool,
+    ];
+    for backend in backends.iter() {
+        let aiomgr = AIOBuilder::default().backend(*backend).build().unwrap();
+        let mut pipe = [0; 2];
+        assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+        // the write end is ready at once
+        let p = aiomgr.poll_fd_raw(pipe[1], libc::POLLOUT);
+        let (res, _) = futures::executor::block_on(p);
+        assert_eq!(res.unwrap() as i16 & libc::POLLOUT, libc::POLLOUT);
+        let p = aiomgr.poll_fd_raw(pipe[0], libc::POLLIN);
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(aiomgr.get_npending(), 1);
+        assert_eq!(unsafe { libc::write(pipe[1], [1u8].as_ptr() as _, 1) }, 1);
+        let (res, _) = futures::executor::block_on(p);
+        assert_eq!(res.unwrap() as i16 & libc::POLLIN, libc::POLLIN);
+        drop(aiomgr);
+        unsafe {
+            libc::close(pipe[0]);
+            libc::close(pipe[1]);
+        }
+    }
+}
+
+#[test]
+fn sigmask1() {
+    let backends = [
+        Backend::Libaio,
+        #[cfg(feature = "uring")]
+        Backend::Uring,
+        Backend::ThreadPool,
+    ];
+    let mut mask: libc::sigset_t = unsafe { std::mem::zeroed() };
+    unsafe { libc::sigfillset(&mut mask) };
+    for (i, backend) in backends.iter().enumerate() {
+        let aiomgr = AIOBuilder::default()
+            .backend(*backend)
+            .sigmask(mask)
+            .build()
+            .unwrap();
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(format!("test63-{}", i))
+            .unwrap();
+        let w = aiomgr.write(&file, 0, "hello".as_bytes(), None);
+        assert_eq!(futures::executor::block_on(w).0, Ok(5));
+        let r = aiomgr.read(&file, 0, 5, None);
+        let (res, data) = futures::executor::block_on(r);
+        assert_eq!(res, Ok(5));
+        assert_eq!(&data[..], b"hello");
+        // waits with a timeout too
+        let aiomgr = AIOBuilder::default()
+            .backend(*backend)
+            .sigmask(mask)
+            .build_manual()
+            .unwrap();
+        let w = aiomgr.write(&file, 0, "world".as_bytes(), None);
+        while aiomgr.get_npending() > 0 {
+            aiomgr.drive(16, Some(std::time::Duration::from_millis(10)));
+        }
+        assert_eq!(w.now_or_never().unwrap().0, Ok(5));
+    }
+}
+
+#[test]
+fn poll_timeout1() {
+    let aiomgr = AIOBuilder::default()
+        .poll_timeout(std::time::Duration::from_millis(5))
+        .build()
+        .unwrap();
+    let mut pipe = [0; 2];
+    assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+    // keeps the listener waiting for the completions
+    let p = aiomgr.poll_fd_raw(pipe[0], libc::POLLIN);
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test64")
+        .unwrap();
+    // submitted once the wait times out
+    let start = std::time::Instant::now();
+    let w = aiomgr.write(&file, 0, "hello".as_bytes(), None);
+    assert_eq!(futures::executor::block_on(w).0, Ok(5));
+    assert!(start.elapsed() < std::time::Duration::from_millis(500));
+    drop(p);
+    drop(aiomgr);
+    unsafe {
+        libc::close(pipe[0]);
+        libc::close(pipe[1]);
+    }
+}
+
+#[test]
+fn max_events1() {
+    let limit: u64 = std::fs::read_to_string("/proc/sys/fs/aio-max-nr")
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+    let requested = (limit + 1) as u32;
+    match AIOBuilder::default()
+        .backend(Backend::Libaio)
+        .max_events(requested)
+        .build()
+    {
+        Err(e @ aiofut::Error::MaxEventsTooLarge { .. }) => {
+            assert!(e
+                .to_string()
+                .contains(&format!("of {} AIO events", limit)));
+            match e {
+                aiofut::Error::MaxEventsTooLarge {
+                    requested: r,
+                    in_use,
+                    limit: l,
+                } => {
+                    assert_eq!(r, requested);
+                    assert!(in_use.is_some());
+                    assert_eq!(l, Some(limit));
+                }
+                _ => unreachable!(),
+            }
+        }
+        _ => panic!("max_events not rejected"),
+    }
+}
+
+#[test]
+fn grow1() {
+    // the kernel AIO rounds up the size of its context
+    let backends = [
+        #[cfg(feature = "uring")]
+        Backend::Uring,
+        Backend::ThreadPool,
+    ];
+    for (i, backend) in backends.iter().enumerate() {
+        let aiomgr = AIOBuilder::default()
+            .backend(*backend)
+            .max_events(2)
+            .grow_max_events(64)
+            .poll_timeout(std::time::Duration::from_millis(5))
+            .build()
+            .unwrap();
+        let mut pipe = [0; 2];
+        assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+        // fill up the context, as they never finish by themselves
+        let polls = (0..2)
+            .map(|_| aiomgr.poll_fd_raw(pipe[0], libc::POLLIN))
+            .collect::<Vec<_>>();
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(format!("test66-{}", i))
+            .unwrap();
+        let w = aiomgr.write(&file, 0, "hello".as_bytes(), None);
+        assert_eq!(futures::executor::block_on(w).0, Ok(5));
+        // including those in the previous context
+        assert_eq!(unsafe { libc::write(pipe[1], [1u8].as_ptr() as _, 1) }, 1);
+        for (res, _) in
+            futures::executor::block_on(futures::future::join_all(polls))
+        {
+            assert_eq!(res.unwrap() as i16 & libc::POLLIN, libc::POLLIN);
+        }
+        assert_eq!(aiomgr.get_npending(), 0);
+        drop(aiomgr);
+        unsafe {
+            libc::close(pipe[0]);
+            libc::close(pipe[1]);
+        }
+    }
+}
+
+#[test]
+fn shards1() {
+    let backends = [
+        Backend::Libaio,
+        #[cfg(feature = "uring")]
+        Backend::Uring,
+        Backend::ThreadPool,
+    ];
+    let mut i = 0;
+    for backend in backends.iter() {
+        for by in [Sharding::Fd, Sharding::RoundRobin].iter() {
+            let aiomgr = AIOBuilder::default()
+                .backend(*backend)
+                .max_events(4)
+                .shards(3, *by)
+                .build()
+                .unwrap();
+            let files = (0..4)
+                .map(|j| {
+                    std::fs::OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .create(true)
+                        .truncate(true)
+                        .open(format!("test67-{}-{}", i, j))
+                        .unwrap()
+                })
+                .collect::<Vec<_>>();
+            i += 1;
+            // more than a single context takes
+            let ws = (0..32)
+                .map(|k| {
+                    let data = vec![k as u8; 512];
+                    aiomgr.write(
+                        &files[k % 4],
+                        (k / 4 * 512) as u64,
+                        data,
+                        None,
+                    )
+                })
+                .collect::<Vec<_>>();
+            for (res, _) in
+                futures::executor::block_on(futures::future::join_all(ws))
+            {
+                assert_eq!(res, Ok(512));
+            }
+            let rs = (0..32)
+                .map(|k| {
+                    aiomgr.read(&files[k % 4], (k / 4 * 512) as u64, 512, None)
+                })
+                .collect::<Vec<_>>();
+            for (k, (res, buf)) in
+                futures::executor::block_on(futures::future::join_all(rs))
+                    .into_iter()
+                    .enumerate()
+            {
+                assert_eq!(res, Ok(512));
+                assert!(buf.iter().all(|b| *b == k as u8));
+            }
+            assert_eq!(aiomgr.get_npending(), 0);
+        }
+    }
+}
+
+#[test]
+fn long_lived1() {
+    let aiomgr = AIOBuilder::default().max_events(16).build().unwrap();
+    // finished, but its result is only taken after many others
+    let first = aiomgr.noop();
+    let first_id = first.get_id();
+    for _ in 0..1000 {
+        let ns = (0..4).map(|_| aiomgr.noop()).collect::<Vec<_>>();
+        for (res, _) in
+            futures::executor::block_on(futures::future::join_all(ns))
+        {
+            assert_eq!(res, Ok(0));
+        }
+    }
+    assert_eq!(aiomgr.copy_data(first_id), Some(Vec::new()));
+    assert_eq!(futures::executor::block_on(first).0, Ok(0));
+    assert_eq!(aiomgr.copy_data(first_id), None);
+    assert_eq!(aiomgr.get_npending(), 0);
+}
+
+#[test]
+fn handoff1() {
+    let backends = [
+        #[cfg(feature = "uring")]
+        Backend::Uring,
+        Backend::ThreadPool,
+    ];
+    for backend in backends.iter() {
+        let aiomgr = AIOBuilder::default()
+            .backend(*backend)
+            .reaper_threads(4)
+            .build()
+            .unwrap();
+        for _ in 0..500 {
+            let mut ns = (0..32).map(|_| aiomgr.noop()).collect::<Vec<_>>();
+            // the futures are polled by other threads while the reapers finish them
+            std::thread::scope(|s| {
+                while !ns.is_empty() {
+                    let part = ns.split_off(ns.len() - 8);
+                    s.spawn(move || {
+                        for (res, _) in futures::executor::block_on(
+                            futures::future::join_all(part),
+                        ) {
+                            assert_eq!(res, Ok(0));
+                        }
+                    });
+                }
+            });
+        }
+        assert_eq!(aiomgr.get_npending(), 0);
+        assert_eq!(aiomgr.stats().completed, 500 * 32);
+    }
+}
+
+#[test]
+fn wakers1() {
+    use futures::task::{waker, ArcWake};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    struct Counter(AtomicUsize);
+    impl ArcWake for Counter {
+        fn wake_by_ref(arc: &std::sync::Arc<Self>) {
+            arc.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    let counter = std::sync::Arc::new(Counter(AtomicUsize::new(0)));
+    let w = waker(counter.clone());
+    let mut cx = std::task::Context::from_waker(&w);
+    let aiomgr = AIOBuilder::default()
+        .backend(Backend::Libaio)
+        .build_manual()
+        .unwrap();
+    let mut ns = (0..8).map(|_| aiomgr.noop()).collect::<Vec<_>>();
+    for n in ns.iter_mut() {
+        assert!(n.poll_unpin(&mut cx).is_pending());
+    }
+    // finished together, so the task is woken up once
+    aiomgr.drive(8, Some(std::time::Duration::from_millis(10)));
+    assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+    for n in ns.iter_mut() {
+        assert_eq!(
+            n.poll_unpin(&mut cx).map(|r| r.0),
+            std::task::Poll::Ready(Ok(0))
+        );
+    }
+}
+
+#[test]
+fn ring1() {
+    // the completions are taken from the ring while other reapers may be in io_getevents
+    for (i, nreapers) in [1, 3].iter().enumerate() {
+        let aiomgr = AIOBuilder::default()
+            .backend(Backend::Libaio)
+            .max_events(16)
+            .reaper_threads(*nreapers)
+            .build()
+            .unwrap();
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(format!("test68-{}", i))
+            .unwrap();
+        let ws = (0..256)
+            .map(|k| aiomgr.write(&file, k * 8, vec![k as u8; 8], None))
+            .collect::<Vec<_>>();
+        for (res, _) in
+            futures::executor::block_on(futures::future::join_all(ws))
+        {
+            assert_eq!(res, Ok(8));
+        }
+        let rs = (0..256)
+            .map(|k| aiomgr.read(&file, k * 8, 8, None))
+            .collect::<Vec<_>>();
+        for (k, (res, buf)) in
+            futures::executor::block_on(futures::future::join_all(rs))
+                .into_iter()
+                .enumerate()
+        {
+            assert_eq!(res, Ok(8));
+            assert!(buf.iter().all(|b| *b == k as u8));
+        }
+        assert_eq!(aiomgr.get_npending(), 0);
+    }
+}
+
+#[test]
+fn submit_delay1() {
+    use std::time::{Duration, Instant};
+    let delay = Duration::from_millis(300);
+    let aiomgr = AIOBuilder::default()
+        .max_nbatched(4)
+        .submit_delay(delay)
+        .build()
+        .unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test69")
+        .unwrap();
+    // waits for others to come
+    let now = Instant::now();
+    let w = aiomgr.write(&file, 0, "hello".as_bytes(), None);
+    assert_eq!(futures::executor::block_on(w).0, Ok(5));
+    assert!(now.elapsed() >= delay);
+    // unless there are enough for a batch
+    let now = Instant::now();
+    let ws = (0..4)
+        .map(|i| aiomgr.write(&file, i * 5, "hello".as_bytes(), None))
+        .collect::<Vec<_>>();
+    for (res, _) in futures::executor::block_on(futures::future::join_all(ws)) {
+        assert_eq!(res, Ok(5));
+    }
+    assert!(now.elapsed() < delay);
+}
+
+#[test]
+fn submit_delay2() {
+    use aiofut::IoPriority;
+    use std::time::{Duration, Instant};
+    let delay = Duration::from_millis(300);
+    let aiomgr = AIOBuilder::default()
+        .priority_submit_delays(Duration::ZERO, delay, delay)
+        .build()
+        .unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test70")
+        .unwrap();
+    // the background write waits for others to come
+    let now = Instant::now();
+    let w = aiomgr.write(&file, 0, "hello".as_bytes(), Some(IoPriority::Idle));
+    assert_eq!(futures::executor::block_on(w).0, Ok(5));
+    assert!(now.elapsed() >= delay);
+    // while the urgent read goes right away
+    let now = Instant::now();
+    let r = aiomgr.read(&file, 0, 5, Some(IoPriority::Rt(0)));
+    assert_eq!(&futures::executor::block_on(r).1[..], "hello".as_bytes());
+    assert!(now.elapsed() < delay);
+}
+
+#[test]
+fn introspection1() {
+    let aiomgr = AIOBuilder::default().max_events(16).build_manual().unwrap();
+    assert_eq!(aiomgr.capacity(), 16);
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test71")
+        .unwrap();
+    let ws = (0..4)
+        .map(|i| aiomgr.write(&file, i * 5, "hello".as_bytes(), None))
+        .collect::<Vec<_>>();
+    // none is submitted before being driven
+    assert_eq!(aiomgr.queued(), 4);
+    assert_eq!(aiomgr.in_flight(), 0);
+    aiomgr.drive(0, None);
+    assert_eq!(aiomgr.queued(), 0);
+    assert_eq!(aiomgr.in_flight(), 4);
+    while aiomgr.get_npending() > 0 {
+        aiomgr.drive(4, None);
+    }
+    assert_eq!(aiomgr.in_flight(), 0);
+    for (res, _) in futures::executor::block_on(futures::future::join_all(ws)) {
+        assert_eq!(res, Ok(5));
+    }
+}
+
+#[test]
+fn pause1() {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test72")
+        .unwrap();
+    for n in [0, 2] {
+        let aiomgr = AIOBuilder::default().reaper_threads(n).build().unwrap();
+        aiomgr.pause();
+        let w = aiomgr.write(&file, 0, "hello".as_bytes(), None);
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert_eq!(aiomgr.queued(), 1);
+        assert_eq!(aiomgr.stats().submitted, 0);
+        aiomgr.resume();
+        assert_eq!(futures::executor::block_on(w).0, Ok(5));
+        // paused again, until the shutdown
+        aiomgr.pause();
+        let w = aiomgr.write(&file, 5, "world".as_bytes(), None);
+        futures::executor::block_on(aiomgr.shutdown()).unwrap();
+        assert_eq!(futures::executor::block_on(w).0, Ok(5));
+    }
+    assert_eq!(std::fs::read("test72").unwrap(), "helloworld".as_bytes());
+}
+
+#[test]
+fn rate_limit1() {
+    use aiofut::RateLimit;
+    use std::time::{Duration, Instant};
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test73")
+        .unwrap();
+    // a second worth of writes right away, then 10 more at 20 per second
+    aiomgr.set_write_rate_limit(RateLimit::new(20, 0));
+    let now = Instant::now();
+    let ws = (0..30)
+        .map(|i| aiomgr.write(&file, i * 5, "hello".as_bytes(), None))
+        .collect::<Vec<_>>();
+    // while the reads are not held back
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(aiomgr.queued() > 0);
+    let r = aiomgr.read(&file, 0, 5, None);
+    assert_eq!(&futures::executor::block_on(r).1[..], "hello".as_bytes());
+    assert!(now.elapsed() < Duration::from_millis(400));
+    for (res, _) in futures::executor::block_on(futures::future::join_all(ws)) {
+        assert_eq!(res, Ok(5));
+    }
+    assert!(now.elapsed() >= Duration::from_millis(400));
+    // by the bytes, until lifted
+    aiomgr.set_write_rate_limit(RateLimit::new(0, 10));
+    let w = aiomgr.write(&file, 0, "helloworld".as_bytes(), None);
+    assert_eq!(futures::executor::block_on(w).0, Ok(10));
+    let w = aiomgr.write(&file, 10, "hello".as_bytes(), None);
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(aiomgr.queued(), 1);
+    aiomgr.set_write_rate_limit(RateLimit::none());
+    assert_eq!(futures::executor::block_on(w).0, Ok(5));
+}
+
+#[test]
+fn tags1() {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test74")
+        .unwrap();
+    // the writes over each other, so the last one submitted is left
+    let last = |tags: [u32; 2], weight: u32| {
+        let aiomgr = AIOBuilder::default().build().unwrap();
+        aiomgr.set_tag_weight(tags[0], weight);
+        aiomgr.pause();
+        let mut ws = (0..4)
+            .map(|_| {
+                let data = vec![b'a'; 64 << 10];
+                aiomgr.write_with_tag(&file, 0, data, None, tags[0])
+            })
+            .collect::<Vec<_>>();
+        let data = vec![b'b'; 64 << 10];
+        ws.push(aiomgr.write_with_tag(&file, 0, data, None, tags[1]));
+        aiomgr.resume();
+        for (res, _) in
+            futures::executor::block_on(futures::future::join_all(ws))
+        {
+            assert_eq!(res, Ok(64 << 10));
+        }
+        std::fs::read("test74").unwrap()[0]
+    };
+    // in the order of scheduling
+    assert_eq!(last([0, 0], 1), b'b');
+    // or in turn by the tags
+    assert_eq!(last([1, 2], 1), b'a');
+    // as many bytes as their weights each time
+    assert_eq!(last([1, 2], 4), b'b');
+}
+
+#[test]
+fn tenants1() {
+    use aiofut::TenantQuota;
+    use std::time::{Duration, Instant};
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test75")
+        .unwrap();
+    // one AIO at a time for the first tenant, not holding back the other one
+    let aiomgr = AIOBuilder::default().build_manual().unwrap();
+    let t1 = aiomgr.register_tenant("t1", TenantQuota::new(1, 0));
+    let t2 = aiomgr.register_tenant("t2", TenantQuota::none());
+    let mut ws = (0..4)
+        .map(|i| {
+            aiomgr.write_with_tag(
+                &file,
+                i * 5,
+                "hello".as_bytes(),
+                None,
+                t1.tag(),
+            )
+        })
+        .collect::<Vec<_>>();
+    ws.push(aiomgr.write_with_tag(
+        &file,
+        20,
+        "world".as_bytes(),
+        None,
+        t2.tag(),
+    ));
+    aiomgr.drive(0, None);
+    assert_eq!(aiomgr.in_flight(), 2);
+    while aiomgr.get_npending() > 0 {
+        aiomgr.drive(1, None);
+        assert!(aiomgr.in_flight() <= 2);
+    }
+    for (res, _) in futures::executor::block_on(futures::future::join_all(ws)) {
+        assert_eq!(res, Ok(5));
+    }
+    let mut stats = aiomgr.tenant_stats();
+    stats.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(stats[0].0, "t1");
+    assert_eq!(
+        (stats[0].1.finished.ops, stats[0].1.finished.bytes),
+        (4, 20)
+    );
+    assert_eq!(stats[0].1.in_flight, 0);
+    assert_eq!((stats[1].1.finished.ops, stats[1].1.finished.bytes), (1, 5));
+    // held back until within the bytes per second
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let t = aiomgr.register_tenant("t", TenantQuota::new(0, 10));
+    let now = Instant::now();
+    let w =
+        aiomgr.write_with_tag(&file, 0, "helloworld".as_bytes(), None, t.tag());
+    assert_eq!(futures::executor::block_on(w).0, Ok(10));
+    let w = aiomgr.write_with_tag(&file, 10, "hello".as_bytes(), None, t.tag());
+    assert_eq!(futures::executor::block_on(w).0, Ok(5));
+    assert!(now.elapsed() >= Duration::from_millis(400));
+}
+
+#[test]
+fn eagain1() {
+    use std::time::{Duration, Instant};
+    let aiomgr = AIOBuilder::default()
+        .backend(Backend::ThreadPool)
+        .max_events(1)
+        .build_manual()
+        .unwrap();
+    let mut pipe = [0; 2];
+    assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+    // fill up the context, as it never finishes by itself
+    let p = aiomgr.poll_fd_raw(pipe[0], libc::POLLIN);
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test76")
+        .unwrap();
+    let w = aiomgr.write(&file, 0, "hello".as_bytes(), None);
+    let now = Instant::now();
+    while now.elapsed() < Duration::from_millis(100) {
+        aiomgr.drive(0, None);
+    }
+    // backing off instead of trying again at every call
+    let eagain = aiomgr.stats().submit_eagain;
+    assert!(eagain > 0 && eagain < 20);
+    assert_eq!((aiomgr.in_flight(), aiomgr.queued()), (1, 1));
+    // submitted once the poll finishes
+    assert_eq!(unsafe { libc::write(pipe[1], [1u8].as_ptr() as _, 1) }, 1);
+    while aiomgr.get_npending() > 0 {
+        aiomgr.drive(1, None);
+    }
+    let (res, _) = futures::executor::block_on(p);
+    assert_eq!(res.unwrap() as i16 & libc::POLLIN, libc::POLLIN);
+    assert_eq!(futures::executor::block_on(w).0, Ok(5));
+    unsafe {
+        libc::close(pipe[0]);
+        libc::close(pipe[1]);
+    }
+}
+
+#[test]
+fn rejected1() {
+    let aiomgr = AIOBuilder::default().build_manual().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test77")
+        .unwrap();
+    let w1 = aiomgr.write(&file, 0, "hello".as_bytes(), None);
+    let w2 = aiomgr.write_raw(-1, 0, "world".as_bytes(), None);
+    let w3 = aiomgr.write(&file, 5, "world".as_bytes(), None);
+    while aiomgr.get_npending() > 0 {
+        aiomgr.drive(3, None);
+    }
+    // only the AIO on the bad fd fails
+    assert_eq!(futures::executor::block_on(w1).0, Ok(5));
+    assert_eq!(futures::executor::block_on(w2).0, Err(libc::EBADF));
+    assert_eq!(futures::executor::block_on(w3).0, Ok(5));
+    assert_eq!(aiomgr.in_flight(), 0);
+    assert_eq!(aiomgr.stats().failed, 1);
+}
+
+#[test]
+fn submit_raw1() {
+    use aiofut::{IOCb, IOCmd};
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test78")
+        .unwrap();
+    let data = "hello".as_bytes();
+    let iocb = IOCb {
+        aio_lio_opcode: IOCmd::PWrite as u16,
+        aio_fildes: file.as_raw_fd() as u32,
+        aio_buf: data.as_ptr() as u64,
+        aio_nbytes: data.len() as u64,
+        aio_offset: 3,
+        aio_rw_flags: aiofut::RWFlags::DSYNC.bits(),
+        ..Default::default()
+    };
+    let w = unsafe { aiomgr.submit_raw(&iocb, None) };
+    let (res, buf) = futures::executor::block_on(w);
+    assert_eq!(res, Ok(5));
+    assert!(buf.is_empty());
+    let r = aiomgr.read(&file, 3, 5, None);
+    assert_eq!(&futures::executor::block_on(r).1[..], data);
+    // the id is that of the AIO, whatever is in the iocb
+    let iocb = IOCb {
+        aio_data: u64::MAX,
+        aio_lio_opcode: IOCmd::FSync as u16,
+        aio_fildes: file.as_raw_fd() as u32,
+        ..Default::default()
+    };
+    let s = unsafe { aiomgr.submit_raw(&iocb, None) };
+    assert_ne!(s.get_id(), u64::MAX);
+    assert_eq!(futures::executor::block_on(s).0, Ok(0));
+}
+
+#[cfg(all(feature = "raw-abi", target_os = "linux"))]
+#[test]
+fn raw_abi1() {
+    use aiofut::abi;
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test79")
+        .unwrap();
+    let data = "hello".as_bytes();
+    // submitted and reaped without any AIOManager
+    let mut ctx = std::ptr::null_mut();
+    assert_eq!(unsafe { abi::io_setup(1, &mut ctx) }, 0);
+    let mut iocb = abi::IOCb {
+        aio_data: 42,
+        aio_lio_opcode: abi::IOCmd::PWrite as u16,
+        aio_fildes: file.as_raw_fd() as u32,
+        aio_buf: data.as_ptr() as u64,
+        aio_nbytes: data.len() as u64,
+        ..Default::default()
+    };
+    let mut iocbs = [&mut iocb as *mut abi::IOCb];
+    assert_eq!(unsafe { abi::io_submit(ctx, 1, iocbs.as_mut_ptr()) }, 1);
+    let mut ev = abi::IOEvent::default();
+    let ret =
+        unsafe { abi::io_getevents(ctx, 1, 1, &mut ev, std::ptr::null_mut()) };
+    assert_eq!(ret, 1);
+    assert_eq!((ev.data, ev.res), (42, 5));
+    assert_eq!(unsafe { abi::io_destroy(ctx) }, 0);
+    assert_eq!(std::fs::read("test79").unwrap(), data);
+}
+
+#[test]
+fn destroy1() {
+    let limit: u32 = std::fs::read_to_string("/proc/sys/fs/aio-max-nr")
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+    // two of them would not fit together
+    let mut noops = Vec::new();
+    for _ in 0..4 {
+        let aiomgr = AIOBuilder::default()
+            .backend(Backend::Libaio)
+            .max_events(limit / 2 + 1)
+            .build()
+            .unwrap();
+        let noop = aiomgr.noop();
+        while aiomgr.get_npending() > 0 {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        // outliving the AIOManager, but not its context
+        noops.push(noop);
+    }
+    for (res, _) in
+        futures::executor::block_on(futures::future::join_all(noops))
+    {
+        assert_eq!(res, Ok(0));
+    }
+}
+
+#[cfg(feature = "posix-aio")]
+#[test]
+fn posix_aio1() {
+    let aiomgr = AIOBuilder::default()
+        .backend(Backend::PosixAio)
+        .max_events(4)
+        .build()
+        .unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test80")
+        .unwrap();
+    let ws = (0..8)
+        .map(|i| aiomgr.write(&file, i * 5, "hello".as_bytes(), None))
+        .collect::<Vec<_>>();
+    for (res, _) in futures::executor::block_on(futures::future::join_all(ws)) {
+        assert_eq!(res, Ok(5));
+    }
+    assert_eq!(futures::executor::block_on(aiomgr.fsync(&file)).0, Ok(0));
+    let r = aiomgr.read(&file, 35, 10, None);
+    let (res, buf) = futures::executor::block_on(r);
+    assert_eq!(res, Ok(5));
+    assert_eq!(&buf[..5], "hello".as_bytes());
+    assert_eq!(futures::executor::block_on(aiomgr.noop()).0, Ok(0));
+    // beyond POSIX AIO
+    let r = aiomgr.read_vectored(&file, 0, &[5, 5], None);
+    assert_eq!(futures::executor::block_on(r).0, Err(libc::EINVAL));
+}
+
+#[cfg(feature = "mock")]
+#[test]
+fn mock1() {
+    use aiofut::{IOCmd, MockDisk};
+    use std::time::Duration;
+    let disk = MockDisk::new();
+    disk.set_contents("a", "old".as_bytes().to_vec());
+    let aiomgr = AIOBuilder::default().mock(&disk).build_manual().unwrap();
+    let file = disk.open("a").unwrap();
+    let w1 = aiomgr.write(&file, 0, "hello".as_bytes(), None);
+    let w2 = aiomgr.write(&file, 5, "world".as_bytes(), None);
+    aiomgr.drive(0, None);
+    let ops = disk.pending();
+    assert_eq!(ops.len(), 2);
+    assert_eq!(
+        (ops[1].cmd, ops[1].offset, ops[1].len),
+        (IOCmd::PWrite, 5, 5)
+    );
+    // in the reverse order
+    assert!(disk.complete(ops[1].id));
+    aiomgr.drive(1, Some(Duration::from_secs(1)));
+    assert_eq!(futures::executor::block_on(w2).0, Ok(5));
+    assert_eq!(disk.contents("a").unwrap(), "old\0\0world".as_bytes());
+    // the unsynced write is lost, and the pending one fails
+    disk.crash();
+    aiomgr.drive(1, Some(Duration::from_secs(1)));
+    assert_eq!(futures::executor::block_on(w1).0, Err(libc::EIO));
+    assert_eq!(disk.contents("a").unwrap(), "old".as_bytes());
+    disk.auto_complete(true);
+    let w = aiomgr.write(&file, 3, "new".as_bytes(), None);
+    let s = aiomgr.fsync(&file);
+    while aiomgr.get_npending() > 0 {
+        aiomgr.drive(1, None);
+    }
+    assert_eq!(futures::executor::block_on(w).0, Ok(3));
+    assert_eq!(futures::executor::block_on(s).0, Ok(0));
+    disk.crash();
+    let reopened = disk.open("a").unwrap();
+    let r = aiomgr.read(&reopened, 0, 10, None);
+    while aiomgr.get_npending() > 0 {
+        aiomgr.drive(1, None);
+    }
+    let (res, buf) = futures::executor::block_on(r);
+    assert_eq!(res, Ok(6));
+    assert_eq!(&buf[..6], "oldnew".as_bytes());
+    // past the end of the file
+    let r = aiomgr.read(&reopened, 10, 5, None);
+    while aiomgr.get_npending() > 0 {
+        aiomgr.drive(1, None);
+    }
+    assert_eq!(futures::executor::block_on(r).0, Ok(0));
+}
+
+#[cfg(feature = "fault-injection")]
+#[test]
+fn fault1() {
+    use aiofut::{FaultInjector, Faults};
+    use std::time::{Duration, Instant};
+    let faults = FaultInjector::new(Faults {
+        eio_every: 3,
+        ..Default::default()
+    });
+    let aiomgr = AIOBuilder::default()
+        .fault_injection(&faults)
+        .build()
+        .unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test81")
+        .unwrap();
+    let write = |off, data: &'static str| {
+        futures::executor::block_on(aiomgr.write(
+            &file,
+            off,
+            data.as_bytes(),
+            None,
+        ))
+        .0
+    };
+    let read = |off, len| {
+        let (res, buf) =
+            futures::executor::block_on(aiomgr.read(&file, off, len, None));
+        (res, buf[..res.unwrap_or(0)].to_vec())
+    };
+    assert_eq!(write(0, "hello"), Ok(5));
+    assert_eq!(write(5, "world"), Ok(5));
+    assert_eq!(write(10, "!!"), Err(libc::EIO));
+    faults.set(Faults {
+        short_every: 1,
+        ..Default::default()
+    });
+    assert_eq!(read(0, 10), (Ok(5), "hello".as_bytes().to_vec()));
+    // only the first half is written, but all of it is reported
+    faults.set(Faults {
+        torn_every: 1,
+        ..Default::default()
+    });
+    assert_eq!(write(0, "HELLO!"), Ok(6));
+    faults.set(Faults::default());
+    assert_eq!(read(0, 10), (Ok(10), "HELloworld".as_bytes().to_vec()));
+    faults.set(Faults {
+        delay_every: 1,
+        delay: Duration::from_millis(50),
+        ..Default::default()
+    });
+    let now = Instant::now();
+    assert_eq!(read(0, 5).0, Ok(5));
+    assert!(now.elapsed() >= Duration::from_millis(50));
+    assert_eq!(faults.injected(), 4);
+}
+
+#[test]
+fn handle1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test82")
+        .unwrap();
+    let fd = file.as_raw_fd();
+    let threads = (0..4u64)
+        .map(|i| {
+            let h = aiomgr.handle();
+            std::thread::spawn(move || {
+                let w = h.write_raw(fd, i * 5, "hello".as_bytes(), None);
+                futures::executor::block_on(w).0
+            })
+        })
+        .collect::<Vec<_>>();
+    for t in threads {
+        assert_eq!(t.join().unwrap(), Ok(5));
+    }
+    let h = aiomgr.handle();
+    let (res, buf) = futures::executor::block_on(h.read(&file, 15, 5, None));
+    assert_eq!(res, Ok(5));
+    assert_eq!(&buf[..], "hello".as_bytes());
+    // no longer scheduled once the manager is gone
+    drop(aiomgr);
+    let r = h.read(&file, 0, 5, None);
+    assert_eq!(futures::executor::block_on(r).0, Err(libc::ESHUTDOWN));
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn spawn1() {
+    fn is_send<T: Send + 'static>(_: &T) {}
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()
+        .unwrap();
+    let aiomgr = rt
+        .block_on(async { AIOBuilder::default().build_tokio() })
+        .unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test83")
+        .unwrap();
+    let fd = file.as_raw_fd();
+    rt.block_on(async {
+        let tasks = (0..4u64)
+            .map(|i| {
+                let h = aiomgr.handle();
+                is_send(&h);
+                tokio::spawn(async move {
+                    let w = h.write_raw(fd, i * 5, "hello".as_bytes(), None);
+                    is_send(&w);
+                    w.await.0
+                })
+            })
+            .collect::<Vec<_>>();
+        for t in tasks {
+            assert_eq!(t.await.unwrap(), Ok(5));
+        }
+        let r = aiomgr.read(&file, 0, 20, None).await;
+        assert_eq!(&r.1[..], "hello".repeat(4).as_bytes());
+    });
+}
+
+#[cfg(feature = "futures-core")]
+#[test]
+fn fused1() {
+    use futures::future::FusedFuture;
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test84")
+        .unwrap();
+    let mut w = aiomgr.write(&file, 0, "hello".as_bytes(), None);
+    assert!(!w.is_terminated());
+    assert_eq!(futures::executor::block_on(&mut w).0, Ok(5));
+    assert!(w.is_terminated());
+    // pending from then on, instead of panicking
+    futures::executor::block_on(async {
+        assert!(futures::poll!(&mut w).is_pending());
+    });
+    let mut r = aiomgr.read(&file, 0, 5, None);
+    let mut s = aiomgr.fsync(&file);
+    let mut n = 0;
+    futures::executor::block_on(async {
+        loop {
+            futures::select! {
+                (res, buf) = r => {
+                    assert_eq!(res, Ok(5));
+                    assert_eq!(&buf[..], "hello".as_bytes());
+                    n += 1
+                }
+                (res, _) = s => {
+                    assert_eq!(res, Ok(0));
+                    n += 1
+                }
+                complete => break,
+            }
+        }
+    });
+    assert_eq!(n, 2);
+}
+
+#[test]
+fn status1() {
+    use aiofut::OpStatus;
+    let aiomgr = AIOBuilder::default()
+        .backend(Backend::ThreadPool)
+        .max_events(1)
+        .build_manual()
+        .unwrap();
+    let mut pipe = [0; 2];
+    assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+    let p = aiomgr.poll_fd_raw(pipe[0], libc::POLLIN);
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test85")
+        .unwrap();
+    let w = aiomgr.write(&file, 0, "hello".as_bytes(), None);
+    assert_eq!(aiomgr.status(p.id()), OpStatus::Queued);
+    // the poll takes the only room in the context
+    aiomgr.drive(0, None);
+    assert_eq!(aiomgr.status(p.id()), OpStatus::Submitted);
+    assert_eq!(aiomgr.status(w.id()), OpStatus::Queued);
+    assert_eq!(unsafe { libc::write(pipe[1], [1u8].as_ptr() as _, 1) }, 1);
+    while aiomgr.get_npending() > 0 {
+        aiomgr.drive(1, None);
+    }
+    assert_eq!(aiomgr.status(p.id()), OpStatus::Done);
+    let id = w.id();
+    assert_eq!(aiomgr.status(id), OpStatus::Done);
+    assert_eq!(futures::executor::block_on(w).0, Ok(5));
+    assert_eq!(aiomgr.status(id), OpStatus::Unknown);
+    drop(p);
+    unsafe {
+        libc::close(pipe[0]);
+        libc::close(pipe[1]);
+    }
+}
+
+#[cfg(feature = "futures-sink")]
+#[test]
+fn sink1() {
+    use aiofut::AIOSink;
+    use futures::SinkExt;
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test86")
+        .unwrap();
+    let mut sink = AIOSink::new(&aiomgr, &file, 4);
+    let pieces = (0..64u64).map(|i| {
+        let data = vec![i as u8; 100].into_boxed_slice();
+        Ok((i * 100, data))
+    });
+    futures::executor::block_on(async {
+        let mut pieces = futures::stream::iter(pieces);
+        sink.send_all(&mut pieces).await.unwrap();
+        sink.close().await.unwrap();
+    });
+    assert_eq!(sink.in_flight(), 0);
+    let (res, buf) =
+        futures::executor::block_on(aiomgr.read(&file, 0, 6400, None));
+    assert_eq!(res, Ok(6400));
+    for (i, c) in buf.chunks(100).enumerate() {
+        assert!(c.iter().all(|b| *b == i as u8));
+    }
+    // a failed write fails the sink
+    let ro = std::fs::File::open("test86").unwrap();
+    let mut sink = AIOSink::new(&aiomgr, &ro, 4);
+    let r = futures::executor::block_on(sink.send((0, vec![0u8; 8].into())));
+    assert_eq!(r.unwrap_err().raw_os_error(), Some(libc::EBADF));
+}
+
+#[test]
+fn exact_at1() {
+    let mut builder = AIOBuilder::default();
+    // every read and write is short
+    #[cfg(feature = "fault-injection")]
+    builder.fault_injection(&aiofut::FaultInjector::new(aiofut::Faults {
+        short_every: 1,
+        ..Default::default()
+    }));
+    let aiomgr = builder.build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("test87")
+        .unwrap();
+    let data = (0..100u8).collect::<Vec<_>>();
+    let w = aiomgr.write_all_at(&file, 0, data.clone(), None);
+    assert_eq!(futures::executor::block_on(w).0, Ok(100));
+    assert_eq!(std::fs::read("test87").unwrap(), data);
+    let r = aiomgr.read_exact_at(&file, 10, vec![0u8; 80], None);
+    let (res, buf) = futures::executor::block_on(r);
+    assert_eq!(res, Ok(80));
+    assert_eq!(&buf[..], &data[10..90]);
+    // the end of the file comes first
+    let r = aiomgr.read_exact_at(&file, 50, vec![0u8; 80], None);
+    let (res, buf) = futures::executor::block_on(r);
+    assert_eq!(res, Err(libc::EIO));
+    assert_eq!(&buf[..50], &data[50..]);
+}
+
+#[test]
+fn read_file1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    // a few chunks and a partial one
+    let data = (0..(3 << 20) + 12345).map(|i| i as u8).collect::<Vec<_>>();
+    std::fs::write("test88", &data).unwrap();
+    let file = std::fs::File::open("test88").unwrap();
+    let r = futures::executor::block_on(aiomgr.read_file(&file)).unwrap();
+    assert_eq!(r, data);
+    // the size is a multiple of the chunks
+    std::fs::write("test88", &data[..2 << 20]).unwrap();
+    let r = futures::executor::block_on(aiomgr.read_file(&file)).unwrap();
+    assert_eq!(&r[..], &data[..2 << 20]);
+    std::fs::write("test88", b"").unwrap();
+    let r = futures::executor::block_on(aiomgr.read_file(&file)).unwrap();
+    assert!(r.is_empty());
+    std::fs::remove_file("test88").unwrap();
+}
+
+#[test]
+fn read_file2() {
+    use std::os::unix::fs::OpenOptionsExt;
+    // with the pool enabled, and more chunks than in flight at once, so the last ones reuse
+    // the buffers of the first ones, which are not zeroed
+    let aiomgr = AIOBuilder::default()
+        .alignment(4096)
+        .buffer_pool(8 << 20)
+        .build()
+        .unwrap();
+    let data = (0..(5 << 20) + 8192)
+        .map(|i| (i / 3) as u8)
+        .collect::<Vec<_>>();
+    std::fs::write("test91", &data).unwrap();
+    let file = std::fs::File::open("test91").unwrap();
+    let r = futures::executor::block_on(aiomgr.read_file(&file)).unwrap();
+    assert_eq!(r, data);
+    std::fs::write("test91", &data[..12345]).unwrap();
+    let r = futures::executor::block_on(aiomgr.read_file(&file)).unwrap();
+    assert_eq!(&r[..], &data[..12345]);
+    // with O_DIRECT, where the file ends at an aligned offset
+    std::fs::write("test91", &data).unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECT)
+        .open("test91")
+        .unwrap();
+    let r = futures::executor::block_on(aiomgr.read_file(&file)).unwrap();
+    assert_eq!(r, data);
+    std::fs::remove_file("test91").unwrap();
+}
+
+#[cfg(feature = "fault-injection")]
+#[test]
+fn read_file3() {
+    use aiofut::{FaultInjector, Faults};
+    // every other read is cut short, well before the end of the file
+    let faults = FaultInjector::new(Faults {
+        short_every: 2,
+        ..Default::default()
+    });
+    let aiomgr = AIOBuilder::default()
+        .fault_injection(&faults)
+        .build()
+        .unwrap();
+    let data = (0..(3 << 20) + 100)
+        .map(|i| (i / 5) as u8)
+        .collect::<Vec<_>>();
+    std::fs::write("test94", &data).unwrap();
+    let file = std::fs::File::open("test94").unwrap();
+    let r = futures::executor::block_on(aiomgr.read_file(&file)).unwrap();
+    assert_eq!(r, data);
+    std::fs::remove_file("test94").unwrap();
+}
+
+#[test]
+fn read_regions1() {
+    let aiomgr = AIOBuilder::default().build().unwrap();
+    let data = (0..4096).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+    std::fs::write("test89", &data).unwrap();
+    let file = std::fs::File::open("test89").unwrap();
+    // adjacent, overlapping, empty and past the end of the file
+    let regions = [
+        (110, 5),
+        (3000, 100),
+        (100, 10),
+        (105, 20),
+        (50, 0),
+        (4000, 200),
+        (5000, 10),
+    ];
+    let r = futures::executor::block_on(aiomgr.read_regions(&file, &regions));
+    assert_eq!(r.len(), regions.len());
+    let expected = [
+        &data[110..115],
+        &data[3000..3100],
+        &data[100..110],
+        &data[105..125],
+        &[][..],
+        &data[4000..],
+        &[][..],
+    ];
+    for (r, e) in r.iter().zip(expected) {
+        assert_eq!(&r.as_ref().unwrap()[..], e);
+    }
+    // more reads than max_events, each failing
+    let aiomgr = AIOBuilder::default().max_events(2).build().unwrap();
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open("test89")
+        .unwrap();
+    let r = futures::executor::block_on(
+        aiomgr.read_regions(&file, &[(0, 10), (100, 10), (200, 10)]),
+    );
+    assert!(r.iter().all(|r| *r == Err(libc::EBADF)));
+    std::fs::remove_file("test89").unwrap();
+}